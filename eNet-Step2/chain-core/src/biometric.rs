@@ -0,0 +1,175 @@
+use crate::codec::{ Decode, DecodeError, Decoder, Encode, Encoder };
+use crate::hash::{ hash_with_domain, Hash, DOMAIN_BIOMETRIC, DOMAIN_BIOMETRIC_BAND, DOMAIN_BIOMETRIC_PLANE, HASH_BYTES };
+use crate::identity::{ Person, PersonId, PersonStatus };
+use crate::state::{ State, StateError };
+use crate::transaction::Transaction;
+
+// Hashes a template is split into for comparison
+pub const LSH_BANDS: usize = 16;
+// Signature bits in each band, too many to try every value of a band hash
+pub const LSH_BAND_BITS: usize = 64;
+/*
+ *  Equal bands at which two templates are taken to be the same human. Two
+ *  unrelated templates agree on a band with chance 2^-64, so on any of the
+ *  16 with about 1e-18: no false hits even across a billion entries. Two
+ *  scans whose signatures differ in 2% of bits share a given band with
+ *  chance 0.98^64, about 27%, and at least one of the 16 about 99% of the
+ *  time.
+ */
+pub const MATCH_BANDS: usize = 1;
+// Most features a vector may have
+pub const MAX_FEATURES: usize = 4096;
+
+const LSH_BITS: usize = LSH_BANDS * LSH_BAND_BITS;
+
+/*
+ *  Declaration of FeatureVector
+ *
+ *  Biometric features as computed on the person's own device. It has no
+ *  encoding on purpose: nothing built from it but a BiometricCommitment can
+ *  go into a transaction or a node-network message.
+ */
+#[derive(Clone, PartialEq)]
+pub struct FeatureVector(Vec<f32>);
+
+impl FeatureVector {
+    // None if empty, too long or not all finite
+    pub fn new(values: Vec<f32>) -> Option<FeatureVector> {
+        match !values.is_empty() && values.len() <= MAX_FEATURES && values.iter().all(|v| v.is_finite()) {
+            true => Some(FeatureVector(values)),
+            false => None,
+        }
+    }
+
+    /*
+     *  One bit per hyperplane through the origin: which side of it the vector
+     *  lies on. Vectors at a small angle agree on most bits. The planes come
+     *  from `seed`, so every node derives the same ones.
+     */
+    pub fn signature(&self, seed: &Hash) -> [u8; LSH_BITS / 8] {
+        let mut bits = [0u8; LSH_BITS / 8];
+        for plane in 0..LSH_BITS {
+            let mut dot = 0f64;
+            for (block, values) in self.0.chunks(HASH_BYTES / 4).enumerate() {
+                let mut data = [0u8; HASH_BYTES + 8];
+                data[..HASH_BYTES].copy_from_slice(seed.as_bytes());
+                data[HASH_BYTES..HASH_BYTES + 4].copy_from_slice(&(plane as u32).to_be_bytes());
+                data[HASH_BYTES + 4..].copy_from_slice(&(block as u32).to_be_bytes());
+                let normal = hash_with_domain(DOMAIN_BIOMETRIC_PLANE, &data);
+                for (value, word) in values.iter().zip(normal.as_bytes().chunks(4)) {
+                    let component = u32::from_be_bytes([word[0], word[1], word[2], word[3]]) as f64 / u32::MAX as f64 * 2.0 - 1.0;
+                    dot += *value as f64 * component;
+                }
+            }
+            if dot >= 0.0 {
+                bits[plane / 8] |= 0x80 >> (plane % 8);
+            }
+        }
+        bits
+    }
+
+    // The commitment to register; `salt` stays on the device to open it to a verifier later
+    pub fn commit(&self, seed: &Hash, salt: &[u8]) -> BiometricCommitment {
+        let signature = self.signature(seed);
+        let mut data = salt.to_vec();
+        data.extend_from_slice(&signature);
+        let template = hash_with_domain(DOMAIN_BIOMETRIC, &data);
+
+        let bands = signature.chunks(LSH_BAND_BITS / 8).enumerate().map(|(band, bits)| {
+            let mut data = seed.as_bytes().to_vec();
+            data.push(band as u8);
+            data.extend_from_slice(bits);
+            hash_with_domain(DOMAIN_BIOMETRIC_BAND, &data)
+        }).collect();
+
+        BiometricCommitment { template, bands }
+    }
+}
+
+/*
+ *  What the registry keeps of a person's biometrics: a salted commitment to
+ *  their signature and one hash per band of it. A band hash only opens to
+ *  whoever searches its 2^64 values, fewer in practice since real faces do
+ *  not spread evenly over the planes. It does not hide equality: anyone
+ *  holding a scan of someone can tell whether they are in the registry.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct BiometricCommitment {
+    pub template: Hash,
+    pub bands: Vec<Hash>,
+}
+
+impl BiometricCommitment {
+    // Bands in which the two agree
+    pub fn matching_bands(&self, other: &BiometricCommitment) -> usize {
+        self.bands.iter().zip(other.bands.iter()).filter(|(a, b)| a == b).count()
+    }
+}
+
+impl Encode for BiometricCommitment {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.hash(&self.template).list(&self.bands);
+    }
+}
+
+impl Decode for BiometricCommitment {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(BiometricCommitment { template: input.hash()?, bands: input.list("bands", LSH_BANDS)? })
+    }
+}
+
+/*
+ *  Payload of a TxKind::CommitBiometric, sent from the person's current key.
+ *  A person commits once. The chain does not refuse near matches, since
+ *  anyone could copy a commitment out of the mempool to lock its owner out;
+ *  they are found with State::biometric_matches and taken to a challenge.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct BiometricRecord {
+    pub person: PersonId,
+    pub commitment: BiometricCommitment,
+}
+
+impl Encode for BiometricRecord {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.hash(&self.person.0);
+        self.commitment.encode_to(out);
+    }
+}
+
+impl Decode for BiometricRecord {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(BiometricRecord { person: PersonId(input.hash()?), commitment: BiometricCommitment::decode_from(input)? })
+    }
+}
+
+impl State {
+    /*
+     *  Persons other than `exclude` and not revoked whose commitment agrees
+     *  with `commitment` in at least MATCH_BANDS bands, best match first. Run
+     *  on the node's own copy of the registry; nothing is sent anywhere.
+     */
+    pub fn biometric_matches(&self, commitment: &BiometricCommitment, exclude: Option<&PersonId>) -> Vec<(PersonId, usize)> {
+        let mut matches: Vec<(PersonId, usize)> = self.persons()
+            .filter(|(id, person)| Some(id) != exclude && person.status != PersonStatus::Revoked)
+            .filter_map(|(id, person)| person.biometric.as_ref().map(|b| (id, b.matching_bands(commitment))))
+            .filter(|(_, bands)| *bands >= MATCH_BANDS)
+            .collect();
+        matches.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        matches
+    }
+
+    pub(crate) fn check_biometric(&self, tx: &Transaction, record: &BiometricRecord, height: u64) -> Result<Vec<(PersonId, Person)>, StateError> {
+        let mut person = self.sending_person(tx, &record.person)?;
+        if person.biometric.is_some() {
+            return Err(StateError::BiometricSet(record.person));
+        }
+        if record.commitment.bands.len() != LSH_BANDS {
+            return Err(StateError::BadBands { found: record.commitment.bands.len(), expected: LSH_BANDS });
+        }
+        person.biometric = Some(record.commitment.clone());
+        person.updated_at = height;
+
+        Ok(vec![(record.person, person)])
+    }
+}
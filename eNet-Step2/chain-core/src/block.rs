@@ -0,0 +1,189 @@
+use secure_sign::{ NistCryptography, DETACHED_SIGNATURE_MAX };
+
+use crate::certificate::QuorumCertificate;
+use crate::codec::{ Decode, DecodeError, Decoder, Encode, Encoder };
+use crate::hash::{ self, Hash, KeyId, DOMAIN_HEADER, DOMAIN_TX };
+use crate::transaction::Transaction;
+
+pub const BLOCK_VERSION: u16 = 2;
+
+// Hard decoding bounds; the configured block and transaction limits are checked elsewhere
+pub const MAX_BLOCK_TXS: usize = 100_000;
+pub const MAX_ENCODED_TX: usize = 1 << 20;
+
+/*
+ *  Declaration of BlockHeader
+ *
+ *  The hash covers every field except the signature, and the proposer signs that
+ *  hash, so signing does not change the name of a block. Every block after
+ *  genesis carries the quorum certificate of its parent, so a chain of headers
+ *  proves the finality of all but the newest block.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockHeader {
+    pub version: u16,
+    pub parent: Hash,
+    pub height: u64,
+    // Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub state_root: Hash,
+    pub tx_root: Hash,
+    pub proposer: KeyId,
+    pub last_commit: Option<QuorumCertificate>,
+    pub signature: Vec<u8>,
+}
+
+impl BlockHeader {
+    pub fn new(parent: Hash, height: u64, timestamp: u64) -> Self {
+        BlockHeader {
+            version: BLOCK_VERSION,
+            parent,
+            height,
+            timestamp,
+            state_root: Hash::ZERO,
+            tx_root: hash::merkle_root(&[]),
+            proposer: KeyId::default(),
+            last_commit: None,
+            signature: Vec::new(),
+        }
+    }
+
+    fn encode_unsigned(&self, out: &mut Encoder) {
+        out.u16(self.version)
+            .hash(&self.parent)
+            .u64(self.height)
+            .u64(self.timestamp)
+            .hash(&self.state_root)
+            .hash(&self.tx_root)
+            .key_id(&self.proposer);
+        match &self.last_commit {
+            Some(certificate) => {
+                out.u8(1);
+                certificate.encode_to(out);
+            },
+            None => {
+                out.u8(0);
+            },
+        }
+    }
+
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut out = Encoder::new();
+        self.encode_unsigned(&mut out);
+        out.finish()
+    }
+
+    pub fn hash(&self) -> Hash {
+        hash::hash_with_domain(DOMAIN_HEADER, &self.signing_bytes())
+    }
+
+    // Set the proposer to `secure`'s key and sign the header hash with it
+    pub fn sign(&mut self, secure: &mut NistCryptography) -> bool {
+        self.proposer = KeyId::from_public_key(&secure.public_key);
+        match secure.sign_detached(self.hash().as_bytes()) {
+            Some(signature) => {
+                self.signature = signature;
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn verify_signature(&self, public_key: &[u8]) -> bool {
+        if KeyId::from_public_key(public_key) != self.proposer {
+            return false;
+        }
+        NistCryptography::verify_detached(self.hash().as_bytes(), &self.signature, public_key)
+    }
+}
+
+impl Encode for BlockHeader {
+    fn encode_to(&self, out: &mut Encoder) {
+        self.encode_unsigned(out);
+        out.bytes(&self.signature);
+    }
+}
+
+impl Decode for BlockHeader {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        let version = input.u16()?;
+        if version != BLOCK_VERSION {
+            return Err(DecodeError::UnknownVersion(version));
+        }
+
+        Ok(BlockHeader {
+            version,
+            parent: input.hash()?,
+            height: input.u64()?,
+            timestamp: input.u64()?,
+            state_root: input.hash()?,
+            tx_root: input.hash()?,
+            proposer: input.key_id()?,
+            last_commit: match input.u8()? {
+                0 => None,
+                1 => Some(QuorumCertificate::decode_from(input)?),
+                tag => return Err(DecodeError::UnknownKind(tag)),
+            },
+            signature: input.bytes("signature", DETACHED_SIGNATURE_MAX)?,
+        })
+    }
+}
+
+/*
+ *  Declaration of Block
+ *
+ *  Transactions are kept in their canonical encoding; `tx_root` commits to them.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub transactions: Vec<Vec<u8>>,
+}
+
+impl Block {
+    // A block over `transactions`, with the header's tx_root filled in
+    pub fn new(mut header: BlockHeader, transactions: Vec<Vec<u8>>) -> Self {
+        header.tx_root = Block::tx_root_of(&transactions);
+        Block { header, transactions }
+    }
+
+    pub fn tx_root_of(transactions: &[Vec<u8>]) -> Hash {
+        let leaves: Vec<Hash> = transactions.iter().map(|tx| hash::hash_with_domain(DOMAIN_TX, tx)).collect();
+        hash::merkle_root(&leaves)
+    }
+
+    pub fn hash(&self) -> Hash {
+        self.header.hash()
+    }
+
+    pub fn has_valid_tx_root(&self) -> bool {
+        self.header.tx_root == Block::tx_root_of(&self.transactions)
+    }
+
+    pub fn decode_transactions(&self) -> Result<Vec<Transaction>, DecodeError> {
+        self.transactions.iter().map(|tx| Transaction::decode(tx)).collect()
+    }
+}
+
+impl Encode for Block {
+    fn encode_to(&self, out: &mut Encoder) {
+        self.header.encode_to(out);
+        out.list(&self.transactions);
+    }
+}
+
+impl Decode for Block {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        let header = BlockHeader::decode_from(input)?;
+        let count = input.u32()? as usize;
+        if count > MAX_BLOCK_TXS {
+            return Err(DecodeError::TooLong { field: "transactions", len: count, max: MAX_BLOCK_TXS });
+        }
+        let mut transactions = Vec::with_capacity(count.min(1024));
+        for _ in 0..count {
+            transactions.push(input.bytes("transaction", MAX_ENCODED_TX)?);
+        }
+
+        Ok(Block { header, transactions })
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt::{ self, Display, Formatter };
+
+use secure_sign::{ NistCryptography, DETACHED_SIGNATURE_MAX };
+
+use crate::codec::{ Decode, DecodeError, Decoder, Encode, Encoder };
+use crate::hash::Hash;
+use crate::vote::{ Validator, ValidatorSet, Vote, VoteKind, MAX_VALIDATORS };
+
+#[derive(Debug, PartialEq)]
+pub enum CertError {
+    // Certifies another block than the one it was checked for
+    WrongBlock { expected: Hash, found: Hash },
+    // The bitmap is sized for a different validator set
+    WrongSet { validators: usize, bitmap: usize },
+    SignatureCount { signers: usize, signatures: usize },
+    NoQuorum { signers: usize, quorum: usize },
+    BadSignature,
+}
+
+impl Display for CertError {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            CertError::WrongBlock { expected, found } => write!(f, "certifies {}, expected {}", found, expected),
+            CertError::WrongSet { validators, bitmap } => write!(f, "bitmap of {} bytes for {} validators", bitmap, validators),
+            CertError::SignatureCount { signers, signatures } => write!(f, "{} signers but {} signatures", signers, signatures),
+            CertError::NoQuorum { signers, quorum } => write!(f, "{} signers, quorum is {}", signers, quorum),
+            CertError::BadSignature => write!(f, "a precommit signature does not verify"),
+        }
+    }
+}
+
+/*
+ *  Declaration of QuorumCertificate
+ *
+ *  Proof that a quorum of validators precommitted `block` at `height` and
+ *  `round`. Falcon signatures do not aggregate, so the certificate carries one
+ *  per signer; who signed is a bitmap over the validator set in KeyId order
+ *  (bit i is bit i % 8 of byte i / 8), so no key IDs are repeated. Anyone
+ *  holding the validator set can check it without the vote messages.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuorumCertificate {
+    pub height: u64,
+    pub round: u32,
+    pub block: Hash,
+    pub signers: Vec<u8>,
+    // One precommit signature per set bit, in bit order
+    pub signatures: Vec<Vec<u8>>,
+}
+
+impl QuorumCertificate {
+    // Bundle the precommits for `block` among `precommits`, ignoring any others
+    pub fn from_precommits(validators: &ValidatorSet, height: u64, round: u32, block: Hash, precommits: &[Vote]) -> Self {
+        let mut certificate = QuorumCertificate {
+            height,
+            round,
+            block,
+            signers: vec![0; validators.len().div_ceil(8)],
+            signatures: Vec::new(),
+        };
+        for (i, validator) in validators.validators.iter().enumerate() {
+            let vote = precommits.iter().find(|v| {
+                v.validator == validator.id && v.kind == VoteKind::Precommit
+                    && v.height == height && v.round == round && v.block == Some(block)
+            });
+            if let Some(vote) = vote {
+                certificate.signers[i / 8] |= 1 << (i % 8);
+                certificate.signatures.push(vote.signature.clone());
+            }
+        }
+
+        certificate
+    }
+
+    pub fn signed_by(&self, index: usize) -> bool {
+        self.signers.get(index / 8).map(|byte| byte & (1 << (index % 8)) != 0).unwrap_or(false)
+    }
+
+    pub fn signer_count(&self) -> usize {
+        self.signers.iter().map(|byte| byte.count_ones() as usize).sum()
+    }
+
+    pub fn signers<'a>(&self, validators: &'a ValidatorSet) -> Vec<&'a Validator> {
+        validators.validators.iter().enumerate()
+            .filter(|(i, _)| self.signed_by(*i))
+            .map(|(_, v)| v)
+            .collect()
+    }
+
+    // The precommits the certificate stands for
+    pub fn precommits(&self, validators: &ValidatorSet) -> Vec<Vote> {
+        self.signers(validators).iter().zip(&self.signatures)
+            .map(|(validator, signature)| Vote {
+                kind: VoteKind::Precommit,
+                height: self.height,
+                round: self.round,
+                block: Some(self.block),
+                validator: validator.id,
+                signature: signature.clone(),
+            })
+            .collect()
+    }
+
+    // Check the shape, the quorum and every signature against `validators`
+    pub fn verify(&self, validators: &ValidatorSet, chain_id: &str) -> Result<(), CertError> {
+        let bitmap = validators.len().div_ceil(8);
+        let padding = !validators.len().is_multiple_of(8) && self.signers.last().map(|b| b >> (validators.len() % 8) != 0).unwrap_or(false);
+        if self.signers.len() != bitmap || padding {
+            return Err(CertError::WrongSet { validators: validators.len(), bitmap: self.signers.len() });
+        }
+        let signers = self.signer_count();
+        if signers != self.signatures.len() {
+            return Err(CertError::SignatureCount { signers, signatures: self.signatures.len() });
+        }
+        if signers < validators.quorum() {
+            return Err(CertError::NoQuorum { signers, quorum: validators.quorum() });
+        }
+
+        let votes = self.precommits(validators);
+        let hashes: Vec<Hash> = votes.iter().map(|v| v.signing_hash(chain_id)).collect();
+        let keys = self.signers(validators);
+        let items: Vec<(&[u8], &[u8], &[u8])> = votes.iter().zip(&hashes).zip(&keys)
+            .map(|((vote, hash), validator)| (hash.as_bytes(), &vote.signature[..], &validator.public_key[..]))
+            .collect();
+        if !NistCryptography::verify_detached_batch(&items) {
+            return Err(CertError::BadSignature);
+        }
+
+        Ok(())
+    }
+}
+
+impl Encode for QuorumCertificate {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.u64(self.height).u32(self.round).hash(&self.block).bytes(&self.signers);
+        out.list(&self.signatures);
+    }
+}
+
+impl Decode for QuorumCertificate {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        let height = input.u64()?;
+        let round = input.u32()?;
+        let block = input.hash()?;
+        let signers = input.bytes("signers", MAX_VALIDATORS.div_ceil(8))?;
+        let count = input.u32()? as usize;
+        if count > MAX_VALIDATORS {
+            return Err(DecodeError::TooLong { field: "signatures", len: count, max: MAX_VALIDATORS });
+        }
+        let mut signatures = Vec::with_capacity(count);
+        for _ in 0..count {
+            signatures.push(input.bytes("signature", DETACHED_SIGNATURE_MAX)?);
+        }
+
+        Ok(QuorumCertificate { height, round, block, signers, signatures })
+    }
+}
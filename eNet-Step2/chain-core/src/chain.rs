@@ -0,0 +1,762 @@
+use std::fmt::{ self, Display, Formatter };
+use std::mem;
+use std::sync::mpsc::{ self, Receiver, Sender };
+
+use secure_sign::{ NistCryptography, DETACHED_SIGNATURE_MAX };
+
+use crate::block::{ Block, BlockHeader, MAX_BLOCK_TXS };
+use crate::certificate::{ CertError, QuorumCertificate };
+use crate::codec::Encode;
+use crate::fork::{ BlockTree, ChainEvent, Executed, TreeBlock, MAX_PENDING_BLOCKS };
+use crate::genesis::{ Genesis, GenesisError };
+use crate::hash::{ Hash, KeyId };
+use crate::mempool::Mempool;
+use crate::snapshot::{ Snapshot, SnapshotError, SnapshotManifest };
+use crate::state::{ State, StateError };
+use crate::store::{ Store, StoreError };
+use crate::transaction::{ Transaction, TxError };
+use crate::vote::ValidatorSet;
+
+// How far ahead of the local clock a block timestamp may be
+pub const MAX_BLOCK_DRIFT: u64 = 15_000;
+
+#[derive(Debug)]
+pub enum ChainError {
+    Store(StoreError),
+    State(StateError),
+    Transaction(usize, TxError),
+    NotOnTip { tip: Hash, parent: Hash },
+    WrongHeight { expected: u64, found: u64 },
+    TimestampNotAfterParent { parent: u64, found: u64 },
+    TimestampInFuture { now: u64, found: u64 },
+    TooLarge { size: usize, max: usize },
+    BadTxRoot,
+    // Signed by a key outside the validator set
+    NotValidator { height: u64 },
+    // Blocks after genesis must carry their parent's quorum certificate
+    MissingLastCommit { height: u64 },
+    Certificate(CertError),
+    SigningFailed,
+    UnknownParent(Hash),
+    // At or below the finalized height but not the finalized chain's block
+    ConflictsWithFinalized { height: u64 },
+    TooManyPending,
+    Snapshot(SnapshotError),
+    // Snapshots only start a chain that has no blocks yet
+    NotEmpty { height: u64 },
+    Genesis(GenesisError),
+    // The store holds the chain of another genesis, or of one it did not record
+    WrongGenesis { expected: Hash, found: Option<Hash> },
+    BeforeGenesis { genesis_time: u64, found: u64 },
+}
+
+impl Display for ChainError {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ChainError::Store(e) => write!(f, "{}", e),
+            ChainError::State(e) => write!(f, "{}", e),
+            ChainError::Transaction(i, e) => write!(f, "transaction {}: {}", i, e),
+            ChainError::NotOnTip { tip, parent } => write!(f, "parent {} is not the tip {}", parent, tip),
+            ChainError::WrongHeight { expected, found } => write!(f, "expected height {}, found {}", expected, found),
+            ChainError::TimestampNotAfterParent { parent, found } => write!(f, "timestamp {} is not after parent's {}", found, parent),
+            ChainError::TimestampInFuture { now, found } => write!(f, "timestamp {} is ahead of local time {}", found, now),
+            ChainError::TooLarge { size, max } => write!(f, "block is {} bytes, at most {} allowed", size, max),
+            ChainError::BadTxRoot => write!(f, "transaction root does not match the transactions"),
+            ChainError::NotValidator { height } => write!(f, "block {} is not signed by a validator", height),
+            ChainError::MissingLastCommit { height } => write!(f, "block {} lacks its parent's certificate", height),
+            ChainError::Certificate(e) => write!(f, "certificate: {}", e),
+            ChainError::SigningFailed => write!(f, "could not sign the block"),
+            ChainError::UnknownParent(h) => write!(f, "parent {} is unknown", h),
+            ChainError::ConflictsWithFinalized { height } => write!(f, "block {} conflicts with the finalized chain", height),
+            ChainError::TooManyPending => write!(f, "too many blocks waiting above the finalized one"),
+            ChainError::Snapshot(e) => write!(f, "snapshot: {}", e),
+            ChainError::NotEmpty { height } => write!(f, "chain already has blocks up to {}", height),
+            ChainError::Genesis(e) => write!(f, "genesis: {}", e),
+            ChainError::WrongGenesis { expected, found } => match found {
+                Some(found) => write!(f, "store holds the chain of genesis {}, not {}", found, expected),
+                None => write!(f, "store holds a chain without a recorded genesis, expected {}", expected),
+            },
+            ChainError::BeforeGenesis { genesis_time, found } => write!(f, "timestamp {} is before genesis time {}", found, genesis_time),
+        }
+    }
+}
+
+impl From<StoreError> for ChainError {
+    fn from(e: StoreError) -> Self {
+        ChainError::Store(e)
+    }
+}
+
+impl From<CertError> for ChainError {
+    fn from(e: CertError) -> Self {
+        ChainError::Certificate(e)
+    }
+}
+
+impl From<StateError> for ChainError {
+    fn from(e: StateError) -> Self {
+        ChainError::State(e)
+    }
+}
+
+impl From<GenesisError> for ChainError {
+    fn from(e: GenesisError) -> Self {
+        ChainError::Genesis(e)
+    }
+}
+
+impl From<SnapshotError> for ChainError {
+    fn from(e: SnapshotError) -> Self {
+        ChainError::Snapshot(e)
+    }
+}
+
+// What a block is checked and executed against
+struct Base<'a> {
+    parent: Option<&'a BlockHeader>,
+    state: &'a State,
+    // Validators of the parent's height, who certified it
+    parent_validators: &'a ValidatorSet,
+}
+
+// The genesis the chain was set up from
+struct GenesisStart {
+    hash: Hash,
+    time: u64,
+    state: State,
+}
+
+// The finalized block's data, kept while the tip is past it
+struct Anchor {
+    header: Option<BlockHeader>,
+    state: State,
+    validators: ValidatorSet,
+}
+
+/*
+ *  Declaration of Class Chain
+ *
+ *  The tip and the state after it, optionally backed by a Store. Consensus
+ *  executes a proposed block with `execute` and, once it is decided, hands the
+ *  resulting state and the quorum certificate to `commit`, which finalizes it.
+ *  The next block then carries that certificate.
+ *
+ *  Blocks from elsewhere go through `add_block` into a BlockTree above the last
+ *  finalized block. Fork choice picks a branch there; switching to it reverts
+ *  to the common ancestor and replays the branch, and a verified certificate
+ *  on the chain moves finality up. Subscribers hear about each change.
+ */
+pub struct Chain {
+    pub chain_id: String,
+    pub max_block_bytes: usize,
+    pub max_tx_bytes: usize,
+    // Public keys that may validate; only those verified in the registry do
+    pub validator_keys: Vec<Vec<u8>>,
+    pub state: State,
+    tip: Option<BlockHeader>,
+    // Certificate of the tip and the validators it is checked against
+    last_commit: Option<QuorumCertificate>,
+    last_validators: ValidatorSet,
+    // Last finalized block, None before genesis
+    finalized: Option<(Hash, u64)>,
+    anchor: Option<Anchor>,
+    pending: BlockTree,
+    subscribers: Vec<Sender<ChainEvent>>,
+    store: Option<Store>,
+    genesis: Option<GenesisStart>,
+}
+
+impl Chain {
+    pub fn new(chain_id: &str, max_block_bytes: usize, max_tx_bytes: usize) -> Self {
+        Chain {
+            chain_id: String::from(chain_id),
+            max_block_bytes,
+            max_tx_bytes,
+            validator_keys: Vec::new(),
+            state: State::new(),
+            tip: None,
+            last_commit: None,
+            last_validators: ValidatorSet::default(),
+            finalized: None,
+            anchor: None,
+            pending: BlockTree::new(),
+            subscribers: Vec::new(),
+            store: None,
+            genesis: None,
+        }
+    }
+
+    /*
+     *  Set the chain up from `genesis`: its ID, limits, validators and the
+     *  accounts the first block executes on. Any store attached must hold the
+     *  chain of this same genesis; an empty one is tied to it.
+     */
+    pub fn set_genesis(&mut self, genesis: &Genesis) -> Result<(), ChainError> {
+        genesis.validate()?;
+        let hash = genesis.hash();
+        if let Some(store) = &self.store {
+            check_genesis(store, &hash)?;
+        }
+        self.chain_id = genesis.chain_id.clone();
+        self.max_block_bytes = genesis.params.max_block_bytes as usize;
+        self.max_tx_bytes = genesis.params.max_tx_bytes as usize;
+        self.validator_keys = genesis.validators.clone();
+        let state = genesis.state();
+        if self.tip.is_none() {
+            self.state = state.clone();
+        }
+        self.genesis = Some(GenesisStart { hash, time: genesis.genesis_time, state });
+
+        Ok(())
+    }
+
+    pub fn genesis_hash(&self) -> Option<Hash> {
+        self.genesis.as_ref().map(|g| g.hash)
+    }
+
+    /*
+     *  Continue from what `store` holds; an empty store starts before genesis.
+     *  The finalized block's validators come from the state before it, which
+     *  for genesis is the state this chain was set up with, and for a block
+     *  restored from a snapshot are the ones the snapshot came with. Blocks
+     *  stored above it go back into the tree, executed, with the tip on them.
+     */
+    pub fn attach_store(&mut self, store: Store) -> Result<(), ChainError> {
+        if let Some(genesis) = &self.genesis {
+            check_genesis(&store, &genesis.hash)?;
+            self.state = genesis.state.clone();
+        }
+        let stored_tip = store.tip()?;
+        match store.finalized()?.or(stored_tip) {
+            Some(tip) => {
+                let block = store.block(&tip.hash)?.ok_or(StoreError::MissingBlock(tip.hash))?;
+                match store.base()?.filter(|(height, _)| *height == tip.height) {
+                    Some((_, validators)) => self.last_validators = validators,
+                    None => {
+                        if block.header.height > 0 {
+                            let parent = store.block(&block.header.parent)?.ok_or(StoreError::MissingBlock(block.header.parent))?;
+                            self.state = store.load_state(&parent.header.state_root)?;
+                        }
+                        self.last_validators = self.validators();
+                    },
+                }
+                self.last_commit = store.certificate(tip.height)?;
+                self.state = store.load_state(&tip.state_root)?;
+                self.tip = Some(block.header);
+            },
+            None => {
+                self.state = self.genesis.as_ref().map(|g| g.state.clone()).unwrap_or_default();
+                self.tip = None;
+                self.last_commit = None;
+                self.last_validators = ValidatorSet::default();
+            },
+        }
+        self.finalized = self.tip.as_ref().map(|t| (t.hash(), t.height));
+        self.anchor = None;
+        self.pending.clear();
+
+        let (first, last) = match (self.finalized, stored_tip) {
+            (Some((_, finalized)), Some(tip)) => (finalized + 1, tip.height),
+            _ => (1, 0),
+        };
+        let mut parent_state = self.state.clone();
+        for height in first..=last {
+            let block = store.block_at(height)?.ok_or(StoreError::MissingBlock(Hash::ZERO))?;
+            let state = store.load_state(&block.header.state_root)?;
+            let validators = ValidatorSet::from_registry(&parent_state, &self.validator_keys);
+            let certificate = store.certificate(height)?;
+            let hash = block.hash();
+            parent_state = state.clone();
+            self.pending.insert(TreeBlock { block, executed: Some(Executed { state, validators }), certificate });
+            self.move_tip(&hash);
+        }
+        self.store = Some(store);
+
+        Ok(())
+    }
+
+    pub fn store(&self) -> Option<&Store> {
+        self.store.as_ref()
+    }
+
+    pub fn tip(&self) -> Option<&BlockHeader> {
+        self.tip.as_ref()
+    }
+
+    pub fn last_commit(&self) -> Option<&QuorumCertificate> {
+        self.last_commit.as_ref()
+    }
+
+    pub fn finalized(&self) -> Option<(Hash, u64)> {
+        self.finalized
+    }
+
+    fn finalized_hash(&self) -> Hash {
+        self.finalized.map(|(hash, _)| hash).unwrap_or(Hash::ZERO)
+    }
+
+    pub fn pending(&self) -> &BlockTree {
+        &self.pending
+    }
+
+    // Events from here on; dropping the receiver unsubscribes
+    pub fn subscribe(&mut self) -> Receiver<ChainEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    fn emit(&mut self, event: ChainEvent) {
+        self.subscribers.retain(|s| s.send(event.clone()).is_ok());
+    }
+
+    pub fn tip_hash(&self) -> Hash {
+        self.tip.as_ref().map(|t| t.hash()).unwrap_or(Hash::ZERO)
+    }
+
+    // Height of the block that goes on top of the tip next
+    pub fn next_height(&self) -> u64 {
+        self.tip.as_ref().map(|t| t.height + 1).unwrap_or(0)
+    }
+
+    // Validators for the next height, taken from the state the tip left
+    pub fn validators(&self) -> ValidatorSet {
+        ValidatorSet::from_registry(&self.state, &self.validator_keys)
+    }
+
+    fn tip_base(&self) -> Base<'_> {
+        Base { parent: self.tip.as_ref(), state: &self.state, parent_validators: &self.last_validators }
+    }
+
+    // The executed block `parent` as a base, if it is the finalized block or an executed pending one
+    fn base_of(&self, parent: &Hash) -> Option<Base<'_>> {
+        if *parent == self.finalized_hash() {
+            return match &self.anchor {
+                Some(a) => Some(Base { parent: a.header.as_ref(), state: &a.state, parent_validators: &a.validators }),
+                None => Some(self.tip_base()),
+            };
+        }
+        let node = self.pending.get(parent)?;
+        let executed = node.executed.as_ref()?;
+        Some(Base { parent: Some(&node.block.header), state: &executed.state, parent_validators: &executed.validators })
+    }
+
+    // Everything about `block` that can be checked before executing it on the tip
+    pub fn check_block(&self, block: &Block, now: u64) -> Result<Vec<Transaction>, ChainError> {
+        self.check_on(&self.tip_base(), block, now)
+    }
+
+    fn check_on(&self, base: &Base, block: &Block, now: u64) -> Result<Vec<Transaction>, ChainError> {
+        let header = &block.header;
+        let parent_hash = base.parent.map(|p| p.hash()).unwrap_or(Hash::ZERO);
+        let expected = base.parent.map(|p| p.height + 1).unwrap_or(0);
+        if header.height != expected {
+            return Err(ChainError::WrongHeight { expected, found: header.height });
+        }
+        if header.parent != parent_hash {
+            return Err(ChainError::NotOnTip { tip: parent_hash, parent: header.parent });
+        }
+        match (base.parent, &self.genesis) {
+            (Some(parent), _) if header.timestamp <= parent.timestamp => {
+                return Err(ChainError::TimestampNotAfterParent { parent: parent.timestamp, found: header.timestamp });
+            },
+            (None, Some(genesis)) if header.timestamp < genesis.time => {
+                return Err(ChainError::BeforeGenesis { genesis_time: genesis.time, found: header.timestamp });
+            },
+            _ => (),
+        }
+        if header.timestamp > now + MAX_BLOCK_DRIFT {
+            return Err(ChainError::TimestampInFuture { now, found: header.timestamp });
+        }
+        match ValidatorSet::from_registry(base.state, &self.validator_keys).get(&header.proposer) {
+            Some(validator) if header.verify_signature(&validator.public_key) => (),
+            _ => return Err(ChainError::NotValidator { height: header.height }),
+        }
+        match (&header.last_commit, base.parent) {
+            (Some(certificate), Some(parent)) => {
+                if certificate.height != parent.height || certificate.block != header.parent {
+                    return Err(ChainError::Certificate(CertError::WrongBlock { expected: header.parent, found: certificate.block }));
+                }
+                certificate.verify(base.parent_validators, &self.chain_id)?;
+            },
+            (Some(certificate), None) => {
+                return Err(ChainError::Certificate(CertError::WrongBlock { expected: Hash::ZERO, found: certificate.block }));
+            },
+            (None, Some(_)) => return Err(ChainError::MissingLastCommit { height: header.height }),
+            (None, None) => (),
+        }
+
+        let size = block.encode().len();
+        if size > self.max_block_bytes {
+            return Err(ChainError::TooLarge { size, max: self.max_block_bytes });
+        }
+        if !block.has_valid_tx_root() {
+            return Err(ChainError::BadTxRoot);
+        }
+        let mut txs = Vec::with_capacity(block.transactions.len());
+        for (i, bytes) in block.transactions.iter().enumerate() {
+            let tx = Transaction::decode_valid(bytes, &self.chain_id, self.max_tx_bytes)
+                .map_err(|e| ChainError::Transaction(i, e))?;
+            txs.push(tx);
+        }
+
+        Ok(txs)
+    }
+
+    // Check and execute `block` on top of the tip, returning the state it leads to
+    pub fn execute(&self, block: &Block, now: u64) -> Result<State, ChainError> {
+        self.execute_on(&self.tip_base(), block, now)
+    }
+
+    fn execute_on(&self, base: &Base, block: &Block, now: u64) -> Result<State, ChainError> {
+        let txs = self.check_on(base, block, now)?;
+
+        let header = &block.header;
+        let mut next = base.state.clone();
+        let computed = next.apply_transactions(&txs, header.height, &header.parent, &header.proposer)?;
+        if computed != header.state_root {
+            return Err(ChainError::State(StateError::StateRootMismatch { expected: header.state_root, computed }));
+        }
+
+        Ok(next)
+    }
+
+    /*
+     *  Build the next block from `pool` as the validator holding `secure`: take
+     *  transactions in pool order, skip any that no longer execute or do not
+     *  fit in `max_block_bytes`, and sign the result. Nothing is committed; the
+     *  block and its state go to consensus.
+     */
+    pub fn build_block(&self, pool: &Mempool, secure: &mut NistCryptography, now: u64) -> Result<(Block, State), ChainError> {
+        let height = self.next_height();
+        let proposer = KeyId::from_public_key(&secure.public_key);
+        if !self.validators().contains(&proposer) {
+            return Err(ChainError::NotValidator { height });
+        }
+        let timestamp = match &self.tip {
+            Some(parent) => now.max(parent.timestamp + 1),
+            None => now,
+        };
+        let mut header = BlockHeader::new(self.tip_hash(), height, timestamp);
+        if self.tip.is_some() {
+            header.last_commit = Some(self.last_commit.clone().ok_or(ChainError::MissingLastCommit { height })?);
+        }
+
+        // Header with the longest signature, then a u32 length per transaction
+        let budget = self.max_block_bytes.saturating_sub(header.encode().len() + DETACHED_SIGNATURE_MAX + 4);
+        let mut used = 0;
+        let mut scratch = self.state.clone();
+        let mut txs = Vec::new();
+        let mut encoded = Vec::new();
+        for tx in pool.select(&self.state, budget, MAX_BLOCK_TXS) {
+            let bytes = tx.encode();
+            if used + 4 + bytes.len() > budget || scratch.apply_transaction(&tx, height).is_err() {
+                continue;
+            }
+            used += 4 + bytes.len();
+            txs.push(tx);
+            encoded.push(bytes);
+        }
+
+        let mut next = self.state.clone();
+        header.state_root = next.apply_transactions(&txs, height, &header.parent, &proposer)?;
+        let mut block = Block::new(header, encoded);
+        if !block.header.sign(secure) {
+            return Err(ChainError::SigningFailed);
+        }
+
+        Ok((block, next))
+    }
+
+    /*
+     *  Make `block`, decided by consensus on top of the tip, the new tip with
+     *  `next`, the state `execute` returned for it. `certificate` finalizes it
+     *  and everything below.
+     */
+    pub fn commit(&mut self, block: &Block, next: State, certificate: QuorumCertificate) -> Result<(), ChainError> {
+        if block.header.parent != self.tip_hash() {
+            return Err(ChainError::NotOnTip { tip: self.tip_hash(), parent: block.header.parent });
+        }
+        if let Some(store) = &self.store {
+            store.commit_block(block, &next, Some(&certificate))?;
+            store.set_finalized(&certificate)?;
+        }
+        let (hash, height) = (block.hash(), block.header.height);
+        self.last_validators = self.validators();
+        self.last_commit = Some(certificate);
+        self.state = next;
+        self.tip = Some(block.header.clone());
+        self.finalized = Some((hash, height));
+        self.anchor = None;
+        self.pending.retain_descendants(&hash);
+
+        self.emit(ChainEvent::NewTip { hash, height });
+        self.emit(ChainEvent::Finalized { hash, height });
+        Ok(())
+    }
+
+    /*
+     *  Everything needed to start another node at the finalized block without
+     *  its history, cut into chunks of about `chunk_accounts` accounts. Needs
+     *  a store to take the block and its certificate from.
+     */
+    pub fn snapshot(&self, chunk_accounts: usize) -> Result<Option<Snapshot>, ChainError> {
+        let (store, (hash, height)) = match (&self.store, self.finalized) {
+            (Some(store), Some(finalized)) => (store, finalized),
+            _ => return Ok(None),
+        };
+        let certificate = match store.certificate(height)? {
+            Some(certificate) => certificate,
+            None => return Ok(None),
+        };
+        let block = store.block(&hash)?.ok_or(StoreError::MissingBlock(hash))?;
+        let (state, validators) = match &self.anchor {
+            Some(a) => (&a.state, &a.validators),
+            None => (&self.state, &self.last_validators),
+        };
+
+        Ok(Some(Snapshot::new(block, certificate, validators.clone(), state, chunk_accounts)))
+    }
+
+    /*
+     *  Start a chain with no blocks at the snapshot `manifest` describes, with
+     *  `state` assembled from its chunks. The snapshot's block becomes the
+     *  finalized tip and blocks are added on top of it as usual.
+     */
+    pub fn restore(&mut self, manifest: &SnapshotManifest, state: State) -> Result<(), ChainError> {
+        if let Some(tip) = &self.tip {
+            return Err(ChainError::NotEmpty { height: tip.height });
+        }
+        manifest.verify(&self.validator_keys, &self.chain_id)?;
+        let root = state.root();
+        if root != manifest.state_root() {
+            return Err(ChainError::Snapshot(SnapshotError::StateMismatch { expected: manifest.state_root(), found: root }));
+        }
+        if let Some(store) = &self.store {
+            store.restore(manifest, &state)?;
+        }
+        let (hash, height) = (manifest.block.hash(), manifest.height());
+        self.last_validators = manifest.validators.clone();
+        self.last_commit = Some(manifest.certificate.clone());
+        self.state = state;
+        self.tip = Some(manifest.block.header.clone());
+        self.finalized = Some((hash, height));
+        self.anchor = None;
+        self.pending.clear();
+
+        self.emit(ChainEvent::NewTip { hash, height });
+        self.emit(ChainEvent::Finalized { hash, height });
+        Ok(())
+    }
+
+    /*
+     *  Take a block from elsewhere, optionally with its certificate, and run
+     *  fork choice. Blocks that fail to execute are dropped with everything on
+     *  them; if that is `block`, its error is returned.
+     */
+    pub fn add_block(&mut self, block: Block, certificate: Option<QuorumCertificate>, now: u64) -> Result<(), ChainError> {
+        let hash = block.hash();
+        let height = block.header.height;
+        if let Some((finalized, finalized_height)) = self.finalized {
+            if height <= finalized_height {
+                let canonical = match &self.store {
+                    Some(store) => store.hash_at(height)? == Some(hash),
+                    None => hash == finalized,
+                };
+                return if canonical { Ok(()) } else { Err(ChainError::ConflictsWithFinalized { height }) };
+            }
+        }
+
+        if let Some(node) = self.pending.get(&hash) {
+            if let (None, Some(certificate)) = (&node.certificate, certificate) {
+                if let Some(executed) = &node.executed {
+                    certificate.verify(&executed.validators, &self.chain_id)?;
+                }
+                self.pending.get_mut(&hash).unwrap().certificate = Some(certificate);
+            }
+            return self.choose(None, now);
+        }
+
+        let parent_height = if block.header.parent == self.finalized_hash() {
+            self.finalized.map(|(_, h)| h)
+        } else {
+            match self.pending.get(&block.header.parent) {
+                Some(parent) => Some(parent.block.header.height),
+                None => return Err(ChainError::UnknownParent(block.header.parent)),
+            }
+        };
+        let expected = parent_height.map(|h| h + 1).unwrap_or(0);
+        if height != expected {
+            return Err(ChainError::WrongHeight { expected, found: height });
+        }
+        if certificate.as_ref().map(|c| c.block != hash || c.height != height).unwrap_or(false) {
+            let found = certificate.map(|c| c.block).unwrap_or(Hash::ZERO);
+            return Err(ChainError::Certificate(CertError::WrongBlock { expected: hash, found }));
+        }
+        if self.pending.len() >= MAX_PENDING_BLOCKS {
+            return Err(ChainError::TooManyPending);
+        }
+
+        self.pending.insert(TreeBlock { block, executed: None, certificate });
+        self.choose(Some(hash), now)
+    }
+
+    fn choose(&mut self, added: Option<Hash>, now: u64) -> Result<(), ChainError> {
+        let mut rejected = None;
+        loop {
+            let root = self.finalized_hash();
+            let best = match self.pending.best(&root, &self.tip_hash()) {
+                Some(best) => best,
+                None => break,
+            };
+            if best == self.tip_hash() {
+                break;
+            }
+            let path = self.pending.path(&root, &best).unwrap_or_default();
+            if path.iter().all(|h| self.pending.get(h).map(|n| n.executed.is_some()).unwrap_or(false)) {
+                self.switch_to(&root, &path)?;
+                break;
+            }
+            // Certificates only count once their block executed, so choose again after
+            if let Err(failed) = self.execute_path(&path, now) {
+                let (bad, e) = *failed;
+                let removed = self.pending.remove_subtree(&bad);
+                if added.map(|h| removed.iter().any(|b| b.hash() == h)).unwrap_or(false) {
+                    rejected = Some(e);
+                }
+            }
+        }
+        self.finalize()?;
+
+        match rejected {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    // Execute what has not been along `path`, stopping at the first block that fails
+    fn execute_path(&mut self, path: &[Hash], now: u64) -> Result<(), Box<(Hash, ChainError)>> {
+        for hash in path {
+            let node = &self.pending.get(hash).unwrap();
+            if node.executed.is_some() {
+                continue;
+            }
+            let base = self.base_of(&node.block.header.parent).unwrap();
+            let validators = ValidatorSet::from_registry(base.state, &self.validator_keys);
+            let state = self.execute_on(&base, &node.block, now).map_err(|e| Box::new((*hash, e)))?;
+            // A claimed certificate that does not hold is dropped, the block stays
+            let certified = node.certificate.as_ref().map(|c| c.verify(&validators, &self.chain_id).is_ok()).unwrap_or(false);
+            let parent_certificate = node.block.header.last_commit.clone();
+            let parent = node.block.header.parent;
+
+            let node = self.pending.get_mut(hash).unwrap();
+            node.executed = Some(Executed { state, validators });
+            if !certified {
+                node.certificate = None;
+            }
+            // The header's certificate for the parent was verified with it
+            if let Some(parent) = self.pending.get_mut(&parent) {
+                if parent.certificate.is_none() {
+                    parent.certificate = parent_certificate;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Move the tip to the end of `path`, all of it executed, reverting what is not on it
+    fn switch_to(&mut self, root: &Hash, path: &[Hash]) -> Result<(), ChainError> {
+        let old = match self.anchor {
+            Some(_) => self.pending.path(root, &self.tip_hash()).unwrap_or_default(),
+            None => Vec::new(),
+        };
+        let common = old.iter().zip(path).take_while(|(a, b)| a == b).count();
+        let ancestor = if common == 0 { *root } else { old[common - 1] };
+        let reverted: Vec<Block> = old[common..].iter().rev().map(|h| self.pending.get(h).unwrap().block.clone()).collect();
+        let applied: Vec<Block> = path[common..].iter().map(|h| self.pending.get(h).unwrap().block.clone()).collect();
+
+        if let Some(store) = &self.store {
+            if !reverted.is_empty() {
+                let ancestor_height = match common {
+                    0 => self.finalized.map(|(_, h)| h),
+                    _ => Some(self.pending.get(&ancestor).unwrap().block.header.height),
+                };
+                store.rewind(ancestor_height)?;
+            }
+            for hash in &path[common..] {
+                let node = self.pending.get(hash).unwrap();
+                store.commit_block(&node.block, &node.executed.as_ref().unwrap().state, node.certificate.as_ref())?;
+            }
+        }
+
+        let (hash, height) = self.move_tip(path.last().unwrap());
+        if !reverted.is_empty() {
+            self.emit(ChainEvent::Reorg { ancestor, reverted, applied });
+        }
+        self.emit(ChainEvent::NewTip { hash, height });
+        Ok(())
+    }
+
+    // Make the executed pending block `hash` the tip, keeping the finalized block's data aside
+    fn move_tip(&mut self, hash: &Hash) -> (Hash, u64) {
+        if self.anchor.is_none() {
+            self.anchor = Some(Anchor {
+                header: self.tip.take(),
+                state: mem::replace(&mut self.state, State::new()),
+                validators: self.last_validators.clone(),
+            });
+        }
+        let node = self.pending.get(hash).unwrap();
+        let executed = node.executed.as_ref().unwrap();
+        self.tip = Some(node.block.header.clone());
+        self.state = executed.state.clone();
+        self.last_validators = executed.validators.clone();
+        self.last_commit = node.certificate.clone();
+
+        (*hash, node.block.header.height)
+    }
+
+    // Finalize up to the highest block on the chain with a verified certificate
+    fn finalize(&mut self) -> Result<(), ChainError> {
+        if self.anchor.is_none() {
+            return Ok(());
+        }
+        let path = self.pending.path(&self.finalized_hash(), &self.tip_hash()).unwrap_or_default();
+        let hash = match path.iter().rev().find(|h| self.pending.get(h).map(|n| n.certificate.is_some()).unwrap_or(false)) {
+            Some(hash) => *hash,
+            None => return Ok(()),
+        };
+        let node = self.pending.get(&hash).unwrap();
+        let height = node.block.header.height;
+        if let (Some(store), Some(certificate)) = (&self.store, &node.certificate) {
+            store.set_finalized(certificate)?;
+        }
+        self.anchor = match hash == self.tip_hash() {
+            true => None,
+            false => {
+                let executed = node.executed.as_ref().unwrap();
+                Some(Anchor {
+                    header: Some(node.block.header.clone()),
+                    state: executed.state.clone(),
+                    validators: executed.validators.clone(),
+                })
+            },
+        };
+        self.finalized = Some((hash, height));
+        self.pending.retain_descendants(&hash);
+
+        self.emit(ChainEvent::Finalized { hash, height });
+        Ok(())
+    }
+}
+
+// Tie an empty store to `hash`, or make sure the store's chain started from it
+fn check_genesis(store: &Store, hash: &Hash) -> Result<(), ChainError> {
+    match store.genesis()? {
+        Some(found) if found == *hash => Ok(()),
+        Some(found) => Err(ChainError::WrongGenesis { expected: *hash, found: Some(found) }),
+        None if store.tip()?.is_none() => Ok(store.set_genesis(hash)?),
+        None => Err(ChainError::WrongGenesis { expected: *hash, found: None }),
+    }
+}
@@ -0,0 +1,225 @@
+use std::fmt::{ self, Display, Formatter };
+
+use crate::hash::{ Hash, KeyId, HASH_BYTES };
+
+/*
+ *  Canonical binary encoding
+ *
+ *  Integers are fixed-width big-endian, byte strings and lists carry a u32 length
+ *  prefix, and nothing is optional or reordered, so each value has exactly one
+ *  encoding. Decoders reject trailing bytes and lengths beyond their limit.
+ */
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    TrailingBytes(usize),
+    UnknownVersion(u16),
+    UnknownKind(u8),
+    InvalidUtf8(&'static str),
+    TooLong { field: &'static str, len: usize, max: usize },
+}
+
+impl Display for DecodeError {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "input ends early"),
+            DecodeError::TrailingBytes(n) => write!(f, "{} trailing bytes", n),
+            DecodeError::UnknownVersion(v) => write!(f, "unknown encoding version {}", v),
+            DecodeError::UnknownKind(k) => write!(f, "unknown kind {}", k),
+            DecodeError::InvalidUtf8(field) => write!(f, "{} is not valid UTF-8", field),
+            DecodeError::TooLong { field, len, max } => write!(f, "{} is {} bytes, at most {} allowed", field, len, max),
+        }
+    }
+}
+
+pub trait Encode {
+    fn encode_to(&self, out: &mut Encoder);
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Encoder::new();
+        self.encode_to(&mut out);
+        out.finish()
+    }
+}
+
+pub trait Decode: Sized {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError>;
+
+    // Decode a complete value, refusing anything left over
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut input = Decoder::new(bytes);
+        let value = Self::decode_from(&mut input)?;
+        input.finish()?;
+        Ok(value)
+    }
+}
+
+/*
+ *  Declaration of Class Encoder
+ */
+#[derive(Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Encoder { buf: Vec::new() }
+    }
+
+    pub fn u8(&mut self, v: u8) -> &mut Self {
+        self.buf.push(v);
+        self
+    }
+
+    pub fn u16(&mut self, v: u16) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, v: u32) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, v: u64) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+
+    pub fn hash(&mut self, v: &Hash) -> &mut Self {
+        self.buf.extend_from_slice(v.as_bytes());
+        self
+    }
+
+    pub fn key_id(&mut self, v: &KeyId) -> &mut Self {
+        self.hash(&v.0)
+    }
+
+    pub fn bytes(&mut self, v: &[u8]) -> &mut Self {
+        self.u32(v.len() as u32);
+        self.buf.extend_from_slice(v);
+        self
+    }
+
+    pub fn list<T: Encode>(&mut self, items: &[T]) -> &mut Self {
+        self.u32(items.len() as u32);
+        for item in items {
+            item.encode_to(self);
+        }
+        self
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/*
+ *  Declaration of Class Decoder
+ */
+pub struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Decoder { buf, pos: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.buf.len() - self.pos < n {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let slice = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, DecodeError> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, DecodeError> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, DecodeError> {
+        let b = self.take(8)?;
+        let mut v = [0u8; 8];
+        v.copy_from_slice(b);
+        Ok(u64::from_be_bytes(v))
+    }
+
+    pub fn hash(&mut self) -> Result<Hash, DecodeError> {
+        let b = self.take(HASH_BYTES)?;
+        Ok(Hash::from_slice(b).unwrap())
+    }
+
+    pub fn key_id(&mut self) -> Result<KeyId, DecodeError> {
+        Ok(KeyId(self.hash()?))
+    }
+
+    pub fn bytes(&mut self, field: &'static str, max: usize) -> Result<Vec<u8>, DecodeError> {
+        let len = self.u32()? as usize;
+        if len > max {
+            return Err(DecodeError::TooLong { field, len, max });
+        }
+        Ok(self.take(len)?.to_vec())
+    }
+
+    // `max` bounds the item count before anything is allocated
+    pub fn list<T: Decode>(&mut self, field: &'static str, max: usize) -> Result<Vec<T>, DecodeError> {
+        let len = self.u32()? as usize;
+        if len > max {
+            return Err(DecodeError::TooLong { field, len, max });
+        }
+        let mut items = Vec::with_capacity(len.min(1024));
+        for _ in 0..len {
+            items.push(T::decode_from(self)?);
+        }
+        Ok(items)
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    pub fn finish(&self) -> Result<(), DecodeError> {
+        match self.remaining() {
+            0 => Ok(()),
+            n => Err(DecodeError::TrailingBytes(n)),
+        }
+    }
+}
+
+impl Encode for Vec<u8> {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.bytes(self);
+    }
+}
+
+impl Decode for Vec<u8> {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        input.bytes("bytes", u32::MAX as usize)
+    }
+}
+
+impl Encode for Hash {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.hash(self);
+    }
+}
+
+impl Decode for Hash {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        input.hash()
+    }
+}
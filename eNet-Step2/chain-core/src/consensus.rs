@@ -0,0 +1,491 @@
+use std::collections::{ BTreeMap, BTreeSet, HashMap, HashSet };
+use std::mem;
+
+use secure_sign::NistCryptography;
+
+use crate::block::Block;
+use crate::certificate::QuorumCertificate;
+use crate::chain::Chain;
+use crate::hash::{ Hash, KeyId };
+use crate::mempool::Mempool;
+use crate::state::State;
+use crate::vote::{ ConsensusMessage, Proposal, ValidatorSet, Vote, VoteKind };
+
+// Messages for the next height kept until we get there
+pub const MAX_FUTURE_MESSAGES: usize = 1024;
+// Messages further ahead than this many rounds are dropped
+pub const MAX_ROUNDS_AHEAD: u32 = 16;
+
+// "For the first time" rules, remembered per round
+const RULE_PREVOTE_TIMEOUT: u8 = 0;
+const RULE_LOCK: u8 = 1;
+const RULE_PRECOMMIT_TIMEOUT: u8 = 2;
+
+/*
+ *  Milliseconds to wait at each step. Round r waits `delta * r` longer than
+ *  round 0, so rounds eventually outlast any network delay.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeouts {
+    pub propose: u64,
+    pub vote: u64,
+    pub delta: u64,
+    // Pause after a decision before the next height starts
+    pub commit: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            propose: 3_000,
+            vote: 1_000,
+            delta: 500,
+            commit: 1_000,
+        }
+    }
+}
+
+impl Timeouts {
+    fn propose_in(&self, round: u32) -> u64 {
+        self.propose + self.delta * round as u64
+    }
+
+    fn vote_in(&self, round: u32) -> u64 {
+        self.vote + self.delta * round as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Step {
+    // Waiting out `Timeouts::commit`, or decided and waiting to be restarted
+    NewHeight,
+    Propose,
+    Prevote,
+    Precommit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Timer {
+    Start,
+    Propose(u32),
+    Prevote(u32),
+    Precommit(u32),
+}
+
+#[derive(Debug)]
+pub enum ConsensusOutput {
+    // Send to every peer
+    Broadcast(ConsensusMessage),
+    // Final: commit `block` with `state`; `certificate` proves it
+    Decided { block: Box<Block>, state: Box<State>, certificate: QuorumCertificate },
+}
+
+// What a step needs from the host
+struct Env<'a> {
+    chain: &'a Chain,
+    pool: &'a Mempool,
+    secure: &'a mut NistCryptography,
+    now: u64,
+}
+
+/*
+ *  Declaration of Class Consensus
+ *
+ *  Tendermint-style agreement on one block per height among the validator set:
+ *  the round's proposer proposes, validators prevote, and on more than two
+ *  thirds of prevotes for a block they lock on it and precommit. More than two
+ *  thirds of precommits decide the block; timeouts move a stuck round on.
+ *  Safe with fewer than a third of validators faulty, and live once messages
+ *  arrive within the timeouts.
+ *
+ *  The engine does no I/O. Messages and clock ticks go in through `handle` and
+ *  `tick`; what to broadcast and what was decided come back as outputs. It
+ *  follows the chain it is given: after the host commits a decision, or the
+ *  tip moves any other way, the next call starts over on the new tip.
+ */
+pub struct Consensus {
+    pub timeouts: Timeouts,
+    started: bool,
+    height: u64,
+    // The tip the height builds on; a reorg to another one restarts it
+    parent: Hash,
+    round: u32,
+    step: Step,
+    decided: bool,
+    validators: ValidatorSet,
+    locked: Option<(u32, Hash)>,
+    valid: Option<(u32, Hash)>,
+    proposals: BTreeMap<u32, Proposal>,
+    blocks: HashMap<Hash, Block>,
+    // Execution result per block hash, None when the block is invalid
+    executed: HashMap<Hash, Option<State>>,
+    votes: HashMap<(u32, VoteKind), BTreeMap<KeyId, Vote>>,
+    timers: Vec<(u64, Timer)>,
+    fired: HashSet<(u8, u32)>,
+    future: Vec<ConsensusMessage>,
+    outputs: Vec<ConsensusOutput>,
+}
+
+impl Consensus {
+    pub fn new(timeouts: Timeouts) -> Self {
+        Consensus {
+            timeouts,
+            started: false,
+            height: 0,
+            parent: Hash::ZERO,
+            round: 0,
+            step: Step::NewHeight,
+            decided: false,
+            validators: ValidatorSet::default(),
+            locked: None,
+            valid: None,
+            proposals: BTreeMap::new(),
+            blocks: HashMap::new(),
+            executed: HashMap::new(),
+            votes: HashMap::new(),
+            timers: Vec::new(),
+            fired: HashSet::new(),
+            future: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn round(&self) -> u32 {
+        self.round
+    }
+
+    pub fn step(&self) -> Step {
+        self.step
+    }
+
+    pub fn locked(&self) -> Option<(u32, Hash)> {
+        self.locked
+    }
+
+    pub fn validators(&self) -> &ValidatorSet {
+        &self.validators
+    }
+
+    // Start the current height over on the next call, e.g. after the validator keys changed
+    pub fn reset(&mut self) {
+        self.started = false;
+    }
+
+    pub fn handle(&mut self, msg: ConsensusMessage, chain: &Chain, pool: &Mempool, secure: &mut NistCryptography, now: u64) -> Vec<ConsensusOutput> {
+        self.follow(chain, now);
+        if msg.height() == self.height + 1 && self.future.len() < MAX_FUTURE_MESSAGES {
+            self.future.push(msg);
+        } else if msg.height() == self.height && self.add_message(msg, &chain.chain_id) {
+            self.update(&mut Env { chain, pool, secure, now });
+        }
+
+        mem::take(&mut self.outputs)
+    }
+
+    pub fn tick(&mut self, chain: &Chain, pool: &Mempool, secure: &mut NistCryptography, now: u64) -> Vec<ConsensusOutput> {
+        self.follow(chain, now);
+        let mut env = Env { chain, pool, secure, now };
+
+        let (due, pending): (Vec<_>, Vec<_>) = self.timers.drain(..).partition(|(at, _)| *at <= now);
+        self.timers = pending;
+        for (_, timer) in due {
+            self.on_timeout(timer, &mut env);
+        }
+        self.update(&mut env);
+
+        mem::take(&mut self.outputs)
+    }
+
+    fn follow(&mut self, chain: &Chain, now: u64) {
+        if self.started && self.height == chain.next_height() && self.parent == chain.tip_hash() {
+            return;
+        }
+
+        self.started = true;
+        self.height = chain.next_height();
+        self.parent = chain.tip_hash();
+        self.round = 0;
+        self.step = Step::NewHeight;
+        self.decided = false;
+        self.validators = chain.validators();
+        self.locked = None;
+        self.valid = None;
+        self.proposals.clear();
+        self.blocks.clear();
+        self.executed.clear();
+        self.votes.clear();
+        self.fired.clear();
+        self.timers = vec![(now + self.timeouts.commit, Timer::Start)];
+
+        // Whatever arrived early for this height now counts
+        let future = mem::take(&mut self.future);
+        for msg in future {
+            if msg.height() == self.height {
+                self.add_message(msg, &chain.chain_id);
+            } else if msg.height() == self.height + 1 {
+                self.future.push(msg);
+            }
+        }
+    }
+
+    // Verify and record a message of the current height; false if it adds nothing
+    fn add_message(&mut self, msg: ConsensusMessage, chain_id: &str) -> bool {
+        if msg.round() > self.round + MAX_ROUNDS_AHEAD {
+            return false;
+        }
+        match msg {
+            ConsensusMessage::Proposal(proposal) => {
+                let proposer = match self.validators.proposer(proposal.height, proposal.round) {
+                    Some(proposer) => proposer,
+                    None => return false,
+                };
+                if proposal.proposer != proposer.id
+                    || proposal.block.header.height != self.height
+                    || self.proposals.contains_key(&proposal.round)
+                    || !proposal.verify(&proposer.public_key, chain_id)
+                {
+                    return false;
+                }
+                self.blocks.entry(proposal.block.hash()).or_insert_with(|| proposal.block.clone());
+                self.proposals.insert(proposal.round, *proposal);
+            },
+            ConsensusMessage::Vote(vote) => {
+                let validator = match self.validators.get(&vote.validator) {
+                    Some(validator) => validator,
+                    None => return false,
+                };
+                // Only a validator's first vote per round and kind counts
+                let known = self.votes.get(&(vote.round, vote.kind)).map(|v| v.contains_key(&vote.validator)).unwrap_or(false);
+                if known || !vote.verify(&validator.public_key, chain_id) {
+                    return false;
+                }
+                self.votes.entry((vote.round, vote.kind)).or_default().insert(vote.validator, vote);
+            },
+        }
+
+        true
+    }
+
+    fn count(&self, round: u32, kind: VoteKind, block: Option<Option<Hash>>) -> usize {
+        match self.votes.get(&(round, kind)) {
+            Some(votes) => match block {
+                Some(block) => votes.values().filter(|v| v.block == block).count(),
+                None => votes.len(),
+            },
+            None => 0,
+        }
+    }
+
+    fn quorum_for(&self, round: u32, kind: VoteKind, block: Option<Hash>) -> bool {
+        self.count(round, kind, Some(block)) >= self.validators.quorum()
+    }
+
+    fn quorum_any(&self, round: u32, kind: VoteKind) -> bool {
+        self.count(round, kind, None) >= self.validators.quorum()
+    }
+
+    // Returns true only the first time `rule` fires in `round`
+    fn fire(&mut self, rule: u8, round: u32) -> bool {
+        self.fired.insert((rule, round))
+    }
+
+    fn is_valid(&mut self, hash: &Hash, env: &Env) -> bool {
+        if let Some(result) = self.executed.get(hash) {
+            return result.is_some();
+        }
+        let result = match self.blocks.get(hash) {
+            Some(block) => env.chain.execute(block, env.now).ok(),
+            None => return false,
+        };
+        let valid = result.is_some();
+        self.executed.insert(*hash, result);
+
+        valid
+    }
+
+    // Sign and record our own vote, if we are a validator
+    fn cast(&mut self, kind: VoteKind, block: Option<Hash>, env: &mut Env) {
+        let me = KeyId::from_public_key(&env.secure.public_key);
+        if !self.validators.contains(&me) {
+            return;
+        }
+        let mut vote = Vote::new(kind, self.height, self.round, block);
+        if !vote.sign(env.secure, &env.chain.chain_id) {
+            return;
+        }
+        self.votes.entry((self.round, kind)).or_default().insert(me, vote.clone());
+        self.outputs.push(ConsensusOutput::Broadcast(ConsensusMessage::Vote(vote)));
+    }
+
+    fn start_round(&mut self, round: u32, env: &mut Env) {
+        self.round = round;
+        self.step = Step::Propose;
+        self.timers.push((env.now + self.timeouts.propose_in(round), Timer::Propose(round)));
+
+        let me = KeyId::from_public_key(&env.secure.public_key);
+        if self.validators.proposer(self.height, round).map(|v| v.id != me).unwrap_or(true) {
+            return;
+        }
+
+        // Re-propose the block that already gathered prevotes, or build a new one
+        let (block, valid_round) = match self.valid {
+            Some((valid_round, hash)) => match self.blocks.get(&hash) {
+                Some(block) => (block.clone(), Some(valid_round)),
+                None => return,
+            },
+            None => match env.chain.build_block(env.pool, env.secure, env.now) {
+                Ok((block, state)) => {
+                    self.executed.insert(block.hash(), Some(state));
+                    self.blocks.insert(block.hash(), block.clone());
+                    (block, None)
+                },
+                Err(_) => return,
+            },
+        };
+        let mut proposal = Proposal::new(self.height, round, valid_round, block);
+        if proposal.sign(env.secure, &env.chain.chain_id) {
+            self.proposals.insert(round, proposal.clone());
+            self.outputs.push(ConsensusOutput::Broadcast(ConsensusMessage::Proposal(Box::new(proposal))));
+        }
+    }
+
+    fn on_timeout(&mut self, timer: Timer, env: &mut Env) {
+        if self.decided {
+            return;
+        }
+        match timer {
+            Timer::Start if self.step == Step::NewHeight => self.start_round(0, env),
+            Timer::Propose(round) if round == self.round && self.step == Step::Propose => {
+                self.cast(VoteKind::Prevote, None, env);
+                self.step = Step::Prevote;
+            },
+            Timer::Prevote(round) if round == self.round && self.step == Step::Prevote => {
+                self.cast(VoteKind::Precommit, None, env);
+                self.step = Step::Precommit;
+            },
+            Timer::Precommit(round) if round == self.round => self.start_round(round + 1, env),
+            _ => (),
+        }
+    }
+
+    fn update(&mut self, env: &mut Env) {
+        while self.apply_rules(env) {}
+    }
+
+    // Apply the first rule whose condition holds; false when none does
+    fn apply_rules(&mut self, env: &mut Env) -> bool {
+        if !self.started || self.decided {
+            return false;
+        }
+
+        // A precommit quorum for a proposed, valid block decides it, whatever our round
+        let proposed: Vec<(u32, Hash)> = self.proposals.iter().map(|(r, p)| (*r, p.block.hash())).collect();
+        for (round, hash) in proposed {
+            if self.quorum_for(round, VoteKind::Precommit, Some(hash)) && self.is_valid(&hash, env) {
+                self.decide(round, hash);
+                return false;
+            }
+        }
+        if self.step == Step::NewHeight {
+            return false;
+        }
+
+        // More than a third is already in a later round, so at least one honest validator is
+        if let Some(round) = self.later_round() {
+            self.start_round(round, env);
+            return true;
+        }
+
+        let round = self.round;
+        let proposal = self.proposals.get(&round).map(|p| (p.block.hash(), p.valid_round));
+
+        if let (Step::Propose, Some((hash, valid_round))) = (self.step, proposal) {
+            let vote = match valid_round {
+                None => Some(self.is_valid(&hash, env) && self.locked.map(|(_, l)| l == hash).unwrap_or(true)),
+                Some(vr) if vr < round && self.quorum_for(vr, VoteKind::Prevote, Some(hash)) => {
+                    Some(self.is_valid(&hash, env) && self.locked.map(|(lr, l)| lr <= vr || l == hash).unwrap_or(true))
+                },
+                _ => None,
+            };
+            if let Some(for_block) = vote {
+                self.cast(VoteKind::Prevote, if for_block { Some(hash) } else { None }, env);
+                self.step = Step::Prevote;
+                return true;
+            }
+        }
+
+        if self.step == Step::Prevote {
+            if self.quorum_any(round, VoteKind::Prevote) && self.fire(RULE_PREVOTE_TIMEOUT, round) {
+                self.timers.push((env.now + self.timeouts.vote_in(round), Timer::Prevote(round)));
+                return true;
+            }
+            if self.quorum_for(round, VoteKind::Prevote, None) {
+                self.cast(VoteKind::Precommit, None, env);
+                self.step = Step::Precommit;
+                return true;
+            }
+        }
+
+        if let Some((hash, _)) = proposal {
+            if self.step >= Step::Prevote
+                && !self.fired.contains(&(RULE_LOCK, round))
+                && self.quorum_for(round, VoteKind::Prevote, Some(hash))
+                && self.is_valid(&hash, env)
+            {
+                self.fire(RULE_LOCK, round);
+                if self.step == Step::Prevote {
+                    self.locked = Some((round, hash));
+                    self.cast(VoteKind::Precommit, Some(hash), env);
+                    self.step = Step::Precommit;
+                }
+                self.valid = Some((round, hash));
+                return true;
+            }
+        }
+
+        if self.quorum_any(round, VoteKind::Precommit) && self.fire(RULE_PRECOMMIT_TIMEOUT, round) {
+            self.timers.push((env.now + self.timeouts.vote_in(round), Timer::Precommit(round)));
+            return true;
+        }
+
+        false
+    }
+
+    // Lowest later round in which more than a third of validators have spoken
+    fn later_round(&self) -> Option<u32> {
+        let mut senders: BTreeMap<u32, BTreeSet<KeyId>> = BTreeMap::new();
+        for ((round, _), votes) in &self.votes {
+            if *round > self.round {
+                senders.entry(*round).or_default().extend(votes.keys().copied());
+            }
+        }
+        for (round, proposal) in self.proposals.range(self.round + 1..) {
+            senders.entry(*round).or_default().insert(proposal.proposer);
+        }
+
+        senders.into_iter()
+            .find(|(_, ids)| ids.len() >= self.validators.weak_quorum())
+            .map(|(round, _)| round)
+    }
+
+    fn decide(&mut self, round: u32, hash: Hash) {
+        let block = self.blocks[&hash].clone();
+        let state = match self.executed.get(&hash) {
+            Some(Some(state)) => state.clone(),
+            _ => return,
+        };
+        let precommits: Vec<Vote> = self.votes.get(&(round, VoteKind::Precommit))
+            .map(|votes| votes.values().cloned().collect())
+            .unwrap_or_default();
+        let certificate = QuorumCertificate::from_precommits(&self.validators, self.height, round, hash, &precommits);
+
+        self.decided = true;
+        self.step = Step::NewHeight;
+        self.timers.clear();
+        self.outputs.push(ConsensusOutput::Decided { block: Box::new(block), state: Box::new(state), certificate });
+    }
+}
@@ -0,0 +1,337 @@
+use crate::codec::{ Decode, DecodeError, Decoder, Encode, Encoder };
+use crate::hash::{ hash_with_domain, Hash, KeyId, DOMAIN_DISPUTE_ID, DOMAIN_JURY, HASH_BYTES };
+use crate::identity::{ PersonId, PersonStatus };
+use crate::state::{ State, StateError };
+use crate::transaction::Transaction;
+
+// Least a challenger must put at stake
+pub const MIN_CHALLENGE_BOND: u64 = 1_000;
+// Jurors drawn for each dispute, fewer if not enough verified persons qualify
+pub const JURY_SIZE: usize = 5;
+// Blocks after the challenge in which the jury is drawn and ballots are taken
+pub const RESOLUTION_BLOCKS: u64 = 100;
+
+/*
+ *  Payload of a TxKind::Challenge: the claim that two registry entries belong
+ *  to one human. Anyone may send it; `bond` leaves their account with it.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Challenge {
+    pub first: PersonId,
+    pub second: PersonId,
+    pub bond: u64,
+}
+
+impl Encode for Challenge {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.hash(&self.first.0).hash(&self.second.0).u64(self.bond);
+    }
+}
+
+impl Decode for Challenge {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(Challenge {
+            first: PersonId(input.hash()?),
+            second: PersonId(input.hash()?),
+            bond: input.u64()?,
+        })
+    }
+}
+
+/*
+ *  Payload of a TxKind::Ballot, sent from the juror's current key so the
+ *  transaction signature is the juror's
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ballot {
+    pub dispute: Hash,
+    pub juror: PersonId,
+    // True when the two entries are the same human
+    pub uphold: bool,
+}
+
+impl Encode for Ballot {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.hash(&self.dispute).hash(&self.juror.0).u8(self.uphold as u8);
+    }
+}
+
+impl Decode for Ballot {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        let dispute = input.hash()?;
+        let juror = PersonId(input.hash()?);
+        let uphold = match input.u8()? {
+            0 => false,
+            1 => true,
+            tag => return Err(DecodeError::UnknownKind(tag)),
+        };
+        Ok(Ballot { dispute, juror, uphold })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    // The later entry is revoked; the challenger gets the bond back and what its vouchers lose
+    Upheld,
+    // The bond is shared among the jurors who dismissed it
+    Dismissed,
+    // No majority in time; the bond goes back
+    Lapsed,
+}
+
+/*
+ *  Declaration of Dispute
+ *
+ *  An open challenge as kept in state until its verdict. At most one is open
+ *  for any pair of entries, since its ID is taken from the pair.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dispute {
+    pub challenger: KeyId,
+    pub first: PersonId,
+    pub second: PersonId,
+    pub bond: u64,
+    pub jurors: Vec<PersonId>,
+    pub upheld: Vec<PersonId>,
+    pub dismissed: Vec<PersonId>,
+    pub opened_at: u64,
+    // Last height a ballot is taken at
+    pub deadline: u64,
+}
+
+impl Dispute {
+    pub fn id(first: &PersonId, second: &PersonId) -> Hash {
+        let (low, high) = match first < second {
+            true => (first, second),
+            false => (second, first),
+        };
+        let mut data = [0u8; HASH_BYTES * 2];
+        data[..HASH_BYTES].copy_from_slice(low.0.as_bytes());
+        data[HASH_BYTES..].copy_from_slice(high.0.as_bytes());
+        hash_with_domain(DOMAIN_DISPUTE_ID, &data)
+    }
+
+    // Ballots one side needs
+    pub fn majority(&self) -> usize {
+        self.jurors.len() / 2 + 1
+    }
+
+    pub fn involves(&self, id: &PersonId) -> bool {
+        self.first == *id || self.second == *id
+    }
+}
+
+impl Encode for Dispute {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.key_id(&self.challenger).hash(&self.first.0).hash(&self.second.0).u64(self.bond);
+        for list in [&self.jurors, &self.upheld, &self.dismissed].iter() {
+            out.u32(list.len() as u32);
+            for id in list.iter() {
+                out.hash(&id.0);
+            }
+        }
+        out.u64(self.opened_at).u64(self.deadline);
+    }
+}
+
+impl Decode for Dispute {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        let challenger = input.key_id()?;
+        let first = PersonId(input.hash()?);
+        let second = PersonId(input.hash()?);
+        let bond = input.u64()?;
+        let jurors = input.list::<Hash>("jurors", JURY_SIZE)?.into_iter().map(PersonId).collect();
+        let upheld = input.list::<Hash>("upheld", JURY_SIZE)?.into_iter().map(PersonId).collect();
+        let dismissed = input.list::<Hash>("dismissed", JURY_SIZE)?.into_iter().map(PersonId).collect();
+
+        Ok(Dispute { challenger, first, second, bond, jurors, upheld, dismissed, opened_at: input.u64()?, deadline: input.u64()? })
+    }
+}
+
+impl State {
+    /*
+     *  Up to JURY_SIZE persons live at `height` outside `exclude`, ranked by
+     *  the hash of `seed` and their ID. Anyone can recompute the draw.
+     *  Disputes take the seed from the block the challenge landed in, which
+     *  its sender cannot know when signing; that block's proposer could still
+     *  try headers until the draw suits them.
+     */
+    pub fn draw_jury(&self, seed: &Hash, exclude: &[PersonId], height: u64) -> Vec<PersonId> {
+        let mut ranked: Vec<(Hash, PersonId)> = self.persons()
+            .filter(|(id, person)| self.is_live(person, height) && !exclude.contains(id))
+            .map(|(id, _)| {
+                let mut data = [0u8; HASH_BYTES * 2];
+                data[..HASH_BYTES].copy_from_slice(seed.as_bytes());
+                data[HASH_BYTES..].copy_from_slice(id.0.as_bytes());
+                (hash_with_domain(DOMAIN_JURY, &data), id)
+            })
+            .collect();
+        ranked.sort();
+
+        ranked.into_iter().take(JURY_SIZE).map(|(_, id)| id).collect()
+    }
+
+    // Persons kept off the jury of `dispute`: the pair, whoever vouched for either and the challenger
+    fn jury_exclusions(&self, dispute: &Dispute) -> Vec<PersonId> {
+        let mut exclude = vec![dispute.first, dispute.second];
+        for id in &[dispute.first, dispute.second] {
+            if let Some(person) = self.person(id) {
+                exclude.extend(person.vouchers.iter().map(|v| v.attester));
+            }
+        }
+        exclude.extend(self.person_of(&dispute.challenger));
+        exclude
+    }
+
+    pub fn under_dispute(&self, id: &PersonId) -> bool {
+        self.disputes().any(|(_, dispute)| dispute.involves(id))
+    }
+
+    /*
+     *  Open the dispute `challenge` from `tx` asks for; its bond is already
+     *  taken. The jury stays empty until the next block draws it.
+     */
+    pub(crate) fn open_dispute(&mut self, tx: &Transaction, challenge: &Challenge, height: u64) -> Result<(), StateError> {
+        if challenge.first == challenge.second {
+            return Err(StateError::SamePerson);
+        }
+        if challenge.bond < MIN_CHALLENGE_BOND {
+            return Err(StateError::BondTooLow { bond: challenge.bond, min: MIN_CHALLENGE_BOND });
+        }
+        for id in &[challenge.first, challenge.second] {
+            let person = self.person(id).ok_or(StateError::UnknownPerson(*id))?;
+            if person.status == PersonStatus::Revoked {
+                return Err(StateError::PersonRevoked(*id));
+            }
+        }
+        let id = Dispute::id(&challenge.first, &challenge.second);
+        if self.dispute(&id).is_some() {
+            return Err(StateError::DisputeOpen(id));
+        }
+
+        let dispute = Dispute {
+            challenger: tx.sender,
+            first: challenge.first,
+            second: challenge.second,
+            bond: challenge.bond,
+            jurors: Vec::new(),
+            upheld: Vec::new(),
+            dismissed: Vec::new(),
+            opened_at: height,
+            deadline: height.checked_add(RESOLUTION_BLOCKS).ok_or(StateError::Overflow)?,
+        };
+        // Any seed tells whether anyone is left to draw
+        if self.draw_jury(&Hash::ZERO, &self.jury_exclusions(&dispute), height).is_empty() {
+            return Err(StateError::NoJurors);
+        }
+        self.set_dispute(id, Some(dispute));
+
+        Ok(())
+    }
+
+    // Count a juror's ballot and settle the dispute once a side has a majority
+    pub(crate) fn cast_ballot(&mut self, tx: &Transaction, ballot: &Ballot, height: u64) -> Result<(), StateError> {
+        let mut dispute = self.dispute(&ballot.dispute).cloned().ok_or(StateError::UnknownDispute(ballot.dispute))?;
+        if height > dispute.deadline {
+            return Err(StateError::DisputeClosed(ballot.dispute));
+        }
+        let juror = self.sending_person(tx, &ballot.juror)?;
+        if !dispute.jurors.contains(&ballot.juror) {
+            return Err(StateError::NotJuror(ballot.juror));
+        }
+        if dispute.upheld.contains(&ballot.juror) || dispute.dismissed.contains(&ballot.juror) {
+            return Err(StateError::AlreadyVoted(ballot.juror));
+        }
+        if !self.is_live(&juror, height) {
+            return Err(StateError::NotVerified(ballot.juror));
+        }
+
+        match ballot.uphold {
+            true => dispute.upheld.push(ballot.juror),
+            false => dispute.dismissed.push(ballot.juror),
+        }
+        if dispute.upheld.len() >= dispute.majority() {
+            self.settle(ballot.dispute, dispute, Verdict::Upheld, height)
+        } else if dispute.dismissed.len() >= dispute.majority() {
+            self.settle(ballot.dispute, dispute, Verdict::Dismissed, height)
+        } else {
+            self.set_dispute(ballot.dispute, Some(dispute));
+            Ok(())
+        }
+    }
+
+    // Let every dispute whose deadline is behind `height` lapse
+    pub(crate) fn close_disputes(&mut self, height: u64) -> Result<(), StateError> {
+        let due: Vec<(Hash, Dispute)> = self.due_records(height).into_iter()
+            .filter_map(|id| self.dispute(&id).map(|dispute| (id, dispute.clone())))
+            .filter(|(_, dispute)| dispute.deadline < height)
+            .collect();
+        for (id, dispute) in due {
+            self.settle(id, dispute, Verdict::Lapsed, height)?;
+        }
+
+        Ok(())
+    }
+
+    /*
+     *  Draw the jury of every dispute opened before `height` that has none,
+     *  seeded by `parent`, the hash of the block the challenge landed in. A
+     *  dispute nobody is left to judge lapses.
+     */
+    pub(crate) fn draw_juries(&mut self, height: u64, parent: &Hash) -> Result<(), StateError> {
+        let due: Vec<(Hash, Dispute)> = self.due_records(height).into_iter()
+            .filter_map(|id| self.dispute(&id).map(|dispute| (id, dispute.clone())))
+            .filter(|(_, dispute)| dispute.jurors.is_empty())
+            .collect();
+        for (id, mut dispute) in due {
+            let mut seed = [0u8; HASH_BYTES * 2];
+            seed[..HASH_BYTES].copy_from_slice(id.as_bytes());
+            seed[HASH_BYTES..].copy_from_slice(parent.as_bytes());
+            dispute.jurors = self.draw_jury(&hash_with_domain(DOMAIN_JURY, &seed), &self.jury_exclusions(&dispute), height);
+            match dispute.jurors.is_empty() {
+                true => self.settle(id, dispute, Verdict::Lapsed, height)?,
+                false => self.set_dispute(id, Some(dispute)),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn settle(&mut self, id: Hash, dispute: Dispute, verdict: Verdict, height: u64) -> Result<(), StateError> {
+        self.set_dispute(id, None);
+        match verdict {
+            Verdict::Upheld => {
+                // The entry registered later is the duplicate
+                let rank = |id: &PersonId| (self.person(id).map(|p| p.registered_at).unwrap_or(0), *id);
+                let duplicate = match rank(&dispute.first) > rank(&dispute.second) {
+                    true => dispute.first,
+                    false => dispute.second,
+                };
+                let taken = match self.person(&duplicate).map(|p| p.status) {
+                    Some(PersonStatus::Revoked) | None => 0,
+                    Some(_) => self.revoke_duplicate(&duplicate, height)?,
+                };
+                let reward = dispute.bond.checked_add(taken).ok_or(StateError::Overflow)?;
+                self.credit(&dispute.challenger, reward)
+            },
+            Verdict::Dismissed => {
+                // What does not divide evenly is burnt
+                let share = dispute.bond / dispute.dismissed.len() as u64;
+                for juror in &dispute.dismissed {
+                    if let Some(key) = self.person(juror).map(|p| p.key_id()) {
+                        self.credit(&key, share)?;
+                    }
+                }
+                Ok(())
+            },
+            Verdict::Lapsed => self.credit(&dispute.challenger, dispute.bond),
+        }
+    }
+
+    fn credit(&mut self, id: &KeyId, amount: u64) -> Result<(), StateError> {
+        let mut account = self.account(id).cloned().unwrap_or_default();
+        account.balance = account.balance.checked_add(amount).ok_or(StateError::Overflow)?;
+        self.set_account(*id, account);
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::block::Block;
+use crate::certificate::QuorumCertificate;
+use crate::hash::Hash;
+use crate::state::State;
+use crate::vote::ValidatorSet;
+
+// Blocks held above the last finalized one
+pub const MAX_PENDING_BLOCKS: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainEvent {
+    NewTip { hash: Hash, height: u64 },
+    // Sent before the NewTip of a switch that left `reverted` off the chain, newest first
+    Reorg { ancestor: Hash, reverted: Vec<Block>, applied: Vec<Block> },
+    Finalized { hash: Hash, height: u64 },
+}
+
+// The state after a block and the validators of its height
+#[derive(Debug, Clone)]
+pub struct Executed {
+    pub state: State,
+    pub validators: ValidatorSet,
+}
+
+#[derive(Debug, Clone)]
+pub struct TreeBlock {
+    pub block: Block,
+    // Set once the block executed on its parent
+    pub executed: Option<Executed>,
+    // Checked once the block has executed; until then only claimed
+    pub certificate: Option<QuorumCertificate>,
+}
+
+/*
+ *  Declaration of Class BlockTree
+ *
+ *  Blocks above the last finalized block, which is the root, on any number of
+ *  competing branches. Fork choice prefers the branch with the highest
+ *  certified block, counting certificates carried by a child's header, then
+ *  the longer branch; the current tip wins ties and otherwise the lower hash
+ *  does. Only certificates of executed blocks count, those being verified.
+ *  Nothing that does not descend from the root is ever chosen.
+ */
+#[derive(Default)]
+pub struct BlockTree {
+    nodes: HashMap<Hash, TreeBlock>,
+}
+
+impl BlockTree {
+    pub fn new() -> Self {
+        BlockTree { nodes: HashMap::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.nodes.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&TreeBlock> {
+        self.nodes.get(hash)
+    }
+
+    pub fn get_mut(&mut self, hash: &Hash) -> Option<&mut TreeBlock> {
+        self.nodes.get_mut(hash)
+    }
+
+    pub fn insert(&mut self, node: TreeBlock) {
+        self.nodes.insert(node.block.hash(), node);
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+    }
+
+    // Remove `hash` and everything built on it
+    pub fn remove_subtree(&mut self, hash: &Hash) -> Vec<Block> {
+        let mut removed = Vec::new();
+        let mut stack = vec![*hash];
+        while let Some(hash) = stack.pop() {
+            if let Some(node) = self.nodes.remove(&hash) {
+                stack.extend(self.children(&hash));
+                removed.push(node.block);
+            }
+        }
+
+        removed
+    }
+
+    fn children(&self, hash: &Hash) -> Vec<Hash> {
+        self.nodes.iter().filter(|(_, n)| n.block.header.parent == *hash).map(|(h, _)| *h).collect()
+    }
+
+    // Blocks from just after `root` up to `to`, or None if `to` does not descend from it
+    pub fn path(&self, root: &Hash, to: &Hash) -> Option<Vec<Hash>> {
+        let mut path = Vec::new();
+        let mut hash = *to;
+        while hash != *root {
+            let node = self.nodes.get(&hash)?;
+            path.push(hash);
+            hash = node.block.header.parent;
+        }
+        path.reverse();
+
+        Some(path)
+    }
+
+    // The leaf fork choice picks among those descending from `root`
+    pub fn best(&self, root: &Hash, tip: &Hash) -> Option<Hash> {
+        self.nodes.keys()
+            .filter(|hash| self.children(hash).is_empty())
+            .filter_map(|leaf| self.path(root, leaf).map(|path| (*leaf, path)))
+            .max_by_key(|(leaf, path)| {
+                let certified = path.iter().filter_map(|h| {
+                    let node = &self.nodes[h];
+                    let header = &node.block.header;
+                    match (&node.executed, &node.certificate, &header.last_commit) {
+                        (None, _, _) => None,
+                        (Some(_), Some(_), _) => Some(header.height),
+                        // Every branch certifies the root, so that says nothing
+                        (Some(_), None, Some(_)) if header.parent != *root => Some(header.height - 1),
+                        _ => None,
+                    }
+                }).max();
+                (certified, self.nodes[leaf].block.header.height, leaf == tip, Reverse(*leaf))
+            })
+            .map(|(leaf, _)| leaf)
+    }
+
+    // Keep only what descends from `root`, which itself leaves the tree
+    pub fn retain_descendants(&mut self, root: &Hash) {
+        let keep: Vec<Hash> = self.nodes.keys()
+            .filter(|hash| *hash != root && self.path(root, hash).is_some())
+            .copied()
+            .collect();
+        self.nodes.retain(|hash, _| keep.contains(hash));
+    }
+}
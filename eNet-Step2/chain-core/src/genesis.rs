@@ -0,0 +1,203 @@
+use std::fmt::{ self, Display, Formatter };
+
+use secure_sign::CRYPTO_PUBLICKEYBYTES;
+
+use crate::codec::{ Encode, Encoder };
+use crate::hash::{ Hash, KeyId, hash_with_domain, DOMAIN_GENESIS };
+use crate::identity::{ Person, PersonId, PersonStatus };
+use crate::liveness::DEFAULT_LIVENESS_EPOCH;
+use crate::state::{ Account, IdentityStatus, State, StateParams };
+use crate::transaction::MAX_CHAIN_ID_BYTES;
+use crate::vote::MAX_VALIDATORS;
+
+#[derive(Debug, PartialEq)]
+pub enum GenesisError {
+    EmptyChainId,
+    ChainIdTooLong(usize),
+    NoValidators,
+    TooManyValidators(usize),
+    // A public key that is not a Falcon public key
+    BadKey { index: usize, len: usize },
+    DuplicateValidator(KeyId),
+    DuplicateAccount(KeyId),
+    // A parameter that must be above zero
+    ZeroParam(&'static str),
+    TxLargerThanBlock { tx: u64, block: u64 },
+}
+
+impl Display for GenesisError {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            GenesisError::EmptyChainId => write!(f, "chain ID must not be empty"),
+            GenesisError::ChainIdTooLong(len) => write!(f, "chain ID is {} bytes, at most {} allowed", len, MAX_CHAIN_ID_BYTES),
+            GenesisError::NoValidators => write!(f, "at least one validator is needed"),
+            GenesisError::TooManyValidators(count) => write!(f, "{} validators, at most {} allowed", count, MAX_VALIDATORS),
+            GenesisError::BadKey { index, len } => write!(f, "key {} is {} bytes, expected {}", index, len, CRYPTO_PUBLICKEYBYTES),
+            GenesisError::DuplicateValidator(id) => write!(f, "validator {} is listed twice", id),
+            GenesisError::DuplicateAccount(id) => write!(f, "account {} is listed twice", id),
+            GenesisError::ZeroParam(name) => write!(f, "{} must be greater than 0", name),
+            GenesisError::TxLargerThanBlock { tx, block } => write!(f, "transactions of {} bytes do not fit blocks of {}", tx, block),
+        }
+    }
+}
+
+// Protocol parameters every node of the network must agree on
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenesisParams {
+    pub max_block_bytes: u64,
+    pub max_tx_bytes: u64,
+    pub block_time_ms: u64,
+    // Blocks a verified person stays live without a new liveness claim
+    pub liveness_epoch: u64,
+}
+
+impl Default for GenesisParams {
+    fn default() -> Self {
+        GenesisParams {
+            max_block_bytes: 1024 * 1024,
+            max_tx_bytes: 64 * 1024,
+            block_time_ms: 5_000,
+            liveness_epoch: DEFAULT_LIVENESS_EPOCH,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenesisAccount {
+    pub public_key: Vec<u8>,
+    pub balance: u64,
+    pub identity: IdentityStatus,
+}
+
+/*
+ *  Declaration of Genesis
+ *
+ *  What a network starts from: its chain ID, protocol parameters, the initial
+ *  validators by Falcon public key, initial balances and the time before which
+ *  no block is valid. Validators start as verified persons in the registry, as
+ *  does any account listed as verified or revoked. The hash is
+ *  taken over a canonical encoding with validators and accounts in key order,
+ *  so two nodes with the same genesis agree on it however the file was written.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Genesis {
+    pub chain_id: String,
+    // Milliseconds since the Unix epoch
+    pub genesis_time: u64,
+    pub params: GenesisParams,
+    pub validators: Vec<Vec<u8>>,
+    pub accounts: Vec<GenesisAccount>,
+}
+
+impl Genesis {
+    pub fn new(chain_id: &str, genesis_time: u64) -> Self {
+        Genesis {
+            chain_id: String::from(chain_id),
+            genesis_time,
+            params: GenesisParams::default(),
+            validators: Vec::new(),
+            accounts: Vec::new(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), GenesisError> {
+        if self.chain_id.trim().is_empty() {
+            return Err(GenesisError::EmptyChainId);
+        }
+        if self.chain_id.len() > MAX_CHAIN_ID_BYTES {
+            return Err(GenesisError::ChainIdTooLong(self.chain_id.len()));
+        }
+        let params = &self.params;
+        let positive = [
+            ("max_block_bytes", params.max_block_bytes),
+            ("max_tx_bytes", params.max_tx_bytes),
+            ("block_time_ms", params.block_time_ms),
+            ("liveness_epoch", params.liveness_epoch),
+        ];
+        for (name, value) in positive.iter() {
+            if *value == 0 {
+                return Err(GenesisError::ZeroParam(name));
+            }
+        }
+        if params.max_tx_bytes > params.max_block_bytes {
+            return Err(GenesisError::TxLargerThanBlock { tx: params.max_tx_bytes, block: params.max_block_bytes });
+        }
+
+        if self.validators.is_empty() {
+            return Err(GenesisError::NoValidators);
+        }
+        if self.validators.len() > MAX_VALIDATORS {
+            return Err(GenesisError::TooManyValidators(self.validators.len()));
+        }
+        let keys = self.validators.iter().chain(self.accounts.iter().map(|a| &a.public_key));
+        for (index, key) in keys.enumerate() {
+            if key.len() != CRYPTO_PUBLICKEYBYTES as usize {
+                return Err(GenesisError::BadKey { index, len: key.len() });
+            }
+        }
+        let mut ids: Vec<KeyId> = self.validators.iter().map(|k| KeyId::from_public_key(k)).collect();
+        ids.sort();
+        if let Some(pair) = ids.windows(2).find(|w| w[0] == w[1]) {
+            return Err(GenesisError::DuplicateValidator(pair[0]));
+        }
+        let mut ids: Vec<KeyId> = self.accounts.iter().map(|a| KeyId::from_public_key(&a.public_key)).collect();
+        ids.sort();
+        if let Some(pair) = ids.windows(2).find(|w| w[0] == w[1]) {
+            return Err(GenesisError::DuplicateAccount(pair[0]));
+        }
+
+        Ok(())
+    }
+
+    pub fn hash(&self) -> Hash {
+        hash_with_domain(DOMAIN_GENESIS, &self.encode())
+    }
+
+    // Accounts and persons before the first block; a validator listed among the accounts keeps its balance
+    pub fn state(&self) -> State {
+        let mut state = State::new();
+        state.set_params(StateParams { liveness_epoch: self.params.liveness_epoch });
+        for account in &self.accounts {
+            let id = KeyId::from_public_key(&account.public_key);
+            state.set_account(id, Account { balance: account.balance, nonce: 0, identity: account.identity });
+        }
+        for key in &self.validators {
+            let id = KeyId::from_public_key(key);
+            let balance = state.balance(&id);
+            state.set_account(id, Account { balance, nonce: 0, identity: IdentityStatus::Verified });
+        }
+
+        let listed = self.accounts.iter().map(|a| (&a.public_key, a.identity));
+        for (key, identity) in listed.chain(self.validators.iter().map(|k| (k, IdentityStatus::Verified))) {
+            let status = match identity {
+                IdentityStatus::Unverified => continue,
+                IdentityStatus::Verified => PersonStatus::Verified,
+                IdentityStatus::Revoked => PersonStatus::Revoked,
+            };
+            let mut person = Person::new(key.clone(), status, Vec::new(), 0);
+            person.live_until = self.params.liveness_epoch;
+            state.set_person(PersonId::from_public_key(key), person);
+        }
+        state
+    }
+}
+
+impl Encode for Genesis {
+    fn encode_to(&self, out: &mut Encoder) {
+        let mut validators: Vec<&Vec<u8>> = self.validators.iter().collect();
+        validators.sort();
+        let mut accounts: Vec<&GenesisAccount> = self.accounts.iter().collect();
+        accounts.sort_by(|a, b| a.public_key.cmp(&b.public_key));
+
+        out.bytes(self.chain_id.as_bytes()).u64(self.genesis_time);
+        out.u64(self.params.max_block_bytes).u64(self.params.max_tx_bytes).u64(self.params.block_time_ms).u64(self.params.liveness_epoch);
+        out.u32(validators.len() as u32);
+        for key in validators {
+            out.bytes(key);
+        }
+        out.u32(accounts.len() as u32);
+        for account in accounts {
+            out.bytes(&account.public_key).u64(account.balance).u8(account.identity.tag());
+        }
+    }
+}
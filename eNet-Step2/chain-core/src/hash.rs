@@ -0,0 +1,143 @@
+use sha3::{ Digest, Sha3_256 };
+use std::fmt::{ self, Debug, Display, Formatter };
+
+pub const HASH_BYTES: usize = 32;
+
+/*
+ *  Domain tags, so a header hash can never be mistaken for a transaction hash,
+ *  a Merkle node or a key ID even when the hashed bytes happen to collide
+ */
+pub const DOMAIN_HEADER: &[u8] = b"frink/header";
+pub const DOMAIN_TX: &[u8] = b"frink/tx";
+pub const DOMAIN_TX_ID: &[u8] = b"frink/tx-id";
+pub const DOMAIN_MERKLE_LEAF: &[u8] = b"frink/merkle/leaf";
+pub const DOMAIN_MERKLE_NODE: &[u8] = b"frink/merkle/node";
+pub const DOMAIN_KEY_ID: &[u8] = b"frink/key-id";
+pub const DOMAIN_STATE_LEAF: &[u8] = b"frink/state/leaf";
+pub const DOMAIN_STATE_NODE: &[u8] = b"frink/state/node";
+pub const DOMAIN_STATE_VALUE: &[u8] = b"frink/state/value";
+pub const DOMAIN_VOTE: &[u8] = b"frink/vote";
+pub const DOMAIN_PROPOSAL: &[u8] = b"frink/proposal";
+pub const DOMAIN_GENESIS: &[u8] = b"frink/genesis";
+pub const DOMAIN_PERSON_ID: &[u8] = b"frink/person-id";
+pub const DOMAIN_STATE_RECORD: &[u8] = b"frink/state/record";
+pub const DOMAIN_DISPUTE_ID: &[u8] = b"frink/dispute-id";
+pub const DOMAIN_JURY: &[u8] = b"frink/jury";
+pub const DOMAIN_KEY_UPDATE: &[u8] = b"frink/key-update";
+pub const DOMAIN_LIVENESS: &[u8] = b"frink/liveness";
+pub const DOMAIN_STATE_PARAMS: &[u8] = b"frink/state/params";
+pub const DOMAIN_BIOMETRIC: &[u8] = b"frink/biometric";
+pub const DOMAIN_BIOMETRIC_BAND: &[u8] = b"frink/biometric/band";
+pub const DOMAIN_BIOMETRIC_PLANE: &[u8] = b"frink/biometric/plane";
+
+/*
+ *  Declaration of Hash
+ *
+ *  SHA3-256 digest. Ordered and hashable so it can key maps.
+ */
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Hash(pub [u8; HASH_BYTES]);
+
+impl Hash {
+    pub const ZERO: Hash = Hash([0; HASH_BYTES]);
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Option<Hash> {
+        if bytes.len() != HASH_BYTES {
+            return None;
+        }
+        let mut hash = [0u8; HASH_BYTES];
+        hash.copy_from_slice(bytes);
+        Some(Hash(hash))
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+}
+
+impl Debug for Hash {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+impl Display for Hash {
+    // Short form for logs
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", &self.to_hex()[..16])
+    }
+}
+
+// SHA3-256(len(domain) || domain || data)
+pub fn hash_with_domain(domain: &[u8], data: &[u8]) -> Hash {
+    let mut hasher = Sha3_256::new();
+    hasher.update([domain.len() as u8]);
+    hasher.update(domain);
+    hasher.update(data);
+
+    let mut out = [0u8; HASH_BYTES];
+    out.copy_from_slice(&hasher.finalize());
+    Hash(out)
+}
+
+/*
+ *  Declaration of KeyId
+ *
+ *  Short, fixed-size name of a Falcon public key: the domain-tagged hash of its
+ *  897 bytes. Headers and transactions carry this instead of the full key.
+ */
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyId(pub Hash);
+
+impl KeyId {
+    pub fn from_public_key(public_key: &[u8]) -> KeyId {
+        KeyId(hash_with_domain(DOMAIN_KEY_ID, public_key))
+    }
+}
+
+impl Debug for KeyId {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "KeyId({})", self.0.to_hex())
+    }
+}
+
+impl Display for KeyId {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+/*
+ *  Binary Merkle root over `leaves`. Leaves and inner nodes use different domains
+ *  and an odd node is carried up unchanged rather than paired with itself, so no
+ *  two different leaf lists share a root.
+ */
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return hash_with_domain(DOMAIN_MERKLE_LEAF, &[]);
+    }
+
+    let mut level: Vec<Hash> = leaves.iter().map(|l| hash_with_domain(DOMAIN_MERKLE_LEAF, l.as_bytes())).collect();
+    while level.len() > 1 {
+        let mut next = Vec::with_capacity(level.len().div_ceil(2));
+        for pair in level.chunks(2) {
+            match pair {
+                [left, right] => {
+                    let mut data = [0u8; HASH_BYTES * 2];
+                    data[..HASH_BYTES].copy_from_slice(left.as_bytes());
+                    data[HASH_BYTES..].copy_from_slice(right.as_bytes());
+                    next.push(hash_with_domain(DOMAIN_MERKLE_NODE, &data));
+                },
+                [single] => next.push(*single),
+                _ => unreachable!(),
+            }
+        }
+        level = next;
+    }
+
+    level[0]
+}
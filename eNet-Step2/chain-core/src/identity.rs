@@ -0,0 +1,601 @@
+use std::fmt::{ self, Debug, Display, Formatter };
+
+use secure_sign::{ NistCryptography, CRYPTO_PUBLICKEYBYTES, DETACHED_SIGNATURE_MAX };
+
+use crate::biometric::{ BiometricCommitment, BiometricRecord };
+use crate::codec::{ Decode, DecodeError, Decoder, Encode, Encoder };
+use crate::dispute::{ Ballot, Challenge };
+use crate::liveness::LivenessClaim;
+use crate::recovery::{ Guardianship, PendingRecovery, RecoveryApproval, RecoveryCancel, MAX_GUARDIANS };
+use crate::hash::{ hash_with_domain, Hash, KeyId, DOMAIN_KEY_UPDATE, DOMAIN_PERSON_ID, HASH_BYTES };
+use crate::state::{ IdentityStatus, State, StateError };
+use crate::transaction::{ Transaction, TxKind };
+
+// Evidence commitments one person may carry
+pub const MAX_EVIDENCE: usize = 16;
+// Unique-human attestations from different verified persons that verify a registrant
+pub const ATTESTATIONS_TO_VERIFY: usize = 3;
+// People one person may vouch for, ever
+pub const MAX_VOUCHES: u32 = 16;
+// Taken from a voucher for each of their subjects proven a duplicate
+pub const VOUCH_PENALTY: u64 = 100;
+// Vouches for duplicates after which the voucher is suspended for good
+pub const MAX_STRIKES: u32 = 2;
+
+/*
+ *  Declaration of PersonId
+ *
+ *  Name of a person in the registry: the domain-tagged hash of the key they
+ *  registered with. It stays the same when they move to another key.
+ */
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PersonId(pub Hash);
+
+impl PersonId {
+    pub fn from_public_key(public_key: &[u8]) -> PersonId {
+        PersonId(hash_with_domain(DOMAIN_PERSON_ID, public_key))
+    }
+}
+
+impl Debug for PersonId {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "PersonId({})", self.0.to_hex())
+    }
+}
+
+impl Display for PersonId {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl Encode for PersonId {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.hash(&self.0);
+    }
+}
+
+impl Decode for PersonId {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(PersonId(input.hash()?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PersonStatus {
+    // Registered, not yet verified
+    Pending,
+    Verified,
+    // Vouched for MAX_STRIKES duplicates. Nothing verifies them again, but
+    // unlike a revoked entry theirs is not a duplicate and keeps its key
+    Suspended,
+    // For good
+    Revoked,
+    // Verified once, but gave no sign of life within the epoch
+    Lapsed,
+}
+
+impl PersonStatus {
+    pub fn tag(self) -> u8 {
+        match self {
+            PersonStatus::Pending => 0,
+            PersonStatus::Verified => 1,
+            PersonStatus::Suspended => 2,
+            PersonStatus::Revoked => 3,
+            PersonStatus::Lapsed => 4,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<PersonStatus> {
+        match tag {
+            0 => Some(PersonStatus::Pending),
+            1 => Some(PersonStatus::Verified),
+            2 => Some(PersonStatus::Suspended),
+            3 => Some(PersonStatus::Revoked),
+            4 => Some(PersonStatus::Lapsed),
+            _ => None,
+        }
+    }
+
+    // What the account of the person's key shows
+    pub fn identity(self) -> IdentityStatus {
+        match self {
+            PersonStatus::Verified => IdentityStatus::Verified,
+            PersonStatus::Revoked => IdentityStatus::Revoked,
+            PersonStatus::Pending | PersonStatus::Suspended | PersonStatus::Lapsed => IdentityStatus::Unverified,
+        }
+    }
+}
+
+/*
+ *  Declaration of Person
+ *
+ *  One entry of the proof-of-person registry. Evidence is only committed to by
+ *  hash; what it is and where it is kept is up to the verifiers. Times are
+ *  block heights, as everywhere else in state.
+ *
+ *  `vouchers` are the attestations that count towards verifying this person;
+ *  they are kept after verification so the vouchers can be held to account.
+ *  A verified person stays so through `live_until`, then lapses unless they
+ *  have sent a liveness claim. `guardian_threshold` of the `guardians` can
+ *  move them to a new key if they lose theirs. `biometric` is only ever a
+ *  commitment, see `biometric`.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Person {
+    pub public_key: Vec<u8>,
+    pub status: PersonStatus,
+    pub evidence: Vec<Hash>,
+    pub vouchers: Vec<Voucher>,
+    // People this person has vouched for
+    pub vouches: u32,
+    // Of those, how many were proven duplicates
+    pub strikes: u32,
+    pub registered_at: u64,
+    pub updated_at: u64,
+    pub live_until: u64,
+    pub guardians: Vec<PersonId>,
+    pub guardian_threshold: u32,
+    pub recovery: Option<PendingRecovery>,
+    pub biometric: Option<BiometricCommitment>,
+}
+
+impl Person {
+    // A new entry at `height` with nothing vouched
+    pub fn new(public_key: Vec<u8>, status: PersonStatus, evidence: Vec<Hash>, height: u64) -> Self {
+        Person {
+            public_key,
+            status,
+            evidence,
+            vouchers: Vec::new(),
+            vouches: 0,
+            strikes: 0,
+            registered_at: height,
+            updated_at: height,
+            live_until: 0,
+            guardians: Vec::new(),
+            guardian_threshold: 0,
+            recovery: None,
+            biometric: None,
+        }
+    }
+
+    pub fn key_id(&self) -> KeyId {
+        KeyId::from_public_key(&self.public_key)
+    }
+}
+
+impl Encode for Person {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.bytes(&self.public_key).u8(self.status.tag()).list(&self.evidence).list(&self.vouchers);
+        out.u32(self.vouches).u32(self.strikes).u64(self.registered_at).u64(self.updated_at).u64(self.live_until);
+        out.list(&self.guardians).u32(self.guardian_threshold);
+        match &self.recovery {
+            Some(recovery) => recovery.encode_to(out.u8(1)),
+            None => { out.u8(0); },
+        }
+        match &self.biometric {
+            Some(biometric) => biometric.encode_to(out.u8(1)),
+            None => { out.u8(0); },
+        }
+    }
+}
+
+impl Decode for Person {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        let public_key = input.bytes("public_key", CRYPTO_PUBLICKEYBYTES as usize)?;
+        let tag = input.u8()?;
+        Ok(Person {
+            public_key,
+            status: PersonStatus::from_tag(tag).ok_or(DecodeError::UnknownKind(tag))?,
+            evidence: input.list("evidence", MAX_EVIDENCE)?,
+            vouchers: input.list("vouchers", ATTESTATIONS_TO_VERIFY)?,
+            vouches: input.u32()?,
+            strikes: input.u32()?,
+            registered_at: input.u64()?,
+            updated_at: input.u64()?,
+            live_until: input.u64()?,
+            guardians: input.list("guardians", MAX_GUARDIANS)?,
+            guardian_threshold: input.u32()?,
+            recovery: match input.u8()? {
+                0 => None,
+                1 => Some(PendingRecovery::decode_from(input)?),
+                tag => return Err(DecodeError::UnknownKind(tag)),
+            },
+            biometric: match input.u8()? {
+                0 => None,
+                1 => Some(BiometricCommitment::decode_from(input)?),
+                tag => return Err(DecodeError::UnknownKind(tag)),
+            },
+        })
+    }
+}
+
+// What an attester vouches for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClaimType {
+    // The subject is a real human not registered under any other entry
+    UniqueHuman,
+}
+
+impl ClaimType {
+    pub fn tag(self) -> u8 {
+        match self {
+            ClaimType::UniqueHuman => 0,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<ClaimType> {
+        match tag {
+            0 => Some(ClaimType::UniqueHuman),
+            _ => None,
+        }
+    }
+}
+
+// An attestation as kept on its subject
+#[derive(Debug, Clone, PartialEq)]
+pub struct Voucher {
+    pub attester: PersonId,
+    pub claim: ClaimType,
+    pub expiry_height: u64,
+}
+
+impl Voucher {
+    pub fn is_expired(&self, height: u64) -> bool {
+        self.expiry_height != 0 && height > self.expiry_height
+    }
+}
+
+impl Encode for Voucher {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.hash(&self.attester.0).u8(self.claim.tag()).u64(self.expiry_height);
+    }
+}
+
+impl Decode for Voucher {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        let attester = PersonId(input.hash()?);
+        let tag = input.u8()?;
+        Ok(Voucher {
+            attester,
+            claim: ClaimType::from_tag(tag).ok_or(DecodeError::UnknownKind(tag))?,
+            expiry_height: input.u64()?,
+        })
+    }
+}
+
+/*
+ *  Payload of a TxKind::Register: the sender's key becomes a pending person
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Registration {
+    pub evidence: Vec<Hash>,
+}
+
+impl Encode for Registration {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.list(&self.evidence);
+    }
+}
+
+impl Decode for Registration {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(Registration { evidence: input.list("evidence", MAX_EVIDENCE)? })
+    }
+}
+
+/*
+ *  Payload of a TxKind::UpdateKey, sent from the person's current key. The new
+ *  key signs the person and the current key, so nobody can bind a key they do
+ *  not hold.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyUpdate {
+    pub person: PersonId,
+    pub public_key: Vec<u8>,
+    pub proof: Vec<u8>,
+}
+
+impl KeyUpdate {
+    pub fn new(person: PersonId, current: &KeyId, secure: &mut NistCryptography) -> Option<KeyUpdate> {
+        let proof = secure.sign_detached(KeyUpdate::message(&person, current).as_bytes())?;
+        Some(KeyUpdate { person, public_key: secure.public_key.to_vec(), proof })
+    }
+
+    fn message(person: &PersonId, current: &KeyId) -> Hash {
+        let mut data = [0u8; HASH_BYTES * 2];
+        data[..HASH_BYTES].copy_from_slice(person.0.as_bytes());
+        data[HASH_BYTES..].copy_from_slice(current.0.as_bytes());
+        hash_with_domain(DOMAIN_KEY_UPDATE, &data)
+    }
+
+    pub fn verify(&self, current: &KeyId) -> bool {
+        self.public_key.len() == CRYPTO_PUBLICKEYBYTES as usize
+            && NistCryptography::verify_detached(KeyUpdate::message(&self.person, current).as_bytes(), &self.proof, &self.public_key)
+    }
+}
+
+impl Encode for KeyUpdate {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.hash(&self.person.0).bytes(&self.public_key).bytes(&self.proof);
+    }
+}
+
+impl Decode for KeyUpdate {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(KeyUpdate {
+            person: PersonId(input.hash()?),
+            public_key: input.bytes("public_key", CRYPTO_PUBLICKEYBYTES as usize)?,
+            proof: input.bytes("proof", DETACHED_SIGNATURE_MAX)?,
+        })
+    }
+}
+
+/*
+ *  Payload of a TxKind::Deactivate: the person gives up their entry for good
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Deactivation {
+    pub person: PersonId,
+}
+
+impl Encode for Deactivation {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.hash(&self.person.0);
+    }
+}
+
+impl Decode for Deactivation {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(Deactivation { person: PersonId(input.hash()?) })
+    }
+}
+
+/*
+ *  Payload of a TxKind::Attest, sent from the attester's current key so the
+ *  transaction signature is the attester's. It counts until `expiry_height`
+ *  (0 for never) and only while the subject is still pending.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attestation {
+    pub attester: PersonId,
+    pub subject: PersonId,
+    pub claim: ClaimType,
+    pub expiry_height: u64,
+}
+
+impl Encode for Attestation {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.hash(&self.attester.0).hash(&self.subject.0).u8(self.claim.tag()).u64(self.expiry_height);
+    }
+}
+
+impl Decode for Attestation {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        let attester = PersonId(input.hash()?);
+        let subject = PersonId(input.hash()?);
+        let tag = input.u8()?;
+        Ok(Attestation {
+            attester,
+            subject,
+            claim: ClaimType::from_tag(tag).ok_or(DecodeError::UnknownKind(tag))?,
+            expiry_height: input.u64()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdentityAction {
+    Register(Registration),
+    UpdateKey(KeyUpdate),
+    Deactivate(Deactivation),
+    Attest(Attestation),
+    Challenge(Challenge),
+    Ballot(Ballot),
+    Liveness(LivenessClaim),
+    SetGuardians(Guardianship),
+    Recover(RecoveryApproval),
+    CancelRecovery(RecoveryCancel),
+    CommitBiometric(BiometricRecord),
+}
+
+impl IdentityAction {
+    // The payload of an identity transaction; None for other kinds
+    pub fn decode(kind: TxKind, payload: &[u8]) -> Result<Option<IdentityAction>, DecodeError> {
+        match kind {
+            TxKind::Register => Ok(Some(IdentityAction::Register(Registration::decode(payload)?))),
+            TxKind::UpdateKey => Ok(Some(IdentityAction::UpdateKey(KeyUpdate::decode(payload)?))),
+            TxKind::Deactivate => Ok(Some(IdentityAction::Deactivate(Deactivation::decode(payload)?))),
+            TxKind::Attest => Ok(Some(IdentityAction::Attest(Attestation::decode(payload)?))),
+            TxKind::Challenge => Ok(Some(IdentityAction::Challenge(Challenge::decode(payload)?))),
+            TxKind::Ballot => Ok(Some(IdentityAction::Ballot(Ballot::decode(payload)?))),
+            TxKind::Liveness => Ok(Some(IdentityAction::Liveness(LivenessClaim::decode(payload)?))),
+            TxKind::SetGuardians => Ok(Some(IdentityAction::SetGuardians(Guardianship::decode(payload)?))),
+            TxKind::Recover => Ok(Some(IdentityAction::Recover(RecoveryApproval::decode(payload)?))),
+            TxKind::CancelRecovery => Ok(Some(IdentityAction::CancelRecovery(RecoveryCancel::decode(payload)?))),
+            TxKind::CommitBiometric => Ok(Some(IdentityAction::CommitBiometric(BiometricRecord::decode(payload)?))),
+            TxKind::Transfer | TxKind::Data => Ok(None),
+        }
+    }
+
+    // What leaves the sender's account besides the fee
+    pub fn amount(&self) -> u64 {
+        match self {
+            IdentityAction::Challenge(challenge) => challenge.bond,
+            _ => 0,
+        }
+    }
+}
+
+impl State {
+    // Carry out `action` from `tx` at `height`; the fee and any bond are already taken
+    pub(crate) fn apply_identity(&mut self, tx: &Transaction, action: &IdentityAction, height: u64) -> Result<(), StateError> {
+        let persons = match action {
+            IdentityAction::Challenge(challenge) => return self.open_dispute(tx, challenge, height),
+            IdentityAction::Ballot(ballot) => return self.cast_ballot(tx, ballot, height),
+            IdentityAction::Attest(attestation) => self.check_attestation(tx, attestation, height)?,
+            IdentityAction::Liveness(claim) => self.check_liveness(tx, claim, height)?,
+            IdentityAction::SetGuardians(guardianship) => self.check_guardians(tx, guardianship, height)?,
+            IdentityAction::Recover(approval) => self.approve_recovery(tx, approval, height)?,
+            IdentityAction::CancelRecovery(cancel) => self.cancel_recovery(tx, cancel, height)?,
+            IdentityAction::CommitBiometric(record) => self.check_biometric(tx, record, height)?,
+            _ => self.check_person(tx, action, height)?,
+        };
+        for (id, person) in persons {
+            self.write_person(id, person);
+        }
+
+        Ok(())
+    }
+
+    // The record a registration, key update or deactivation leads to
+    fn check_person(&self, tx: &Transaction, action: &IdentityAction, height: u64) -> Result<Vec<(PersonId, Person)>, StateError> {
+        let id = match action {
+            IdentityAction::Register(registration) => {
+                let id = PersonId::from_public_key(&tx.public_key);
+                if let Some(existing) = self.person_of(&tx.sender) {
+                    return Err(StateError::AlreadyRegistered(existing));
+                }
+                if self.person(&id).is_some() {
+                    return Err(StateError::AlreadyRegistered(id));
+                }
+                let person = Person::new(tx.public_key.clone(), PersonStatus::Pending, registration.evidence.clone(), height);
+                return Ok(vec![(id, person)]);
+            },
+            IdentityAction::UpdateKey(update) => update.person,
+            IdentityAction::Deactivate(deactivation) => deactivation.person,
+            _ => unreachable!(),
+        };
+
+        let mut person = self.sending_person(tx, &id)?;
+        match action {
+            IdentityAction::UpdateKey(update) => {
+                let key = KeyId::from_public_key(&update.public_key);
+                if self.person_of(&key).is_some() {
+                    return Err(StateError::KeyInUse(key));
+                }
+                person.public_key = update.public_key.clone();
+                // Approvals were for the old key
+                person.recovery = None;
+            },
+            IdentityAction::Deactivate(_) => {
+                // Leaving would dodge what a pending verdict costs the vouchers
+                if self.under_dispute(&id) {
+                    return Err(StateError::UnderDispute(id));
+                }
+                person.status = PersonStatus::Revoked;
+            },
+            _ => unreachable!(),
+        }
+        person.updated_at = height;
+
+        Ok(vec![(id, person)])
+    }
+
+    // The attester with one more vouch and the subject with the attestation, verified once it has enough
+    fn check_attestation(&self, tx: &Transaction, attestation: &Attestation, height: u64) -> Result<Vec<(PersonId, Person)>, StateError> {
+        let mut attester = self.sending_person(tx, &attestation.attester)?;
+        if !self.is_live(&attester, height) {
+            return Err(StateError::NotVerified(attestation.attester));
+        }
+        let voucher = Voucher { attester: attestation.attester, claim: attestation.claim, expiry_height: attestation.expiry_height };
+        if voucher.is_expired(height) {
+            return Err(StateError::Expired { expiry_height: attestation.expiry_height, height });
+        }
+        let mut subject = self.person(&attestation.subject).cloned().ok_or(StateError::UnknownPerson(attestation.subject))?;
+        if subject.status != PersonStatus::Pending {
+            return Err(StateError::NotPending(attestation.subject));
+        }
+        if subject.vouchers.iter().any(|v| v.attester == attestation.attester && !v.is_expired(height)) {
+            return Err(StateError::AlreadyAttested(attestation.attester));
+        }
+        if attester.vouches >= MAX_VOUCHES {
+            return Err(StateError::VouchLimit(attestation.attester));
+        }
+
+        attester.vouches += 1;
+        attester.updated_at = height;
+        // Vouchers that have expired or since lost their standing no longer count
+        subject.vouchers.retain(|v| {
+            !v.is_expired(height) && self.person(&v.attester).map(|p| p.status == PersonStatus::Verified).unwrap_or(false)
+        });
+        subject.vouchers.push(voucher);
+        if subject.vouchers.iter().filter(|v| v.claim == ClaimType::UniqueHuman).count() >= ATTESTATIONS_TO_VERIFY {
+            subject.status = PersonStatus::Verified;
+            subject.live_until = height.checked_add(self.params().liveness_epoch).ok_or(StateError::Overflow)?;
+        }
+        subject.updated_at = height;
+
+        Ok(vec![(attestation.attester, attester), (attestation.subject, subject)])
+    }
+
+    // The record of `id`, which `tx` must come from and which must not be revoked
+    pub(crate) fn sending_person(&self, tx: &Transaction, id: &PersonId) -> Result<Person, StateError> {
+        let person = self.person(id).cloned().ok_or(StateError::UnknownPerson(*id))?;
+        if person.key_id() != tx.sender {
+            return Err(StateError::NotPersonKey(*id));
+        }
+        if person.status == PersonStatus::Revoked {
+            return Err(StateError::PersonRevoked(*id));
+        }
+
+        Ok(person)
+    }
+
+    // Store a checked record and show its status on the accounts of its keys
+    pub(crate) fn write_person(&mut self, id: PersonId, person: Person) {
+        if let Some(old) = self.person(&id).map(|p| p.key_id()) {
+            if old != person.key_id() {
+                let mut account = self.account(&old).cloned().unwrap_or_default();
+                account.identity = IdentityStatus::Unverified;
+                self.set_account(old, account);
+            }
+        }
+        let key = person.key_id();
+        let mut account = self.account(&key).cloned().unwrap_or_default();
+        account.identity = person.status.identity();
+        self.set_account(key, account);
+        self.set_person(id, person);
+    }
+
+    /*
+     *  Revoke `id` as a duplicate of someone already registered. Each person
+     *  who vouched for it loses VOUCH_PENALTY, or what they have if less, and
+     *  takes a strike; at MAX_STRIKES a verified voucher is suspended for
+     *  good. Returns the total taken, which leaves circulation.
+     */
+    pub fn revoke_duplicate(&mut self, id: &PersonId, height: u64) -> Result<u64, StateError> {
+        let mut person = self.person(id).cloned().ok_or(StateError::UnknownPerson(*id))?;
+        if person.status == PersonStatus::Revoked {
+            return Err(StateError::PersonRevoked(*id));
+        }
+
+        let mut taken = 0;
+        for voucher in &person.vouchers {
+            let mut attester = match self.person(&voucher.attester) {
+                Some(attester) => attester.clone(),
+                None => continue,
+            };
+            let key = attester.key_id();
+            let mut account = self.account(&key).cloned().unwrap_or_default();
+            let penalty = account.balance.min(VOUCH_PENALTY);
+            account.balance -= penalty;
+            taken += penalty;
+            self.set_account(key, account);
+
+            attester.strikes += 1;
+            let standing = attester.status == PersonStatus::Verified || attester.status == PersonStatus::Lapsed;
+            if attester.strikes >= MAX_STRIKES && standing {
+                attester.status = PersonStatus::Suspended;
+            }
+            attester.updated_at = height;
+            self.write_person(voucher.attester, attester);
+        }
+        person.status = PersonStatus::Revoked;
+        person.updated_at = height;
+        self.write_person(*id, person);
+
+        Ok(taken)
+    }
+}
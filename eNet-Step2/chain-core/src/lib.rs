@@ -0,0 +1,51 @@
+extern crate hex;
+extern crate secure_sign;
+extern crate sha3;
+extern crate sled;
+
+/*
+ *  Chain data model shared by every node: blocks, transactions, account state,
+ *  their canonical encoding and hashing. Anything that feeds a hash goes through
+ *  `codec` so all nodes agree on the bytes.
+ */
+mod biometric;
+mod block;
+mod certificate;
+mod chain;
+mod codec;
+mod consensus;
+mod dispute;
+mod fork;
+mod genesis;
+mod hash;
+mod identity;
+mod liveness;
+mod mempool;
+mod recovery;
+mod snapshot;
+mod state;
+mod store;
+mod transaction;
+mod vote;
+
+pub use biometric::{ BiometricCommitment, BiometricRecord, FeatureVector, LSH_BANDS, LSH_BAND_BITS, MATCH_BANDS, MAX_FEATURES };
+pub use block::{ Block, BlockHeader, BLOCK_VERSION, MAX_BLOCK_TXS, MAX_ENCODED_TX };
+pub use certificate::{ CertError, QuorumCertificate };
+pub use chain::{ Chain, ChainError, MAX_BLOCK_DRIFT };
+pub use codec::{ Decode, DecodeError, Decoder, Encode, Encoder };
+pub use consensus::{ Consensus, ConsensusOutput, Step, Timeouts, MAX_FUTURE_MESSAGES, MAX_ROUNDS_AHEAD };
+pub use dispute::{ Ballot, Challenge, Dispute, Verdict, JURY_SIZE, MIN_CHALLENGE_BOND, RESOLUTION_BLOCKS };
+pub use fork::{ BlockTree, ChainEvent, Executed, TreeBlock, MAX_PENDING_BLOCKS };
+pub use genesis::{ Genesis, GenesisAccount, GenesisError, GenesisParams };
+pub use hash::{ Hash, KeyId, HASH_BYTES, hash_with_domain, merkle_root };
+pub use hash::{ DOMAIN_BIOMETRIC, DOMAIN_BIOMETRIC_BAND, DOMAIN_BIOMETRIC_PLANE, DOMAIN_DISPUTE_ID, DOMAIN_GENESIS, DOMAIN_JURY, DOMAIN_HEADER, DOMAIN_KEY_ID, DOMAIN_KEY_UPDATE, DOMAIN_LIVENESS, DOMAIN_MERKLE_LEAF, DOMAIN_MERKLE_NODE, DOMAIN_PERSON_ID, DOMAIN_PROPOSAL, DOMAIN_STATE_LEAF, DOMAIN_STATE_NODE, DOMAIN_STATE_PARAMS, DOMAIN_STATE_RECORD, DOMAIN_STATE_VALUE, DOMAIN_TX, DOMAIN_TX_ID, DOMAIN_VOTE };
+pub use identity::{ Attestation, ClaimType, Deactivation, IdentityAction, KeyUpdate, Person, PersonId, PersonStatus, Registration, Voucher };
+pub use identity::{ ATTESTATIONS_TO_VERIFY, MAX_EVIDENCE, MAX_STRIKES, MAX_VOUCHES, VOUCH_PENALTY };
+pub use liveness::{ Cosign, LivenessClaim, DEFAULT_LIVENESS_EPOCH };
+pub use mempool::{ Mempool, PoolError, DEFAULT_POOL_BYTES, DEFAULT_POOL_TTL, MAX_NONCE_GAP };
+pub use recovery::{ Guardianship, PendingRecovery, RecoveryApproval, RecoveryCancel, MAX_GUARDIANS, RECOVERY_DELAY };
+pub use snapshot::{ Snapshot, SnapshotError, SnapshotManifest, SNAPSHOT_CHUNK_ACCOUNTS };
+pub use state::{ Account, ChunkSibling, IdentityStatus, Record, State, StateChunk, StateError, StateParams, StateProof, TrieNode, MAX_CHUNK_ACCOUNTS, MAX_CHUNK_DEPTH };
+pub use store::{ ChainTip, Store, StoreError, TxLocation, SNAPSHOTS_KEPT };
+pub use transaction::{ Transaction, Transfer, TxError, TxKind, MAX_CHAIN_ID_BYTES, TX_VERSION };
+pub use vote::{ ConsensusMessage, Proposal, Validator, ValidatorSet, Vote, VoteKind, MAX_VALIDATORS };
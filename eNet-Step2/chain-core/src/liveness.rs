@@ -0,0 +1,124 @@
+use secure_sign::{ NistCryptography, DETACHED_SIGNATURE_MAX };
+
+use crate::codec::{ Decode, DecodeError, Decoder, Encode, Encoder };
+use crate::hash::{ hash_with_domain, Hash, DOMAIN_LIVENESS, HASH_BYTES };
+use crate::identity::{ Person, PersonId, PersonStatus };
+use crate::state::{ State, StateError };
+use crate::transaction::Transaction;
+
+// Blocks a liveness claim lasts for unless genesis says otherwise, about a month at 5s blocks
+pub const DEFAULT_LIVENESS_EPOCH: u64 = 500_000;
+
+/*
+ *  A verified person's signature on someone else's liveness claim. It covers
+ *  the claimant and the height their liveness ran to, so it counts for one
+ *  claim only.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cosign {
+    pub verifier: PersonId,
+    pub signature: Vec<u8>,
+}
+
+impl Cosign {
+    pub fn new(person: &PersonId, live_until: u64, verifier: PersonId, secure: &mut NistCryptography) -> Option<Cosign> {
+        let signature = secure.sign_detached(Cosign::message(person, live_until).as_bytes())?;
+        Some(Cosign { verifier, signature })
+    }
+
+    fn message(person: &PersonId, live_until: u64) -> Hash {
+        let mut data = [0u8; HASH_BYTES + 8];
+        data[..HASH_BYTES].copy_from_slice(person.0.as_bytes());
+        data[HASH_BYTES..].copy_from_slice(&live_until.to_be_bytes());
+        hash_with_domain(DOMAIN_LIVENESS, &data)
+    }
+
+    pub fn verify(&self, person: &PersonId, live_until: u64, public_key: &[u8]) -> bool {
+        NistCryptography::verify_detached(Cosign::message(person, live_until).as_bytes(), &self.signature, public_key)
+    }
+}
+
+/*
+ *  Payload of a TxKind::Liveness, sent from the person's current key. It keeps
+ *  a verified person live for another epoch; a lapsed person needs `cosign`
+ *  to be verified again.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct LivenessClaim {
+    pub person: PersonId,
+    pub cosign: Option<Cosign>,
+}
+
+impl Encode for LivenessClaim {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.hash(&self.person.0);
+        match &self.cosign {
+            Some(cosign) => out.u8(1).hash(&cosign.verifier.0).bytes(&cosign.signature),
+            None => out.u8(0),
+        };
+    }
+}
+
+impl Decode for LivenessClaim {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        let person = PersonId(input.hash()?);
+        let cosign = match input.u8()? {
+            0 => None,
+            1 => Some(Cosign { verifier: PersonId(input.hash()?), signature: input.bytes("signature", DETACHED_SIGNATURE_MAX)? }),
+            tag => return Err(DecodeError::UnknownKind(tag)),
+        };
+        Ok(LivenessClaim { person, cosign })
+    }
+}
+
+impl State {
+    // Whether `person` is verified and has shown liveness for `height`
+    pub fn is_live(&self, person: &Person, height: u64) -> bool {
+        person.status == PersonStatus::Verified && height <= person.live_until
+    }
+
+    // The claimant live for another epoch from `height`, verified again if they had lapsed
+    pub(crate) fn check_liveness(&self, tx: &Transaction, claim: &LivenessClaim, height: u64) -> Result<Vec<(PersonId, Person)>, StateError> {
+        let mut person = self.sending_person(tx, &claim.person)?;
+        match person.status {
+            PersonStatus::Verified | PersonStatus::Lapsed => (),
+            _ => return Err(StateError::NotVerified(claim.person)),
+        }
+        match &claim.cosign {
+            Some(cosign) => {
+                let verifier = self.person(&cosign.verifier).ok_or(StateError::UnknownPerson(cosign.verifier))?;
+                if cosign.verifier == claim.person || !self.is_live(verifier, height) {
+                    return Err(StateError::NotVerified(cosign.verifier));
+                }
+                if !cosign.verify(&claim.person, person.live_until, &verifier.public_key) {
+                    return Err(StateError::BadCosign(cosign.verifier));
+                }
+            },
+            None if !self.is_live(&person, height) => return Err(StateError::NeedsVerifier(claim.person)),
+            None => (),
+        }
+
+        person.status = PersonStatus::Verified;
+        person.live_until = height.checked_add(self.params().liveness_epoch).ok_or(StateError::Overflow)?;
+        person.updated_at = height;
+
+        Ok(vec![(claim.person, person)])
+    }
+
+    /*
+     *  Mark every verified person whose liveness ran out before `height` as
+     *  lapsed, taking their account's verified status and with it their place
+     *  among validators
+     */
+    pub(crate) fn lapse_persons(&mut self, height: u64) {
+        let lapsed: Vec<(PersonId, Person)> = self.due_records(height).into_iter()
+            .filter_map(|key| self.person(&PersonId(key)).map(|person| (PersonId(key), person.clone())))
+            .filter(|(_, person)| person.status == PersonStatus::Verified && person.live_until < height)
+            .collect();
+        for (id, mut person) in lapsed {
+            person.status = PersonStatus::Lapsed;
+            person.updated_at = height;
+            self.write_person(id, person);
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use std::cmp::{ Ordering, Reverse };
+use std::collections::{ BTreeMap, BinaryHeap, HashMap };
+use std::fmt::{ self, Display, Formatter };
+
+use crate::codec::Encode;
+use crate::hash::{ Hash, KeyId };
+use crate::state::State;
+use crate::transaction::{ Transaction, TxError };
+
+pub const DEFAULT_POOL_BYTES: usize = 16 * 1024 * 1024;
+pub const DEFAULT_POOL_TTL: u64 = 10 * 60 * 1000;
+// How far past the account nonce a sender may queue transactions
+pub const MAX_NONCE_GAP: u64 = 64;
+
+#[derive(Debug, PartialEq)]
+pub enum PoolError {
+    Invalid(TxError),
+    Known,
+    Expired { expiry_height: u64, height: u64 },
+    NonceTooLow { expected: u64, found: u64 },
+    NonceTooHigh { expected: u64, found: u64 },
+    // Fee plus value of this and the sender's queued transactions exceeds the balance
+    InsufficientBalance { needed: u64, available: u64 },
+    // Replacing a queued transaction needs a strictly higher fee
+    Underpriced { fee: u64, existing: u64 },
+    // The pool is full of transactions paying at least as much per byte
+    PoolFull,
+}
+
+impl Display for PoolError {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            PoolError::Invalid(e) => write!(f, "{}", e),
+            PoolError::Known => write!(f, "already in the pool"),
+            PoolError::Expired { expiry_height, height } => write!(f, "expired at height {}, now {}", expiry_height, height),
+            PoolError::NonceTooLow { expected, found } => write!(f, "nonce {} already used, account is at {}", found, expected),
+            PoolError::NonceTooHigh { expected, found } => write!(f, "nonce {} is too far ahead of {}", found, expected),
+            PoolError::InsufficientBalance { needed, available } => write!(f, "needs {}, balance is {}", needed, available),
+            PoolError::Underpriced { fee, existing } => write!(f, "fee {} does not beat queued fee {}", fee, existing),
+            PoolError::PoolFull => write!(f, "pool is full"),
+        }
+    }
+}
+
+struct PoolEntry {
+    tx: Transaction,
+    size: usize,
+    added: u64,
+}
+
+impl PoolEntry {
+    fn cost(&self) -> u64 {
+        self.tx.fee.saturating_add(self.tx.amount())
+    }
+
+    // Fee per byte, compared without division
+    fn cheaper_than(&self, fee: u64, size: usize) -> bool {
+        (self.tx.fee as u128) * (size as u128) < (fee as u128) * (self.size as u128)
+    }
+}
+
+/*
+ *  Declaration of Class Mempool
+ *
+ *  Transactions waiting for a block. Each is checked on the way in, statelessly
+ *  and against the account state it would execute on, and kept per sender in
+ *  nonce order. The pool holds at most `max_bytes` of encoded transactions;
+ *  beyond that the cheapest per byte make way, always from the end of a
+ *  sender's queue so no queue gets a gap.
+ */
+pub struct Mempool {
+    pub chain_id: String,
+    pub max_tx_bytes: usize,
+    pub max_bytes: usize,
+    // Milliseconds a transaction may wait before it is dropped
+    pub ttl: u64,
+    entries: HashMap<Hash, PoolEntry>,
+    senders: HashMap<KeyId, BTreeMap<u64, Hash>>,
+    bytes: usize,
+}
+
+impl Mempool {
+    pub fn new(chain_id: &str, max_tx_bytes: usize, max_bytes: usize, ttl: u64) -> Self {
+        Mempool {
+            chain_id: String::from(chain_id),
+            max_tx_bytes,
+            max_bytes,
+            ttl,
+            entries: HashMap::new(),
+            senders: HashMap::new(),
+            bytes: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn contains(&self, id: &Hash) -> bool {
+        self.entries.contains_key(id)
+    }
+
+    pub fn get(&self, id: &Hash) -> Option<&Transaction> {
+        self.entries.get(id).map(|e| &e.tx)
+    }
+
+    // Decode, validate and admit bytes received from a peer
+    pub fn insert_bytes(&mut self, bytes: &[u8], state: &State, height: u64, now: u64) -> Result<Hash, PoolError> {
+        let tx = Transaction::decode_valid(bytes, &self.chain_id, self.max_tx_bytes).map_err(PoolError::Invalid)?;
+        self.admit(tx, state, height, now)
+    }
+
+    pub fn insert(&mut self, tx: Transaction, state: &State, height: u64, now: u64) -> Result<Hash, PoolError> {
+        tx.validate(&self.chain_id, self.max_tx_bytes).map_err(PoolError::Invalid)?;
+        self.admit(tx, state, height, now)
+    }
+
+    // Stateful checks, replacement and eviction for an already valid transaction
+    fn admit(&mut self, tx: Transaction, state: &State, height: u64, now: u64) -> Result<Hash, PoolError> {
+        let id = tx.id();
+        if self.entries.contains_key(&id) {
+            return Err(PoolError::Known);
+        }
+        if tx.is_expired(height) {
+            return Err(PoolError::Expired { expiry_height: tx.expiry_height, height });
+        }
+        let account_nonce = state.nonce(&tx.sender);
+        if tx.nonce < account_nonce {
+            return Err(PoolError::NonceTooLow { expected: account_nonce, found: tx.nonce });
+        }
+        if tx.nonce >= account_nonce + MAX_NONCE_GAP {
+            return Err(PoolError::NonceTooHigh { expected: account_nonce, found: tx.nonce });
+        }
+
+        let entry = PoolEntry { size: tx.encode().len(), tx, added: now };
+        let sender = entry.tx.sender;
+        let queue = self.senders.get(&sender);
+        let replaced = queue.and_then(|q| q.get(&entry.tx.nonce)).copied();
+        if let Some(old) = replaced {
+            let existing = self.entries[&old].tx.fee;
+            if entry.tx.fee <= existing {
+                return Err(PoolError::Underpriced { fee: entry.tx.fee, existing });
+            }
+        }
+
+        // Everything the sender has queued up to this nonce must be payable together
+        let mut needed = entry.cost();
+        if let Some(queue) = queue {
+            for (_, queued) in queue.range(..entry.tx.nonce) {
+                needed = needed.saturating_add(self.entries[queued].cost());
+            }
+        }
+        let available = state.balance(&sender);
+        if needed > available {
+            return Err(PoolError::InsufficientBalance { needed, available });
+        }
+
+        let freed = replaced.map(|old| self.entries[&old].size).unwrap_or(0);
+        let evict = self.plan_eviction(&entry, freed)?;
+        for victim in evict {
+            self.remove(&victim);
+        }
+        if let Some(old) = replaced {
+            self.remove(&old);
+        }
+
+        self.bytes += entry.size;
+        self.senders.entry(sender).or_default().insert(entry.tx.nonce, id);
+        self.entries.insert(id, entry);
+
+        Ok(id)
+    }
+
+    /*
+     *  Pick the transactions to drop so `entry` fits. Only the last transaction
+     *  of other senders' queues are candidates, cheapest per byte first, and only
+     *  while they pay less per byte than `entry`.
+     */
+    fn plan_eviction(&self, entry: &PoolEntry, freed: usize) -> Result<Vec<Hash>, PoolError> {
+        let mut used = self.bytes - freed;
+        if used + entry.size <= self.max_bytes {
+            return Ok(Vec::new());
+        }
+        let mut tails: Vec<(KeyId, Vec<Hash>)> = self.senders.iter()
+            .filter(|(sender, _)| **sender != entry.tx.sender)
+            .map(|(sender, queue)| (*sender, queue.values().copied().collect()))
+            .collect();
+        let mut evict = Vec::new();
+
+        while used + entry.size > self.max_bytes {
+            let cheapest = tails.iter_mut()
+                .filter(|(_, queue)| !queue.is_empty())
+                .min_by(|(a_sender, a), (b_sender, b)| {
+                    let a = &self.entries[a.last().unwrap()];
+                    let b = &self.entries[b.last().unwrap()];
+                    match (a.cheaper_than(b.tx.fee, b.size), b.cheaper_than(a.tx.fee, a.size)) {
+                        (true, _) => Ordering::Less,
+                        (_, true) => Ordering::Greater,
+                        _ => a_sender.cmp(b_sender),
+                    }
+                });
+            let queue = match cheapest {
+                Some((_, queue)) => queue,
+                None => return Err(PoolError::PoolFull),
+            };
+            let victim = &self.entries[queue.last().unwrap()];
+            if !victim.cheaper_than(entry.tx.fee, entry.size) {
+                return Err(PoolError::PoolFull);
+            }
+            used -= victim.size;
+            evict.push(queue.pop().unwrap());
+        }
+
+        Ok(evict)
+    }
+
+    pub fn remove(&mut self, id: &Hash) -> Option<Transaction> {
+        let entry = self.entries.remove(id)?;
+        self.bytes -= entry.size;
+        if let Some(queue) = self.senders.get_mut(&entry.tx.sender) {
+            queue.remove(&entry.tx.nonce);
+            if queue.is_empty() {
+                self.senders.remove(&entry.tx.sender);
+            }
+        }
+
+        Some(entry.tx)
+    }
+
+    /*
+     *  Up to `max_bytes` and `max_txs` of transactions in the order a block
+     *  should carry them: highest fee first, but each sender's strictly in nonce
+     *  order starting at the account's current nonce.
+     */
+    pub fn select(&self, state: &State, max_bytes: usize, max_txs: usize) -> Vec<Transaction> {
+        let mut heads = BinaryHeap::new();
+        for (sender, queue) in &self.senders {
+            let nonce = state.nonce(sender);
+            if let Some(id) = queue.get(&nonce) {
+                heads.push((self.entries[id].tx.fee, Reverse(*id), *sender, nonce));
+            }
+        }
+
+        let mut picked = Vec::new();
+        let mut bytes = 0;
+        while let Some((_, Reverse(id), sender, nonce)) = heads.pop() {
+            if picked.len() >= max_txs {
+                break;
+            }
+            let entry = &self.entries[&id];
+            // Too big for what is left; the rest of this sender's queue waits too
+            if bytes + entry.size > max_bytes {
+                continue;
+            }
+            bytes += entry.size;
+            picked.push(entry.tx.clone());
+
+            if let Some(next) = self.senders[&sender].get(&(nonce + 1)) {
+                heads.push((self.entries[next].tx.fee, Reverse(*next), sender, nonce + 1));
+            }
+        }
+
+        picked
+    }
+
+    /*
+     *  Drop what can no longer be included after the chain moved to `height` with
+     *  `state`: used nonces (including the transactions just committed), expired
+     *  heights, and anything older than the TTL.
+     */
+    pub fn prune(&mut self, state: &State, height: u64, now: u64) -> usize {
+        let ttl = self.ttl;
+        let stale: Vec<Hash> = self.entries.iter()
+            .filter(|(_, e)| {
+                e.tx.nonce < state.nonce(&e.tx.sender)
+                    || e.tx.is_expired(height)
+                    || now.saturating_sub(e.added) > ttl
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &stale {
+            self.remove(id);
+        }
+
+        stale.len()
+    }
+
+    /*
+     *  After a reorg, offer the transactions of blocks that left the canonical
+     *  chain back to the pool, checked against the new `state`. Ones the new
+     *  chain already contains fail the nonce check and are dropped.
+     */
+    pub fn reinject(&mut self, txs: Vec<Transaction>, state: &State, height: u64, now: u64) -> usize {
+        let mut txs = txs;
+        txs.sort_by_key(|tx| (tx.sender, tx.nonce));
+        txs.into_iter()
+            .filter(|tx| self.insert(tx.clone(), state, height, now).is_ok())
+            .count()
+    }
+}
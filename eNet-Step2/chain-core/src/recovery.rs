@@ -0,0 +1,211 @@
+use secure_sign::CRYPTO_PUBLICKEYBYTES;
+
+use crate::codec::{ Decode, DecodeError, Decoder, Encode, Encoder };
+use crate::hash::KeyId;
+use crate::identity::{ KeyUpdate, Person, PersonId, PersonStatus };
+use crate::state::{ State, StateError };
+use crate::transaction::Transaction;
+
+// Guardians one person may name
+pub const MAX_GUARDIANS: usize = 8;
+// Blocks between enough approvals and the new key taking over, in which the old key can cancel
+pub const RECOVERY_DELAY: u64 = 1_000;
+
+/*
+ *  Payload of a TxKind::SetGuardians, sent from the person's current key.
+ *  `threshold` of the `guardians` together can move the person to a new key;
+ *  an empty list with a threshold of 0 removes them. Any recovery under way
+ *  is dropped.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Guardianship {
+    pub person: PersonId,
+    pub guardians: Vec<PersonId>,
+    pub threshold: u32,
+}
+
+impl Encode for Guardianship {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.hash(&self.person.0).list(&self.guardians).u32(self.threshold);
+    }
+}
+
+impl Decode for Guardianship {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(Guardianship {
+            person: PersonId(input.hash()?),
+            guardians: input.list("guardians", MAX_GUARDIANS)?,
+            threshold: input.u32()?,
+        })
+    }
+}
+
+/*
+ *  Payload of a TxKind::Recover, sent from a guardian's current key. The new
+ *  key signs for the person and their lost key just as for a key update, so
+ *  approvals lapse once the key changes by any other means.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecoveryApproval {
+    pub guardian: PersonId,
+    pub update: KeyUpdate,
+}
+
+impl Encode for RecoveryApproval {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.hash(&self.guardian.0);
+        self.update.encode_to(out);
+    }
+}
+
+impl Decode for RecoveryApproval {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(RecoveryApproval { guardian: PersonId(input.hash()?), update: KeyUpdate::decode_from(input)? })
+    }
+}
+
+/*
+ *  Payload of a TxKind::CancelRecovery: the current key still works and
+ *  stops a recovery of the person
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecoveryCancel {
+    pub person: PersonId,
+}
+
+impl Encode for RecoveryCancel {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.hash(&self.person.0);
+    }
+}
+
+impl Decode for RecoveryCancel {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(RecoveryCancel { person: PersonId(input.hash()?) })
+    }
+}
+
+// A recovery under way, as kept on the person
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingRecovery {
+    pub public_key: Vec<u8>,
+    pub approvals: Vec<PersonId>,
+    pub opened_at: u64,
+    // First height the new key holds at, 0 until enough guardians approve
+    pub ready_at: u64,
+}
+
+impl Encode for PendingRecovery {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.bytes(&self.public_key).list(&self.approvals).u64(self.opened_at).u64(self.ready_at);
+    }
+}
+
+impl Decode for PendingRecovery {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(PendingRecovery {
+            public_key: input.bytes("public_key", CRYPTO_PUBLICKEYBYTES as usize)?,
+            approvals: input.list("approvals", MAX_GUARDIANS)?,
+            opened_at: input.u64()?,
+            ready_at: input.u64()?,
+        })
+    }
+}
+
+impl State {
+    // The person with the guardians `guardianship` names, each a live verified person other than them
+    pub(crate) fn check_guardians(&self, tx: &Transaction, guardianship: &Guardianship, height: u64) -> Result<Vec<(PersonId, Person)>, StateError> {
+        let mut person = self.sending_person(tx, &guardianship.person)?;
+        let count = guardianship.guardians.len() as u32;
+        if guardianship.threshold > count || (count > 0 && guardianship.threshold == 0) {
+            return Err(StateError::BadThreshold { threshold: guardianship.threshold, guardians: count });
+        }
+        for (i, id) in guardianship.guardians.iter().enumerate() {
+            let live = self.person(id).map(|g| self.is_live(g, height)).unwrap_or(false);
+            if !live || *id == guardianship.person || guardianship.guardians[..i].contains(id) {
+                return Err(StateError::BadGuardian(*id));
+            }
+        }
+
+        person.guardians = guardianship.guardians.clone();
+        person.guardian_threshold = guardianship.threshold;
+        person.recovery = None;
+        person.updated_at = height;
+
+        Ok(vec![(guardianship.person, person)])
+    }
+
+    /*
+     *  The person with one more guardian approving their move to a new key.
+     *  A recovery to another key is refused while the current one has enough
+     *  approvals or is younger than RECOVERY_DELAY, so one guardian cannot
+     *  keep resetting it.
+     */
+    pub(crate) fn approve_recovery(&self, tx: &Transaction, approval: &RecoveryApproval, height: u64) -> Result<Vec<(PersonId, Person)>, StateError> {
+        let guardian = self.sending_person(tx, &approval.guardian)?;
+        if !self.is_live(&guardian, height) {
+            return Err(StateError::NotVerified(approval.guardian));
+        }
+        let id = approval.update.person;
+        let mut person = self.person(&id).cloned().ok_or(StateError::UnknownPerson(id))?;
+        if person.status == PersonStatus::Revoked {
+            return Err(StateError::PersonRevoked(id));
+        }
+        if !person.guardians.contains(&approval.guardian) {
+            return Err(StateError::NotGuardian(approval.guardian));
+        }
+        if !approval.update.verify(&person.key_id()) {
+            return Err(StateError::BadKeyProof);
+        }
+        let key = KeyId::from_public_key(&approval.update.public_key);
+        if self.person_of(&key).is_some() {
+            return Err(StateError::KeyInUse(key));
+        }
+
+        let mut recovery = match person.recovery.take() {
+            Some(recovery) if recovery.public_key == approval.update.public_key => recovery,
+            Some(recovery) if recovery.ready_at != 0 || height <= recovery.opened_at.saturating_add(RECOVERY_DELAY) => {
+                return Err(StateError::RecoveryPending(id));
+            },
+            _ => PendingRecovery { public_key: approval.update.public_key.clone(), approvals: Vec::new(), opened_at: height, ready_at: 0 },
+        };
+        if recovery.approvals.contains(&approval.guardian) {
+            return Err(StateError::AlreadyApproved(approval.guardian));
+        }
+        recovery.approvals.push(approval.guardian);
+        if recovery.ready_at == 0 && recovery.approvals.len() as u32 >= person.guardian_threshold {
+            recovery.ready_at = height.checked_add(RECOVERY_DELAY).ok_or(StateError::Overflow)?;
+        }
+        person.recovery = Some(recovery);
+        person.updated_at = height;
+
+        Ok(vec![(id, person)])
+    }
+
+    pub(crate) fn cancel_recovery(&self, tx: &Transaction, cancel: &RecoveryCancel, height: u64) -> Result<Vec<(PersonId, Person)>, StateError> {
+        let mut person = self.sending_person(tx, &cancel.person)?;
+        if person.recovery.take().is_none() {
+            return Err(StateError::NoRecovery(cancel.person));
+        }
+        person.updated_at = height;
+
+        Ok(vec![(cancel.person, person)])
+    }
+
+    // Move every person whose recovery is due by `height` to their new key
+    pub(crate) fn complete_recoveries(&mut self, height: u64) {
+        let due: Vec<(PersonId, Person)> = self.due_records(height).into_iter()
+            .filter_map(|key| self.person(&PersonId(key)).map(|person| (PersonId(key), person.clone())))
+            .filter(|(_, person)| person.recovery.as_ref().map(|r| r.ready_at != 0 && r.ready_at <= height).unwrap_or(false))
+            .collect();
+        for (id, mut person) in due {
+            let public_key = person.recovery.take().map(|r| r.public_key).unwrap_or_default();
+            // Someone took the key meanwhile; the guardians have to start over
+            if self.person_of(&KeyId::from_public_key(&public_key)).is_none() {
+                person.public_key = public_key;
+            }
+            person.updated_at = height;
+            self.write_person(id, person);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::fmt::{ self, Display, Formatter };
+
+use crate::block::Block;
+use crate::certificate::{ CertError, QuorumCertificate };
+use crate::codec::{ Decode, DecodeError, Decoder, Encode, Encoder };
+use crate::hash::{ Hash, KeyId };
+use crate::state::{ State, StateChunk, MAX_CHUNK_DEPTH };
+use crate::vote::ValidatorSet;
+
+// Accounts a snapshot chunk aims to stay under
+pub const SNAPSHOT_CHUNK_ACCOUNTS: usize = 2048;
+
+#[derive(Debug, PartialEq)]
+pub enum SnapshotError {
+    // The certificate is for another block than the snapshot's
+    WrongBlock { expected: Hash, found: Hash },
+    // Listed as a validator but not among the configured keys
+    UnknownValidator(KeyId),
+    NotValidator { height: u64 },
+    Certificate(CertError),
+    TooDeep(u8),
+    // The assembled accounts do not give the block's state root
+    StateMismatch { expected: Hash, found: Hash },
+}
+
+impl Display for SnapshotError {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::WrongBlock { expected, found } => write!(f, "certificate is for {}, snapshot is of {}", found, expected),
+            SnapshotError::UnknownValidator(id) => write!(f, "validator {} is not a configured key", id),
+            SnapshotError::NotValidator { height } => write!(f, "block {} is not signed by a validator", height),
+            SnapshotError::Certificate(e) => write!(f, "certificate: {}", e),
+            SnapshotError::TooDeep(depth) => write!(f, "split into 2^{} chunks, at most 2^{} allowed", depth, MAX_CHUNK_DEPTH),
+            SnapshotError::StateMismatch { expected, found } => write!(f, "assembled state {} does not match block state {}", found, expected),
+        }
+    }
+}
+
+impl From<CertError> for SnapshotError {
+    fn from(e: CertError) -> Self {
+        SnapshotError::Certificate(e)
+    }
+}
+
+/*
+ *  Declaration of SnapshotManifest
+ *
+ *  Describes the state after a finalized block: the block, the certificate
+ *  that finalized it, the validators of its height that the certificate is
+ *  checked against, and how many prefix bits split the state into chunks.
+ *  Chunks are checked against the block's state root.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotManifest {
+    pub block: Block,
+    pub certificate: QuorumCertificate,
+    pub validators: ValidatorSet,
+    pub depth: u8,
+}
+
+impl SnapshotManifest {
+    pub fn height(&self) -> u64 {
+        self.block.header.height
+    }
+
+    pub fn state_root(&self) -> Hash {
+        self.block.header.state_root
+    }
+
+    pub fn chunk_count(&self) -> u32 {
+        1 << self.depth
+    }
+
+    /*
+     *  Check the manifest against the configured validator keys: every listed
+     *  validator must be one of `candidates`, one of them must have signed the
+     *  block, and a quorum of them must have certified it. The validators come
+     *  from the snapshot's side, so this trusts those keys, nothing more.
+     */
+    pub fn verify(&self, candidates: &[Vec<u8>], chain_id: &str) -> Result<(), SnapshotError> {
+        if self.depth > MAX_CHUNK_DEPTH {
+            return Err(SnapshotError::TooDeep(self.depth));
+        }
+        for validator in &self.validators.validators {
+            if !candidates.contains(&validator.public_key) {
+                return Err(SnapshotError::UnknownValidator(validator.id));
+            }
+        }
+        let header = &self.block.header;
+        match self.validators.get(&header.proposer) {
+            Some(validator) if header.verify_signature(&validator.public_key) => (),
+            _ => return Err(SnapshotError::NotValidator { height: header.height }),
+        }
+        let hash = self.block.hash();
+        if self.certificate.block != hash || self.certificate.height != header.height {
+            return Err(SnapshotError::WrongBlock { expected: hash, found: self.certificate.block });
+        }
+        self.certificate.verify(&self.validators, chain_id)?;
+
+        Ok(())
+    }
+
+    // Put checked chunks back together into the state they were cut from
+    pub fn assemble(&self, chunks: &[StateChunk]) -> Result<State, SnapshotError> {
+        let state = State::from_chunks(chunks);
+        let root = state.root();
+        if root != self.state_root() {
+            return Err(SnapshotError::StateMismatch { expected: self.state_root(), found: root });
+        }
+
+        Ok(state)
+    }
+}
+
+impl Encode for SnapshotManifest {
+    fn encode_to(&self, out: &mut Encoder) {
+        self.block.encode_to(out);
+        self.certificate.encode_to(out);
+        self.validators.encode_to(out);
+        out.u8(self.depth);
+    }
+}
+
+impl Decode for SnapshotManifest {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(SnapshotManifest {
+            block: Block::decode_from(input)?,
+            certificate: QuorumCertificate::decode_from(input)?,
+            validators: ValidatorSet::decode_from(input)?,
+            depth: input.u8()?,
+        })
+    }
+}
+
+/*
+ *  Declaration of Snapshot
+ *
+ *  A manifest with all of its chunks, as a node takes it from its own chain.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub manifest: SnapshotManifest,
+    pub chunks: Vec<StateChunk>,
+}
+
+impl Snapshot {
+    // Cut `state`, the state after `block`, into chunks of about `chunk_accounts` accounts
+    pub fn new(block: Block, certificate: QuorumCertificate, validators: ValidatorSet, state: &State, chunk_accounts: usize) -> Self {
+        let depth = state.chunk_depth(chunk_accounts);
+        Snapshot {
+            manifest: SnapshotManifest { block, certificate, validators, depth },
+            chunks: state.chunks(depth),
+        }
+    }
+}
@@ -0,0 +1,1153 @@
+use std::collections::{ BTreeMap, BTreeSet, HashMap };
+use std::fmt::{ self, Display, Formatter };
+
+use crate::block::Block;
+use crate::codec::{ Decode, DecodeError, Decoder, Encode, Encoder };
+use crate::dispute::Dispute;
+use crate::hash::{ self, hash_with_domain, Hash, KeyId, DOMAIN_STATE_LEAF, DOMAIN_STATE_NODE, DOMAIN_STATE_PARAMS, DOMAIN_STATE_RECORD, DOMAIN_STATE_VALUE, HASH_BYTES };
+use crate::identity::{ IdentityAction, Person, PersonId, PersonStatus, MAX_VOUCHES };
+use crate::liveness::DEFAULT_LIVENESS_EPOCH;
+use crate::transaction::{ Transaction, Transfer, TxKind };
+
+/*
+ *  Declaration of IdentityStatus
+ *
+ *  Where an account stands in the proof-of-person registry.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdentityStatus {
+    Unverified,
+    Verified,
+    Revoked,
+}
+
+impl IdentityStatus {
+    pub fn tag(self) -> u8 {
+        match self {
+            IdentityStatus::Unverified => 0,
+            IdentityStatus::Verified => 1,
+            IdentityStatus::Revoked => 2,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<IdentityStatus> {
+        match tag {
+            0 => Some(IdentityStatus::Unverified),
+            1 => Some(IdentityStatus::Verified),
+            2 => Some(IdentityStatus::Revoked),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Account {
+    pub balance: u64,
+    // Nonce the next transaction from this account must carry
+    pub nonce: u64,
+    pub identity: IdentityStatus,
+}
+
+impl Default for Account {
+    fn default() -> Self {
+        Account {
+            balance: 0,
+            nonce: 0,
+            identity: IdentityStatus::Unverified,
+        }
+    }
+}
+
+impl Encode for Account {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.u64(self.balance).u64(self.nonce).u8(self.identity.tag());
+    }
+}
+
+impl Decode for Account {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        let balance = input.u64()?;
+        let nonce = input.u64()?;
+        let tag = input.u8()?;
+        Ok(Account {
+            balance,
+            nonce,
+            identity: IdentityStatus::from_tag(tag).ok_or(DecodeError::UnknownKind(tag))?,
+        })
+    }
+}
+
+/*
+ *  Protocol parameters state transitions depend on. Kept as a record so
+ *  snapshots carry them; a state without one uses the defaults.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateParams {
+    // Blocks a liveness claim or a verification lasts for
+    pub liveness_epoch: u64,
+}
+
+impl Default for StateParams {
+    fn default() -> Self {
+        StateParams { liveness_epoch: DEFAULT_LIVENESS_EPOCH }
+    }
+}
+
+impl Encode for StateParams {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.u64(self.liveness_epoch);
+    }
+}
+
+impl Decode for StateParams {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(StateParams { liveness_epoch: input.u64()? })
+    }
+}
+
+/*
+ *  Declaration of Record
+ *
+ *  Registry entries kept in the state tree next to the accounts. Their keys
+ *  come from other hash domains than key IDs, so they never meet an account.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    Person(Person),
+    Dispute(Dispute),
+    Params(StateParams),
+}
+
+impl Encode for Record {
+    fn encode_to(&self, out: &mut Encoder) {
+        match self {
+            Record::Person(person) => {
+                out.u8(0);
+                person.encode_to(out);
+            },
+            Record::Dispute(dispute) => {
+                out.u8(1);
+                dispute.encode_to(out);
+            },
+            Record::Params(params) => {
+                out.u8(2);
+                params.encode_to(out);
+            },
+        }
+    }
+}
+
+impl Decode for Record {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        match input.u8()? {
+            0 => Ok(Record::Person(Person::decode_from(input)?)),
+            1 => Ok(Record::Dispute(Dispute::decode_from(input)?)),
+            2 => Ok(Record::Params(StateParams::decode_from(input)?)),
+            tag => Err(DecodeError::UnknownKind(tag)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StateError {
+    Expired { expiry_height: u64, height: u64 },
+    BadNonce { expected: u64, found: u64 },
+    InsufficientBalance { needed: u64, available: u64 },
+    Overflow,
+    InvalidPayload(DecodeError),
+    StateRootMismatch { expected: Hash, computed: Hash },
+    // The sender's key, or the person it would register, is already in the registry
+    AlreadyRegistered(PersonId),
+    UnknownPerson(PersonId),
+    // The sender is not the person's current key
+    NotPersonKey(PersonId),
+    KeyInUse(KeyId),
+    PersonRevoked(PersonId),
+    // Only verified persons may attest
+    NotVerified(PersonId),
+    // Attestations are only taken for pending persons
+    NotPending(PersonId),
+    AlreadyAttested(PersonId),
+    VouchLimit(PersonId),
+    // A challenge naming one entry twice
+    SamePerson,
+    BondTooLow { bond: u64, min: u64 },
+    DisputeOpen(Hash),
+    NoJurors,
+    UnknownDispute(Hash),
+    // Past the dispute's deadline
+    DisputeClosed(Hash),
+    NotJuror(PersonId),
+    AlreadyVoted(PersonId),
+    UnderDispute(PersonId),
+    // A lapsed person's liveness claim without a verifier
+    NeedsVerifier(PersonId),
+    BadCosign(PersonId),
+    BadThreshold { threshold: u32, guardians: u32 },
+    BadGuardian(PersonId),
+    NotGuardian(PersonId),
+    // The new key did not sign for the person and their current key
+    BadKeyProof,
+    AlreadyApproved(PersonId),
+    // Another recovery of the person is under way
+    RecoveryPending(PersonId),
+    NoRecovery(PersonId),
+    BiometricSet(PersonId),
+    BadBands { found: usize, expected: usize },
+}
+
+impl Display for StateError {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            StateError::Expired { expiry_height, height } => write!(f, "expired at height {}, now {}", expiry_height, height),
+            StateError::BadNonce { expected, found } => write!(f, "expected nonce {}, found {}", expected, found),
+            StateError::InsufficientBalance { needed, available } => write!(f, "needs {}, balance is {}", needed, available),
+            StateError::Overflow => write!(f, "balance overflow"),
+            StateError::InvalidPayload(e) => write!(f, "invalid payload: {}", e),
+            StateError::StateRootMismatch { expected, computed } => write!(f, "state root {} does not match header {}", computed, expected),
+            StateError::AlreadyRegistered(id) => write!(f, "person {} is already registered", id),
+            StateError::UnknownPerson(id) => write!(f, "no person {} in the registry", id),
+            StateError::NotPersonKey(id) => write!(f, "sender is not the key of person {}", id),
+            StateError::KeyInUse(key) => write!(f, "key {} belongs to a person already", key),
+            StateError::PersonRevoked(id) => write!(f, "person {} is revoked", id),
+            StateError::NotVerified(id) => write!(f, "person {} is not verified", id),
+            StateError::NotPending(id) => write!(f, "person {} is not pending verification", id),
+            StateError::AlreadyAttested(id) => write!(f, "person {} already attested for this subject", id),
+            StateError::VouchLimit(id) => write!(f, "person {} has vouched for {} people already", id, MAX_VOUCHES),
+            StateError::SamePerson => write!(f, "a challenge needs two different persons"),
+            StateError::BondTooLow { bond, min } => write!(f, "bond of {} is below the minimum of {}", bond, min),
+            StateError::DisputeOpen(id) => write!(f, "dispute {} over these persons is still open", id),
+            StateError::NoJurors => write!(f, "no verified persons left to sit on the jury"),
+            StateError::UnknownDispute(id) => write!(f, "no open dispute {}", id),
+            StateError::DisputeClosed(id) => write!(f, "dispute {} no longer takes ballots", id),
+            StateError::NotJuror(id) => write!(f, "person {} is not on the jury", id),
+            StateError::AlreadyVoted(id) => write!(f, "person {} has voted already", id),
+            StateError::UnderDispute(id) => write!(f, "person {} is under dispute", id),
+            StateError::NeedsVerifier(id) => write!(f, "person {} has lapsed and needs a verifier to co-sign", id),
+            StateError::BadCosign(id) => write!(f, "co-signature of verifier {} does not verify", id),
+            StateError::BadThreshold { threshold, guardians } => write!(f, "threshold of {} does not suit {} guardians", threshold, guardians),
+            StateError::BadGuardian(id) => write!(f, "person {} cannot be a guardian", id),
+            StateError::NotGuardian(id) => write!(f, "person {} is not a guardian", id),
+            StateError::BadKeyProof => write!(f, "new key did not sign for the move"),
+            StateError::AlreadyApproved(id) => write!(f, "guardian {} has approved already", id),
+            StateError::RecoveryPending(id) => write!(f, "another recovery of person {} is under way", id),
+            StateError::NoRecovery(id) => write!(f, "no recovery of person {} is under way", id),
+            StateError::BiometricSet(id) => write!(f, "person {} has a biometric commitment already", id),
+            StateError::BadBands { found, expected } => write!(f, "commitment has {} bands, expected {}", found, expected),
+        }
+    }
+}
+
+/*
+ *  Declaration of StateProof
+ *
+ *  Siblings from the root down to where the key's path ends. The path ends on
+ *  the key's own leaf (inclusion), on an empty subtree, or on the single leaf of
+ *  another key sharing the prefix (both exclusion).
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateProof {
+    pub siblings: Vec<Hash>,
+    pub leaf: Option<(Hash, Hash)>,
+}
+
+impl StateProof {
+    // Check that `key` maps to `account` (or to nothing) under `root`
+    pub fn verify(&self, root: &Hash, key: &Hash, account: Option<&Account>) -> bool {
+        self.verify_value(root, key, account.map(value_hash))
+    }
+
+    pub fn verify_record(&self, root: &Hash, key: &Hash, record: Option<&Record>) -> bool {
+        self.verify_value(root, key, record.map(record_hash))
+    }
+
+    fn verify_value(&self, root: &Hash, key: &Hash, expected: Option<Hash>) -> bool {
+        if self.siblings.len() > HASH_BYTES * 8 {
+            return false;
+        }
+        let bottom = match (expected, &self.leaf) {
+            (Some(expected), Some((leaf_key, value))) => {
+                if leaf_key != key || *value != expected {
+                    return false;
+                }
+                leaf_hash(key, value)
+            },
+            (None, Some((leaf_key, value))) => {
+                // Another key's leaf can only stand here if it shares the path so far
+                if leaf_key == key || !(0..self.siblings.len()).all(|d| bit(leaf_key, d) == bit(key, d)) {
+                    return false;
+                }
+                leaf_hash(leaf_key, value)
+            },
+            (None, None) => Hash::ZERO,
+            (Some(_), None) => return false,
+        };
+
+        let mut node = bottom;
+        for depth in (0..self.siblings.len()).rev() {
+            node = match bit(key, depth) {
+                false => node_hash(&node, &self.siblings[depth]),
+                true => node_hash(&self.siblings[depth], &node),
+            };
+        }
+
+        node == *root
+    }
+}
+
+impl Encode for StateProof {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.u32(self.siblings.len() as u32);
+        for sibling in &self.siblings {
+            out.hash(sibling);
+        }
+        match &self.leaf {
+            Some((key, value)) => out.u8(1).hash(key).hash(value),
+            None => out.u8(0),
+        };
+    }
+}
+
+impl Decode for StateProof {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        let count = input.u32()? as usize;
+        if count > HASH_BYTES * 8 {
+            return Err(DecodeError::TooLong { field: "siblings", len: count, max: HASH_BYTES * 8 });
+        }
+        let mut siblings = Vec::with_capacity(count);
+        for _ in 0..count {
+            siblings.push(input.hash()?);
+        }
+        let leaf = match input.u8()? {
+            0 => None,
+            1 => Some((input.hash()?, input.hash()?)),
+            tag => return Err(DecodeError::UnknownKind(tag)),
+        };
+
+        Ok(StateProof { siblings, leaf })
+    }
+}
+
+// Deepest split of the key space into snapshot chunks
+pub const MAX_CHUNK_DEPTH: u8 = 20;
+// Decoding bound on the accounts in one chunk
+pub const MAX_CHUNK_ACCOUNTS: usize = 1 << 16;
+
+/*
+ *  Declaration of ChunkSibling
+ *
+ *  What a chunk proof says about the other half at one level. A subtree with a
+ *  single account collapses to its leaf wherever it sits, so a proof has to
+ *  tell that apart from a larger subtree; a single leaf is given as its key and
+ *  value hash, which also pins down where it may be.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChunkSibling {
+    Empty,
+    Leaf(Hash, Hash),
+    Node(Hash),
+}
+
+impl Encode for ChunkSibling {
+    fn encode_to(&self, out: &mut Encoder) {
+        match self {
+            ChunkSibling::Empty => out.u8(0),
+            ChunkSibling::Leaf(key, value) => out.u8(1).hash(key).hash(value),
+            ChunkSibling::Node(hash) => out.u8(2).hash(hash),
+        };
+    }
+}
+
+impl Decode for ChunkSibling {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        match input.u8()? {
+            0 => Ok(ChunkSibling::Empty),
+            1 => Ok(ChunkSibling::Leaf(input.hash()?, input.hash()?)),
+            2 => Ok(ChunkSibling::Node(input.hash()?)),
+            tag => Err(DecodeError::UnknownKind(tag)),
+        }
+    }
+}
+
+// A subtree as seen from above: nothing, one leaf standing in for it, or a branch
+enum Subtree {
+    Empty,
+    One(Hash),
+    Many(Hash),
+}
+
+impl Subtree {
+    fn hash(&self) -> Hash {
+        match self {
+            Subtree::Empty => Hash::ZERO,
+            Subtree::One(hash) | Subtree::Many(hash) => *hash,
+        }
+    }
+}
+
+/*
+ *  Declaration of StateChunk
+ *
+ *  The accounts and records whose keys start with the `depth` bits of `index`,
+ *  and the siblings from the root down to that subtree (root first). Checking
+ *  it against a state root proves the chunk holds exactly those entries, so
+ *  chunks from different peers can be checked one by one.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateChunk {
+    pub depth: u8,
+    pub index: u32,
+    pub accounts: Vec<(KeyId, Account)>,
+    pub records: Vec<(Hash, Record)>,
+    pub proof: Vec<ChunkSibling>,
+}
+
+impl StateChunk {
+    pub fn verify(&self, root: &Hash) -> bool {
+        let depth = self.depth as usize;
+        if self.depth > MAX_CHUNK_DEPTH || self.proof.len() != depth || (self.index as u64) >> depth != 0 {
+            return false;
+        }
+        let mut leaves: Vec<(Hash, Hash)> = self.accounts.iter().map(|(id, account)| (id.0, value_hash(account)))
+            .chain(self.records.iter().map(|(key, record)| (*key, record_hash(record))))
+            .collect();
+        leaves.sort_by_key(|leaf| leaf.0);
+        let keys_sorted = self.accounts.windows(2).all(|pair| pair[0].0 < pair[1].0)
+            && self.records.windows(2).all(|pair| pair[0].0 < pair[1].0)
+            && leaves.windows(2).all(|pair| pair[0].0 != pair[1].0);
+        if !keys_sorted || !leaves.iter().all(|(key, _)| prefix(key, depth) == self.index) {
+            return false;
+        }
+
+        let mut node = match leaves.len() {
+            0 => Subtree::Empty,
+            1 => Subtree::One(leaf_hash(&leaves[0].0, &leaves[0].1)),
+            _ => Subtree::Many(subtree_root(&leaves, depth)),
+        };
+        for level in (0..depth).rev() {
+            let right = (self.index >> (depth - level - 1)) & 1 == 1;
+            let sibling = match &self.proof[level] {
+                ChunkSibling::Empty => Subtree::Empty,
+                ChunkSibling::Leaf(key, value) => {
+                    if prefix(key, level + 1) != (self.index >> (depth - level - 1)) ^ 1 {
+                        return false;
+                    }
+                    Subtree::One(leaf_hash(key, value))
+                },
+                ChunkSibling::Node(hash) => Subtree::Many(*hash),
+            };
+            node = match (node, sibling) {
+                (Subtree::Empty, Subtree::Empty) => Subtree::Empty,
+                (Subtree::One(hash), Subtree::Empty) | (Subtree::Empty, Subtree::One(hash)) => Subtree::One(hash),
+                (node, sibling) => match right {
+                    false => Subtree::Many(node_hash(&node.hash(), &sibling.hash())),
+                    true => Subtree::Many(node_hash(&sibling.hash(), &node.hash())),
+                },
+            };
+        }
+
+        node.hash() == *root
+    }
+}
+
+impl Encode for StateChunk {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.u8(self.depth).u32(self.index).u32(self.accounts.len() as u32);
+        for (id, account) in &self.accounts {
+            out.key_id(id);
+            account.encode_to(out);
+        }
+        out.u32(self.records.len() as u32);
+        for (key, record) in &self.records {
+            out.hash(key);
+            record.encode_to(out);
+        }
+        out.list(&self.proof);
+    }
+}
+
+impl Decode for StateChunk {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        let depth = input.u8()?;
+        let index = input.u32()?;
+        let count = input.u32()? as usize;
+        if count > MAX_CHUNK_ACCOUNTS {
+            return Err(DecodeError::TooLong { field: "accounts", len: count, max: MAX_CHUNK_ACCOUNTS });
+        }
+        let mut accounts = Vec::with_capacity(count.min(1024));
+        for _ in 0..count {
+            accounts.push((input.key_id()?, Account::decode_from(input)?));
+        }
+        let count = input.u32()? as usize;
+        if count > MAX_CHUNK_ACCOUNTS {
+            return Err(DecodeError::TooLong { field: "records", len: count, max: MAX_CHUNK_ACCOUNTS });
+        }
+        let mut records = Vec::with_capacity(count.min(1024));
+        for _ in 0..count {
+            records.push((input.hash()?, Record::decode_from(input)?));
+        }
+
+        Ok(StateChunk { depth, index, accounts, records, proof: input.list("proof", MAX_CHUNK_DEPTH as usize)? })
+    }
+}
+
+/*
+ *  Declaration of TrieNode
+ *
+ *  Stored form of the tree: a branch names its two children by hash (zero for
+ *  an empty side), a leaf carries the account or record itself. Nodes are
+ *  addressed by their hash, so the tree behind any root can be reloaded and
+ *  checked.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrieNode {
+    Leaf(Hash, Account),
+    Branch(Hash, Hash),
+    Record(Hash, Box<Record>),
+}
+
+impl TrieNode {
+    pub fn hash(&self) -> Hash {
+        match self {
+            TrieNode::Leaf(key, account) => leaf_hash(key, &value_hash(account)),
+            TrieNode::Branch(left, right) => node_hash(left, right),
+            TrieNode::Record(key, record) => leaf_hash(key, &record_hash(record)),
+        }
+    }
+}
+
+impl Encode for TrieNode {
+    fn encode_to(&self, out: &mut Encoder) {
+        match self {
+            TrieNode::Leaf(key, account) => {
+                out.u8(0).hash(key);
+                account.encode_to(out);
+            },
+            TrieNode::Branch(left, right) => {
+                out.u8(1).hash(left).hash(right);
+            },
+            TrieNode::Record(key, record) => {
+                out.u8(2).hash(key);
+                record.encode_to(out);
+            },
+        }
+    }
+}
+
+impl Decode for TrieNode {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        match input.u8()? {
+            0 => Ok(TrieNode::Leaf(input.hash()?, Account::decode_from(input)?)),
+            1 => Ok(TrieNode::Branch(input.hash()?, input.hash()?)),
+            2 => Ok(TrieNode::Record(input.hash()?, Box::new(Record::decode_from(input)?))),
+            tag => Err(DecodeError::UnknownKind(tag)),
+        }
+    }
+}
+
+fn bit(key: &Hash, depth: usize) -> bool {
+    (key.0[depth / 8] >> (7 - depth % 8)) & 1 == 1
+}
+
+// The first `bits` bits of `key` as a number
+fn prefix(key: &Hash, bits: usize) -> u32 {
+    (0..bits).fold(0, |acc, depth| (acc << 1) | bit(key, depth) as u32)
+}
+
+// How a subtree of sorted `leaves` below `depth` bits appears in a chunk proof
+fn summarize(leaves: &[(Hash, Hash)], depth: usize) -> ChunkSibling {
+    match leaves.len() {
+        0 => ChunkSibling::Empty,
+        1 => ChunkSibling::Leaf(leaves[0].0, leaves[0].1),
+        _ => ChunkSibling::Node(subtree_root(leaves, depth)),
+    }
+}
+
+// The parent of two sibling subtrees; a lone leaf moves up in place of its parent
+fn combine(left: &ChunkSibling, right: &ChunkSibling) -> ChunkSibling {
+    let hash = |side: &ChunkSibling| match side {
+        ChunkSibling::Empty => Hash::ZERO,
+        ChunkSibling::Leaf(key, value) => leaf_hash(key, value),
+        ChunkSibling::Node(hash) => *hash,
+    };
+    match (left, right) {
+        (ChunkSibling::Empty, ChunkSibling::Empty) => ChunkSibling::Empty,
+        (ChunkSibling::Leaf(..), ChunkSibling::Empty) => left.clone(),
+        (ChunkSibling::Empty, ChunkSibling::Leaf(..)) => right.clone(),
+        _ => ChunkSibling::Node(node_hash(&hash(left), &hash(right))),
+    }
+}
+
+fn value_hash(account: &Account) -> Hash {
+    hash::hash_with_domain(DOMAIN_STATE_VALUE, &account.encode())
+}
+
+fn record_hash(record: &Record) -> Hash {
+    hash::hash_with_domain(DOMAIN_STATE_RECORD, &record.encode())
+}
+
+// Heights at which the chain itself has to act on `record`
+fn due_heights(record: &Record) -> Vec<u64> {
+    let mut heights = Vec::new();
+    match record {
+        Record::Person(person) => {
+            if person.status == PersonStatus::Verified {
+                heights.extend(person.live_until.checked_add(1));
+            }
+            if let Some(recovery) = person.recovery.as_ref().filter(|r| r.ready_at != 0) {
+                heights.push(recovery.ready_at);
+            }
+        },
+        Record::Dispute(dispute) => {
+            if dispute.jurors.is_empty() {
+                heights.extend(dispute.opened_at.checked_add(1));
+            }
+            heights.extend(dispute.deadline.checked_add(1));
+        },
+        Record::Params(_) => (),
+    }
+    heights
+}
+
+fn leaf_hash(key: &Hash, value: &Hash) -> Hash {
+    let mut data = [0u8; HASH_BYTES * 2];
+    data[..HASH_BYTES].copy_from_slice(key.as_bytes());
+    data[HASH_BYTES..].copy_from_slice(value.as_bytes());
+    hash::hash_with_domain(DOMAIN_STATE_LEAF, &data)
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut data = [0u8; HASH_BYTES * 2];
+    data[..HASH_BYTES].copy_from_slice(left.as_bytes());
+    data[HASH_BYTES..].copy_from_slice(right.as_bytes());
+    hash::hash_with_domain(DOMAIN_STATE_NODE, &data)
+}
+
+// Lowest and highest key sharing the first `depth` bits of `key`
+fn subtree_bounds(key: &Hash, depth: usize) -> (Hash, Hash) {
+    let (mut low, mut high) = (*key, *key);
+    for i in depth..HASH_BYTES * 8 {
+        low.0[i / 8] &= !(0x80 >> (i % 8));
+        high.0[i / 8] |= 0x80 >> (i % 8);
+    }
+    (low, high)
+}
+
+/*
+ *  Declaration of Class State
+ *
+ *  Accounts keyed by KeyId and registry records keyed by their own IDs,
+ *  committed to by one sparse Merkle tree over the 256 bits of the key. An empty subtree
+ *  hashes to zero and a subtree holding one entry collapses to that entry's
+ *  leaf, so the root only costs work in proportion to the entries present.
+ *  The hashes of subtrees holding two entries or more are kept, and a write
+ *  only rehashes the ones on its own key's path.
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct State {
+    accounts: BTreeMap<Hash, Account>,
+    records: BTreeMap<Hash, Record>,
+    // Current key of each person back to the person, not committed to
+    person_keys: BTreeMap<KeyId, PersonId>,
+    // Record keys by the height they fall due at, not committed to
+    due: BTreeSet<(u64, Hash)>,
+    // Value hash of every entry, and node hash by depth and lowest key of the subtree
+    values: BTreeMap<Hash, Hash>,
+    nodes: HashMap<(u16, Hash), Hash>,
+    // Set while a batch is applied
+    journal: Option<Journal>,
+}
+
+impl State {
+    pub fn new() -> Self {
+        State::default()
+    }
+
+    pub fn account(&self, id: &KeyId) -> Option<&Account> {
+        self.accounts.get(&id.0)
+    }
+
+    pub fn balance(&self, id: &KeyId) -> u64 {
+        self.account(id).map(|a| a.balance).unwrap_or(0)
+    }
+
+    pub fn nonce(&self, id: &KeyId) -> u64 {
+        self.account(id).map(|a| a.nonce).unwrap_or(0)
+    }
+
+    pub fn set_account(&mut self, id: KeyId, account: Account) {
+        if let Some(journal) = &mut self.journal {
+            let accounts = &self.accounts;
+            journal.accounts.entry(id.0).or_insert_with(|| accounts.get(&id.0).cloned());
+        }
+        self.set_value(id.0, Some(value_hash(&account)));
+        self.accounts.insert(id.0, account);
+    }
+
+    pub fn person(&self, id: &PersonId) -> Option<&Person> {
+        match self.records.get(&id.0) {
+            Some(Record::Person(person)) => Some(person),
+            _ => None,
+        }
+    }
+
+    // The person whose current key is `key`
+    pub fn person_of(&self, key: &KeyId) -> Option<PersonId> {
+        self.person_keys.get(key).copied()
+    }
+
+    pub fn set_person(&mut self, id: PersonId, person: Person) {
+        self.put_record(id.0, Some(Record::Person(person)));
+    }
+
+    pub fn persons(&self) -> impl Iterator<Item = (PersonId, &Person)> {
+        self.records.iter().filter_map(|(k, r)| match r {
+            Record::Person(person) => Some((PersonId(*k), person)),
+            _ => None,
+        })
+    }
+
+    pub fn dispute(&self, id: &Hash) -> Option<&Dispute> {
+        match self.records.get(id) {
+            Some(Record::Dispute(dispute)) => Some(dispute),
+            _ => None,
+        }
+    }
+
+    pub fn disputes(&self) -> impl Iterator<Item = (Hash, &Dispute)> {
+        self.records.iter().filter_map(|(k, r)| match r {
+            Record::Dispute(dispute) => Some((*k, dispute)),
+            _ => None,
+        })
+    }
+
+    pub fn set_dispute(&mut self, id: Hash, dispute: Option<Dispute>) {
+        self.put_record(id, dispute.map(Record::Dispute));
+    }
+
+    pub fn params(&self) -> StateParams {
+        match self.records.get(&State::params_key()) {
+            Some(Record::Params(params)) => params.clone(),
+            _ => StateParams::default(),
+        }
+    }
+
+    pub fn set_params(&mut self, params: StateParams) {
+        self.put_record(State::params_key(), Some(Record::Params(params)));
+    }
+
+    fn params_key() -> Hash {
+        hash_with_domain(DOMAIN_STATE_PARAMS, &[])
+    }
+
+    pub fn record(&self, key: &Hash) -> Option<&Record> {
+        self.records.get(key)
+    }
+
+    // Accounts held; records are counted apart
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty() && self.records.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (KeyId, &Account)> {
+        self.accounts.iter().map(|(k, a)| (KeyId(*k), a))
+    }
+
+    pub fn root(&self) -> Hash {
+        self.node(&Hash::ZERO, 0)
+    }
+
+    // Every node of the current tree with its hash, the root's node last
+    pub fn trie_nodes(&self) -> Vec<(Hash, TrieNode)> {
+        let mut nodes = Vec::new();
+        self.collect_nodes(&self.leaves(), 0, &mut nodes);
+        nodes
+    }
+
+    /*
+     *  Rebuild the state committed to by `root`, fetching nodes through `lookup`.
+     *  Returns None when a node is missing or does not hash to the name it was
+     *  fetched by.
+     */
+    pub fn from_trie<E, F>(root: &Hash, mut lookup: F) -> Result<Option<State>, E>
+        where F: FnMut(&Hash) -> Result<Option<TrieNode>, E>
+    {
+        let mut state = State::new();
+        let mut pending = vec![*root];
+        while let Some(hash) = pending.pop() {
+            if hash == Hash::ZERO {
+                continue;
+            }
+            let node = match lookup(&hash)? {
+                Some(node) if node.hash() == hash => node,
+                _ => return Ok(None),
+            };
+            match node {
+                TrieNode::Leaf(key, account) => {
+                    state.set_account(KeyId(key), account);
+                },
+                TrieNode::Branch(left, right) => {
+                    pending.push(left);
+                    pending.push(right);
+                },
+                TrieNode::Record(key, record) => {
+                    state.put_record(key, Some(*record));
+                },
+            }
+        }
+
+        // Leaves in the wrong place would give a different root
+        match state.root() == *root {
+            true => Ok(Some(state)),
+            false => Ok(None),
+        }
+    }
+
+    pub fn prove(&self, id: &KeyId) -> StateProof {
+        self.prove_key(&id.0)
+    }
+
+    // Proof for the record under `key`, or for there being none
+    pub fn prove_record(&self, key: &Hash) -> StateProof {
+        self.prove_key(key)
+    }
+
+    fn prove_key(&self, key: &Hash) -> StateProof {
+        let leaves = self.leaves();
+
+        let mut siblings = Vec::new();
+        let mut slice = &leaves[..];
+        let mut depth = 0;
+        while slice.len() > 1 {
+            let split = slice.partition_point(|(k, _)| !bit(k, depth));
+            let (left, right) = slice.split_at(split);
+            match bit(key, depth) {
+                false => {
+                    siblings.push(subtree_root(right, depth + 1));
+                    slice = left;
+                },
+                true => {
+                    siblings.push(subtree_root(left, depth + 1));
+                    slice = right;
+                },
+            }
+            depth += 1;
+        }
+
+        StateProof { siblings, leaf: slice.first().copied() }
+    }
+
+    // Fewest prefix bits that leave no chunk with more than `target` entries
+    pub fn chunk_depth(&self, target: usize) -> u8 {
+        let keys: Vec<Hash> = self.leaves().into_iter().map(|(k, _)| k).collect();
+        let mut depth = 0;
+        while depth < MAX_CHUNK_DEPTH {
+            let mut largest = 0;
+            let mut start = 0;
+            while start < keys.len() {
+                let first = prefix(&keys[start], depth as usize);
+                let len = keys[start..].partition_point(|k| prefix(k, depth as usize) == first);
+                largest = largest.max(len);
+                start += len;
+            }
+            if largest <= target.max(1) {
+                break;
+            }
+            depth += 1;
+        }
+
+        depth
+    }
+
+    /*
+     *  The state cut into the 2^depth subtrees under each prefix of `depth`
+     *  bits, in prefix order, each with its proof. Every subtree on the way is
+     *  summarized once, bottom up, and proofs are read off that.
+     */
+    pub fn chunks(&self, depth: u8) -> Vec<StateChunk> {
+        let leaves = self.leaves();
+        let bits = depth as usize;
+        let mut bounds = vec![0; (1 << bits) + 1];
+        for index in 0..(1usize << bits) {
+            bounds[index + 1] = leaves.partition_point(|(k, _)| prefix(k, bits) as usize <= index);
+        }
+
+        // levels[l] summarizes the 2^l subtrees under each prefix of l bits
+        let mut levels = vec![(0..(1usize << bits)).map(|i| summarize(&leaves[bounds[i]..bounds[i + 1]], bits)).collect::<Vec<_>>()];
+        for _ in 0..bits {
+            let above = levels.last().unwrap().chunks(2).map(|pair| combine(&pair[0], &pair[1])).collect();
+            levels.push(above);
+        }
+        levels.reverse();
+
+        (0..(1u32 << depth)).map(|index| {
+            let proof = (0..bits).map(|level| levels[level + 1][((index >> (bits - level - 1)) ^ 1) as usize].clone()).collect();
+            let mut accounts = Vec::new();
+            let mut records = Vec::new();
+            for (key, _) in &leaves[bounds[index as usize]..bounds[index as usize + 1]] {
+                match self.accounts.get(key) {
+                    Some(account) => accounts.push((KeyId(*key), account.clone())),
+                    None => records.push((*key, self.records[key].clone())),
+                }
+            }
+            StateChunk { depth, index, accounts, records, proof }
+        }).collect()
+    }
+
+    // The state holding the entries of `chunks`; compare its root before trusting it
+    pub fn from_chunks(chunks: &[StateChunk]) -> State {
+        let mut state = State::new();
+        for chunk in chunks {
+            for (id, account) in &chunk.accounts {
+                state.set_account(*id, account.clone());
+            }
+            for (key, record) in &chunk.records {
+                state.put_record(*key, Some(record.clone()));
+            }
+        }
+
+        state
+    }
+
+    // Key and value hash of every entry, in key order
+    fn leaves(&self) -> Vec<(Hash, Hash)> {
+        self.values.iter().map(|(k, v)| (*k, *v)).collect()
+    }
+
+    // Hash of the subtree under the first `depth` bits of `key`
+    fn node(&self, key: &Hash, depth: usize) -> Hash {
+        let (low, high) = subtree_bounds(key, depth);
+        let mut entries = self.values.range(low..=high);
+        match (entries.next(), entries.next()) {
+            (None, _) => Hash::ZERO,
+            (Some((key, value)), None) => leaf_hash(key, value),
+            _ => self.nodes[&(depth as u16, low)],
+        }
+    }
+
+    // Set or remove the value hash under `key` and rehash the nodes on its path
+    fn set_value(&mut self, key: Hash, value: Option<Hash>) {
+        match value {
+            Some(value) => self.values.insert(key, value),
+            None => self.values.remove(&key),
+        };
+
+        // Below `depth` the path holds at most this one entry
+        let mut depth = 0;
+        loop {
+            let (low, high) = subtree_bounds(&key, depth);
+            if self.values.range(low..=high).nth(1).is_none() {
+                break;
+            }
+            depth += 1;
+        }
+        let mut stale = depth;
+        while self.nodes.remove(&(stale as u16, subtree_bounds(&key, stale).0)).is_some() {
+            stale += 1;
+        }
+        for depth in (0..depth).rev() {
+            let (low, high) = subtree_bounds(&key, depth);
+            let (right, _) = subtree_bounds(&high, depth + 1);
+            let hash = node_hash(&self.node(&low, depth + 1), &self.node(&right, depth + 1));
+            self.nodes.insert((depth as u16, low), hash);
+        }
+    }
+
+    fn collect_nodes(&self, leaves: &[(Hash, Hash)], depth: usize, out: &mut Vec<(Hash, TrieNode)>) -> Hash {
+        let node = match leaves.len() {
+            0 => return Hash::ZERO,
+            1 => {
+                let key = leaves[0].0;
+                match self.accounts.get(&key) {
+                    Some(account) => TrieNode::Leaf(key, account.clone()),
+                    None => TrieNode::Record(key, Box::new(self.records[&key].clone())),
+                }
+            },
+            _ => {
+                let split = leaves.partition_point(|(k, _)| !bit(k, depth));
+                let (left, right) = leaves.split_at(split);
+                TrieNode::Branch(self.collect_nodes(left, depth + 1, out), self.collect_nodes(right, depth + 1, out))
+            },
+        };
+        let hash = node.hash();
+        out.push((hash, node));
+        hash
+    }
+
+    // Keys of the records due at `height` or before, each once
+    pub(crate) fn due_records(&self, height: u64) -> Vec<Hash> {
+        let keys: BTreeSet<Hash> = self.due.range(..=(height, Hash([0xff; HASH_BYTES]))).map(|(_, key)| *key).collect();
+        keys.into_iter().collect()
+    }
+
+    // Set or remove a record, keeping the key and due indexes in step
+    fn put_record(&mut self, key: Hash, record: Option<Record>) {
+        if let Some(journal) = &mut self.journal {
+            let records = &self.records;
+            journal.records.entry(key).or_insert_with(|| records.get(&key).cloned());
+        }
+        if let Some(old) = self.records.get(&key) {
+            if let Record::Person(person) = old {
+                self.person_keys.remove(&person.key_id());
+            }
+            for height in due_heights(old) {
+                self.due.remove(&(height, key));
+            }
+        }
+        if let Some(record) = &record {
+            if let Record::Person(person) = record {
+                self.person_keys.insert(person.key_id(), PersonId(key));
+            }
+            self.due.extend(due_heights(record).into_iter().map(|height| (height, key)));
+        }
+        self.set_value(key, record.as_ref().map(record_hash));
+        match record {
+            Some(record) => self.records.insert(key, record),
+            None => self.records.remove(&key),
+        };
+    }
+
+    // Check and apply one transaction; on error nothing is changed
+    pub fn apply_transaction(&mut self, tx: &Transaction, height: u64) -> Result<(), StateError> {
+        self.journaled(|state| state.apply_one(tx, height))
+    }
+
+    fn apply_one(&mut self, tx: &Transaction, height: u64) -> Result<(), StateError> {
+        if tx.is_expired(height) {
+            return Err(StateError::Expired { expiry_height: tx.expiry_height, height });
+        }
+        let sender = self.account(&tx.sender).cloned().unwrap_or_default();
+        if tx.nonce != sender.nonce {
+            return Err(StateError::BadNonce { expected: sender.nonce, found: tx.nonce });
+        }
+
+        let transfer = match tx.kind {
+            TxKind::Transfer => Some(Transfer::decode(&tx.payload).map_err(StateError::InvalidPayload)?),
+            _ => None,
+        };
+        let action = IdentityAction::decode(tx.kind, &tx.payload).map_err(StateError::InvalidPayload)?;
+        let amount = transfer.as_ref().map(|t| t.amount).or_else(|| action.as_ref().map(|a| a.amount())).unwrap_or(0);
+        let needed = tx.fee.checked_add(amount).ok_or(StateError::Overflow)?;
+        if sender.balance < needed {
+            return Err(StateError::InsufficientBalance { needed, available: sender.balance });
+        }
+
+        let mut sender = sender;
+        sender.balance -= needed;
+        sender.nonce += 1;
+        self.set_account(tx.sender, sender);
+
+        if let Some(transfer) = transfer {
+            let mut recipient = self.account(&transfer.to).cloned().unwrap_or_default();
+            recipient.balance = recipient.balance.checked_add(transfer.amount).ok_or(StateError::Overflow)?;
+            self.set_account(transfer.to, recipient);
+        }
+        if let Some(action) = action {
+            self.apply_identity(tx, &action, height)?;
+        }
+
+        Ok(())
+    }
+
+    /*
+     *  Apply `txs` in order at `height`, on top of the block hashed `parent`,
+     *  and credit their fees to `proposer`. All or nothing: on the first
+     *  failure the state is restored.
+     */
+    pub fn apply_transactions(&mut self, txs: &[Transaction], height: u64, parent: &Hash, proposer: &KeyId) -> Result<Hash, StateError> {
+        self.journaled(|state| state.apply_all(txs, height, parent, proposer)).map(|()| self.root())
+    }
+
+    fn apply_all(&mut self, txs: &[Transaction], height: u64, parent: &Hash, proposer: &KeyId) -> Result<(), StateError> {
+        self.close_disputes(height)?;
+        // Lapsed persons must not be drawn
+        self.lapse_persons(height);
+        self.draw_juries(height, parent)?;
+        self.complete_recoveries(height);
+        let mut fees: u64 = 0;
+        for tx in txs {
+            self.apply_transaction(tx, height)?;
+            fees = fees.checked_add(tx.fee).ok_or(StateError::Overflow)?;
+        }
+
+        let mut account = self.account(proposer).cloned().unwrap_or_default();
+        account.balance = account.balance.checked_add(fees).ok_or(StateError::Overflow)?;
+        self.set_account(*proposer, account);
+
+        Ok(())
+    }
+
+    // Execute a received block and check it ends at the root its header claims
+    pub fn apply_block(&mut self, block: &Block) -> Result<(), StateError> {
+        let txs = block.decode_transactions().map_err(StateError::InvalidPayload)?;
+
+        self.journaled(|state| {
+            state.apply_all(&txs, block.header.height, &block.header.parent, &block.header.proposer)?;
+            let computed = state.root();
+            match computed == block.header.state_root {
+                true => Ok(()),
+                false => Err(StateError::StateRootMismatch { expected: block.header.state_root, computed }),
+            }
+        })
+    }
+
+    // Run `apply`, undoing every change it made if it fails
+    fn journaled<F>(&mut self, apply: F) -> Result<(), StateError>
+        where F: FnOnce(&mut State) -> Result<(), StateError>
+    {
+        // Inside a batch, the batch's journal undoes it
+        if self.journal.is_some() {
+            return apply(self);
+        }
+        self.journal = Some(Journal::default());
+        let res = apply(self);
+        let journal = self.journal.take().unwrap_or_default();
+        if res.is_err() {
+            journal.rollback(self);
+        }
+
+        res
+    }
+}
+
+fn subtree_root(leaves: &[(Hash, Hash)], depth: usize) -> Hash {
+    match leaves.len() {
+        0 => Hash::ZERO,
+        1 => leaf_hash(&leaves[0].0, &leaves[0].1),
+        _ => {
+            // Sorted keys: the ones with a 0 bit at `depth` come first
+            let split = leaves.partition_point(|(k, _)| !bit(k, depth));
+            let (left, right) = leaves.split_at(split);
+            node_hash(&subtree_root(left, depth + 1), &subtree_root(right, depth + 1))
+        },
+    }
+}
+
+/*
+ *  First value of every account and record changed, to undo a partly applied
+ *  batch
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Journal {
+    accounts: BTreeMap<Hash, Option<Account>>,
+    records: BTreeMap<Hash, Option<Record>>,
+}
+
+impl Journal {
+    fn rollback(self, state: &mut State) {
+        for (key, record) in self.records {
+            state.put_record(key, record);
+        }
+        for (key, account) in self.accounts {
+            state.set_value(key, account.as_ref().map(value_hash));
+            match account {
+                Some(account) => state.accounts.insert(key, account),
+                None => state.accounts.remove(&key),
+            };
+        }
+    }
+}
@@ -0,0 +1,571 @@
+use std::fmt::{ self, Display, Formatter };
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use sled::Transactional;
+use sled::transaction::{ ConflictableTransactionError, TransactionError };
+
+use crate::block::Block;
+use crate::certificate::QuorumCertificate;
+use crate::codec::{ Decode, DecodeError, Decoder, Encode, Encoder };
+use crate::hash::Hash;
+use crate::snapshot::{ Snapshot, SnapshotManifest };
+use crate::state::{ State, StateChunk, TrieNode };
+use crate::transaction::Transaction;
+use crate::vote::ValidatorSet;
+
+const TIP_KEY: &[u8] = b"tip";
+const BASE_KEY: &[u8] = b"base";
+const GENESIS_KEY: &[u8] = b"genesis";
+const FINALIZED_KEY: &[u8] = b"finalized";
+// Snapshots kept to serve to other nodes, the newest ones
+pub const SNAPSHOTS_KEPT: usize = 2;
+const OPEN_ATTEMPTS: u32 = 40;
+const OPEN_RETRY: Duration = Duration::from_millis(25);
+
+/*
+ *  Every write is flushed explicitly, so the background flusher is off. A Store
+ *  dropped just before may still hold the file lock from sled's worker threads
+ *  for a moment, hence the retries.
+ */
+fn open_db(path: &Path) -> Result<sled::Db, sled::Error> {
+    let mut attempt = 1;
+    loop {
+        match sled::Config::new().path(path).flush_every_ms(None).open() {
+            Err(sled::Error::Io(_)) if attempt < OPEN_ATTEMPTS => {
+                attempt += 1;
+                thread::sleep(OPEN_RETRY);
+            },
+            res => return res,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Db(sled::Error),
+    Decode(DecodeError),
+    MissingBlock(Hash),
+    MissingState(Hash),
+    // The block does not carry the root of the state handed in with it
+    StateMismatch { expected: Hash, found: Hash },
+    // Only the block directly on top of the tip can be committed
+    NotExtendingTip { tip: Option<ChainTip>, parent: Hash, height: u64 },
+    // A snapshot can only be restored into an empty store
+    NotEmpty(ChainTip),
+}
+
+impl Display for StoreError {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            StoreError::Db(e) => write!(f, "database: {}", e),
+            StoreError::Decode(e) => write!(f, "stored record: {}", e),
+            StoreError::MissingBlock(h) => write!(f, "block {} is not stored", h),
+            StoreError::MissingState(h) => write!(f, "state {} is not stored", h),
+            StoreError::StateMismatch { expected, found } => write!(f, "block claims state {}, got {}", expected, found),
+            StoreError::NotExtendingTip { tip, parent, height } => match tip {
+                Some(tip) => write!(f, "block {} on {} does not extend tip {} at {}", height, parent, tip.hash, tip.height),
+                None => write!(f, "block {} on {} committed before genesis", height, parent),
+            },
+            StoreError::NotEmpty(tip) => write!(f, "store already holds the chain up to {}", tip.height),
+        }
+    }
+}
+
+impl From<sled::Error> for StoreError {
+    fn from(e: sled::Error) -> Self {
+        StoreError::Db(e)
+    }
+}
+
+impl From<DecodeError> for StoreError {
+    fn from(e: DecodeError) -> Self {
+        StoreError::Decode(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChainTip {
+    pub hash: Hash,
+    pub height: u64,
+    pub state_root: Hash,
+}
+
+impl Encode for ChainTip {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.hash(&self.hash).u64(self.height).hash(&self.state_root);
+    }
+}
+
+impl Decode for ChainTip {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(ChainTip {
+            hash: input.hash()?,
+            height: input.u64()?,
+            state_root: input.hash()?,
+        })
+    }
+}
+
+// Where a committed transaction sits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TxLocation {
+    pub block: Hash,
+    pub index: u32,
+}
+
+impl Encode for TxLocation {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.hash(&self.block).u32(self.index);
+    }
+}
+
+impl Decode for TxLocation {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(TxLocation {
+            block: input.hash()?,
+            index: input.u32()?,
+        })
+    }
+}
+
+/*
+ *  Declaration of Class Store
+ *
+ *  Chain data on disk, one sled tree per kind of record:
+ *
+ *      blocks      block hash       -> encoded block
+ *      heights     height (BE u64)  -> block hash on the canonical chain
+ *      txs         transaction id   -> TxLocation
+ *      commits     height (BE u64)  -> QuorumCertificate of the canonical block
+ *      state       trie node hash   -> encoded TrieNode
+ *      snapshots   height (BE u64)  -> SnapshotManifest
+ *      chunks      height, index    -> StateChunk of that snapshot
+ *      meta        "tip"            -> ChainTip
+ *                  "base"           -> height and validators of a restored snapshot
+ *                  "genesis"        -> hash of the genesis the chain started from
+ *                  "finalized"      -> ChainTip of the last finalized block
+ *
+ *  A store restored from a snapshot has no blocks below it; "base" stands in
+ *  for what the chain would otherwise work out from them. Blocks above
+ *  "finalized" are the branch fork choice is on and may still be rewound.
+ *
+ *  A block and everything derived from it go in with one transaction across all
+ *  trees and are flushed before `commit_block` returns, so a crash leaves either
+ *  the old tip or the new one.
+ */
+pub struct Store {
+    db: sled::Db,
+    blocks: sled::Tree,
+    heights: sled::Tree,
+    txs: sled::Tree,
+    commits: sled::Tree,
+    state: sled::Tree,
+    snapshots: sled::Tree,
+    chunks: sled::Tree,
+    meta: sled::Tree,
+}
+
+impl Store {
+    // Open or create the store at `path` and bring it back to a consistent tip
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Store, StoreError> {
+        let db = open_db(path.as_ref())?;
+        let store = Store {
+            blocks: db.open_tree("blocks")?,
+            heights: db.open_tree("heights")?,
+            txs: db.open_tree("txs")?,
+            commits: db.open_tree("commits")?,
+            state: db.open_tree("state")?,
+            snapshots: db.open_tree("snapshots")?,
+            chunks: db.open_tree("chunks")?,
+            meta: db.open_tree("meta")?,
+            db,
+        };
+        store.recover()?;
+
+        Ok(store)
+    }
+
+    pub fn tip(&self) -> Result<Option<ChainTip>, StoreError> {
+        match self.meta.get(TIP_KEY)? {
+            Some(bytes) => Ok(Some(ChainTip::decode(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn block(&self, hash: &Hash) -> Result<Option<Block>, StoreError> {
+        match self.blocks.get(hash.as_bytes())? {
+            Some(bytes) => Ok(Some(Block::decode(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn hash_at(&self, height: u64) -> Result<Option<Hash>, StoreError> {
+        match self.heights.get(height.to_be_bytes())? {
+            Some(bytes) => Ok(Hash::from_slice(&bytes)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn block_at(&self, height: u64) -> Result<Option<Block>, StoreError> {
+        match self.hash_at(height)? {
+            Some(hash) => self.block(&hash),
+            None => Ok(None),
+        }
+    }
+
+    pub fn transaction(&self, id: &Hash) -> Result<Option<(Transaction, TxLocation)>, StoreError> {
+        let location = match self.txs.get(id.as_bytes())? {
+            Some(bytes) => TxLocation::decode(&bytes)?,
+            None => return Ok(None),
+        };
+        let block = self.block(&location.block)?.ok_or(StoreError::MissingBlock(location.block))?;
+        let bytes = block.transactions.get(location.index as usize).ok_or(StoreError::MissingBlock(location.block))?;
+
+        Ok(Some((Transaction::decode(bytes)?, location)))
+    }
+
+    pub fn certificate(&self, height: u64) -> Result<Option<QuorumCertificate>, StoreError> {
+        match self.commits.get(height.to_be_bytes())? {
+            Some(bytes) => Ok(Some(QuorumCertificate::decode(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn trie_node(&self, hash: &Hash) -> Result<Option<TrieNode>, StoreError> {
+        match self.state.get(hash.as_bytes())? {
+            Some(bytes) => Ok(Some(TrieNode::decode(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn load_state(&self, root: &Hash) -> Result<State, StoreError> {
+        State::from_trie(root, |hash| self.trie_node(hash))?
+            .ok_or(StoreError::MissingState(*root))
+    }
+
+    pub fn genesis(&self) -> Result<Option<Hash>, StoreError> {
+        match self.meta.get(GENESIS_KEY)? {
+            Some(bytes) => Ok(Hash::from_slice(&bytes)),
+            None => Ok(None),
+        }
+    }
+
+    // Tie the store to the genesis whose chain it holds
+    pub fn set_genesis(&self, hash: &Hash) -> Result<(), StoreError> {
+        self.meta.insert(GENESIS_KEY, hash.as_bytes())?;
+        self.db.flush()?;
+
+        Ok(())
+    }
+
+    // The last finalized block, if one was recorded; stores written without it finalized their tip
+    pub fn finalized(&self) -> Result<Option<ChainTip>, StoreError> {
+        match self.meta.get(FINALIZED_KEY)? {
+            Some(bytes) => Ok(Some(ChainTip::decode(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /*
+     *  Record the canonical block `certificate` is for as finalized, keeping
+     *  the certificate with it. The block may have been committed without one
+     *  while fork choice had not settled on it.
+     */
+    pub fn set_finalized(&self, certificate: &QuorumCertificate) -> Result<ChainTip, StoreError> {
+        let height = certificate.height;
+        let finalized = match self.complete_at(height)? {
+            Some(tip) if tip.hash == certificate.block => tip,
+            _ => return Err(StoreError::MissingBlock(certificate.block)),
+        };
+        let certificate_bytes = certificate.encode();
+
+        let res: Result<(), TransactionError<()>> = (&self.commits, &self.meta)
+            .transaction(|(commits, meta)| {
+                commits.insert(&height.to_be_bytes()[..], certificate_bytes.as_slice())?;
+                meta.insert(FINALIZED_KEY, finalized.encode())?;
+                Ok::<(), ConflictableTransactionError<()>>(())
+            });
+        match res {
+            Ok(()) => (),
+            Err(TransactionError::Storage(e)) => return Err(StoreError::Db(e)),
+            Err(TransactionError::Abort(())) => unreachable!(),
+        }
+        self.db.flush()?;
+
+        Ok(finalized)
+    }
+
+    // Height and validators of the snapshot the store was restored from, if it was
+    pub fn base(&self) -> Result<Option<(u64, ValidatorSet)>, StoreError> {
+        let bytes = match self.meta.get(BASE_KEY)? {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+        let mut input = Decoder::new(&bytes);
+        let height = input.u64()?;
+        let validators = ValidatorSet::decode_from(&mut input)?;
+        input.finish()?;
+
+        Ok(Some((height, validators)))
+    }
+
+    pub fn latest_snapshot(&self) -> Result<Option<SnapshotManifest>, StoreError> {
+        match self.snapshots.last()? {
+            Some((_, bytes)) => Ok(Some(SnapshotManifest::decode(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn snapshot_chunk(&self, height: u64, index: u32) -> Result<Option<StateChunk>, StoreError> {
+        match self.chunks.get(chunk_key(height, index))? {
+            Some(bytes) => Ok(Some(StateChunk::decode(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Keep `snapshot` to serve, dropping all but the newest SNAPSHOTS_KEPT
+    pub fn save_snapshot(&self, snapshot: &Snapshot) -> Result<(), StoreError> {
+        let height = snapshot.manifest.height();
+        let mut batch = sled::Batch::default();
+        for chunk in &snapshot.chunks {
+            batch.insert(&chunk_key(height, chunk.index)[..], chunk.encode());
+        }
+        self.chunks.apply_batch(batch)?;
+        self.snapshots.insert(height.to_be_bytes(), snapshot.manifest.encode())?;
+
+        let heights = self.snapshots.iter().keys().collect::<Result<Vec<_>, _>>()?;
+        for key in heights.iter().rev().skip(SNAPSHOTS_KEPT) {
+            self.snapshots.remove(key)?;
+            for chunk in self.chunks.scan_prefix(key).keys() {
+                self.chunks.remove(chunk?)?;
+            }
+        }
+        self.db.flush()?;
+
+        Ok(())
+    }
+
+    /*
+     *  Store `block` as the new tip together with `state`, the state after
+     *  executing it, and the certificate that finalized it if there is one.
+     *  Trie nodes are content addressed, so only the ones this block changed
+     *  are new.
+     */
+    pub fn commit_block(&self, block: &Block, state: &State, certificate: Option<&QuorumCertificate>) -> Result<ChainTip, StoreError> {
+        let header = &block.header;
+        let state_root = state.root();
+        if state_root != header.state_root {
+            return Err(StoreError::StateMismatch { expected: header.state_root, found: state_root });
+        }
+        let tip = self.tip()?;
+        let extends = match &tip {
+            Some(tip) => header.parent == tip.hash && header.height == tip.height + 1,
+            None => header.height == 0,
+        };
+        if !extends {
+            return Err(StoreError::NotExtendingTip { tip, parent: header.parent, height: header.height });
+        }
+
+        self.write_block(block, state, certificate, None)
+    }
+
+    /*
+     *  Start an empty store at the snapshot `manifest` describes, with `state`
+     *  assembled from its chunks. Blocks on top are committed as usual.
+     */
+    pub fn restore(&self, manifest: &SnapshotManifest, state: &State) -> Result<ChainTip, StoreError> {
+        if let Some(tip) = self.tip()? {
+            return Err(StoreError::NotEmpty(tip));
+        }
+        let state_root = state.root();
+        if state_root != manifest.state_root() {
+            return Err(StoreError::StateMismatch { expected: manifest.state_root(), found: state_root });
+        }
+        let mut base = Encoder::new();
+        base.u64(manifest.height());
+        manifest.validators.encode_to(&mut base);
+
+        self.write_block(&manifest.block, state, Some(&manifest.certificate), Some(base.finish()))
+    }
+
+    // Write `block` as the tip in one transaction, and `base` if restoring
+    fn write_block(&self, block: &Block, state: &State, certificate: Option<&QuorumCertificate>, base: Option<Vec<u8>>) -> Result<ChainTip, StoreError> {
+        let header = &block.header;
+        let state_root = header.state_root;
+        let hash = block.hash();
+        let new_tip = ChainTip { hash, height: header.height, state_root };
+        let block_bytes = block.encode();
+        let certificate_bytes = certificate.map(|c| c.encode());
+        let tx_ids = block.decode_transactions()?.iter().map(|tx| tx.id()).collect::<Vec<Hash>>();
+        let mut nodes = Vec::new();
+        for (node_hash, node) in state.trie_nodes() {
+            if !self.state.contains_key(node_hash.as_bytes())? {
+                nodes.push((node_hash, node.encode()));
+            }
+        }
+
+        let res: Result<(), TransactionError<()>> = (&self.blocks, &self.heights, &self.txs, &self.commits, &self.state, &self.meta)
+            .transaction(|(blocks, heights, txs, commits, state, meta)| {
+                blocks.insert(hash.as_bytes(), block_bytes.as_slice())?;
+                heights.insert(&header.height.to_be_bytes()[..], hash.as_bytes())?;
+                match &certificate_bytes {
+                    Some(bytes) => commits.insert(&header.height.to_be_bytes()[..], bytes.as_slice())?,
+                    None => commits.remove(&header.height.to_be_bytes()[..])?,
+                };
+                for (index, id) in tx_ids.iter().enumerate() {
+                    let location = TxLocation { block: hash, index: index as u32 };
+                    txs.insert(id.as_bytes(), location.encode())?;
+                }
+                for (node_hash, node) in &nodes {
+                    state.insert(node_hash.as_bytes(), node.as_slice())?;
+                }
+                meta.insert(TIP_KEY, new_tip.encode())?;
+                if let Some(base) = &base {
+                    meta.insert(BASE_KEY, base.as_slice())?;
+                }
+                Ok::<(), ConflictableTransactionError<()>>(())
+            });
+        match res {
+            Ok(()) => (),
+            Err(TransactionError::Storage(e)) => return Err(StoreError::Db(e)),
+            Err(TransactionError::Abort(())) => unreachable!(),
+        }
+        self.db.flush()?;
+
+        Ok(new_tip)
+    }
+
+    /*
+     *  Make the canonical block at `height` the tip again, or go back to before
+     *  genesis for None, to switch branches. Height, certificate and
+     *  transaction entries above it go; blocks and trie nodes stay, being
+     *  content addressed.
+     */
+    pub fn rewind(&self, height: Option<u64>) -> Result<Option<ChainTip>, StoreError> {
+        let tip = match height {
+            Some(height) => {
+                let hash = self.hash_at(height)?.ok_or(StoreError::MissingBlock(Hash::ZERO))?;
+                Some(self.complete_at(height)?.ok_or(StoreError::MissingBlock(hash))?)
+            },
+            None => None,
+        };
+        let above = height.map(|h| h + 1).unwrap_or(0);
+        let mut dropped = Vec::new();
+        let mut tx_ids = Vec::new();
+        for entry in self.heights.range(above.to_be_bytes()..) {
+            let (key, value) = entry?;
+            if let Some(block) = Hash::from_slice(&value).map(|h| self.block(&h)).transpose()?.flatten() {
+                tx_ids.extend(block.decode_transactions()?.iter().map(|tx| tx.id()));
+            }
+            dropped.push(key);
+        }
+
+        let res: Result<(), TransactionError<()>> = (&self.heights, &self.commits, &self.txs, &self.meta)
+            .transaction(|(heights, commits, txs, meta)| {
+                for key in &dropped {
+                    heights.remove(key)?;
+                    commits.remove(key)?;
+                }
+                for id in &tx_ids {
+                    txs.remove(id.as_bytes())?;
+                }
+                match &tip {
+                    Some(tip) => meta.insert(TIP_KEY, tip.encode())?,
+                    None => {
+                        meta.remove(BASE_KEY)?;
+                        meta.remove(FINALIZED_KEY)?;
+                        meta.remove(TIP_KEY)?
+                    },
+                };
+                Ok::<(), ConflictableTransactionError<()>>(())
+            });
+        match res {
+            Ok(()) => (),
+            Err(TransactionError::Storage(e)) => return Err(StoreError::Db(e)),
+            Err(TransactionError::Abort(())) => unreachable!(),
+        }
+        self.db.flush()?;
+
+        Ok(tip)
+    }
+
+    /*
+     *  Check the recorded tip against the data it points at. If its block or
+     *  state is gone, fall back to the highest canonical block that is complete.
+     *  Height and certificate entries above the tip are dropped either way, and
+     *  so is a finalized mark the tip no longer reaches.
+     */
+    pub fn recover(&self) -> Result<Option<ChainTip>, StoreError> {
+        let recorded = match self.tip()? {
+            Some(tip) => tip,
+            None => return Ok(None),
+        };
+
+        let mut height = recorded.height;
+        let tip = loop {
+            if let Some(tip) = self.complete_at(height)? {
+                break Some(tip);
+            }
+            if height == 0 {
+                break None;
+            }
+            height -= 1;
+        };
+
+        if tip != Some(recorded) {
+            match &tip {
+                Some(tip) => self.meta.insert(TIP_KEY, tip.encode())?,
+                None => self.meta.remove(TIP_KEY)?,
+            };
+        }
+        if let Some(finalized) = self.finalized()? {
+            if self.hash_at(finalized.height)? != Some(finalized.hash) || tip.map(|t| t.height < finalized.height).unwrap_or(true) {
+                self.meta.remove(FINALIZED_KEY)?;
+            }
+        }
+        let above = tip.map(|t| t.height + 1).unwrap_or(0);
+        for entry in self.heights.range(above.to_be_bytes()..) {
+            let (key, _) = entry?;
+            self.heights.remove(key)?;
+        }
+        for entry in self.commits.range(above.to_be_bytes()..) {
+            let (key, _) = entry?;
+            self.commits.remove(key)?;
+        }
+        self.db.flush()?;
+
+        Ok(tip)
+    }
+
+    fn complete_at(&self, height: u64) -> Result<Option<ChainTip>, StoreError> {
+        let hash = match self.hash_at(height)? {
+            Some(hash) => hash,
+            None => return Ok(None),
+        };
+        let block = match self.blocks.get(hash.as_bytes())? {
+            Some(bytes) => match Block::decode(&bytes) {
+                Ok(block) => block,
+                Err(_) => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+        if block.hash() != hash || block.header.height != height {
+            return Ok(None);
+        }
+        let state_root = block.header.state_root;
+        if self.load_state(&state_root).is_err() {
+            return Ok(None);
+        }
+
+        Ok(Some(ChainTip { hash, height, state_root }))
+    }
+}
+
+fn chunk_key(height: u64, index: u32) -> [u8; 12] {
+    let mut key = [0u8; 12];
+    key[..8].copy_from_slice(&height.to_be_bytes());
+    key[8..].copy_from_slice(&index.to_be_bytes());
+    key
+}
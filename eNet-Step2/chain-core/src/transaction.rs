@@ -0,0 +1,308 @@
+use std::fmt::{ self, Display, Formatter };
+
+use secure_sign::{ NistCryptography, CRYPTO_PUBLICKEYBYTES, DETACHED_SIGNATURE_MAX };
+
+use crate::codec::{ Decode, DecodeError, Decoder, Encode, Encoder };
+use crate::hash::{ self, Hash, KeyId, DOMAIN_TX_ID };
+use crate::block::MAX_ENCODED_TX;
+use crate::identity::IdentityAction;
+
+pub const TX_VERSION: u16 = 1;
+pub const MAX_CHAIN_ID_BYTES: usize = 64;
+
+/*
+ *  Declaration of TxKind
+ *
+ *  Tells how the payload is to be read. The tag is part of the signed bytes.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxKind {
+    Transfer,
+    // Opaque application data, only the fee is charged
+    Data,
+    // Proof-of-person registry, see `identity`
+    Register,
+    UpdateKey,
+    Deactivate,
+    Attest,
+    // Duplicate-identity disputes, see `dispute`
+    Challenge,
+    Ballot,
+    Liveness,
+    // Social recovery, see `recovery`
+    SetGuardians,
+    Recover,
+    CancelRecovery,
+    CommitBiometric,
+}
+
+impl TxKind {
+    pub fn tag(self) -> u8 {
+        match self {
+            TxKind::Transfer => 0,
+            TxKind::Data => 1,
+            TxKind::Register => 2,
+            TxKind::UpdateKey => 3,
+            TxKind::Deactivate => 4,
+            TxKind::Attest => 5,
+            TxKind::Challenge => 6,
+            TxKind::Ballot => 7,
+            TxKind::Liveness => 8,
+            TxKind::SetGuardians => 9,
+            TxKind::Recover => 10,
+            TxKind::CancelRecovery => 11,
+            TxKind::CommitBiometric => 12,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<TxKind> {
+        match tag {
+            0 => Some(TxKind::Transfer),
+            1 => Some(TxKind::Data),
+            2 => Some(TxKind::Register),
+            3 => Some(TxKind::UpdateKey),
+            4 => Some(TxKind::Deactivate),
+            5 => Some(TxKind::Attest),
+            6 => Some(TxKind::Challenge),
+            7 => Some(TxKind::Ballot),
+            8 => Some(TxKind::Liveness),
+            9 => Some(TxKind::SetGuardians),
+            10 => Some(TxKind::Recover),
+            11 => Some(TxKind::CancelRecovery),
+            12 => Some(TxKind::CommitBiometric),
+            _ => None,
+        }
+    }
+}
+
+/*
+ *  Payload of a TxKind::Transfer
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transfer {
+    pub to: KeyId,
+    pub amount: u64,
+}
+
+impl Encode for Transfer {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.key_id(&self.to).u64(self.amount);
+    }
+}
+
+impl Decode for Transfer {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        Ok(Transfer {
+            to: input.key_id()?,
+            amount: input.u64()?,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TxError {
+    Malformed(DecodeError),
+    TooLarge { size: usize, max: usize },
+    WrongChain { expected: String, found: String },
+    InvalidPublicKey,
+    SenderMismatch,
+    InvalidPayload(DecodeError),
+    BadSignature,
+    // The new key of a key update did not sign for it
+    BadKeyProof,
+}
+
+impl Display for TxError {
+    fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            TxError::Malformed(e) => write!(f, "malformed transaction: {}", e),
+            TxError::TooLarge { size, max } => write!(f, "transaction is {} bytes, at most {} allowed", size, max),
+            TxError::WrongChain { expected, found } => write!(f, "transaction is for chain `{}`, this is `{}`", found, expected),
+            TxError::InvalidPublicKey => write!(f, "sender public key has the wrong length"),
+            TxError::SenderMismatch => write!(f, "sender key ID does not match the public key"),
+            TxError::InvalidPayload(e) => write!(f, "invalid payload: {}", e),
+            TxError::BadSignature => write!(f, "signature does not verify"),
+            TxError::BadKeyProof => write!(f, "new key did not sign the key update"),
+        }
+    }
+}
+
+/*
+ *  Declaration of Transaction
+ *
+ *  Signed by the sender over every field but the signature. The chain ID is part
+ *  of the signed bytes, so a transaction cannot be replayed on another chain; the
+ *  nonce does the same within a chain once account state checks it. The full
+ *  public key travels along so the signature can be checked without any state.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    pub version: u16,
+    pub chain_id: String,
+    pub sender: KeyId,
+    pub public_key: Vec<u8>,
+    pub nonce: u64,
+    pub kind: TxKind,
+    pub payload: Vec<u8>,
+    pub fee: u64,
+    // Last height the transaction may be included at, 0 for no expiry
+    pub expiry_height: u64,
+    pub signature: Vec<u8>,
+}
+
+impl Transaction {
+    pub fn new(chain_id: &str, kind: TxKind, payload: Vec<u8>, nonce: u64, fee: u64, expiry_height: u64) -> Self {
+        Transaction {
+            version: TX_VERSION,
+            chain_id: String::from(chain_id),
+            sender: KeyId::default(),
+            public_key: Vec::new(),
+            nonce,
+            kind,
+            payload,
+            fee,
+            expiry_height,
+            signature: Vec::new(),
+        }
+    }
+
+    pub fn transfer(chain_id: &str, to: KeyId, amount: u64, nonce: u64, fee: u64, expiry_height: u64) -> Self {
+        let payload = Transfer { to, amount }.encode();
+        Transaction::new(chain_id, TxKind::Transfer, payload, nonce, fee, expiry_height)
+    }
+
+    fn encode_unsigned(&self, out: &mut Encoder) {
+        out.u16(self.version)
+            .bytes(self.chain_id.as_bytes())
+            .key_id(&self.sender)
+            .bytes(&self.public_key)
+            .u64(self.nonce)
+            .u8(self.kind.tag())
+            .bytes(&self.payload)
+            .u64(self.fee)
+            .u64(self.expiry_height);
+    }
+
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut out = Encoder::new();
+        self.encode_unsigned(&mut out);
+        out.finish()
+    }
+
+    // Transaction ID, unchanged by signing
+    pub fn id(&self) -> Hash {
+        hash::hash_with_domain(DOMAIN_TX_ID, &self.signing_bytes())
+    }
+
+    pub fn sign(&mut self, secure: &mut NistCryptography) -> bool {
+        self.public_key = secure.public_key.to_vec();
+        self.sender = KeyId::from_public_key(&self.public_key);
+        match secure.sign_detached(self.id().as_bytes()) {
+            Some(signature) => {
+                self.signature = signature;
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn is_expired(&self, height: u64) -> bool {
+        self.expiry_height != 0 && height > self.expiry_height
+    }
+
+    pub fn transfer_payload(&self) -> Option<Transfer> {
+        match self.kind {
+            TxKind::Transfer => Transfer::decode(&self.payload).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn identity_action(&self) -> Option<IdentityAction> {
+        IdentityAction::decode(self.kind, &self.payload).ok().flatten()
+    }
+
+    // What leaves the sender's account besides the fee
+    pub fn amount(&self) -> u64 {
+        match self.transfer_payload() {
+            Some(transfer) => transfer.amount,
+            None => self.identity_action().map(|a| a.amount()).unwrap_or(0),
+        }
+    }
+
+    // Everything that can be checked without account state
+    pub fn validate(&self, chain_id: &str, max_tx_bytes: usize) -> Result<(), TxError> {
+        let size = self.encode().len();
+        if size > max_tx_bytes {
+            return Err(TxError::TooLarge { size, max: max_tx_bytes });
+        }
+        if self.chain_id != chain_id {
+            return Err(TxError::WrongChain { expected: String::from(chain_id), found: self.chain_id.clone() });
+        }
+        if self.public_key.len() != CRYPTO_PUBLICKEYBYTES as usize {
+            return Err(TxError::InvalidPublicKey);
+        }
+        if KeyId::from_public_key(&self.public_key) != self.sender {
+            return Err(TxError::SenderMismatch);
+        }
+        if self.kind == TxKind::Transfer {
+            Transfer::decode(&self.payload).map_err(TxError::InvalidPayload)?;
+        }
+        if let Some(IdentityAction::UpdateKey(update)) = IdentityAction::decode(self.kind, &self.payload).map_err(TxError::InvalidPayload)? {
+            if !update.verify(&self.sender) {
+                return Err(TxError::BadKeyProof);
+            }
+        }
+        if !NistCryptography::verify_detached(self.id().as_bytes(), &self.signature, &self.public_key) {
+            return Err(TxError::BadSignature);
+        }
+
+        Ok(())
+    }
+
+    // Decode and validate bytes received from a peer
+    pub fn decode_valid(bytes: &[u8], chain_id: &str, max_tx_bytes: usize) -> Result<Transaction, TxError> {
+        if bytes.len() > max_tx_bytes {
+            return Err(TxError::TooLarge { size: bytes.len(), max: max_tx_bytes });
+        }
+        let tx = Transaction::decode(bytes).map_err(TxError::Malformed)?;
+        tx.validate(chain_id, max_tx_bytes)?;
+
+        Ok(tx)
+    }
+}
+
+impl Encode for Transaction {
+    fn encode_to(&self, out: &mut Encoder) {
+        self.encode_unsigned(out);
+        out.bytes(&self.signature);
+    }
+}
+
+impl Decode for Transaction {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        let version = input.u16()?;
+        if version != TX_VERSION {
+            return Err(DecodeError::UnknownVersion(version));
+        }
+        let chain_id = input.bytes("chain_id", MAX_CHAIN_ID_BYTES)?;
+        let chain_id = String::from_utf8(chain_id).map_err(|_| DecodeError::InvalidUtf8("chain_id"))?;
+        let sender = input.key_id()?;
+        let public_key = input.bytes("public_key", CRYPTO_PUBLICKEYBYTES as usize)?;
+        let nonce = input.u64()?;
+        let tag = input.u8()?;
+        let kind = TxKind::from_tag(tag).ok_or(DecodeError::UnknownKind(tag))?;
+
+        Ok(Transaction {
+            version,
+            chain_id,
+            sender,
+            public_key,
+            nonce,
+            kind,
+            payload: input.bytes("payload", MAX_ENCODED_TX)?,
+            fee: input.u64()?,
+            expiry_height: input.u64()?,
+            signature: input.bytes("signature", DETACHED_SIGNATURE_MAX)?,
+        })
+    }
+}
@@ -0,0 +1,351 @@
+use secure_sign::{ NistCryptography, CRYPTO_PUBLICKEYBYTES, DETACHED_SIGNATURE_MAX };
+
+use crate::block::Block;
+use crate::codec::{ Decode, DecodeError, Decoder, Encode, Encoder };
+use crate::hash::{ self, Hash, KeyId, DOMAIN_PROPOSAL, DOMAIN_VOTE };
+use crate::state::{ IdentityStatus, State };
+
+// Decoding bound for anything sized by the validator set
+pub const MAX_VALIDATORS: usize = 10_000;
+
+/*
+ *  Declaration of ValidatorSet
+ *
+ *  The verified persons allowed to vote at a height, in KeyId order. Every
+ *  validator is one person and carries one vote, so quorums are plain counts.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Validator {
+    pub id: KeyId,
+    pub public_key: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidatorSet {
+    pub validators: Vec<Validator>,
+}
+
+impl ValidatorSet {
+    // The `candidates` whose accounts are verified in `state`
+    pub fn from_registry(state: &State, candidates: &[Vec<u8>]) -> Self {
+        let mut validators: Vec<Validator> = candidates.iter()
+            .map(|key| Validator { id: KeyId::from_public_key(key), public_key: key.clone() })
+            .filter(|v| state.account(&v.id).map(|a| a.identity == IdentityStatus::Verified).unwrap_or(false))
+            .collect();
+        validators.sort_by_key(|v| v.id);
+        validators.dedup_by(|a, b| a.id == b.id);
+
+        ValidatorSet { validators }
+    }
+
+    // All of `keys`, for a set already decided elsewhere
+    pub fn from_keys(keys: &[Vec<u8>]) -> Self {
+        let mut validators: Vec<Validator> = keys.iter()
+            .map(|key| Validator { id: KeyId::from_public_key(key), public_key: key.clone() })
+            .collect();
+        validators.sort_by_key(|v| v.id);
+        validators.dedup_by(|a, b| a.id == b.id);
+
+        ValidatorSet { validators }
+    }
+
+    pub fn len(&self) -> usize {
+        self.validators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    pub fn get(&self, id: &KeyId) -> Option<&Validator> {
+        self.validators.binary_search_by(|v| v.id.cmp(id)).ok().map(|i| &self.validators[i])
+    }
+
+    pub fn contains(&self, id: &KeyId) -> bool {
+        self.get(id).is_some()
+    }
+
+    // More than two thirds: any two quorums share an honest validator
+    pub fn quorum(&self) -> usize {
+        self.len() * 2 / 3 + 1
+    }
+
+    // More than a third: at least one of them is honest
+    pub fn weak_quorum(&self) -> usize {
+        self.len() / 3 + 1
+    }
+
+    pub fn proposer(&self, height: u64, round: u32) -> Option<&Validator> {
+        match self.len() {
+            0 => None,
+            n => Some(&self.validators[((height + round as u64) % n as u64) as usize]),
+        }
+    }
+}
+
+// Encoded as the public keys in order; the IDs follow from them
+impl Encode for ValidatorSet {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.u32(self.validators.len() as u32);
+        for validator in &self.validators {
+            out.bytes(&validator.public_key);
+        }
+    }
+}
+
+impl Decode for ValidatorSet {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        let count = input.u32()? as usize;
+        if count > MAX_VALIDATORS {
+            return Err(DecodeError::TooLong { field: "validators", len: count, max: MAX_VALIDATORS });
+        }
+        let mut keys = Vec::with_capacity(count.min(1024));
+        for _ in 0..count {
+            keys.push(input.bytes("public key", CRYPTO_PUBLICKEYBYTES as usize)?);
+        }
+
+        Ok(ValidatorSet::from_keys(&keys))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VoteKind {
+    Prevote,
+    Precommit,
+}
+
+impl VoteKind {
+    pub fn tag(self) -> u8 {
+        match self {
+            VoteKind::Prevote => 0,
+            VoteKind::Precommit => 1,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<VoteKind> {
+        match tag {
+            0 => Some(VoteKind::Prevote),
+            1 => Some(VoteKind::Precommit),
+            _ => None,
+        }
+    }
+}
+
+/*
+ *  Declaration of Vote
+ *
+ *  A validator's prevote or precommit for a block hash, or for nothing (nil), at
+ *  one height and round. The chain ID is part of the signed bytes.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vote {
+    pub kind: VoteKind,
+    pub height: u64,
+    pub round: u32,
+    pub block: Option<Hash>,
+    pub validator: KeyId,
+    pub signature: Vec<u8>,
+}
+
+fn encode_block_ref(out: &mut Encoder, block: &Option<Hash>) {
+    match block {
+        Some(hash) => out.u8(1).hash(hash),
+        None => out.u8(0),
+    };
+}
+
+fn decode_block_ref(input: &mut Decoder) -> Result<Option<Hash>, DecodeError> {
+    match input.u8()? {
+        0 => Ok(None),
+        1 => Ok(Some(input.hash()?)),
+        tag => Err(DecodeError::UnknownKind(tag)),
+    }
+}
+
+impl Vote {
+    pub fn new(kind: VoteKind, height: u64, round: u32, block: Option<Hash>) -> Self {
+        Vote { kind, height, round, block, validator: KeyId::default(), signature: Vec::new() }
+    }
+
+    pub fn signing_hash(&self, chain_id: &str) -> Hash {
+        let mut out = Encoder::new();
+        out.bytes(chain_id.as_bytes()).u8(self.kind.tag()).u64(self.height).u32(self.round);
+        encode_block_ref(&mut out, &self.block);
+        out.key_id(&self.validator);
+        hash::hash_with_domain(DOMAIN_VOTE, &out.finish())
+    }
+
+    pub fn sign(&mut self, secure: &mut NistCryptography, chain_id: &str) -> bool {
+        self.validator = KeyId::from_public_key(&secure.public_key);
+        match secure.sign_detached(self.signing_hash(chain_id).as_bytes()) {
+            Some(signature) => {
+                self.signature = signature;
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn verify(&self, public_key: &[u8], chain_id: &str) -> bool {
+        KeyId::from_public_key(public_key) == self.validator
+            && NistCryptography::verify_detached(self.signing_hash(chain_id).as_bytes(), &self.signature, public_key)
+    }
+}
+
+impl Encode for Vote {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.u8(self.kind.tag()).u64(self.height).u32(self.round);
+        encode_block_ref(out, &self.block);
+        out.key_id(&self.validator).bytes(&self.signature);
+    }
+}
+
+impl Decode for Vote {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        let tag = input.u8()?;
+        Ok(Vote {
+            kind: VoteKind::from_tag(tag).ok_or(DecodeError::UnknownKind(tag))?,
+            height: input.u64()?,
+            round: input.u32()?,
+            block: decode_block_ref(input)?,
+            validator: input.key_id()?,
+            signature: input.bytes("signature", DETACHED_SIGNATURE_MAX)?,
+        })
+    }
+}
+
+/*
+ *  Declaration of Proposal
+ *
+ *  The round's proposer offering a block. `valid_round` is set when the block is
+ *  one that already gathered a prevote quorum in that earlier round.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Proposal {
+    pub height: u64,
+    pub round: u32,
+    pub valid_round: Option<u32>,
+    pub block: Block,
+    pub proposer: KeyId,
+    pub signature: Vec<u8>,
+}
+
+impl Proposal {
+    pub fn new(height: u64, round: u32, valid_round: Option<u32>, block: Block) -> Self {
+        Proposal { height, round, valid_round, block, proposer: KeyId::default(), signature: Vec::new() }
+    }
+
+    pub fn signing_hash(&self, chain_id: &str) -> Hash {
+        let mut out = Encoder::new();
+        out.bytes(chain_id.as_bytes()).u64(self.height).u32(self.round);
+        match self.valid_round {
+            Some(round) => out.u8(1).u32(round),
+            None => out.u8(0),
+        };
+        out.hash(&self.block.hash()).key_id(&self.proposer);
+        hash::hash_with_domain(DOMAIN_PROPOSAL, &out.finish())
+    }
+
+    pub fn sign(&mut self, secure: &mut NistCryptography, chain_id: &str) -> bool {
+        self.proposer = KeyId::from_public_key(&secure.public_key);
+        match secure.sign_detached(self.signing_hash(chain_id).as_bytes()) {
+            Some(signature) => {
+                self.signature = signature;
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn verify(&self, public_key: &[u8], chain_id: &str) -> bool {
+        KeyId::from_public_key(public_key) == self.proposer
+            && NistCryptography::verify_detached(self.signing_hash(chain_id).as_bytes(), &self.signature, public_key)
+    }
+}
+
+impl Encode for Proposal {
+    fn encode_to(&self, out: &mut Encoder) {
+        out.u64(self.height).u32(self.round);
+        match self.valid_round {
+            Some(round) => out.u8(1).u32(round),
+            None => out.u8(0),
+        };
+        self.block.encode_to(out);
+        out.key_id(&self.proposer).bytes(&self.signature);
+    }
+}
+
+impl Decode for Proposal {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        let height = input.u64()?;
+        let round = input.u32()?;
+        let valid_round = match input.u8()? {
+            0 => None,
+            1 => Some(input.u32()?),
+            tag => return Err(DecodeError::UnknownKind(tag)),
+        };
+
+        Ok(Proposal {
+            height,
+            round,
+            valid_round,
+            block: Block::decode_from(input)?,
+            proposer: input.key_id()?,
+            signature: input.bytes("signature", DETACHED_SIGNATURE_MAX)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConsensusMessage {
+    Proposal(Box<Proposal>),
+    Vote(Vote),
+}
+
+impl ConsensusMessage {
+    pub fn height(&self) -> u64 {
+        match self {
+            ConsensusMessage::Proposal(p) => p.height,
+            ConsensusMessage::Vote(v) => v.height,
+        }
+    }
+
+    pub fn round(&self) -> u32 {
+        match self {
+            ConsensusMessage::Proposal(p) => p.round,
+            ConsensusMessage::Vote(v) => v.round,
+        }
+    }
+
+    pub fn sender(&self) -> KeyId {
+        match self {
+            ConsensusMessage::Proposal(p) => p.proposer,
+            ConsensusMessage::Vote(v) => v.validator,
+        }
+    }
+}
+
+impl Encode for ConsensusMessage {
+    fn encode_to(&self, out: &mut Encoder) {
+        match self {
+            ConsensusMessage::Proposal(p) => {
+                out.u8(0);
+                p.encode_to(out);
+            },
+            ConsensusMessage::Vote(v) => {
+                out.u8(1);
+                v.encode_to(out);
+            },
+        }
+    }
+}
+
+impl Decode for ConsensusMessage {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        match input.u8()? {
+            0 => Ok(ConsensusMessage::Proposal(Box::new(Proposal::decode_from(input)?))),
+            1 => Ok(ConsensusMessage::Vote(Vote::decode_from(input)?)),
+            tag => Err(DecodeError::UnknownKind(tag)),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use chain_core::{ Account, BiometricRecord, Encode, FeatureVector, KeyId, PersonId, Registration, State, StateError, TxKind, hash_with_domain };
+use chain_core::{ LSH_BANDS, MATCH_BANDS };
+use common::{ keypair, send };
+
+// 128 features in [-1, 1] drawn from `name`, moved by up to `noise` each
+fn features(name: &str, noise: f32) -> FeatureVector {
+    let values = (0..128u8).map(|i| {
+        let bytes = hash_with_domain(b"biometric-test", &[name.as_bytes(), &[i]].concat());
+        let value = bytes.as_bytes()[0] as f32 / 127.5 - 1.0;
+        let jitter = hash_with_domain(b"biometric-noise", &[name.as_bytes(), &[i]].concat()).as_bytes()[0] as f32 / 127.5 - 1.0;
+        value + noise * jitter
+    }).collect();
+    FeatureVector::new(values).unwrap()
+}
+
+#[test]
+fn near_templates_match_and_only_commitments_reach_the_chain() {
+    let seed = hash_with_domain(b"biometric-test", b"network");
+    assert!(FeatureVector::new(vec![1.0, f32::NAN]).is_none());
+
+    // A second scan of the same face lands in the same bands, someone else's does not
+    let scan = features("alice", 0.0);
+    assert_eq!(scan.signature(&seed), features("alice", 0.0).signature(&seed));
+    let first = scan.commit(&seed, b"salt one");
+    let again = scan.commit(&seed, b"salt two");
+    assert_eq!(first.bands, again.bands);
+    assert_ne!(first.template, again.template);
+    assert_eq!(first.bands.len(), LSH_BANDS);
+    let near = features("alice", 0.02).commit(&seed, b"salt three");
+    assert!(first.matching_bands(&near) >= MATCH_BANDS);
+    let other = features("bob", 0.0).commit(&seed, b"salt one");
+    assert!(first.matching_bands(&other) < MATCH_BANDS);
+
+    // Unrelated templates share no band at all
+    let crowd: Vec<_> = (0..20).map(|i| features(&format!("person {}", i), 0.0).commit(&seed, b"salt")).collect();
+    for (i, a) in crowd.iter().enumerate() {
+        assert!(crowd[i + 1..].iter().all(|b| a.matching_bands(b) == 0));
+    }
+
+    let mut state = State::new();
+    let mut alice = keypair();
+    let mut mallory = keypair();
+    for secure in [&mut alice, &mut mallory] {
+        state.set_account(KeyId::from_public_key(&secure.public_key), Account { balance: 10, ..Account::default() });
+        send(&mut state, secure, TxKind::Register, Registration { evidence: Vec::new() }.encode(), 1).unwrap();
+    }
+    let (alice_id, mallory_id) = (PersonId::from_public_key(&alice.public_key), PersonId::from_public_key(&mallory.public_key));
+
+    let record = BiometricRecord { person: alice_id, commitment: first.clone() }.encode();
+    send(&mut state, &mut alice, TxKind::CommitBiometric, record.clone(), 1).unwrap();
+    assert_eq!(state.person(&alice_id).unwrap().biometric, Some(first.clone()));
+    assert_eq!(send(&mut state, &mut alice, TxKind::CommitBiometric, record, 1), Err(StateError::BiometricSet(alice_id)));
+    let mut short = near.clone();
+    short.bands.pop();
+    let record = BiometricRecord { person: mallory_id, commitment: short }.encode();
+    assert_eq!(send(&mut state, &mut mallory, TxKind::CommitBiometric, record, 1), Err(StateError::BadBands { found: LSH_BANDS - 1, expected: LSH_BANDS }));
+
+    // Mallory registering again under a fresh salt is caught locally
+    let record = BiometricRecord { person: mallory_id, commitment: near.clone() }.encode();
+    send(&mut state, &mut mallory, TxKind::CommitBiometric, record, 1).unwrap();
+    let found = state.biometric_matches(&near, Some(&mallory_id));
+    assert_eq!(found.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![alice_id]);
+    assert!(state.biometric_matches(&other, None).is_empty());
+    assert_eq!(state.biometric_matches(&first, Some(&alice_id))[0].0, mallory_id);
+}
@@ -0,0 +1,68 @@
+use chain_core::{ Block, BlockHeader, Decode, DecodeError, Encode, Hash, KeyId, QuorumCertificate, merkle_root };
+use secure_sign::NistCryptography;
+
+fn sample_block() -> Block {
+    let mut header = BlockHeader::new(Hash([7; 32]), 42, 1_600_000_000_000);
+    header.state_root = Hash([9; 32]);
+    Block::new(header, vec![b"first".to_vec(), b"second".to_vec(), b"third".to_vec()])
+}
+
+#[test]
+fn encoding_roundtrip_and_stable_hash() {
+    let block = sample_block();
+    let bytes = block.encode();
+    assert_eq!(Block::decode(&bytes).unwrap(), block);
+    assert!(block.has_valid_tx_root());
+
+    // Fixed vector: any change to the encoding or hashing breaks consensus between versions
+    assert_eq!(block.hash().to_hex(), "e00f597845f796bb72c581be01825d67ba09080d28ad441c3db0595e8bc69487");
+
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert_eq!(Block::decode(&trailing), Err(DecodeError::TrailingBytes(1)));
+    assert_eq!(Block::decode(&bytes[..bytes.len() - 1]), Err(DecodeError::UnexpectedEnd));
+
+    let mut future = bytes.clone();
+    future[1] = 3;
+    assert_eq!(Block::decode(&future), Err(DecodeError::UnknownVersion(3)));
+
+    // The parent's certificate is part of the header and its hash
+    let mut certified = block.clone();
+    certified.header.last_commit = Some(QuorumCertificate {
+        height: 41, round: 0, block: Hash([7; 32]), signers: vec![0b101], signatures: vec![vec![1; 10], vec![2; 12]],
+    });
+    assert_eq!(Block::decode(&certified.encode()).unwrap(), certified);
+    assert_ne!(certified.hash(), block.hash());
+
+    // Order matters and odd leaves are not duplicated
+    let (a, b, c) = (Hash([1; 32]), Hash([2; 32]), Hash([3; 32]));
+    assert_ne!(merkle_root(&[a, b]), merkle_root(&[b, a]));
+    assert_ne!(merkle_root(&[a, b, c]), merkle_root(&[a, b, c, c]));
+}
+
+#[test]
+fn header_signature() {
+    let mut secure = NistCryptography::new();
+    secure.init();
+    assert_eq!(secure.generate_keypair(), 0);
+
+    let mut block = sample_block();
+    block.header.proposer = KeyId::from_public_key(&secure.public_key);
+    let unsigned_hash = block.hash();
+    assert!(block.header.sign(&mut secure));
+    assert_eq!(block.header.proposer, KeyId::from_public_key(&secure.public_key));
+    assert_eq!(block.hash(), unsigned_hash);
+    assert!(block.header.verify_signature(&secure.public_key));
+
+    let decoded = Block::decode(&block.encode()).unwrap();
+    assert!(decoded.header.verify_signature(&secure.public_key));
+
+    let mut tampered = block.clone();
+    tampered.header.height += 1;
+    assert!(!tampered.header.verify_signature(&secure.public_key));
+
+    let mut other = NistCryptography::new();
+    other.init();
+    assert_eq!(other.generate_keypair(), 0);
+    assert!(!block.header.verify_signature(&other.public_key));
+}
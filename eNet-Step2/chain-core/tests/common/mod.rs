@@ -0,0 +1,35 @@
+// Helpers shared by the chain-core tests; not every test uses each of them
+#![allow(dead_code)]
+
+use chain_core::{ Block, Chain, KeyId, QuorumCertificate, State, StateError, Transaction, TxKind, Vote, VoteKind };
+use secure_sign::NistCryptography;
+
+pub const CHAIN: &str = "frink-test";
+pub const MAX_TX: usize = 64 * 1024;
+
+pub fn keypair() -> NistCryptography {
+    let mut secure = NistCryptography::new();
+    secure.init();
+    assert_eq!(secure.generate_keypair(), 0);
+    secure
+}
+
+// Sign a transaction of `kind` from `secure` at its next nonce and apply it at `height`
+pub fn send(state: &mut State, secure: &mut NistCryptography, kind: TxKind, payload: Vec<u8>, height: u64) -> Result<(), StateError> {
+    let nonce = state.nonce(&KeyId::from_public_key(&secure.public_key));
+    let mut tx = Transaction::new(CHAIN, kind, payload, nonce, 1, 0);
+    assert!(tx.sign(secure));
+    tx.validate(CHAIN, MAX_TX).unwrap();
+    state.apply_transaction(&tx, height)
+}
+
+// Precommits from the first three of four validators
+pub fn certify(block: &Block, keys: &mut [NistCryptography], chain: &Chain) -> QuorumCertificate {
+    let mut precommits = Vec::new();
+    for secure in &mut keys[..3] {
+        let mut vote = Vote::new(VoteKind::Precommit, block.header.height, 0, Some(block.hash()));
+        assert!(vote.sign(secure, CHAIN));
+        precommits.push(vote);
+    }
+    QuorumCertificate::from_precommits(&chain.validators(), block.header.height, 0, block.hash(), &precommits)
+}
@@ -0,0 +1,162 @@
+mod common;
+
+use chain_core::{ Account, CertError, Chain, Consensus, ConsensusMessage, ConsensusOutput, IdentityStatus, KeyId, Mempool, State, Timeouts, QuorumCertificate, Transaction, TxKind, ValidatorSet, Vote, VoteKind };
+use chain_core::{ Decode, Encode, Hash };
+use secure_sign::NistCryptography;
+use common::{ keypair, CHAIN, MAX_TX };
+
+fn verified(state: &mut State, secure: &NistCryptography, balance: u64) {
+    let account = Account { balance, identity: IdentityStatus::Verified, ..Account::default() };
+    state.set_account(KeyId::from_public_key(&secure.public_key), account);
+}
+
+struct Node {
+    secure: NistCryptography,
+    chain: Chain,
+    pool: Mempool,
+    engine: Consensus,
+}
+
+#[test]
+fn validator_set_and_votes() {
+    let keys: Vec<NistCryptography> = (0..4).map(|_| keypair()).collect();
+    let mut state = State::new();
+    for secure in &keys[..3] {
+        verified(&mut state, secure, 0);
+    }
+    let mut candidates: Vec<Vec<u8>> = keys.iter().map(|k| k.public_key.to_vec()).collect();
+    candidates.push(keys[0].public_key.to_vec());
+
+    // The unverified candidate and the duplicate do not count
+    let set = ValidatorSet::from_registry(&state, &candidates);
+    assert_eq!(set.len(), 3);
+    assert!(!set.contains(&KeyId::from_public_key(&keys[3].public_key)));
+    assert_eq!((set.quorum(), set.weak_quorum()), (3, 2));
+    assert_eq!(set.proposer(5, 1), set.proposer(0, 0));
+
+    let mut signer = keypair();
+    let mut vote = Vote::new(VoteKind::Precommit, 4, 2, None);
+    assert!(vote.sign(&mut signer, CHAIN));
+    assert!(vote.verify(&signer.public_key, CHAIN));
+    assert!(!vote.verify(&signer.public_key, "other-chain"));
+    assert!(!vote.verify(&keys[0].public_key, CHAIN));
+    vote.round = 3;
+    assert!(!vote.verify(&signer.public_key, CHAIN));
+}
+
+#[test]
+fn quorum_certificate_checks() {
+    let mut keys: Vec<NistCryptography> = (0..4).map(|_| keypair()).collect();
+    let mut state = State::new();
+    for secure in &keys {
+        verified(&mut state, secure, 0);
+    }
+    let candidates: Vec<Vec<u8>> = keys.iter().map(|k| k.public_key.to_vec()).collect();
+    let set = ValidatorSet::from_registry(&state, &candidates);
+    let block = Hash([5; 32]);
+
+    // Three of four precommit the block, plus one vote that does not belong
+    let mut precommits = Vec::new();
+    for secure in &mut keys[..3] {
+        let mut vote = Vote::new(VoteKind::Precommit, 7, 1, Some(block));
+        assert!(vote.sign(secure, CHAIN));
+        precommits.push(vote);
+    }
+    let mut nil = Vote::new(VoteKind::Precommit, 7, 1, None);
+    assert!(nil.sign(&mut keys[3], CHAIN));
+    precommits.push(nil);
+
+    let certificate = QuorumCertificate::from_precommits(&set, 7, 1, block, &precommits);
+    assert_eq!((certificate.signer_count(), certificate.signatures.len(), certificate.signers.len()), (3, 3, 1));
+    certificate.verify(&set, CHAIN).unwrap();
+    assert_eq!(QuorumCertificate::decode(&certificate.encode()).unwrap(), certificate);
+    assert_eq!(certificate.verify(&set, "other-chain"), Err(CertError::BadSignature));
+
+    let short = QuorumCertificate::from_precommits(&set, 7, 1, block, &precommits[..2]);
+    assert_eq!(short.verify(&set, CHAIN), Err(CertError::NoQuorum { signers: 2, quorum: 3 }));
+
+    // Claiming a signer without its signature, or one past the set
+    let mut forged = certificate.clone();
+    forged.signers[0] = 0x0f;
+    assert_eq!(forged.verify(&set, CHAIN), Err(CertError::SignatureCount { signers: 4, signatures: 3 }));
+    forged.signers[0] = certificate.signers[0] | 0x10;
+    assert!(matches!(forged.verify(&set, CHAIN), Err(CertError::WrongSet { .. })));
+
+    let mut moved = certificate.clone();
+    moved.round = 2;
+    assert_eq!(moved.verify(&set, CHAIN), Err(CertError::BadSignature));
+}
+
+#[test]
+fn validators_agree_with_one_silent() {
+    let mut sender = keypair();
+    let keys: Vec<NistCryptography> = (0..4).map(|_| keypair()).collect();
+    let mut genesis = State::new();
+    for secure in &keys {
+        verified(&mut genesis, secure, 0);
+    }
+    verified(&mut genesis, &sender, 100);
+    let mut tx = Transaction::new(CHAIN, TxKind::Data, vec![1, 2, 3], 0, 10, 0);
+    assert!(tx.sign(&mut sender));
+
+    let timeouts = Timeouts { propose: 300, vote: 200, delta: 100, commit: 100 };
+    let validator_keys: Vec<Vec<u8>> = keys.iter().map(|k| k.public_key.to_vec()).collect();
+    let mut nodes: Vec<Node> = keys.into_iter().map(|secure| {
+        let mut chain = Chain::new(CHAIN, 1 << 20, MAX_TX);
+        chain.state = genesis.clone();
+        chain.validator_keys = validator_keys.clone();
+        let mut pool = Mempool::new(CHAIN, MAX_TX, 1 << 20, 600_000);
+        pool.insert(tx.clone(), &genesis, 0, 0).unwrap();
+        Node { secure, chain, pool, engine: Consensus::new(timeouts.clone()) }
+    }).collect();
+
+    // The last validator never speaks or listens; its turns to propose time out
+    let active = 3;
+    let mut now = 1_000_000;
+    let mut rounds = Vec::new();
+    while nodes[..active].iter().any(|n| n.chain.next_height() < 6) {
+        assert!(now < 1_100_000, "no progress");
+        now += 50;
+
+        let mut queue: Vec<(usize, ConsensusMessage)> = Vec::new();
+        for (i, node) in nodes[..active].iter_mut().enumerate() {
+            let outputs = node.engine.tick(&node.chain, &node.pool, &mut node.secure, now);
+            collect(i, node, outputs, &mut queue, &mut rounds, now);
+        }
+        while let Some((from, msg)) = queue.pop() {
+            for (i, node) in nodes[..active].iter_mut().enumerate() {
+                if i != from {
+                    let outputs = node.engine.handle(msg.clone(), &node.chain, &node.pool, &mut node.secure, now);
+                    collect(i, node, outputs, &mut queue, &mut rounds, now);
+                }
+            }
+        }
+    }
+
+    let tip = nodes[0].chain.tip_hash();
+    for node in &nodes[..active] {
+        assert_eq!(node.chain.tip_hash(), tip);
+        assert_eq!(node.chain.state.root(), nodes[0].chain.state.root());
+        assert_eq!(node.chain.state.nonce(&tx.sender), 1);
+        assert!(node.pool.is_empty());
+    }
+    assert_eq!(nodes[active].chain.next_height(), 0);
+    // Some height needed a second round to get past the silent proposer
+    assert!(rounds.iter().any(|r| *r > 0));
+}
+
+fn collect(i: usize, node: &mut Node, outputs: Vec<ConsensusOutput>, queue: &mut Vec<(usize, ConsensusMessage)>, rounds: &mut Vec<u32>, now: u64) {
+    for output in outputs {
+        match output {
+            ConsensusOutput::Broadcast(msg) => queue.push((i, msg)),
+            ConsensusOutput::Decided { block, state, certificate } => {
+                assert_eq!(certificate.block, block.hash());
+                certificate.verify(node.engine.validators(), CHAIN).unwrap();
+                assert_eq!(block.header.last_commit.is_some(), block.header.height > 0);
+                rounds.push(certificate.round);
+                node.chain.commit(&block, *state, certificate).unwrap();
+                node.pool.prune(&node.chain.state, node.chain.next_height(), now);
+            },
+        }
+    }
+}
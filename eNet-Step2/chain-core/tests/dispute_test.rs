@@ -0,0 +1,159 @@
+mod common;
+
+use chain_core::{ Account, Ballot, Challenge, Deactivation, Dispute, Encode, Genesis, GenesisAccount, Hash, IdentityStatus, KeyId, PersonId, PersonStatus, Registration, State, StateError, TxKind, hash_with_domain };
+use chain_core::{ JURY_SIZE, MIN_CHALLENGE_BOND, RESOLUTION_BLOCKS };
+use secure_sign::NistCryptography;
+use common::{ keypair, send, CHAIN };
+
+// Eight verified validators, a funded challenger and two pending registrants
+struct Registry {
+    state: State,
+    validators: Vec<NistCryptography>,
+    challenger: NistCryptography,
+    carol: NistCryptography,
+    carol_id: PersonId,
+    dave_id: PersonId,
+}
+
+fn registry() -> Registry {
+    let validators: Vec<NistCryptography> = (0..8).map(|_| keypair()).collect();
+    let challenger = keypair();
+    let mut genesis = Genesis::new(CHAIN, 0);
+    genesis.validators = validators.iter().map(|k| k.public_key.to_vec()).collect();
+    for public_key in validators.iter().chain(Some(&challenger)).map(|k| k.public_key.to_vec()) {
+        genesis.accounts.push(GenesisAccount { public_key, balance: 5_000, identity: IdentityStatus::Unverified });
+    }
+    let mut state = genesis.state();
+
+    let mut carol = keypair();
+    let mut dave = keypair();
+    for (secure, height) in [(&mut carol, 1), (&mut dave, 2)] {
+        state.set_account(KeyId::from_public_key(&secure.public_key), Account { balance: 10, ..Account::default() });
+        send(&mut state, secure, TxKind::Register, Registration { evidence: Vec::new() }.encode(), height).unwrap();
+    }
+    let carol_id = PersonId::from_public_key(&carol.public_key);
+    let dave_id = PersonId::from_public_key(&dave.public_key);
+
+    Registry { state, validators, challenger, carol, carol_id, dave_id }
+}
+
+// Apply an empty block at `height` over a parent hash made up from it
+fn next_block(registry: &mut Registry, height: u64) {
+    let parent = hash_with_domain(b"dispute-test", &height.to_be_bytes());
+    registry.state.apply_transactions(&[], height, &parent, &KeyId::from_public_key(b"proposer")).unwrap();
+}
+
+fn ballot(registry: &mut Registry, dispute: Hash, juror: PersonId, uphold: bool, height: u64) -> Result<(), StateError> {
+    let secure = registry.validators.iter_mut().find(|k| PersonId::from_public_key(&k.public_key) == juror).unwrap();
+    send(&mut registry.state, secure, TxKind::Ballot, Ballot { dispute, juror, uphold }.encode(), height)
+}
+
+#[test]
+fn upheld_challenge_revokes_the_later_entry() {
+    let mut r = registry();
+    let (carol_id, dave_id) = (r.carol_id, r.dave_id);
+    let challenger_key = KeyId::from_public_key(&r.challenger.public_key);
+    let challenge = |first, second, bond| Challenge { first, second, bond }.encode();
+
+    assert_eq!(send(&mut r.state, &mut r.challenger, TxKind::Challenge, challenge(carol_id, carol_id, MIN_CHALLENGE_BOND), 3), Err(StateError::SamePerson));
+    assert_eq!(send(&mut r.state, &mut r.challenger, TxKind::Challenge, challenge(carol_id, dave_id, 10), 3), Err(StateError::BondTooLow { bond: 10, min: MIN_CHALLENGE_BOND }));
+    send(&mut r.state, &mut r.challenger, TxKind::Challenge, challenge(dave_id, carol_id, MIN_CHALLENGE_BOND), 3).unwrap();
+    assert_eq!(r.state.balance(&challenger_key), 5_000 - 1 - MIN_CHALLENGE_BOND);
+
+    // One dispute per pair, whichever way round
+    let id = Dispute::id(&carol_id, &dave_id);
+    assert_eq!(send(&mut r.state, &mut r.challenger, TxKind::Challenge, challenge(carol_id, dave_id, MIN_CHALLENGE_BOND), 4), Err(StateError::DisputeOpen(id)));
+    let dispute = r.state.dispute(&id).unwrap().clone();
+    assert_eq!((dispute.jurors.len(), dispute.deadline), (0, 3 + RESOLUTION_BLOCKS));
+
+    // Nobody sits until the next block draws the jury from the hash of the one the challenge landed in
+    let juror = PersonId::from_public_key(&r.validators[0].public_key);
+    assert_eq!(ballot(&mut r, id, juror, true, 3), Err(StateError::NotJuror(juror)));
+    next_block(&mut r, 4);
+    let dispute = r.state.dispute(&id).unwrap().clone();
+    assert_eq!(dispute.jurors.len(), JURY_SIZE);
+
+    // The accused cannot walk away meanwhile
+    let deactivate = Deactivation { person: carol_id }.encode();
+    assert_eq!(send(&mut r.state, &mut r.carol, TxKind::Deactivate, deactivate, 4), Err(StateError::UnderDispute(carol_id)));
+
+    let outsider = r.validators.iter().map(|k| PersonId::from_public_key(&k.public_key)).find(|p| !dispute.jurors.contains(p)).unwrap();
+    assert_eq!(ballot(&mut r, id, outsider, true, 4), Err(StateError::NotJuror(outsider)));
+    ballot(&mut r, id, dispute.jurors[0], true, 4).unwrap();
+    assert_eq!(ballot(&mut r, id, dispute.jurors[0], false, 4), Err(StateError::AlreadyVoted(dispute.jurors[0])));
+    ballot(&mut r, id, dispute.jurors[1], false, 4).unwrap();
+    ballot(&mut r, id, dispute.jurors[2], true, 5).unwrap();
+    assert_eq!(r.state.person(&dave_id).unwrap().status, PersonStatus::Pending);
+
+    // The majority settles it: Dave registered later, and the bond comes back
+    ballot(&mut r, id, dispute.jurors[3], true, 5).unwrap();
+    assert_eq!(r.state.dispute(&id), None);
+    assert_eq!(r.state.person(&dave_id).unwrap().status, PersonStatus::Revoked);
+    assert_eq!(r.state.person(&carol_id).unwrap().status, PersonStatus::Pending);
+    assert_eq!(r.state.balance(&challenger_key), 5_000 - 1);
+    assert_eq!(ballot(&mut r, id, dispute.jurors[4], true, 5), Err(StateError::UnknownDispute(id)));
+}
+
+#[test]
+fn dismissed_bonds_pay_the_jury_and_silent_juries_lapse() {
+    let mut r = registry();
+    let (carol_id, dave_id) = (r.carol_id, r.dave_id);
+    let challenger_key = KeyId::from_public_key(&r.challenger.public_key);
+    let challenge = Challenge { first: carol_id, second: dave_id, bond: MIN_CHALLENGE_BOND }.encode();
+
+    send(&mut r.state, &mut r.challenger, TxKind::Challenge, challenge.clone(), 3).unwrap();
+    next_block(&mut r, 4);
+    let id = Dispute::id(&carol_id, &dave_id);
+    let jurors = r.state.dispute(&id).unwrap().jurors.clone();
+    for juror in &jurors[..3] {
+        ballot(&mut r, id, *juror, false, 4).unwrap();
+    }
+    assert_eq!(r.state.dispute(&id), None);
+    for juror in &jurors[..3] {
+        let key = r.state.person(juror).unwrap().key_id();
+        assert_eq!(r.state.balance(&key), 5_000 - 1 + MIN_CHALLENGE_BOND / 3);
+    }
+    assert_eq!(r.state.person(&dave_id).unwrap().status, PersonStatus::Pending);
+    assert_eq!(r.state.balance(&challenger_key), 5_000 - 1 - MIN_CHALLENGE_BOND);
+
+    // Nobody votes in time: the next block past the deadline returns the bond
+    send(&mut r.state, &mut r.challenger, TxKind::Challenge, challenge.clone(), 10).unwrap();
+    next_block(&mut r, 11);
+    let jurors = r.state.dispute(&id).unwrap().jurors.clone();
+    next_block(&mut r, 10 + RESOLUTION_BLOCKS);
+    assert!(r.state.dispute(&id).is_some());
+    assert_eq!(ballot(&mut r, id, jurors[0], true, 11 + RESOLUTION_BLOCKS), Err(StateError::DisputeClosed(id)));
+    next_block(&mut r, 11 + RESOLUTION_BLOCKS);
+    assert_eq!(r.state.dispute(&id), None);
+    assert_eq!(r.state.balance(&challenger_key), 5_000 - 2 - MIN_CHALLENGE_BOND);
+
+    // A deadline past the last height is refused rather than wrapped
+    assert_eq!(send(&mut r.state, &mut r.challenger, TxKind::Challenge, challenge, u64::MAX - 1), Err(StateError::Overflow));
+}
+
+#[test]
+fn persons_out_of_liveness_are_not_drawn() {
+    let mut r = registry();
+    let (carol_id, dave_id) = (r.carol_id, r.dave_id);
+
+    // Three validators' liveness runs out before the jury is drawn
+    let stale: Vec<PersonId> = r.validators[..3].iter().map(|k| PersonId::from_public_key(&k.public_key)).collect();
+    for id in &stale {
+        let mut person = r.state.person(id).unwrap().clone();
+        person.live_until = 3;
+        r.state.set_person(*id, person);
+    }
+    let challenge = Challenge { first: carol_id, second: dave_id, bond: MIN_CHALLENGE_BOND }.encode();
+    send(&mut r.state, &mut r.challenger, TxKind::Challenge, challenge, 3).unwrap();
+    next_block(&mut r, 4);
+
+    // Every juror drawn can still vote
+    let id = Dispute::id(&carol_id, &dave_id);
+    let jurors = r.state.dispute(&id).unwrap().jurors.clone();
+    assert_eq!(jurors.len(), JURY_SIZE);
+    assert!(jurors.iter().all(|juror| !stale.contains(juror)));
+    for juror in &jurors[..3] {
+        ballot(&mut r, id, *juror, false, 4).unwrap();
+    }
+    assert_eq!(r.state.dispute(&id), None);
+}
@@ -0,0 +1,163 @@
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use chain_core::{ Account, Block, Chain, ChainError, ChainEvent, IdentityStatus, KeyId, Mempool, QuorumCertificate, State, Store, Transaction, TxKind };
+use secure_sign::NistCryptography;
+use common::{ certify, keypair, CHAIN, MAX_TX };
+
+const NOW: u64 = 1_600_000_000_000;
+
+fn chain_at(genesis: &State, keys: &[NistCryptography]) -> Chain {
+    let mut chain = Chain::new(CHAIN, 1 << 20, MAX_TX);
+    chain.state = genesis.clone();
+    chain.validator_keys = keys.iter().map(|k| k.public_key.to_vec()).collect();
+    chain
+}
+
+fn stored_at(dir: &Path, genesis: &State, keys: &[NistCryptography]) -> Chain {
+    let mut chain = chain_at(genesis, keys);
+    chain.attach_store(Store::open(dir).unwrap()).unwrap();
+    // Without a Genesis the chain cannot tell what an empty store starts from
+    if chain.tip().is_none() {
+        chain.state = genesis.clone();
+    }
+    chain
+}
+
+// Build on `chain` as validator `proposer`, certify and commit
+fn extend(chain: &mut Chain, pool: &Mempool, keys: &mut [NistCryptography], proposer: usize, now: u64) -> (Block, QuorumCertificate) {
+    let (block, state) = chain.build_block(pool, &mut keys[proposer], now).unwrap();
+    let certificate = certify(&block, keys, chain);
+    chain.commit(&block, state, certificate.clone()).unwrap();
+    (block, certificate)
+}
+
+#[test]
+fn reorg_to_certified_branch() {
+    let dir = env::temp_dir().join(format!("frink-fork-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    let mut keys: Vec<NistCryptography> = (0..4).map(|_| keypair()).collect();
+    let mut sender = keypair();
+    let mut genesis = State::new();
+    for secure in keys.iter().chain(Some(&sender)) {
+        let account = Account { balance: 100, identity: IdentityStatus::Verified, ..Account::default() };
+        genesis.set_account(KeyId::from_public_key(&secure.public_key), account);
+    }
+    let mut tx = Transaction::new(CHAIN, TxKind::Data, vec![1], 0, 5, 0);
+    assert!(tx.sign(&mut sender));
+    let mut pool = Mempool::new(CHAIN, MAX_TX, 1 << 20, 600_000);
+    pool.insert(tx.clone(), &genesis, 0, 0).unwrap();
+    let empty = Mempool::new(CHAIN, MAX_TX, 1 << 20, 600_000);
+
+    // Two nodes agree on genesis, then build different first blocks
+    let mut left = chain_at(&genesis, &keys);
+    let mut right = chain_at(&genesis, &keys);
+    let (g, g_cert) = extend(&mut left, &empty, &mut keys, 0, NOW);
+    right.add_block(g.clone(), Some(g_cert.clone()), NOW).unwrap();
+    assert_eq!(right.finalized(), Some((g.hash(), 0)));
+    let (a1, _) = extend(&mut left, &pool, &mut keys, 1, NOW + 10);
+    let (b1, _) = extend(&mut right, &empty, &mut keys, 2, NOW + 20);
+    let (b2, _) = extend(&mut right, &empty, &mut keys, 3, NOW + 30);
+
+    let mut chain = chain_at(&genesis, &keys);
+    chain.attach_store(Store::open(&dir).unwrap()).unwrap();
+    chain.state = genesis.clone();
+    let events = chain.subscribe();
+    chain.add_block(g.clone(), Some(g_cert), NOW).unwrap();
+    chain.add_block(a1.clone(), None, NOW).unwrap();
+    assert_eq!((chain.tip_hash(), chain.finalized()), (a1.hash(), Some((g.hash(), 0))));
+    assert!(chain.store().unwrap().transaction(&tx.id()).unwrap().is_some());
+
+    // Same height and nothing certified: the current tip stays
+    chain.add_block(b1.clone(), None, NOW).unwrap();
+    assert_eq!(chain.tip_hash(), a1.hash());
+    assert_eq!(chain.pending().len(), 2);
+
+    // b2 carries the certificate of b1, which outweighs a1 and finalizes b1
+    chain.add_block(b2.clone(), None, NOW).unwrap();
+    assert_eq!(chain.tip_hash(), b2.hash());
+    assert_eq!(chain.finalized(), Some((b1.hash(), 1)));
+    assert_eq!(chain.state, right.state);
+    let store = chain.store().unwrap();
+    assert_eq!(store.hash_at(1).unwrap(), Some(b1.hash()));
+    assert_eq!(store.tip().unwrap().unwrap().hash, b2.hash());
+    assert!(store.transaction(&tx.id()).unwrap().is_none());
+
+    let received: Vec<ChainEvent> = events.try_iter().collect();
+    assert!(received.contains(&ChainEvent::Reorg { ancestor: g.hash(), reverted: vec![a1.clone()], applied: vec![b1.clone(), b2.clone()] }));
+    assert_eq!(received.last(), Some(&ChainEvent::Finalized { hash: b1.hash(), height: 1 }));
+
+    // The losing branch can no longer come back
+    assert!(matches!(chain.add_block(a1.clone(), None, NOW), Err(ChainError::ConflictsWithFinalized { height: 1 })));
+    chain.add_block(b1.clone(), None, NOW).unwrap();
+
+    // A bad block is dropped without moving the tip
+    let (mut bad, _) = right.build_block(&empty, &mut keys[0], NOW + 40).unwrap();
+    bad.header.state_root = a1.header.state_root;
+    assert!(bad.header.sign(&mut keys[0]));
+    assert!(matches!(chain.add_block(bad, None, NOW), Err(ChainError::State(_))));
+    assert_eq!(chain.tip_hash(), b2.hash());
+    assert_eq!(chain.pending().len(), 1);
+
+    drop(chain);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn restart_keeps_unfinalized_blocks_pending() {
+    let dir = env::temp_dir().join(format!("frink-fork-restart-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    let mut keys: Vec<NistCryptography> = (0..4).map(|_| keypair()).collect();
+    let mut genesis = State::new();
+    for secure in &keys {
+        let account = Account { balance: 100, identity: IdentityStatus::Verified, ..Account::default() };
+        genesis.set_account(KeyId::from_public_key(&secure.public_key), account);
+    }
+    let empty = Mempool::new(CHAIN, MAX_TX, 1 << 20, 600_000);
+
+    let mut left = chain_at(&genesis, &keys);
+    let mut right = chain_at(&genesis, &keys);
+    let (g, g_cert) = extend(&mut left, &empty, &mut keys, 0, NOW);
+    right.add_block(g.clone(), Some(g_cert.clone()), NOW).unwrap();
+    let (a1, _) = extend(&mut left, &empty, &mut keys, 1, NOW + 10);
+    let (b1, _) = extend(&mut right, &empty, &mut keys, 2, NOW + 20);
+    let (b2, _) = extend(&mut right, &empty, &mut keys, 3, NOW + 30);
+
+    // a1 is stored without a certificate and must not come back as final
+    let mut chain = stored_at(&dir, &genesis, &keys);
+    chain.add_block(g.clone(), Some(g_cert), NOW).unwrap();
+    chain.add_block(a1.clone(), None, NOW).unwrap();
+    drop(chain);
+    let mut chain = stored_at(&dir, &genesis, &keys);
+    assert_eq!((chain.tip_hash(), chain.finalized()), (a1.hash(), Some((g.hash(), 0))));
+    assert!(chain.pending().contains(&a1.hash()));
+    assert!(chain.last_commit().is_none());
+
+    // So the certified branch can still replace it
+    chain.add_block(b1.clone(), None, NOW).unwrap();
+    chain.add_block(b2.clone(), None, NOW).unwrap();
+    assert_eq!((chain.tip_hash(), chain.finalized()), (b2.hash(), Some((b1.hash(), 1))));
+    drop(chain);
+
+    let mut chain = stored_at(&dir, &genesis, &keys);
+    assert_eq!((chain.tip_hash(), chain.finalized()), (b2.hash(), Some((b1.hash(), 1))));
+    assert_eq!(chain.pending().len(), 1);
+    assert_eq!(chain.state, right.state);
+    assert!(matches!(chain.add_block(a1.clone(), None, NOW), Err(ChainError::ConflictsWithFinalized { height: 1 })));
+
+    // The next certified block finalizes the restored tip and building goes on from it
+    let (b3, b3_cert) = extend(&mut right, &empty, &mut keys, 0, NOW + 40);
+    chain.add_block(b3.clone(), Some(b3_cert), NOW + 40).unwrap();
+    assert_eq!((chain.tip_hash(), chain.finalized()), (b3.hash(), Some((b3.hash(), 3))));
+    assert_eq!(chain.store().unwrap().finalized().unwrap().unwrap().hash, b3.hash());
+    let (b4, _) = chain.build_block(&empty, &mut keys[1], NOW + 50).unwrap();
+    assert_eq!(b4.header.last_commit.as_ref().map(|c| c.block), Some(b3.hash()));
+
+    drop(chain);
+    let _ = fs::remove_dir_all(&dir);
+}
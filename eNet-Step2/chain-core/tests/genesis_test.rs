@@ -0,0 +1,104 @@
+mod common;
+
+use std::env;
+use std::fs;
+
+use chain_core::{ Chain, ChainError, Genesis, GenesisAccount, GenesisError, IdentityStatus, KeyId, Mempool, QuorumCertificate, Store, Vote, VoteKind };
+use secure_sign::NistCryptography;
+use common::{ keypair, CHAIN, MAX_TX };
+
+const NOW: u64 = 1_600_000_000_000;
+
+fn genesis_of(keys: &[NistCryptography]) -> Genesis {
+    let mut genesis = Genesis::new(CHAIN, NOW);
+    genesis.validators = keys.iter().map(|k| k.public_key.to_vec()).collect();
+    genesis.accounts.push(GenesisAccount { public_key: keys[0].public_key.to_vec(), balance: 500, identity: IdentityStatus::Unverified });
+    genesis
+}
+
+#[test]
+fn genesis_hash_and_state() {
+    let keys: Vec<NistCryptography> = (0..3).map(|_| keypair()).collect();
+    let genesis = genesis_of(&keys);
+    genesis.validate().unwrap();
+
+    // Order does not matter, values do
+    let mut reordered = genesis.clone();
+    reordered.validators.reverse();
+    assert_eq!(reordered.hash(), genesis.hash());
+    let mut richer = genesis.clone();
+    richer.accounts[0].balance += 1;
+    assert_ne!(richer.hash(), genesis.hash());
+
+    // Validators start verified, keeping a listed balance
+    let state = genesis.state();
+    assert_eq!(state.len(), 3);
+    let first = state.account(&KeyId::from_public_key(&keys[0].public_key)).unwrap();
+    assert_eq!((first.balance, first.identity), (500, IdentityStatus::Verified));
+
+    let mut twice = genesis.clone();
+    twice.validators.push(keys[1].public_key.to_vec());
+    assert!(matches!(twice.validate(), Err(GenesisError::DuplicateValidator(_))));
+    let mut short = genesis.clone();
+    short.validators[1].pop();
+    assert!(matches!(short.validate(), Err(GenesisError::BadKey { index: 1, .. })));
+    let mut nobody = genesis.clone();
+    nobody.validators.clear();
+    assert_eq!(nobody.validate(), Err(GenesisError::NoValidators));
+    let mut big_tx = genesis.clone();
+    big_tx.params.max_tx_bytes = big_tx.params.max_block_bytes + 1;
+    assert!(matches!(big_tx.validate(), Err(GenesisError::TxLargerThanBlock { .. })));
+}
+
+#[test]
+fn chain_starts_from_genesis_and_keeps_to_it() {
+    let dir = env::temp_dir().join(format!("frink-genesis-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    let mut keys: Vec<NistCryptography> = (0..3).map(|_| keypair()).collect();
+    let genesis = genesis_of(&keys);
+    let mut chain = Chain::new("other", 1, 1);
+    chain.set_genesis(&genesis).unwrap();
+    chain.attach_store(Store::open(&dir).unwrap()).unwrap();
+    assert_eq!((chain.chain_id.as_str(), chain.max_tx_bytes), (CHAIN, MAX_TX));
+    assert_eq!(chain.validators().len(), 3);
+    assert_eq!(chain.state, genesis.state());
+    assert_eq!(chain.store().unwrap().genesis().unwrap(), Some(genesis.hash()));
+
+    // No block before genesis time
+    let pool = Mempool::new(CHAIN, MAX_TX, 1 << 20, 600_000);
+    let (early, _) = chain.build_block(&pool, &mut keys[0], NOW - 1).unwrap();
+    match chain.check_block(&early, NOW) {
+        Err(ChainError::BeforeGenesis { genesis_time: NOW, found }) => assert_eq!(found, NOW - 1),
+        other => panic!("{:?}", other),
+    }
+
+    let (block, state) = chain.build_block(&pool, &mut keys[0], NOW).unwrap();
+    let mut precommits = Vec::new();
+    for secure in &mut keys {
+        let mut vote = Vote::new(VoteKind::Precommit, 0, 0, Some(block.hash()));
+        assert!(vote.sign(secure, CHAIN));
+        precommits.push(vote);
+    }
+    let certificate = QuorumCertificate::from_precommits(&chain.validators(), 0, 0, block.hash(), &precommits);
+    chain.commit(&block, state, certificate).unwrap();
+    drop(chain);
+
+    // The store belongs to this genesis only
+    let mut other = genesis.clone();
+    other.genesis_time += 1;
+    let mut chain = Chain::new(CHAIN, 1 << 20, MAX_TX);
+    chain.set_genesis(&other).unwrap();
+    match chain.attach_store(Store::open(&dir).unwrap()) {
+        Err(ChainError::WrongGenesis { expected, found }) => assert_eq!((expected, found), (other.hash(), Some(genesis.hash()))),
+        other => panic!("{:?}", other.err()),
+    }
+    let mut chain = Chain::new(CHAIN, 1 << 20, MAX_TX);
+    chain.set_genesis(&genesis).unwrap();
+    chain.attach_store(Store::open(&dir).unwrap()).unwrap();
+    assert_eq!(chain.tip_hash(), block.hash());
+    assert_eq!(chain.validators().len(), 3);
+
+    drop(chain);
+    let _ = fs::remove_dir_all(&dir);
+}
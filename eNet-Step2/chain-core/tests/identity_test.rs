@@ -0,0 +1,191 @@
+mod common;
+
+use chain_core::{ Account, Attestation, ClaimType, Deactivation, Encode, Genesis, GenesisAccount, Hash, IdentityStatus, KeyId, KeyUpdate, LivenessClaim, PersonId, PersonStatus, Registration, State, StateError, Transaction, TxError, TxKind, hash_with_domain };
+use chain_core::{ ATTESTATIONS_TO_VERIFY, MAX_STRIKES, MAX_VOUCHES, VOUCH_PENALTY };
+use secure_sign::NistCryptography;
+use common::{ keypair, CHAIN, MAX_TX };
+
+fn signed(kind: TxKind, payload: Vec<u8>, nonce: u64, secure: &mut NistCryptography) -> Transaction {
+    let mut tx = Transaction::new(CHAIN, kind, payload, nonce, 1, 0);
+    assert!(tx.sign(secure));
+    tx.validate(CHAIN, MAX_TX).unwrap();
+    tx
+}
+
+#[test]
+fn register_update_key_and_deactivate() {
+    let mut alice = keypair();
+    let alice_key = KeyId::from_public_key(&alice.public_key);
+    let proposer = KeyId::from_public_key(&keypair().public_key);
+    let mut state = State::new();
+    state.set_account(alice_key, Account { balance: 100, ..Account::default() });
+
+    let evidence = vec![hash_with_domain(b"identity-test", b"passport scan")];
+    let register = signed(TxKind::Register, Registration { evidence: evidence.clone() }.encode(), 0, &mut alice);
+    state.apply_transactions(std::slice::from_ref(&register), 3, &Hash::ZERO, &proposer).unwrap();
+    let id = PersonId::from_public_key(&alice.public_key);
+    let person = state.person(&id).unwrap().clone();
+    assert_eq!((person.status, person.evidence, person.registered_at), (PersonStatus::Pending, evidence, 3));
+    assert_eq!(state.person_of(&alice_key), Some(id));
+    assert!(state.prove_record(&id.0).verify_record(&state.root(), &id.0, state.record(&id.0)));
+
+    // One entry per key
+    let again = signed(TxKind::Register, Registration { evidence: Vec::new() }.encode(), 1, &mut alice);
+    assert_eq!(state.apply_transaction(&again, 4), Err(StateError::AlreadyRegistered(id)));
+
+    // The new key has to sign for the move
+    let mut next = keypair();
+    let next_key = KeyId::from_public_key(&next.public_key);
+    let update = KeyUpdate::new(id, &alice_key, &mut next).unwrap();
+    let mut forged = update.clone();
+    forged.public_key = keypair().public_key.to_vec();
+    let mut tx = Transaction::new(CHAIN, TxKind::UpdateKey, forged.encode(), 1, 1, 0);
+    assert!(tx.sign(&mut alice));
+    assert_eq!(tx.validate(CHAIN, MAX_TX), Err(TxError::BadKeyProof));
+
+    // A failing transaction later in the batch undoes the move
+    let move_key = signed(TxKind::UpdateKey, update.encode(), 1, &mut alice);
+    let before = state.clone();
+    assert_eq!(state.apply_transactions(&[move_key.clone(), again], 5, &Hash::ZERO, &proposer), Err(StateError::BadNonce { expected: 2, found: 1 }));
+    assert_eq!(state, before);
+
+    state.apply_transactions(&[move_key], 5, &Hash::ZERO, &proposer).unwrap();
+    let person = state.person(&id).unwrap();
+    assert_eq!((person.key_id(), person.registered_at, person.updated_at), (next_key, 3, 5));
+    assert_eq!((state.person_of(&alice_key), state.person_of(&next_key)), (None, Some(id)));
+
+    // Only the current key speaks for the person
+    let deactivate = Deactivation { person: id }.encode();
+    let stale = signed(TxKind::Deactivate, deactivate.clone(), 2, &mut alice);
+    assert_eq!(state.apply_transaction(&stale, 6), Err(StateError::NotPersonKey(id)));
+    let unknown = signed(TxKind::Deactivate, Deactivation { person: PersonId(Hash::ZERO) }.encode(), 2, &mut alice);
+    assert_eq!(state.apply_transaction(&unknown, 6), Err(StateError::UnknownPerson(PersonId(Hash::ZERO))));
+
+    state.set_account(next_key, Account { balance: 10, ..state.account(&next_key).unwrap().clone() });
+    let done = signed(TxKind::Deactivate, deactivate.clone(), 0, &mut next);
+    state.apply_transaction(&done, 6).unwrap();
+    assert_eq!(state.person(&id).unwrap().status, PersonStatus::Revoked);
+    assert_eq!(state.account(&next_key).unwrap().identity, IdentityStatus::Revoked);
+    let twice = signed(TxKind::Deactivate, deactivate, 1, &mut next);
+    assert_eq!(state.apply_transaction(&twice, 7), Err(StateError::PersonRevoked(id)));
+}
+
+// A funded key registered as a pending person
+fn register(state: &mut State, secure: &mut NistCryptography) -> PersonId {
+    state.set_account(KeyId::from_public_key(&secure.public_key), Account { balance: 10, ..Account::default() });
+    let tx = signed(TxKind::Register, Registration { evidence: Vec::new() }.encode(), 0, secure);
+    state.apply_transaction(&tx, 1).unwrap();
+    PersonId::from_public_key(&secure.public_key)
+}
+
+fn attest(state: &mut State, secure: &mut NistCryptography, subject: PersonId, expiry_height: u64, height: u64) -> Result<(), StateError> {
+    let attester = PersonId::from_public_key(&secure.public_key);
+    let payload = Attestation { attester, subject, claim: ClaimType::UniqueHuman, expiry_height }.encode();
+    let nonce = state.nonce(&KeyId::from_public_key(&secure.public_key));
+    state.apply_transaction(&signed(TxKind::Attest, payload, nonce, secure), height)
+}
+
+#[test]
+fn attestations_verify_and_vouchers_answer_for_duplicates() {
+    let mut vouchers: Vec<NistCryptography> = (0..4).map(|_| keypair()).collect();
+    let ids: Vec<PersonId> = vouchers.iter().map(|k| PersonId::from_public_key(&k.public_key)).collect();
+    let mut genesis = Genesis::new(CHAIN, 0);
+    genesis.validators = vouchers.iter().map(|k| k.public_key.to_vec()).collect();
+    for secure in &vouchers {
+        genesis.accounts.push(GenesisAccount { public_key: secure.public_key.to_vec(), balance: 150, identity: IdentityStatus::Unverified });
+    }
+    let mut state = genesis.state();
+    assert_eq!(ATTESTATIONS_TO_VERIFY, 3);
+
+    let mut carol = keypair();
+    let carol_id = register(&mut state, &mut carol);
+    let mut dave = keypair();
+    let dave_id = register(&mut state, &mut dave);
+
+    // Pending persons cannot vouch, and an expired attestation is refused
+    assert_eq!(attest(&mut state, &mut dave, carol_id, 0, 2), Err(StateError::NotVerified(dave_id)));
+    assert_eq!(attest(&mut state, &mut vouchers[0], carol_id, 1, 2), Err(StateError::Expired { expiry_height: 1, height: 2 }));
+
+    // The same attester counts once; an attestation that expires stops counting
+    attest(&mut state, &mut vouchers[0], carol_id, 0, 2).unwrap();
+    assert_eq!(attest(&mut state, &mut vouchers[0], carol_id, 0, 2), Err(StateError::AlreadyAttested(ids[0])));
+    attest(&mut state, &mut vouchers[1], carol_id, 3, 2).unwrap();
+    attest(&mut state, &mut vouchers[2], carol_id, 0, 4).unwrap();
+    assert_eq!(state.person(&carol_id).unwrap().status, PersonStatus::Pending);
+    attest(&mut state, &mut vouchers[3], carol_id, 0, 4).unwrap();
+    let carol_person = state.person(&carol_id).unwrap();
+    assert_eq!((carol_person.status, carol_person.updated_at), (PersonStatus::Verified, 4));
+    assert_eq!(state.account(&KeyId::from_public_key(&carol.public_key)).unwrap().identity, IdentityStatus::Verified);
+    assert_eq!(state.person(&ids[1]).unwrap().vouches, 1);
+    assert_eq!(attest(&mut state, &mut vouchers[1], carol_id, 0, 5), Err(StateError::NotPending(carol_id)));
+
+    // Nobody vouches without limit
+    let mut busy = state.person(&ids[1]).unwrap().clone();
+    busy.vouches = MAX_VOUCHES;
+    state.set_person(ids[1], busy);
+    assert_eq!(attest(&mut state, &mut vouchers[1], dave_id, 0, 5), Err(StateError::VouchLimit(ids[1])));
+
+    // Carol turns out to be someone already registered: her vouchers pay, the
+    // one who vouched twice for duplicates is suspended
+    attest(&mut state, &mut vouchers[0], dave_id, 0, 5).unwrap();
+    assert_eq!(state.revoke_duplicate(&carol_id, 6), Ok(3 * VOUCH_PENALTY));
+    assert_eq!(state.person(&carol_id).unwrap().status, PersonStatus::Revoked);
+    assert_eq!(state.account(&KeyId::from_public_key(&carol.public_key)).unwrap().identity, IdentityStatus::Revoked);
+    assert_eq!(state.person(&ids[1]).unwrap().status, PersonStatus::Verified);
+    let first = state.person(&ids[0]).unwrap();
+    assert_eq!((first.strikes, state.balance(&first.key_id())), (1, 150 - 2 - VOUCH_PENALTY));
+
+    assert_eq!(MAX_STRIKES, 2);
+    assert_eq!(state.revoke_duplicate(&dave_id, 7), Ok(150 - 2 - VOUCH_PENALTY));
+    assert_eq!(state.person(&ids[0]).unwrap().status, PersonStatus::Suspended);
+    assert_eq!(state.account(&KeyId::from_public_key(&vouchers[0].public_key)).unwrap().identity, IdentityStatus::Unverified);
+    assert_eq!(state.revoke_duplicate(&dave_id, 7), Err(StateError::PersonRevoked(dave_id)));
+
+    // Suspension is for good: they vouch for nobody, and no claim of liveness brings them back
+    let key = KeyId::from_public_key(&vouchers[0].public_key);
+    let mut account = state.account(&key).unwrap().clone();
+    account.balance = 10;
+    state.set_account(key, account);
+    let mut erin = keypair();
+    let erin_id = register(&mut state, &mut erin);
+    assert_eq!(attest(&mut state, &mut vouchers[0], erin_id, 0, 8), Err(StateError::NotVerified(ids[0])));
+    let claim = LivenessClaim { person: ids[0], cosign: None }.encode();
+    let nonce = state.nonce(&key);
+    assert_eq!(state.apply_transaction(&signed(TxKind::Liveness, claim, nonce, &mut vouchers[0]), 8), Err(StateError::NotVerified(ids[0])));
+}
+
+#[test]
+fn persons_are_part_of_the_root_and_chunks() {
+    let validators: Vec<NistCryptography> = (0..3).map(|_| keypair()).collect();
+    let mut genesis = Genesis::new(CHAIN, 0);
+    genesis.validators = validators.iter().map(|k| k.public_key.to_vec()).collect();
+    let state = genesis.state();
+
+    // Validators start as verified persons
+    assert_eq!(state.persons().count(), 3);
+    let id = PersonId::from_public_key(&validators[0].public_key);
+    assert_eq!(state.person(&id).unwrap().status, PersonStatus::Verified);
+
+    let mut changed = state.clone();
+    let mut person = changed.person(&id).unwrap().clone();
+    person.status = PersonStatus::Suspended;
+    changed.set_person(id, person);
+    assert_ne!(changed.root(), state.root());
+    assert!(!state.prove_record(&id.0).verify_record(&state.root(), &id.0, changed.record(&id.0)));
+
+    let root = state.root();
+    let chunks = state.chunks(2);
+    assert!(chunks.iter().all(|c| c.verify(&root)));
+    // The three persons and the protocol parameters
+    assert_eq!(chunks.iter().map(|c| c.records.len()).sum::<usize>(), 4);
+    assert_eq!(State::from_chunks(&chunks), state);
+
+    let full = chunks.iter().position(|c| !c.records.is_empty()).unwrap();
+    let mut dropped = chunks[full].clone();
+    dropped.records.pop();
+    assert!(!dropped.verify(&root));
+
+    let nodes = state.trie_nodes();
+    let rebuilt = State::from_trie(&root, |hash| Ok::<_, ()>(nodes.iter().find(|(h, _)| h == hash).map(|(_, n)| n.clone()))).unwrap();
+    assert_eq!(rebuilt, Some(state));
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use chain_core::{ Cosign, Encode, Genesis, GenesisAccount, Hash, IdentityStatus, KeyId, LivenessClaim, PersonId, PersonStatus, State, StateError, Transaction, TxKind };
+use secure_sign::NistCryptography;
+use common::{ keypair, CHAIN, MAX_TX };
+
+const EPOCH: u64 = 10;
+
+fn claim(state: &mut State, secure: &mut NistCryptography, cosign: Option<Cosign>, height: u64) -> Result<(), StateError> {
+    let person = PersonId::from_public_key(&secure.public_key);
+    let nonce = state.nonce(&KeyId::from_public_key(&secure.public_key));
+    let mut tx = Transaction::new(CHAIN, TxKind::Liveness, LivenessClaim { person, cosign }.encode(), nonce, 1, 0);
+    assert!(tx.sign(secure));
+    tx.validate(CHAIN, MAX_TX).unwrap();
+    state.apply_transaction(&tx, height)
+}
+
+#[test]
+fn persons_lapse_without_liveness_and_come_back_cosigned() {
+    let mut alice = keypair();
+    let mut bob = keypair();
+    let (alice_id, bob_id) = (PersonId::from_public_key(&alice.public_key), PersonId::from_public_key(&bob.public_key));
+    let bob_key = KeyId::from_public_key(&bob.public_key);
+    let mut genesis = Genesis::new(CHAIN, 0);
+    genesis.params.liveness_epoch = EPOCH;
+    genesis.validators = vec![alice.public_key.to_vec(), bob.public_key.to_vec()];
+    for secure in &[&alice, &bob] {
+        genesis.accounts.push(GenesisAccount { public_key: secure.public_key.to_vec(), balance: 100, identity: IdentityStatus::Unverified });
+    }
+    let mut state = genesis.state();
+    assert_eq!(state.params().liveness_epoch, EPOCH);
+    assert_eq!(state.person(&bob_id).unwrap().live_until, EPOCH);
+
+    // A live person just claims; the epoch runs from the claim
+    claim(&mut state, &mut alice, None, 5).unwrap();
+    assert_eq!(state.person(&alice_id).unwrap().live_until, 5 + EPOCH);
+
+    // Bob says nothing and loses his standing with the next block past the epoch
+    let proposer = KeyId::from_public_key(&keypair().public_key);
+    state.apply_transactions(&[], EPOCH, &Hash::ZERO, &proposer).unwrap();
+    assert_eq!(state.person(&bob_id).unwrap().status, PersonStatus::Verified);
+    state.apply_transactions(&[], EPOCH + 1, &Hash::ZERO, &proposer).unwrap();
+    assert_eq!(state.person(&bob_id).unwrap().status, PersonStatus::Lapsed);
+    assert_eq!(state.account(&bob_key).unwrap().identity, IdentityStatus::Unverified);
+
+    // Coming back takes a live verifier's signature over where his liveness ended
+    assert_eq!(claim(&mut state, &mut bob, None, 12), Err(StateError::NeedsVerifier(bob_id)));
+    let stale = Cosign::new(&bob_id, EPOCH + 1, alice_id, &mut alice);
+    assert_eq!(claim(&mut state, &mut bob, stale, 12), Err(StateError::BadCosign(alice_id)));
+    let own = Cosign::new(&bob_id, EPOCH, bob_id, &mut bob);
+    assert_eq!(claim(&mut state, &mut bob, own, 12), Err(StateError::NotVerified(bob_id)));
+    let cosign = Cosign::new(&bob_id, EPOCH, alice_id, &mut alice);
+    claim(&mut state, &mut bob, cosign.clone(), 12).unwrap();
+    let person = state.person(&bob_id).unwrap();
+    assert_eq!((person.status, person.live_until), (PersonStatus::Verified, 12 + EPOCH));
+    assert_eq!(state.account(&bob_key).unwrap().identity, IdentityStatus::Verified);
+
+    // A co-signature counts once
+    assert_eq!(claim(&mut state, &mut bob, cosign, 13), Err(StateError::BadCosign(alice_id)));
+
+    // Once Alice lapses she vouches for nobody
+    state.apply_transactions(&[], 6 + EPOCH, &Hash::ZERO, &proposer).unwrap();
+    assert_eq!(state.person(&alice_id).unwrap().status, PersonStatus::Lapsed);
+    let late = Cosign::new(&bob_id, 12 + EPOCH, alice_id, &mut alice);
+    assert_eq!(claim(&mut state, &mut bob, late, 17), Err(StateError::NotVerified(alice_id)));
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use chain_core::{ Account, Encode, KeyId, Mempool, PoolError, State, Transaction, TxKind };
+use secure_sign::NistCryptography;
+use common::{ keypair, CHAIN, MAX_TX };
+
+fn data_tx(secure: &mut NistCryptography, nonce: u64, fee: u64, expiry: u64) -> Transaction {
+    let mut tx = Transaction::new(CHAIN, TxKind::Data, vec![7; 100], nonce, fee, expiry);
+    assert!(tx.sign(secure));
+    tx
+}
+
+#[test]
+fn admission_ordering_and_replacement() {
+    let mut alice = keypair();
+    let mut bob = keypair();
+    let alice_id = KeyId::from_public_key(&alice.public_key);
+    let bob_id = KeyId::from_public_key(&bob.public_key);
+    let mut state = State::new();
+    state.set_account(alice_id, Account { balance: 100, ..Account::default() });
+    state.set_account(bob_id, Account { balance: 100, ..Account::default() });
+
+    let mut pool = Mempool::new(CHAIN, MAX_TX, 1 << 20, 60_000);
+    let a0 = data_tx(&mut alice, 0, 5, 0);
+    let a1 = data_tx(&mut alice, 1, 50, 0);
+    let b0 = data_tx(&mut bob, 0, 20, 0);
+    for tx in &[&a1, &a0, &b0] {
+        pool.insert((*tx).clone(), &state, 1, 0).unwrap();
+    }
+    assert_eq!(pool.insert(a0.clone(), &state, 1, 0), Err(PoolError::Known));
+
+    // Bob pays more than Alice's first, Alice's second has to wait for her first
+    let order: Vec<u64> = pool.select(&state, usize::MAX, 10).iter().map(|tx| tx.fee).collect();
+    assert_eq!(order, vec![20, 5, 50]);
+    assert_eq!(pool.select(&state, usize::MAX, 2).len(), 2);
+
+    // Queued fees count against the balance
+    assert_eq!(pool.insert(data_tx(&mut alice, 2, 46, 0), &state, 1, 0),
+        Err(PoolError::InsufficientBalance { needed: 101, available: 100 }));
+
+    // Same nonce replaces only with a higher fee
+    let cheaper = data_tx(&mut alice, 0, 5, 7);
+    assert_eq!(pool.insert(cheaper, &state, 1, 0), Err(PoolError::Underpriced { fee: 5, existing: 5 }));
+    let bump = data_tx(&mut alice, 0, 30, 0);
+    pool.insert(bump.clone(), &state, 1, 0).unwrap();
+    assert!(!pool.contains(&a0.id()));
+    assert_eq!(pool.len(), 3);
+    assert_eq!(pool.select(&state, usize::MAX, 10)[0], bump);
+
+    let mut tampered = data_tx(&mut bob, 1, 1, 0);
+    tampered.fee = 2;
+    match pool.insert(tampered.clone(), &state, 1, 0) {
+        Err(PoolError::Invalid(_)) => (),
+        other => panic!("{:?}", other),
+    }
+    assert!(pool.insert_bytes(&tampered.encode(), &state, 1, 0).is_err());
+
+    // Once Alice's first is committed, it and anything expired or too old goes
+    let mut after = state.clone();
+    after.apply_transaction(&bump, 2).unwrap();
+    let expiring = data_tx(&mut bob, 1, 1, 3);
+    pool.insert(expiring.clone(), &state, 2, 10).unwrap();
+    assert_eq!(pool.insert(data_tx(&mut bob, 2, 1, 2), &state, 3, 10).unwrap_err(),
+        PoolError::Expired { expiry_height: 2, height: 3 });
+    assert_eq!(pool.prune(&after, 4, 20), 2);
+    assert!(!pool.contains(&bump.id()) && !pool.contains(&expiring.id()));
+    assert_eq!(pool.insert(a0.clone(), &after, 4, 20), Err(PoolError::NonceTooLow { expected: 1, found: 0 }));
+    assert_eq!(pool.prune(&after, 4, 60_011), 2);
+    assert!(pool.is_empty());
+
+    // Transactions of an orphaned block come back unless the new chain has them
+    assert_eq!(pool.reinject(vec![a1.clone(), bump.clone(), b0.clone()], &after, 4, 70_000), 2);
+    assert!(pool.contains(&a1.id()) && pool.contains(&b0.id()));
+}
+
+#[test]
+fn eviction_under_memory_cap() {
+    let mut senders: Vec<NistCryptography> = (0..4).map(|_| keypair()).collect();
+    let mut state = State::new();
+    for secure in &senders {
+        state.set_account(KeyId::from_public_key(&secure.public_key), Account { balance: 1000, ..Account::default() });
+    }
+
+    // Signatures vary a little in length, so leave some slack over three transactions
+    let low = data_tx(&mut senders[0], 0, 2, 0);
+    let mid = data_tx(&mut senders[1], 0, 5, 0);
+    let mid_next = data_tx(&mut senders[1], 1, 3, 0);
+    let cap = low.encode().len() + mid.encode().len() + mid_next.encode().len() + 100;
+    let mut pool = Mempool::new(CHAIN, MAX_TX, cap, 60_000);
+    for tx in &[&low, &mid, &mid_next] {
+        pool.insert((*tx).clone(), &state, 1, 0).unwrap();
+    }
+
+    // Not paying more per byte than anything evictable
+    assert_eq!(pool.insert(data_tx(&mut senders[2], 0, 1, 0), &state, 1, 0), Err(PoolError::PoolFull));
+
+    let high = data_tx(&mut senders[2], 0, 6, 0);
+    pool.insert(high.clone(), &state, 1, 0).unwrap();
+    assert!(!pool.contains(&low.id()));
+
+    // Sender 1's queue is trimmed from its end, never leaving a gap
+    let higher = data_tx(&mut senders[3], 0, 10, 0);
+    pool.insert(higher.clone(), &state, 1, 0).unwrap();
+    assert!(pool.contains(&mid.id()) && !pool.contains(&mid_next.id()));
+    assert!(pool.contains(&high.id()) && pool.contains(&higher.id()));
+    assert!(pool.bytes() <= cap);
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use chain_core::{ Encode, Genesis, GenesisAccount, Guardianship, Hash, IdentityStatus, KeyId, KeyUpdate, PersonId, RecoveryApproval, RecoveryCancel, StateError, TxKind };
+use chain_core::RECOVERY_DELAY;
+use secure_sign::NistCryptography;
+use common::{ keypair, send, CHAIN };
+
+#[test]
+fn guardians_recover_a_lost_key_unless_the_old_key_cancels() {
+    let mut alice = keypair();
+    let mut guardians: Vec<NistCryptography> = (0..3).map(|_| keypair()).collect();
+    let alice_id = PersonId::from_public_key(&alice.public_key);
+    let alice_key = KeyId::from_public_key(&alice.public_key);
+    let ids: Vec<PersonId> = guardians.iter().map(|k| PersonId::from_public_key(&k.public_key)).collect();
+    let mut genesis = Genesis::new(CHAIN, 0);
+    genesis.validators = guardians.iter().chain(Some(&alice)).map(|k| k.public_key.to_vec()).collect();
+    for public_key in genesis.validators.clone() {
+        genesis.accounts.push(GenesisAccount { public_key, balance: 100, identity: IdentityStatus::Unverified });
+    }
+    let mut state = genesis.state();
+
+    let guardianship = |guardians: Vec<PersonId>, threshold| Guardianship { person: alice_id, guardians, threshold }.encode();
+    assert_eq!(send(&mut state, &mut alice, TxKind::SetGuardians, guardianship(ids.clone(), 4), 1), Err(StateError::BadThreshold { threshold: 4, guardians: 3 }));
+    assert_eq!(send(&mut state, &mut alice, TxKind::SetGuardians, guardianship(vec![ids[0], alice_id], 1), 1), Err(StateError::BadGuardian(alice_id)));
+    send(&mut state, &mut alice, TxKind::SetGuardians, guardianship(ids.clone(), 2), 1).unwrap();
+
+    // Guardians approve a key its holder signed over for Alice
+    let mut next = keypair();
+    let next_key = KeyId::from_public_key(&next.public_key);
+    let update = KeyUpdate::new(alice_id, &alice_key, &mut next).unwrap();
+    let approve = |guardian: PersonId, update: &KeyUpdate| RecoveryApproval { guardian, update: update.clone() }.encode();
+    send(&mut state, &mut guardians[0], TxKind::Recover, approve(ids[0], &update), 2).unwrap();
+    assert_eq!(send(&mut state, &mut guardians[0], TxKind::Recover, approve(ids[0], &update), 2), Err(StateError::AlreadyApproved(ids[0])));
+    assert_eq!(send(&mut state, &mut alice, TxKind::Recover, approve(alice_id, &update), 2), Err(StateError::NotGuardian(alice_id)));
+    let mut forged = update.clone();
+    forged.public_key = keypair().public_key.to_vec();
+    assert_eq!(send(&mut state, &mut guardians[1], TxKind::Recover, approve(ids[1], &forged), 2), Err(StateError::BadKeyProof));
+    send(&mut state, &mut guardians[1], TxKind::Recover, approve(ids[1], &update), 3).unwrap();
+    assert_eq!(state.person(&alice_id).unwrap().recovery.as_ref().unwrap().ready_at, 3 + RECOVERY_DELAY);
+
+    // Alice still has her key and stops it
+    let cancel = RecoveryCancel { person: alice_id }.encode();
+    send(&mut state, &mut alice, TxKind::CancelRecovery, cancel.clone(), 4).unwrap();
+    assert_eq!(state.person(&alice_id).unwrap().recovery, None);
+    assert_eq!(send(&mut state, &mut alice, TxKind::CancelRecovery, cancel, 4), Err(StateError::NoRecovery(alice_id)));
+
+    // This time she has lost it; another key cannot cut in
+    send(&mut state, &mut guardians[0], TxKind::Recover, approve(ids[0], &update), 5).unwrap();
+    send(&mut state, &mut guardians[2], TxKind::Recover, approve(ids[2], &update), 6).unwrap();
+    let other = KeyUpdate::new(alice_id, &alice_key, &mut keypair()).unwrap();
+    assert_eq!(send(&mut state, &mut guardians[1], TxKind::Recover, approve(ids[1], &other), 7), Err(StateError::RecoveryPending(alice_id)));
+
+    let proposer = KeyId::from_public_key(&keypair().public_key);
+    state.apply_transactions(&[], 5 + RECOVERY_DELAY, &Hash::ZERO, &proposer).unwrap();
+    assert_eq!(state.person_of(&alice_key), Some(alice_id));
+    state.apply_transactions(&[], 6 + RECOVERY_DELAY, &Hash::ZERO, &proposer).unwrap();
+    assert_eq!((state.person_of(&alice_key), state.person_of(&next_key)), (None, Some(alice_id)));
+    assert_eq!(state.account(&next_key).unwrap().identity, IdentityStatus::Verified);
+    assert_eq!(state.person(&alice_id).unwrap().recovery, None);
+}
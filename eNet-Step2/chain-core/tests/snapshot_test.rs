@@ -0,0 +1,165 @@
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use chain_core::{ Account, Chain, ChainError, Decode, Encode, IdentityStatus, KeyId, Mempool, SnapshotError, State, StateChunk, Store, hash_with_domain, SNAPSHOTS_KEPT };
+use secure_sign::NistCryptography;
+use common::{ certify, keypair, CHAIN, MAX_TX };
+
+const NOW: u64 = 1_600_000_000_000;
+
+fn account_id(i: u32) -> KeyId {
+    KeyId(hash_with_domain(b"snapshot-test", &i.to_be_bytes()))
+}
+
+fn chain_in(dir: &Path, genesis: &State, keys: &[NistCryptography]) -> Chain {
+    let mut chain = Chain::new(CHAIN, 1 << 20, MAX_TX);
+    chain.validator_keys = keys.iter().map(|k| k.public_key.to_vec()).collect();
+    chain.attach_store(Store::open(dir).unwrap()).unwrap();
+    if chain.tip().is_none() {
+        chain.state = genesis.clone();
+    }
+    chain
+}
+
+#[test]
+fn chunks_prove_against_the_root() {
+    let mut state = State::new();
+    for i in 0..500 {
+        state.set_account(account_id(i), Account { balance: i as u64, ..Account::default() });
+    }
+    let root = state.root();
+
+    let depth = state.chunk_depth(64);
+    let chunks = state.chunks(depth);
+    assert_eq!(chunks.len(), 1 << depth);
+    assert!(chunks.iter().all(|c| c.accounts.len() <= 64));
+    assert!(chunks.iter().all(|c| c.verify(&root)));
+    assert_eq!(chunks.iter().map(|c| c.accounts.len()).sum::<usize>(), 500);
+    assert_eq!(State::from_chunks(&chunks), state);
+    assert_eq!(StateChunk::decode(&chunks[1].encode()).unwrap(), chunks[1]);
+
+    // Whole state in one chunk, and more chunks than accounts
+    assert_eq!(state.chunk_depth(500), 0);
+    assert!(state.chunks(0)[0].verify(&root));
+    let sparse = state.chunks(12);
+    assert!(sparse.iter().any(|c| c.accounts.is_empty()));
+    assert!(sparse.iter().all(|c| c.verify(&root)));
+
+    let full = chunks.iter().position(|c| c.accounts.len() > 1).unwrap();
+    let mut dropped = chunks[full].clone();
+    dropped.accounts.pop();
+    assert!(!dropped.verify(&root));
+
+    let mut changed = chunks[full].clone();
+    changed.accounts[0].1.balance += 1;
+    assert!(!changed.verify(&root));
+
+    // An account handed over in another chunk is outside that chunk's range
+    let other = (full + 1) % chunks.len();
+    let mut moved = chunks[other].clone();
+    moved.accounts.push(chunks[full].accounts[0].clone());
+    moved.accounts.sort_by_key(|a| a.0);
+    assert!(!moved.verify(&root));
+
+    let mut wrong_index = chunks[full].clone();
+    wrong_index.index = other as u32;
+    assert!(!wrong_index.verify(&root));
+}
+
+#[test]
+fn chain_restores_from_snapshot_and_continues() {
+    let base = env::temp_dir().join(format!("frink-snapshot-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&base);
+
+    let mut keys: Vec<NistCryptography> = (0..4).map(|_| keypair()).collect();
+    let mut genesis = State::new();
+    for secure in &keys {
+        let account = Account { identity: IdentityStatus::Verified, ..Account::default() };
+        genesis.set_account(KeyId::from_public_key(&secure.public_key), account);
+    }
+    for i in 0..300 {
+        genesis.set_account(account_id(i), Account { balance: 10, ..Account::default() });
+    }
+
+    // The source chain snapshots at 5, then goes on to 7 with a snapshot each block
+    let pool = Mempool::new(CHAIN, MAX_TX, 1 << 20, 600_000);
+    let mut source = chain_in(&base.join("source"), &genesis, &keys);
+    let mut blocks = Vec::new();
+    let mut snapshot = None;
+    for height in 0..8 {
+        let (block, state) = source.build_block(&pool, &mut keys[height % 4], NOW + height as u64 * 10).unwrap();
+        let certificate = certify(&block, &mut keys, &source);
+        source.commit(&block, state, certificate.clone()).unwrap();
+        blocks.push((block, certificate));
+        if height >= 5 {
+            let taken = source.snapshot(64).unwrap().unwrap();
+            source.store().unwrap().save_snapshot(&taken).unwrap();
+            snapshot.get_or_insert(taken);
+        }
+    }
+    let snapshot = snapshot.unwrap();
+    let manifest = &snapshot.manifest;
+    assert_eq!(manifest.height(), 5);
+    assert_eq!(snapshot.chunks.len() as u32, manifest.chunk_count());
+    assert!(snapshot.chunks.len() > 1);
+    manifest.verify(&source.validator_keys, CHAIN).unwrap();
+
+    // Only the newest snapshots stay in the store
+    let store = source.store().unwrap();
+    assert_eq!(SNAPSHOTS_KEPT, 2);
+    assert_eq!(store.latest_snapshot().unwrap().map(|m| m.height()), Some(7));
+    assert_eq!(store.snapshot_chunk(5, 0).unwrap(), None);
+    assert_eq!(store.snapshot_chunk(6, 0).unwrap().map(|c| c.index), Some(0));
+
+    // Only configured validators, and only the certified block, are trusted
+    match manifest.verify(&source.validator_keys[1..], CHAIN) {
+        Err(SnapshotError::UnknownValidator(_)) => (),
+        other => panic!("{:?}", other),
+    }
+    let mut other_block = manifest.clone();
+    other_block.certificate = blocks[4].1.clone();
+    assert!(other_block.verify(&source.validator_keys, CHAIN).is_err());
+    match manifest.assemble(&snapshot.chunks[1..]) {
+        Err(SnapshotError::StateMismatch { .. }) => (),
+        other => panic!("{:?}", other),
+    }
+
+    // A fresh node starts at the snapshot without blocks 0 to 4
+    let dir = base.join("restored");
+    let mut restored = chain_in(&dir, &genesis, &keys);
+    let events = restored.subscribe();
+    let state = manifest.assemble(&snapshot.chunks).unwrap();
+    let mut tampered = state.clone();
+    tampered.set_account(account_id(0), Account::default());
+    match restored.restore(manifest, tampered) {
+        Err(ChainError::Snapshot(SnapshotError::StateMismatch { .. })) => (),
+        other => panic!("{:?}", other),
+    }
+    restored.restore(manifest, state.clone()).unwrap();
+    assert_eq!(restored.finalized(), Some((manifest.block.hash(), 5)));
+    assert_eq!(restored.next_height(), 6);
+    assert_eq!(events.try_iter().count(), 2);
+    assert!(restored.store().unwrap().block_at(4).unwrap().is_none());
+    match restored.restore(manifest, state) {
+        Err(ChainError::NotEmpty { height: 5 }) => (),
+        other => panic!("{:?}", other),
+    }
+
+    // Blocks above it are taken as usual, also after a restart
+    let (block, certificate) = blocks[6].clone();
+    restored.add_block(block, Some(certificate), NOW + 1000).unwrap();
+    drop(restored);
+    let mut restored = chain_in(&dir, &genesis, &keys);
+    assert_eq!(restored.next_height(), 7);
+    let (block, certificate) = blocks[7].clone();
+    restored.add_block(block, Some(certificate), NOW + 1000).unwrap();
+    assert_eq!(restored.tip_hash(), source.tip_hash());
+    assert_eq!(restored.state, source.state);
+
+    drop(restored);
+    drop(source);
+    let _ = fs::remove_dir_all(&base);
+}
@@ -0,0 +1,147 @@
+mod common;
+
+use chain_core::{ Account, Block, BlockHeader, Decode, Encode, Hash, IdentityStatus, KeyId, State, StateError, StateProof, Transaction };
+use common::{ keypair, CHAIN };
+
+fn funded(balance: u64) -> Account {
+    Account { balance, ..Account::default() }
+}
+
+#[test]
+fn transfers_apply_atomically() {
+    let mut alice = keypair();
+    let alice_id = KeyId::from_public_key(&alice.public_key);
+    let bob_id = KeyId::from_public_key(&keypair().public_key);
+    let proposer = KeyId::from_public_key(&keypair().public_key);
+
+    let mut state = State::new();
+    state.set_account(alice_id, funded(1000));
+    let genesis_root = state.root();
+
+    let mut first = Transaction::transfer(CHAIN, bob_id, 300, 0, 5, 0);
+    assert!(first.sign(&mut alice));
+    let mut second = Transaction::transfer(CHAIN, bob_id, 200, 1, 5, 0);
+    assert!(second.sign(&mut alice));
+
+    // Replaying nonce 0 fails, and the first transfer is rolled back with it
+    let res = state.apply_transactions(&[first.clone(), first.clone()], 1, &Hash::ZERO, &proposer);
+    assert_eq!(res, Err(StateError::BadNonce { expected: 1, found: 0 }));
+    assert_eq!(state.root(), genesis_root);
+    assert!(state.account(&bob_id).is_none());
+
+    let root = state.apply_transactions(&[first, second], 1, &Hash::ZERO, &proposer).unwrap();
+    assert_eq!(root, state.root());
+    assert_eq!(state.balance(&alice_id), 490);
+    assert_eq!(state.nonce(&alice_id), 2);
+    assert_eq!(state.balance(&bob_id), 500);
+    assert_eq!(state.balance(&proposer), 10);
+
+    let mut broke = Transaction::transfer(CHAIN, bob_id, 490, 2, 1, 0);
+    assert!(broke.sign(&mut alice));
+    assert_eq!(state.apply_transaction(&broke, 2), Err(StateError::InsufficientBalance { needed: 491, available: 490 }));
+
+    let mut stale = Transaction::transfer(CHAIN, bob_id, 1, 2, 1, 5);
+    assert!(stale.sign(&mut alice));
+    assert_eq!(state.apply_transaction(&stale, 6), Err(StateError::Expired { expiry_height: 5, height: 6 }));
+
+    // A block whose header claims the wrong root is refused and leaves no trace
+    let mut pay = Transaction::transfer(CHAIN, bob_id, 10, 2, 1, 0);
+    assert!(pay.sign(&mut alice));
+    let mut header = BlockHeader::new(Hash::ZERO, 2, 0);
+    header.proposer = proposer;
+    let mut block = Block::new(header, vec![pay.encode()]);
+    let before = state.clone();
+    match state.apply_block(&block) {
+        Err(StateError::StateRootMismatch { .. }) => (),
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(state, before);
+
+    let mut expected = state.clone();
+    block.header.state_root = expected.apply_transactions(&[pay], 2, &block.header.parent, &proposer).unwrap();
+    assert_eq!(state.apply_block(&block), Ok(()));
+    assert_eq!(state, expected);
+}
+
+#[test]
+fn root_and_proofs() {
+    let ids: Vec<KeyId> = (0..50u8).map(|i| KeyId::from_public_key(&[i; 8])).collect();
+
+    let mut forward = State::new();
+    let mut backward = State::new();
+    assert_eq!(forward.root(), Hash::ZERO);
+    for (i, id) in ids.iter().enumerate() {
+        forward.set_account(*id, funded(i as u64));
+    }
+    for (i, id) in ids.iter().enumerate().rev() {
+        backward.set_account(*id, funded(i as u64));
+    }
+    let root = forward.root();
+    assert_eq!(root, backward.root());
+
+    for (i, id) in ids.iter().enumerate() {
+        let proof = forward.prove(id);
+        let account = forward.account(id).unwrap();
+        assert!(proof.verify(&root, &id.0, Some(account)));
+        assert!(!proof.verify(&root, &id.0, Some(&funded(i as u64 + 1))));
+        assert!(!proof.verify(&root, &id.0, None));
+        assert_eq!(StateProof::decode(&proof.encode()).unwrap(), proof);
+    }
+
+    // Absent keys prove exclusion, ending on an empty subtree or a neighbour
+    for i in 50..100u8 {
+        let absent = KeyId::from_public_key(&[i; 8]);
+        let proof = forward.prove(&absent);
+        assert!(proof.verify(&root, &absent.0, None));
+        assert!(!proof.verify(&root, &absent.0, Some(&Account::default())));
+    }
+
+    // A proof for one key does not vouch for another
+    let proof = forward.prove(&ids[0]);
+    assert!(!proof.verify(&root, &ids[1].0, forward.account(&ids[1])));
+
+    let mut tampered = forward.prove(&ids[3]);
+    tampered.siblings[0] = Hash::ZERO;
+    assert!(!tampered.verify(&root, &ids[3].0, forward.account(&ids[3])));
+
+    // Any change to an account, identity included, moves the root
+    let mut verified = forward.account(&ids[7]).unwrap().clone();
+    verified.identity = IdentityStatus::Verified;
+    forward.set_account(ids[7], verified);
+    assert_ne!(forward.root(), root);
+
+    let mut single = State::new();
+    single.set_account(ids[0], funded(1));
+    assert!(single.prove(&ids[0]).verify(&single.root(), &ids[0].0, single.account(&ids[0])));
+    assert!(single.prove(&ids[1]).verify(&single.root(), &ids[1].0, None));
+}
+
+#[test]
+fn cached_root_follows_writes() {
+    let ids: Vec<KeyId> = (0..200u8).map(|i| KeyId::from_public_key(&[i; 4])).collect();
+    let full_root = |state: &State| state.trie_nodes().last().map(|(hash, _)| *hash).unwrap_or(Hash::ZERO);
+
+    // Overwrites, and a failed batch rolling back entries it had just created
+    let mut state = State::new();
+    for (i, id) in ids.iter().enumerate() {
+        state.set_account(*id, funded(i as u64));
+        if i % 7 == 0 {
+            state.set_account(ids[i / 2], funded(i as u64 * 3));
+        }
+        assert_eq!(state.root(), full_root(&state));
+    }
+    let mut alice = keypair();
+    let proposer = KeyId::from_public_key(&keypair().public_key);
+    state.set_account(KeyId::from_public_key(&alice.public_key), funded(100));
+    let before = state.clone();
+    let mut pay = Transaction::transfer(CHAIN, KeyId::from_public_key(&[7; 9]), 1, 0, 1, 0);
+    assert!(pay.sign(&mut alice));
+    assert!(state.apply_transactions(&[pay.clone(), pay], 1, &Hash::ZERO, &proposer).is_err());
+    assert_eq!(state, before);
+    assert_eq!(state.root(), full_root(&state));
+
+    // A state rebuilt from its nodes ends up with the same cache
+    let nodes = state.trie_nodes();
+    let rebuilt = State::from_trie(&state.root(), |hash| Ok::<_, ()>(nodes.iter().find(|(h, _)| h == hash).map(|(_, n)| n.clone()))).unwrap();
+    assert_eq!(rebuilt, Some(state));
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use std::env;
+use std::fs;
+
+use chain_core::{ Account, Block, BlockHeader, Encode, KeyId, QuorumCertificate, State, Store, StoreError, Transaction };
+use secure_sign::NistCryptography;
+use common::{ keypair, CHAIN };
+
+// Build the next block over `state`, applying `txs` to it
+fn next_block(parent: Option<&Block>, state: &mut State, txs: Vec<Transaction>, proposer: &mut NistCryptography) -> Block {
+    let (parent_hash, height) = match parent {
+        Some(p) => (p.hash(), p.header.height + 1),
+        None => (Default::default(), 0),
+    };
+    let proposer_id = KeyId::from_public_key(&proposer.public_key);
+    let mut header = BlockHeader::new(parent_hash, height, 1_600_000_000 + height);
+    header.state_root = state.apply_transactions(&txs, height, &parent_hash, &proposer_id).unwrap();
+    let mut block = Block::new(header, txs.iter().map(|tx| tx.encode()).collect());
+    assert!(block.header.sign(proposer));
+    block
+}
+
+// Stands in for a real certificate; the store does not check signatures
+fn certificate_for(block: &Block) -> QuorumCertificate {
+    QuorumCertificate { height: block.header.height, round: 0, block: block.hash(), signers: vec![1], signatures: vec![vec![1; 8]] }
+}
+
+#[test]
+fn blocks_survive_restart_and_recover() {
+    let dir = env::temp_dir().join(format!("frink-store-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    let mut alice = keypair();
+    let mut proposer = keypair();
+    let alice_id = KeyId::from_public_key(&alice.public_key);
+    let bob_id = KeyId::from_public_key(&keypair().public_key);
+
+    let mut state = State::new();
+    state.set_account(alice_id, Account { balance: 1000, ..Account::default() });
+    let genesis = next_block(None, &mut state, vec![], &mut proposer);
+
+    let mut pay = Transaction::transfer(CHAIN, bob_id, 100, 0, 2, 0);
+    assert!(pay.sign(&mut alice));
+    let first_state;
+    let first;
+    let second;
+    {
+        let store = Store::open(&dir).unwrap();
+        assert_eq!(store.tip().unwrap(), None);
+        store.commit_block(&genesis, &state, None).unwrap();
+
+        first = next_block(Some(&genesis), &mut state, vec![pay.clone()], &mut proposer);
+        store.commit_block(&first, &state, Some(&certificate_for(&first))).unwrap();
+        first_state = state.clone();
+
+        // Neither a replay of the tip nor a block with the wrong state is taken
+        match store.commit_block(&first, &state, None) {
+            Err(StoreError::NotExtendingTip { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        let mut next = state.clone();
+        let mut stale = BlockHeader::new(first.hash(), 2, 0);
+        stale.state_root = first.header.state_root;
+        next.set_account(bob_id, Account::default());
+        match store.commit_block(&Block::new(stale, vec![]), &next, None) {
+            Err(StoreError::StateMismatch { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+
+        let mut again = Transaction::transfer(CHAIN, bob_id, 50, 1, 2, 0);
+        assert!(again.sign(&mut alice));
+        second = next_block(Some(&first), &mut state, vec![again], &mut proposer);
+        store.commit_block(&second, &state, Some(&certificate_for(&second))).unwrap();
+    }
+
+    {
+        let store = Store::open(&dir).unwrap();
+        let tip = store.tip().unwrap().unwrap();
+        assert_eq!((tip.hash, tip.height, tip.state_root), (second.hash(), 2, state.root()));
+        assert_eq!(store.block_at(1).unwrap().unwrap(), first);
+        assert_eq!(store.load_state(&tip.state_root).unwrap(), state);
+        assert_eq!(store.load_state(&first.header.state_root).unwrap(), first_state);
+        assert_eq!(store.certificate(1).unwrap(), Some(certificate_for(&first)));
+        assert_eq!(store.certificate(0).unwrap(), None);
+
+        let (tx, location) = store.transaction(&pay.id()).unwrap().unwrap();
+        assert_eq!(tx, pay);
+        assert_eq!((location.block, location.index), (first.hash(), 0));
+    }
+
+    // Lose the newest block behind the store's back: it falls back one height
+    {
+        let db = sled::Config::new().path(&dir).flush_every_ms(None).open().unwrap();
+        db.open_tree("blocks").unwrap().remove(second.hash().as_bytes()).unwrap();
+        db.flush().unwrap();
+    }
+    {
+        let store = Store::open(&dir).unwrap();
+        let tip = store.tip().unwrap().unwrap();
+        assert_eq!((tip.hash, tip.height), (first.hash(), 1));
+        assert_eq!(store.hash_at(2).unwrap(), None);
+        assert_eq!(store.certificate(2).unwrap(), None);
+        assert_eq!(store.load_state(&tip.state_root).unwrap(), first_state);
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use chain_core::{ Decode, Encode, KeyId, Transaction, TxError, TxKind };
+use common::{ keypair, CHAIN, MAX_TX };
+
+#[test]
+fn signed_transfer_validates() {
+    let mut alice = keypair();
+    let bob = keypair();
+
+    let mut tx = Transaction::transfer(CHAIN, KeyId::from_public_key(&bob.public_key), 250, 3, 1, 100);
+    let id = tx.id();
+    assert!(tx.sign(&mut alice));
+    assert_ne!(tx.id(), id);
+    assert_eq!(tx.sender, KeyId::from_public_key(&alice.public_key));
+
+    let bytes = tx.encode();
+    let decoded = Transaction::decode_valid(&bytes, CHAIN, MAX_TX).unwrap();
+    assert_eq!(decoded, tx);
+    assert_eq!(decoded.transfer_payload().unwrap().amount, 250);
+    assert!(!decoded.is_expired(100));
+    assert!(decoded.is_expired(101));
+}
+
+#[test]
+fn stateless_rejections() {
+    let mut alice = keypair();
+    let mut tx = Transaction::new(CHAIN, TxKind::Data, b"Hello World".to_vec(), 0, 0, 0);
+    assert!(tx.sign(&mut alice));
+    assert_eq!(tx.validate(CHAIN, MAX_TX), Ok(()));
+
+    // Same signed bytes presented to another chain
+    match tx.validate("frink-main", MAX_TX) {
+        Err(TxError::WrongChain { .. }) => (),
+        other => panic!("{:?}", other),
+    }
+
+    let mut changed = tx.clone();
+    changed.nonce += 1;
+    assert_eq!(changed.validate(CHAIN, MAX_TX), Err(TxError::BadSignature));
+
+    let mut stolen = tx.clone();
+    stolen.public_key = keypair().public_key.to_vec();
+    assert_eq!(stolen.validate(CHAIN, MAX_TX), Err(TxError::SenderMismatch));
+
+    let mut transfer = Transaction::new(CHAIN, TxKind::Transfer, vec![1, 2, 3], 0, 0, 0);
+    assert!(transfer.sign(&mut alice));
+    match transfer.validate(CHAIN, MAX_TX) {
+        Err(TxError::InvalidPayload(_)) => (),
+        other => panic!("{:?}", other),
+    }
+
+    let mut large = Transaction::new(CHAIN, TxKind::Data, vec![0; MAX_TX], 0, 0, 0);
+    assert!(large.sign(&mut alice));
+    match Transaction::decode_valid(&large.encode(), CHAIN, MAX_TX) {
+        Err(TxError::TooLarge { .. }) => (),
+        other => panic!("{:?}", other),
+    }
+
+    let mut bytes = tx.encode();
+    bytes.push(0);
+    assert!(Transaction::decode(&bytes).is_err());
+}
@@ -4,12 +4,14 @@ extern crate ini;
 extern crate hex;
 
 use ini::Ini;
-use std::path::PathBuf;
-use std::env;
+use std::path::Path;
 use std::fs::File;
 use std::fmt::{ self, Debug, Formatter };
 use std::net::Ipv4Addr;
 
+use crate::node_config::ConfigError;
+use crate::persist;
+
 /*
  *  Declaration PeerInfo 
 */
@@ -72,50 +74,53 @@ impl HostInfo {
  *  Declaration functions to read and save for Config.ini
 */
 #[allow(dead_code)]
-pub fn get_hosts() -> Vec<HostInfo> {
-    let mut conf_path = PathBuf::from(
-        env::var("CARGO_MANIFEST_DIR").expect("`CARGO_MANIFEST_DIR` is alway set by cargo.").to_string()
-    );
-    conf_path.pop();
-    let conf_path = conf_path.join("config\\conf.ini");
+pub fn get_hosts(conf_path: &Path) -> Vec<HostInfo> {
+    match persist::load_or_recover(conf_path, load_hosts) {
+        Ok(hosts) => hosts,
+        Err(e) => {
+            log::error!("{}", e);
+            Vec::<HostInfo>::new()
+        },
+    }
+}
 
-    let conf = match Ini::load_from_file(conf_path) {
-        Ok(v) => v,
-        Err(_) => return Vec::<HostInfo>::new(),
-    };
+pub fn load_hosts(conf_path: &Path) -> Result<Vec<HostInfo>, ConfigError> {
+    let conf = Ini::load_from_file(conf_path)
+        .map_err(|e| ConfigError::new(conf_path, "", e.to_string()))?;
     
     let mut hosts = Vec::<HostInfo>::new();
-    for (_sec, prop) in conf.iter() {        
+    for (sec, prop) in conf.iter() {
+        let section = match sec {
+            Some(name) => name.to_string(),
+            None if prop.is_empty() => continue,
+            None => String::from("general"),
+        };
+        let field = |name: &str| match prop.get(name) {
+            Some(v) => Ok(v.trim()),
+            None => Err(ConfigError::new(conf_path, &format!("{}.{}", section, name), String::from("missing"))),
+        };
+        let port = field("port")?;
+        let public = field("public")?;
+        let private = field("private")?;
+
         let host = HostInfo {
-            port: (prop.get("port").unwrap()).parse::<u16>().unwrap(),
-            public_key: hex::decode(prop.get("public").unwrap()).unwrap(),
-            private_key: hex::decode(prop.get("private").unwrap()).unwrap(),
+            port: port.parse::<u16>().map_err(|_| ConfigError::new(conf_path, &format!("{}.port", section),
+                format!("invalid port `{}`", port)))?,
+            public_key: hex::decode(public).map_err(|e| ConfigError::new(conf_path, &format!("{}.public", section),
+                format!("invalid hex: {}", e)))?,
+            private_key: hex::decode(private).map_err(|e| ConfigError::new(conf_path, &format!("{}.private", section),
+                format!("invalid hex: {}", e)))?,
         };
         hosts.push(host);
     }
     
-    return hosts;
+    Ok(hosts)
 }
 
 #[allow(dead_code)]
-pub fn set_hosts(hosts: &Vec<HostInfo>) -> bool {
-    let mut conf_path = PathBuf::from(
-        env::var("CARGO_MANIFEST_DIR").expect("`CARGO_MANIFEST_DIR` is alway set by cargo.").to_string()
-    );
-    conf_path.pop();
-    let conf_path = conf_path.join("config\\conf.ini");
-
+pub fn set_hosts(conf_path: &Path, hosts: &Vec<HostInfo>) -> bool {
     let mut conf = Ini::new();
-    let ini_hosts = get_hosts();
     let mut index: u8 = 1;
-    for host in ini_hosts {
-        conf.with_section(Some(format!("Host {}", index)))
-            .set("port", host.port.to_string())
-            .set("public", hex::encode_upper(host.public_key.clone()))
-            .set("private", hex::encode_upper(host.private_key.clone()));
-        index = index + 1;
-    }
-    index = 1;
     for host in hosts {
         conf.with_section(Some(format!("Host {}", index)))
             .set("port", host.port.to_string())
@@ -124,21 +129,13 @@ pub fn set_hosts(hosts: &Vec<HostInfo>) -> bool {
         index = index + 1;
     }
 
-    conf.write_to_file(conf_path).unwrap();
-
-    return true;
+    write_ini(conf_path, &conf)
 }
 
 #[allow(dead_code)]
-pub fn set_host(host: &HostInfo, id: u16) -> bool {
-    let mut conf_path = PathBuf::from(
-        env::var("CARGO_MANIFEST_DIR").expect("`CARGO_MANIFEST_DIR` is alway set by cargo.").to_string()
-    );
-    conf_path.pop();
-    let conf_path = conf_path.join("config\\conf.ini");
-
+pub fn set_host(conf_path: &Path, host: &HostInfo, id: u16) -> bool {
     let mut conf = Ini::new();
-    let ini_hosts = get_hosts();
+    let ini_hosts = get_hosts(conf_path);
     let mut index: u8 = 1;
     for host in ini_hosts {
         conf.with_section(Some(format!("Host {}", index)))
@@ -152,68 +149,83 @@ pub fn set_host(host: &HostInfo, id: u16) -> bool {
         .set("port", host.port.to_string())
         .set("public", hex::encode_upper(host.public_key.clone()))
         .set("private", hex::encode_upper(host.private_key.clone()));
-   
-    conf.write_to_file(conf_path).unwrap();
 
-    return true;
+    write_ini(conf_path, &conf)
+}
+
+fn write_ini(conf_path: &Path, conf: &Ini) -> bool {
+    let mut buf = Vec::<u8>::new();
+    if conf.write_to(&mut buf).is_err() {
+        return false;
+    }
+    match persist::write_sealed(conf_path, &String::from_utf8_lossy(&buf)) {
+        Ok(()) => true,
+        Err(e) => {
+            log::error!("Couldn't write {}: {}", conf_path.display(), e);
+            false
+        },
+    }
 }
 
 /*
  *  Declaration functions to read and save for Peerlist.csv
 */
 #[allow(dead_code)]
-pub fn read_peerlist() -> Vec<PeerInfo> {
+pub fn read_peerlist(csv_path: &Path) -> Vec<PeerInfo> {
+    match persist::load_or_recover(csv_path, load_peerlist) {
+        Ok(peers) => peers,
+        Err(e) => {
+            log::error!("{}", e);
+            Vec::<PeerInfo>::new()
+        },
+    }
+}
+
+pub fn load_peerlist(csv_path: &Path) -> Result<Vec<PeerInfo>, ConfigError> {
         
     let mut _peerlist = Vec::<PeerInfo>::new();
 
-    let mut csv_path = PathBuf::from(
-        env::var("CARGO_MANIFEST_DIR").expect("`CARGO_MANIFEST_DIR` is alway set by cargo.").to_string()
-    );
-    csv_path.pop();
-    let csv_path = csv_path.join("config\\peerlist.csv");
-
-    let fp = match File::open(csv_path) {
-        Ok(v) => v,
-        Err(_) => return _peerlist,
-    };
+    let fp = File::open(csv_path).map_err(|e| ConfigError::new(csv_path, "", e.to_string()))?;
 
     let mut csv_file = csv::ReaderBuilder::new()
                             .has_headers(false)
+                            .flexible(true)
                             .delimiter(b',')
+                            .comment(Some(b'#'))
                             .from_reader(fp);
 
-    for result in csv_file.records() {
-        let record = result.expect("a CSV Record");
+    for (line, result) in csv_file.records().enumerate() {
+        let key = |column: &str| format!("line {} {}", line + 1, column);
+        let record = result.map_err(|e| ConfigError::new(csv_path, &key("record"), e.to_string()))?;
+        if record.len() < 3 {
+            return Err(ConfigError::new(csv_path, &key("record"),
+                format!("expected address, port and key, found {} fields", record.len())));
+        }
+        let address = record[0].trim();
+        if parse_string_to_vec(&address.to_string()).len() != 4 {
+            return Err(ConfigError::new(csv_path, &key("address"), format!("invalid IPv4 address `{}`", address)));
+        }
         let _peerdata = PeerInfo {
-            address: ((&record[0]).to_string()).trim().to_string(),
-            port: ((&record[1]).to_string()).trim().parse::<u16>().unwrap(),
-            key: hex::decode((&record[2]).to_string().trim()).unwrap(),
+            address: address.to_string(),
+            port: record[1].trim().parse::<u16>()
+                .map_err(|_| ConfigError::new(csv_path, &key("port"), format!("invalid port `{}`", record[1].trim())))?,
+            key: hex::decode(record[2].trim())
+                .map_err(|e| ConfigError::new(csv_path, &key("key"), format!("invalid hex: {}", e)))?,
             connected: false,
         };
         
         _peerlist.push(_peerdata);
     }
 
-    _peerlist
+    Ok(_peerlist)
 }
 
 #[allow(dead_code)]
-pub fn save_peerlist(peers: &[PeerInfo]) -> bool {
-    let mut csv_path = PathBuf::from(
-        env::var("CARGO_MANIFEST_DIR").expect("`CARGO_MANIFEST_DIR` is alway set by cargo.").to_string()
-    );
-    csv_path.pop();
-    let csv_path = csv_path.join("config\\peerlist.csv");
-
-    // let fp = match File::open(csv_path) {
-    //     Ok(v) => v,
-    //     Err(_) => return false,
-    // };
-
+pub fn save_peerlist(csv_path: &Path, peers: &[PeerInfo]) -> bool {
     let mut csv_file = csv::WriterBuilder::new()
                             .has_headers(false)
                             .delimiter(b',')
-                            .from_path(csv_path).unwrap();
+                            .from_writer(Vec::<u8>::new());
     
     for peer in peers {
         let mut key_str = String::new();
@@ -225,12 +237,18 @@ pub fn save_peerlist(peers: &[PeerInfo]) -> bool {
             Err(_) => return false,
         }
     }
-    match csv_file.flush() {
-        Ok(()) => true,
-        Err(_) => false,
+    let buf = match csv_file.into_inner() {
+        Ok(v) => v,
+        Err(_) => return false,
     };
 
-    return true;
+    match persist::write_sealed(csv_path, &String::from_utf8_lossy(&buf)) {
+        Ok(()) => true,
+        Err(e) => {
+            log::error!("Couldn't write {}: {}", csv_path.display(), e);
+            false
+        },
+    }
 }
 
 #[allow(dead_code)]
@@ -243,7 +261,10 @@ pub fn parse_string_to_vec(ip_str: &String) -> Vec<u8> {
     
     let mut ip_vec = Vec::new();
     for i in 0..4 {
-        let num: i32 = ip_addr[i].parse().unwrap();
+        let num: i32 = match ip_addr[i].parse() {
+            Ok(v) => v,
+            Err(_) => return Vec::new(),
+        };
         if num < 0 || num > 255 {
             return Vec::new();
         }
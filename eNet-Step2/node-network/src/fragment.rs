@@ -0,0 +1,445 @@
+use std::collections::{ HashMap, VecDeque };
+use std::fmt::{ self, Display, Formatter };
+use std::net::SocketAddrV4;
+
+/*
+ *  Declaration of Constants
+ */
+pub const FRAME_WHOLE: u8 = 0x00;
+pub const FRAME_CHUNK: u8 = 0x01;
+pub const FRAME_RESUME: u8 = 0x02;
+
+// Largest piece of payload carried by one packet, before framing and signature
+pub const MAX_CHUNK_SIZE: usize = 4096;
+// Largest payload a sender may announce in a chunk header
+pub const MAX_PAYLOAD_SIZE: usize = 64 * 1024 * 1024;
+
+pub const DEFAULT_REASSEMBLY_TIMEOUT: u64 = 30_000;
+pub const DEFAULT_REASSEMBLY_LIMIT: usize = 16 * 1024 * 1024;
+pub const DEFAULT_OUTBOX_TIMEOUT: u64 = 120_000;
+pub const DEFAULT_OUTBOX_LIMIT: usize = 32 * 1024 * 1024;
+
+// kind(1) + payload id(8) + index(4) + count(4) + total length(4)
+const CHUNK_HEADER_LEN: usize = 21;
+// kind(1) + payload id(8) + missing count(4)
+const RESUME_HEADER_LEN: usize = 13;
+
+/*
+ *  Declaration of FragmentError
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum FragmentError {
+    Malformed,
+    TooLarge(usize),
+    Inconsistent(u64),
+    BufferFull(u64),
+}
+
+impl Display for FragmentError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            FragmentError::Malformed => write!(f, "malformed frame"),
+            FragmentError::TooLarge(len) => write!(f, "payload of {} bytes exceeds the limit", len),
+            FragmentError::Inconsistent(id) => write!(f, "chunks of payload {} do not match", id),
+            FragmentError::BufferFull(id) => write!(f, "no room to reassemble payload {}", id),
+        }
+    }
+}
+
+/*
+ *  Declaration of Chunk and Frame
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub payload_id: u64,
+    pub index: u32,
+    pub count: u32,
+    pub total_len: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Whole(Vec<u8>),
+    Chunk(Chunk),
+    Resume { payload_id: u64, missing: Vec<u32> },
+}
+
+impl Frame {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::<u8>::new();
+        match self {
+            Frame::Whole(data) => {
+                out.push(FRAME_WHOLE);
+                out.extend_from_slice(data);
+            },
+            Frame::Chunk(chunk) => {
+                out.reserve(CHUNK_HEADER_LEN + chunk.data.len());
+                out.push(FRAME_CHUNK);
+                out.extend_from_slice(&chunk.payload_id.to_be_bytes());
+                out.extend_from_slice(&chunk.index.to_be_bytes());
+                out.extend_from_slice(&chunk.count.to_be_bytes());
+                out.extend_from_slice(&chunk.total_len.to_be_bytes());
+                out.extend_from_slice(&chunk.data);
+            },
+            Frame::Resume { payload_id, missing } => {
+                out.push(FRAME_RESUME);
+                out.extend_from_slice(&payload_id.to_be_bytes());
+                out.extend_from_slice(&(missing.len() as u32).to_be_bytes());
+                for index in missing {
+                    out.extend_from_slice(&index.to_be_bytes());
+                }
+            },
+        }
+
+        out
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Frame, FragmentError> {
+        if data.is_empty() {
+            return Err(FragmentError::Malformed);
+        }
+
+        match data[0] {
+            FRAME_WHOLE => Ok(Frame::Whole(data[1..].to_vec())),
+            FRAME_CHUNK => {
+                if data.len() < CHUNK_HEADER_LEN {
+                    return Err(FragmentError::Malformed);
+                }
+                let chunk = Chunk {
+                    payload_id: read_u64(&data[1..9]),
+                    index: read_u32(&data[9..13]),
+                    count: read_u32(&data[13..17]),
+                    total_len: read_u32(&data[17..21]),
+                    data: data[CHUNK_HEADER_LEN..].to_vec(),
+                };
+                if (chunk.total_len as usize) > MAX_PAYLOAD_SIZE {
+                    return Err(FragmentError::TooLarge(chunk.total_len as usize));
+                }
+                // Every payload is split the same way, so the header fixes count and sizes
+                if (chunk.count != chunk_count(chunk.total_len as usize)) || (chunk.index >= chunk.count) {
+                    return Err(FragmentError::Malformed);
+                }
+                if chunk.data.len() != chunk_len(chunk.total_len as usize, chunk.index) {
+                    return Err(FragmentError::Malformed);
+                }
+                Ok(Frame::Chunk(chunk))
+            },
+            FRAME_RESUME => {
+                if data.len() < RESUME_HEADER_LEN {
+                    return Err(FragmentError::Malformed);
+                }
+                let payload_id = read_u64(&data[1..9]);
+                let count = read_u32(&data[9..13]) as usize;
+                if data.len() != RESUME_HEADER_LEN + count * 4 {
+                    return Err(FragmentError::Malformed);
+                }
+                let mut missing = Vec::<u32>::with_capacity(count);
+                for i in 0..count {
+                    let off = RESUME_HEADER_LEN + i * 4;
+                    missing.push(read_u32(&data[off..off + 4]));
+                }
+                Ok(Frame::Resume { payload_id, missing })
+            },
+            _ => Err(FragmentError::Malformed),
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(bytes);
+    u32::from_be_bytes(buf)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(bytes);
+    u64::from_be_bytes(buf)
+}
+
+// Chunks a payload of `total_len` bytes is split into
+fn chunk_count(total_len: usize) -> u32 {
+    total_len.div_ceil(MAX_CHUNK_SIZE).max(1) as u32
+}
+
+// Bytes carried by chunk `index`, all full but the last
+fn chunk_len(total_len: usize, index: u32) -> usize {
+    total_len.saturating_sub(index as usize * MAX_CHUNK_SIZE).min(MAX_CHUNK_SIZE)
+}
+
+/*
+ *  Split a payload into numbered chunks of MAX_CHUNK_SIZE bytes, the last
+ *  one possibly shorter. Payloads over MAX_PAYLOAD_SIZE are refused.
+ */
+pub fn split_payload(payload_id: u64, payload: &[u8]) -> Result<Vec<Chunk>, FragmentError> {
+    if payload.len() > MAX_PAYLOAD_SIZE {
+        return Err(FragmentError::TooLarge(payload.len()));
+    }
+    let count = chunk_count(payload.len());
+
+    let mut chunks = Vec::<Chunk>::with_capacity(count as usize);
+    for index in 0..count {
+        let start = (index as usize) * MAX_CHUNK_SIZE;
+        let end = start + chunk_len(payload.len(), index);
+        chunks.push(Chunk {
+            payload_id,
+            index,
+            count,
+            total_len: payload.len() as u32,
+            data: payload[start..end].to_vec(),
+        });
+    }
+
+    Ok(chunks)
+}
+
+/*
+ *  Declaration of Class Reassembler
+ *
+ *  Collects chunks per (sender, payload id). Partial payloads are dropped once
+ *  they have not made progress for `timeout` milliseconds, and the oldest ones
+ *  are evicted when buffered bytes would exceed `limit`.
+ */
+struct PartialPayload {
+    count: u32,
+    total_len: u32,
+    chunks: Vec<Option<Vec<u8>>>,
+    received: u32,
+    bytes: usize,
+    last_update: u64,
+}
+
+pub struct Reassembler {
+    partials: HashMap<(SocketAddrV4, u64), PartialPayload>,
+    pub timeout: u64,
+    pub limit: usize,
+    used: usize,
+}
+
+impl Reassembler {
+    pub fn new(timeout: u64, limit: usize) -> Self {
+        Reassembler {
+            partials: HashMap::new(),
+            timeout,
+            limit,
+            used: 0,
+        }
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.used
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.partials.len()
+    }
+
+    pub fn insert(&mut self, sender: SocketAddrV4, chunk: Chunk, now: u64) -> Result<Option<Vec<u8>>, FragmentError> {
+        let key = (sender, chunk.payload_id);
+
+        if (chunk.total_len as usize) > self.limit {
+            return Err(FragmentError::TooLarge(chunk.total_len as usize));
+        }
+        if (chunk.count != chunk_count(chunk.total_len as usize)) || (chunk.index >= chunk.count) {
+            return Err(FragmentError::Inconsistent(chunk.payload_id));
+        }
+
+        // Single chunk payloads never touch the buffer
+        if chunk.count == 1 {
+            if chunk.data.len() != chunk.total_len as usize {
+                return Err(FragmentError::Inconsistent(chunk.payload_id));
+            }
+            return Ok(Some(chunk.data));
+        }
+
+        if let Some(partial) = self.partials.get(&key) {
+            if (partial.count != chunk.count) || (partial.total_len != chunk.total_len) {
+                self.remove(&key);
+                return Err(FragmentError::Inconsistent(chunk.payload_id));
+            }
+            if partial.chunks[chunk.index as usize].is_some() {
+                // Duplicate from a resumed transfer
+                return Ok(None);
+            }
+        }
+
+        if self.used + chunk.data.len() > self.limit {
+            self.evict_for(chunk.data.len(), &key);
+            if self.used + chunk.data.len() > self.limit {
+                self.remove(&key);
+                return Err(FragmentError::BufferFull(chunk.payload_id));
+            }
+        }
+
+        let partial = self.partials.entry(key).or_insert_with(|| PartialPayload {
+            count: chunk.count,
+            total_len: chunk.total_len,
+            chunks: vec![None; chunk.count as usize],
+            received: 0,
+            bytes: 0,
+            last_update: now,
+        });
+
+        let len = chunk.data.len();
+        partial.chunks[chunk.index as usize] = Some(chunk.data);
+        partial.received += 1;
+        partial.bytes += len;
+        partial.last_update = now;
+        let overflow = partial.bytes > partial.total_len as usize;
+        let complete = partial.received == partial.count;
+        self.used += len;
+
+        if overflow {
+            self.remove(&key);
+            return Err(FragmentError::Inconsistent(key.1));
+        }
+        if !complete {
+            return Ok(None);
+        }
+
+        let partial = self.partials.remove(&key).unwrap();
+        self.used -= partial.bytes;
+        if partial.bytes != partial.total_len as usize {
+            return Err(FragmentError::Inconsistent(key.1));
+        }
+
+        let mut payload = Vec::<u8>::with_capacity(partial.total_len as usize);
+        for data in partial.chunks {
+            payload.extend_from_slice(&data.unwrap());
+        }
+
+        Ok(Some(payload))
+    }
+
+    // Drop partial payloads which have stalled longer than the timeout
+    pub fn expire(&mut self, now: u64) -> usize {
+        let timeout = self.timeout;
+        let stale: Vec<(SocketAddrV4, u64)> = self.partials.iter()
+            .filter(|(_, p)| now.saturating_sub(p.last_update) > timeout)
+            .map(|(k, _)| *k)
+            .collect();
+
+        for key in &stale {
+            self.remove(key);
+        }
+
+        stale.len()
+    }
+
+    // Missing chunk indices of every partial payload received from `sender`
+    pub fn missing_from(&self, sender: SocketAddrV4) -> Vec<(u64, Vec<u32>)> {
+        let mut result = Vec::new();
+        for ((addr, payload_id), partial) in &self.partials {
+            if *addr != sender {
+                continue;
+            }
+            let missing: Vec<u32> = partial.chunks.iter().enumerate()
+                .filter(|(_, c)| c.is_none())
+                .map(|(i, _)| i as u32)
+                .collect();
+            result.push((*payload_id, missing));
+        }
+
+        result
+    }
+
+    fn remove(&mut self, key: &(SocketAddrV4, u64)) {
+        if let Some(partial) = self.partials.remove(key) {
+            self.used -= partial.bytes;
+        }
+    }
+
+    fn evict_for(&mut self, needed: usize, keep: &(SocketAddrV4, u64)) {
+        while self.used + needed > self.limit {
+            let oldest = self.partials.iter()
+                .filter(|(k, _)| *k != keep)
+                .min_by_key(|(_, p)| p.last_update)
+                .map(|(k, _)| *k);
+            match oldest {
+                Some(key) => self.remove(&key),
+                None => break,
+            }
+        }
+    }
+}
+
+/*
+ *  Declaration of Class Outbox
+ *
+ *  Keeps the chunks of recently sent payloads so that a peer which reconnects
+ *  in the middle of a transfer can ask for the ones it is still missing.
+ */
+struct OutgoingPayload {
+    chunks: Vec<Chunk>,
+    bytes: usize,
+    created: u64,
+}
+
+pub struct Outbox {
+    payloads: HashMap<u64, OutgoingPayload>,
+    order: VecDeque<u64>,
+    pub timeout: u64,
+    pub limit: usize,
+    used: usize,
+}
+
+impl Outbox {
+    pub fn new(timeout: u64, limit: usize) -> Self {
+        Outbox {
+            payloads: HashMap::new(),
+            order: VecDeque::new(),
+            timeout,
+            limit,
+            used: 0,
+        }
+    }
+
+    pub fn store(&mut self, payload_id: u64, chunks: Vec<Chunk>, now: u64) {
+        let bytes: usize = chunks.iter().map(|c| c.data.len()).sum();
+        if bytes > self.limit {
+            return;
+        }
+        while self.used + bytes > self.limit {
+            if !self.pop_oldest() {
+                break;
+            }
+        }
+
+        self.used += bytes;
+        self.order.push_back(payload_id);
+        self.payloads.insert(payload_id, OutgoingPayload { chunks, bytes, created: now });
+    }
+
+    pub fn get(&self, payload_id: u64, index: u32) -> Option<&Chunk> {
+        match self.payloads.get(&payload_id) {
+            Some(payload) => payload.chunks.get(index as usize),
+            None => None,
+        }
+    }
+
+    pub fn expire(&mut self, now: u64) {
+        while let Some(payload_id) = self.order.front() {
+            let expired = match self.payloads.get(payload_id) {
+                Some(payload) => now.saturating_sub(payload.created) > self.timeout,
+                None => true,
+            };
+            if !expired {
+                break;
+            }
+            self.pop_oldest();
+        }
+    }
+
+    fn pop_oldest(&mut self) -> bool {
+        match self.order.pop_front() {
+            Some(payload_id) => {
+                if let Some(payload) = self.payloads.remove(&payload_id) {
+                    self.used -= payload.bytes;
+                }
+                true
+            },
+            None => false,
+        }
+    }
+}
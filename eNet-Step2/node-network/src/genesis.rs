@@ -0,0 +1,153 @@
+use serde::{ Deserialize, Serialize };
+use std::fs;
+use std::path::Path;
+
+use chain_core::{ Genesis, GenesisAccount, GenesisParams, IdentityStatus };
+
+use crate::node_config::ConfigError;
+use crate::persist;
+
+pub const GENESIS_FILE: &str = "genesis.toml";
+
+const PUBLIC_KEY_BYTES: usize = secure_sign::CRYPTO_PUBLICKEYBYTES as usize;
+
+/*
+ *  Declaration of GenesisFile
+ *
+ *  `genesis.toml`, the same file on every node of a network. Keys are hex
+ *  Falcon public keys. Only the values count towards the genesis hash, not
+ *  their order or the formatting of the file.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GenesisFile {
+    pub chain_id: String,
+    // No block may be timestamped before this, in milliseconds since the Unix epoch
+    pub genesis_time_ms: u64,
+    pub validators: Vec<String>,
+    #[serde(default)]
+    pub params: GenesisParamsFile,
+    #[serde(default)]
+    pub accounts: Vec<GenesisAccountFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GenesisParamsFile {
+    pub max_block_bytes: u64,
+    pub max_tx_bytes: u64,
+    pub block_time_ms: u64,
+    // Blocks a verified person stays live without a new liveness claim
+    pub liveness_epoch: u64,
+}
+
+impl Default for GenesisParamsFile {
+    fn default() -> Self {
+        let params = GenesisParams::default();
+        GenesisParamsFile {
+            max_block_bytes: params.max_block_bytes,
+            max_tx_bytes: params.max_tx_bytes,
+            block_time_ms: params.block_time_ms,
+            liveness_epoch: params.liveness_epoch,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GenesisAccountFile {
+    pub public_key: String,
+    #[serde(default)]
+    pub balance: u64,
+    // unverified, verified or revoked
+    #[serde(default = "default_identity")]
+    pub identity: String,
+}
+
+fn default_identity() -> String {
+    String::from("unverified")
+}
+
+fn identity_name(identity: IdentityStatus) -> &'static str {
+    match identity {
+        IdentityStatus::Unverified => "unverified",
+        IdentityStatus::Verified => "verified",
+        IdentityStatus::Revoked => "revoked",
+    }
+}
+
+fn parse_identity(name: &str) -> Option<IdentityStatus> {
+    match name {
+        "unverified" => Some(IdentityStatus::Unverified),
+        "verified" => Some(IdentityStatus::Verified),
+        "revoked" => Some(IdentityStatus::Revoked),
+        _ => None,
+    }
+}
+
+fn parse_key(file: &Path, key: &str, value: &str) -> Result<Vec<u8>, ConfigError> {
+    match hex::decode(value) {
+        Ok(bytes) if bytes.len() == PUBLIC_KEY_BYTES => Ok(bytes),
+        Ok(bytes) => Err(ConfigError::new(file, key, format!("expected {} bytes, found {}", PUBLIC_KEY_BYTES, bytes.len()))),
+        Err(e) => Err(ConfigError::new(file, key, format!("invalid hex: {}", e))),
+    }
+}
+
+impl GenesisFile {
+    pub fn from_genesis(genesis: &Genesis) -> Self {
+        GenesisFile {
+            chain_id: genesis.chain_id.clone(),
+            genesis_time_ms: genesis.genesis_time,
+            validators: genesis.validators.iter().map(hex::encode_upper).collect(),
+            params: GenesisParamsFile {
+                max_block_bytes: genesis.params.max_block_bytes,
+                max_tx_bytes: genesis.params.max_tx_bytes,
+                block_time_ms: genesis.params.block_time_ms,
+                liveness_epoch: genesis.params.liveness_epoch,
+            },
+            accounts: genesis.accounts.iter().map(|a| GenesisAccountFile {
+                public_key: hex::encode_upper(&a.public_key),
+                balance: a.balance,
+                identity: String::from(identity_name(a.identity)),
+            }).collect(),
+        }
+    }
+
+    // The genesis this file describes, checked; `file` is named in errors
+    pub fn to_genesis(&self, file: &Path) -> Result<Genesis, ConfigError> {
+        let mut genesis = Genesis::new(&self.chain_id, self.genesis_time_ms);
+        genesis.params = GenesisParams {
+            max_block_bytes: self.params.max_block_bytes,
+            max_tx_bytes: self.params.max_tx_bytes,
+            block_time_ms: self.params.block_time_ms,
+            liveness_epoch: self.params.liveness_epoch,
+        };
+        for (i, key) in self.validators.iter().enumerate() {
+            genesis.validators.push(parse_key(file, &format!("validators[{}]", i), key)?);
+        }
+        for (i, account) in self.accounts.iter().enumerate() {
+            let public_key = parse_key(file, &format!("accounts[{}].public_key", i), &account.public_key)?;
+            let identity = parse_identity(&account.identity).ok_or_else(|| ConfigError::new(file, &format!("accounts[{}].identity", i),
+                format!("unknown identity `{}` (expected unverified, verified or revoked)", account.identity)))?;
+            genesis.accounts.push(GenesisAccount { public_key, balance: account.balance, identity });
+        }
+        genesis.validate().map_err(|e| ConfigError::new(file, "", e.to_string()))?;
+
+        Ok(genesis)
+    }
+
+    pub fn to_toml(&self, file: &Path) -> Result<String, ConfigError> {
+        toml::to_string_pretty(self).map_err(|e| ConfigError::new(file, "", e.to_string()))
+    }
+}
+
+pub fn load_genesis(path: &Path) -> Result<Genesis, ConfigError> {
+    let text = fs::read_to_string(path).map_err(|e| ConfigError::new(path, "", e.to_string()))?;
+    let file: GenesisFile = toml::from_str(&text).map_err(|e| ConfigError::new(path, "", e.to_string()))?;
+    file.to_genesis(path)
+}
+
+pub fn save_genesis(path: &Path, genesis: &Genesis) -> Result<(), ConfigError> {
+    let text = GenesisFile::from_genesis(genesis).to_toml(path)?;
+    persist::write_atomic(path, text.as_bytes()).map_err(|e| ConfigError::new(path, "", e.to_string()))
+}
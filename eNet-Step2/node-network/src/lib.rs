@@ -3,18 +3,34 @@
 extern crate enet;
 extern crate hex;
 extern crate chrono;
+#[macro_use]
+extern crate log;
 
 use enet::*;
 use std::fmt::{ self, Debug, Formatter };
-use std::net::Ipv4Addr;
+use std::net::{ Ipv4Addr, SocketAddrV4 };
 use libc;
 use std::mem;
+use std::sync::mpsc::Receiver;
 use chrono::prelude::*;
 
 use secure_sign::NistCryptography;
 use secure_sign::CRYPTO_BYTES;
+use chain_core::{ Chain, ChainEvent, Consensus, ConsensusMessage, ConsensusOutput, Decode, Encode, Genesis, Mempool, Store, SNAPSHOT_CHUNK_ACCOUNTS };
+use secure_sign::CRYPTO_PUBLICKEYBYTES;
 
 mod config;
+mod node_config;
+mod logging;
+mod persist;
+mod fragment;
+mod replay;
+mod transport;
+mod sim;
+mod reload;
+mod message;
+mod sync;
+mod genesis;
 pub use config::PeerInfo;
 pub use config::HostInfo;
 pub use config::get_hosts;
@@ -24,13 +40,37 @@ pub use config::save_peerlist;
 pub use config::parse_string_to_vec;
 pub use config::parse_string_to_ip;
 pub use config::parse_string_to_reverse_ip;
+pub use config::load_hosts;
+pub use config::load_peerlist;
+pub use node_config::{ NodeConfig, ConfigPaths, ConfigSource, ConfigError, config_arg, default_data_dir };
+pub use node_config::{ PeerConfig, Limits, LoggingConfig, ChainConfig };
+pub use logging::{ init_logging, set_log_level };
+pub use persist::{ Integrity, BACKUP_COUNT, backup_path, check_integrity, load_or_recover, write_atomic, write_sealed };
+pub use fragment::{ Chunk, Frame, FragmentError, Outbox, Reassembler, split_payload };
+pub use fragment::{ MAX_CHUNK_SIZE, MAX_PAYLOAD_SIZE };
+pub use replay::{ Envelope, ReplayError, ReplayGuard };
+pub use transport::{ Transport, TransportError, TransportEvent, EnetTransport };
+pub use sim::{ SimConfig, SimNetwork, SimTransport };
+pub use message::{ PayloadKind, decode_payload, encode_payload };
+pub use genesis::{ GenesisFile, GenesisAccountFile, GenesisParamsFile, GENESIS_FILE, load_genesis, save_genesis };
+pub use sync::{ SyncMessage, Syncer, MAX_SYNC_BLOCKS, MAX_SYNC_HEADERS, MAX_SYNC_REPLY_BYTES, SYNC_WINDOW };
+pub use reload::{ ConfigWatcher, PeerDiff, install_reload_signal, request_reload };
 pub use secure_sign::randombytes;
 
 /* 
  *  Declaratio of Constants
  */
 const DEFAULT_PORT: u16 = 8875;
-const MAX_PEERS_COUNT: usize = 50;
+// Raw messages kept for display; older ones are dropped first
+pub const MAX_RECV_MESSAGES: usize = 1000;
+const POOL_PRUNE_INTERVAL: u64 = 1000;
+// Milliseconds between attempts to connect to peers whose connect failed
+const RECONNECT_INTERVAL: u64 = 5000;
+pub(crate) const MAX_PEERS_COUNT: usize = 50;
+
+pub fn now_millis() -> u64 {
+    Utc::now().timestamp_millis() as u64
+}
 
 /* 
  *  Declaratio of Receive Buffer
@@ -38,6 +78,7 @@ const MAX_PEERS_COUNT: usize = 50;
 pub struct RecvMsg {
     pub timestamp: String,
     pub sender: u16,
+    pub kind: PayloadKind,
     pub msg: Vec<u8>,
 }
 
@@ -46,6 +87,7 @@ impl RecvMsg {
         RecvMsg {
             timestamp: String::new(),
             sender: 0,
+            kind: PayloadKind::Raw,
             msg: Vec::<u8>::new(),
         }
     }
@@ -56,11 +98,28 @@ impl RecvMsg {
  */
 pub struct HostRepo {
     pub port: u16,
-    pub host: Vec<Host<()>>,
+    pub config: NodeConfig,
+    pub host: Vec<Box<dyn Transport>>,
     pub peers: Vec<PeerInfo>,
     pub secure: NistCryptography,
     pub recv_messages: Vec<RecvMsg>,
     pub received: bool,
+    pub reassembler: Reassembler,
+    pub outbox: Outbox,
+    pub replay_guard: ReplayGuard,
+    pub mempool: Mempool,
+    pub chain: Chain,
+    pub consensus: Consensus,
+    pub sync: Syncer,
+    // Finalized heights that are a multiple of this get a snapshot, 0 for none
+    pub snapshot_interval: u64,
+    chain_events: Receiver<ChainEvent>,
+    next_prune: u64,
+    // Peers to connect to again once next_reconnect has passed
+    reconnect: Vec<SocketAddrV4>,
+    next_reconnect: u64,
+    next_payload_id: u64,
+    next_sequence: u64,
 }
 
 impl Debug for HostRepo {
@@ -89,12 +148,17 @@ impl Debug for HostRepo {
 
 impl HostRepo {
     pub fn new(port: u16) -> Self {
+        let defaults = ChainConfig::default();
+        let mut chain = Chain::new(&defaults.chain_id, defaults.max_block_bytes, defaults.max_tx_bytes);
+        let chain_events = chain.subscribe();
+
         HostRepo {
             port: match port {
                 0 => DEFAULT_PORT,
                 _ => port,
             },
-            host: Vec::<Host<()>>::new(),
+            config: NodeConfig::at(ConfigPaths::locate(None)),
+            host: Vec::<Box<dyn Transport>>::new(),
             peers: Vec::<PeerInfo>::new(),
             secure: NistCryptography::new(),
             recv_messages: {
@@ -104,6 +168,21 @@ impl HostRepo {
                 recv_vec
             },
             received: false,
+            reassembler: Reassembler::new(fragment::DEFAULT_REASSEMBLY_TIMEOUT, fragment::DEFAULT_REASSEMBLY_LIMIT),
+            outbox: Outbox::new(fragment::DEFAULT_OUTBOX_TIMEOUT, fragment::DEFAULT_OUTBOX_LIMIT),
+            replay_guard: ReplayGuard::new(replay::DEFAULT_REPLAY_WINDOW, replay::DEFAULT_MAX_CLOCK_SKEW),
+            mempool: Mempool::new(&defaults.chain_id, defaults.max_tx_bytes, chain_core::DEFAULT_POOL_BYTES, chain_core::DEFAULT_POOL_TTL),
+            chain,
+            consensus: Consensus::new(defaults.timeouts()),
+            sync: Syncer::new(),
+            snapshot_interval: 0,
+            chain_events,
+            next_prune: 0,
+            reconnect: Vec::new(),
+            next_reconnect: 0,
+            next_payload_id: now_millis(),
+            // Seeded from the clock so a restarted sender stays above receivers' high-water marks
+            next_sequence: now_millis() * 1000,
         }
     }
 
@@ -113,7 +192,7 @@ impl HostRepo {
         self.received = false;
 
         if !(&self.read_peerlist()) {
-            println!("Read Fail!!!! from {}", self.port);
+            warn!("No peers to read for host {}", self.port);
             return false;
         }
         if !(&self.connect_peers()) {
@@ -132,8 +211,7 @@ impl HostRepo {
     }
 
     pub fn read_peerlist(&mut self) -> bool {
-        &self.peers.clear();
-        &self.peers.append(&mut (config::read_peerlist()));
+        self.peers = self.config.peers();
 
         match self.peers.len() {
             0 => false, 
@@ -142,39 +220,211 @@ impl HostRepo {
     }
 
     pub fn save_peerlist(&mut self) -> bool {
-        config::save_peerlist(&self.peers)
+        self.config.set_peers(&self.peers);
+        self.config.save_peers()
     }
-    
-    pub fn add_peer_info(&mut self, peer_info: PeerInfo) {
-        self.peers.push(peer_info);
+
+    pub fn apply_limits(&mut self, limits: &Limits) {
+        self.reassembler.limit = limits.reassembly_bytes.min(limits.max_message_bytes);
+        self.reassembler.timeout = limits.reassembly_timeout_ms;
+        self.outbox.limit = limits.outbox_bytes;
+        self.outbox.timeout = limits.outbox_timeout_ms;
+        self.replay_guard.window = limits.replay_window;
+        self.replay_guard.max_skew = limits.max_clock_skew_ms;
+        self.mempool.max_bytes = limits.mempool_bytes;
+        self.mempool.ttl = limits.mempool_ttl_ms;
     }
 
-    #[allow(dead_code)]
-    pub fn connect_peer(&mut self, peer_info: PeerInfo) -> bool {
-        let peer_address = peer_info.address.clone();
-        let peer_port = peer_info.port;
+    pub fn apply_chain(&mut self, chain: &ChainConfig) {
+        if self.mempool.chain_id != chain.chain_id && !self.mempool.is_empty() {
+            warn!("Chain ID changed to `{}`, dropping {} pooled transactions", chain.chain_id, self.mempool.len());
+            self.mempool = Mempool::new(&chain.chain_id, chain.max_tx_bytes, self.mempool.max_bytes, self.mempool.ttl);
+        }
+        self.mempool.chain_id = chain.chain_id.clone();
+        self.mempool.max_tx_bytes = chain.max_tx_bytes;
 
-        if self.host.len() > 0 {
-            if self.same_address(&peer_address, &peer_port) {
+        if self.chain.chain_id != chain.chain_id && self.chain.tip().is_some() {
+            warn!("Chain ID changed to `{}` on a running chain, restart the node with a fresh data directory", chain.chain_id);
+        }
+        self.chain.chain_id = chain.chain_id.clone();
+        self.chain.max_block_bytes = chain.max_block_bytes;
+        self.chain.max_tx_bytes = chain.max_tx_bytes;
+        let validator_keys = chain.validator_keys();
+        if self.chain.validator_keys != validator_keys {
+            self.chain.validator_keys = validator_keys;
+            self.consensus.reset();
+        }
+        self.consensus.timeouts = chain.timeouts();
+        self.snapshot_interval = chain.snapshot_interval;
+        self.sync.fast_sync = chain.fast_sync;
+    }
+
+    // Start the chain from `genesis`; the store opened afterwards must belong to it
+    pub fn apply_genesis(&mut self, genesis: &Genesis) -> bool {
+        if let Err(e) = self.chain.set_genesis(genesis) {
+            error!("Host {} couldn't start from genesis {} -> {}", self.port, genesis.hash(), e);
+            return false;
+        }
+        info!("Host {} starts from genesis {} of `{}` with {} validators", self.port, genesis.hash(), genesis.chain_id, genesis.validators.len());
+
+        true
+    }
+
+    // Keep the chain in `dir`, continuing from whatever it already holds
+    pub fn open_store(&mut self, dir: &std::path::Path) -> bool {
+        let store = match Store::open(dir) {
+            Ok(store) => store,
+            Err(e) => {
+                error!("Couldn't open chain store {} -> {}", dir.display(), e);
                 return false;
+            },
+        };
+        if let Err(e) = self.chain.attach_store(store) {
+            error!("Couldn't load chain from {} -> {}", dir.display(), e);
+            return false;
+        }
+        // Sync picks up from what the store holds
+        self.sync.reset();
+        info!("Host {} continues the chain at height {}", self.port, self.chain.next_height());
+
+        true
+    }
+
+    // Broadcast what consensus wants sent and commit what it decided
+    fn apply_consensus(&mut self, outputs: Vec<ConsensusOutput>) {
+        for output in outputs {
+            match output {
+                ConsensusOutput::Broadcast(msg) => {
+                    self.broadcast_payload(PayloadKind::Consensus, &msg.encode());
+                },
+                ConsensusOutput::Decided { block, state, certificate } => {
+                    let (round, signers) = (certificate.round, certificate.signer_count());
+                    if let Err(e) = self.chain.commit(&block, *state, certificate) {
+                        error!("Host {} couldn't commit block {} -> {}", self.port, block.header.height, e);
+                        self.consensus.reset();
+                        continue;
+                    }
+
+                    info!("Committed block {} {} with {} transactions on {} (round {}, {} signers)",
+                        block.header.height, block.hash(), block.transactions.len(), self.port, round, signers);
+                },
             }
+        }
+        self.apply_chain_events();
+    }
 
-            let peer_host = &mut self.host[0];
-            if !peer_info.connected {
-                peer_host.connect(&Address::new(peer_address.parse().unwrap(), peer_port), MAX_PEERS_COUNT, 0)
-                    .expect("Connect failed");
+    // Keep the pool in line with the chain, whichever way the tip moved
+    fn apply_chain_events(&mut self) {
+        let now = self.now_millis();
+        while let Ok(event) = self.chain_events.try_recv() {
+            match event {
+                ChainEvent::NewTip { .. } => {
+                    self.mempool.prune(&self.chain.state, self.chain.next_height(), now);
+                },
+                ChainEvent::Reorg { ancestor, reverted, applied } => {
+                    let txs = reverted.iter().flat_map(|b| b.decode_transactions().unwrap_or_default()).collect();
+                    let back = self.mempool.reinject(txs, &self.chain.state, self.chain.next_height(), now);
+                    warn!("Host {} reorganized onto {}: {} blocks reverted, {} applied, {} transactions back in the pool",
+                        self.port, ancestor, reverted.len(), applied.len(), back);
+                },
+                ChainEvent::Finalized { hash, height } => {
+                    debug!("Block {} {} is final on {}", height, hash, self.port);
+                    if self.snapshot_interval > 0 && height > 0 && height % self.snapshot_interval == 0 {
+                        self.save_snapshot();
+                    }
+                },
             }
-        } else {
+        }
+    }
+
+    // Keep a snapshot of the finalized state for peers that fast sync
+    fn save_snapshot(&mut self) {
+        let snapshot = match self.chain.snapshot(SNAPSHOT_CHUNK_ACCOUNTS) {
+            Ok(Some(snapshot)) => snapshot,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Host {} couldn't take a snapshot -> {}", self.port, e);
+                return;
+            },
+        };
+        let saved = match self.chain.store() {
+            Some(store) => store.save_snapshot(&snapshot),
+            None => return,
+        };
+        match saved {
+            Ok(()) => info!("Host {} saved snapshot {} in {} chunks", self.port, snapshot.manifest.height(), snapshot.chunks.len()),
+            Err(e) => error!("Host {} couldn't save snapshot {} -> {}", self.port, snapshot.manifest.height(), e),
+        }
+    }
+
+    /*
+     *  Bring `peers` in line with `desired` without touching unchanged links:
+     *  new peers are connected, dropped ones disconnected and forgotten, and
+     *  peers whose public key changed keep their connection.
+     */
+    pub fn update_peers(&mut self, desired: Vec<PeerInfo>) -> PeerDiff {
+        let mut diff = PeerDiff::default();
+        let addr_of = |peer: &PeerInfo| SocketAddrV4::new(parse_string_to_ip(&peer.address), peer.port);
+
+        let mut kept = Vec::<PeerInfo>::new();
+        for peer in self.peers.drain(..) {
+            let addr = addr_of(&peer);
+            if desired.iter().any(|p| addr_of(p) == addr) {
+                kept.push(peer);
+                continue;
+            }
+            if let Some(transport) = self.host.first_mut() {
+                transport.disconnect(addr);
+            }
+            self.replay_guard.forget(addr);
+            self.reconnect.retain(|a| *a != addr);
+            diff.removed.push(addr);
+        }
+        self.peers = kept;
+
+        for peer in desired {
+            let addr = addr_of(&peer);
+            match self.peers.iter_mut().find(|p| addr_of(p) == addr) {
+                Some(existing) => {
+                    if existing.key != peer.key {
+                        existing.key = peer.key;
+                        diff.rekeyed.push(addr);
+                    }
+                },
+                None => {
+                    if !self.same_address(&peer.address, &peer.port) {
+                        self.connect_addr(addr);
+                    }
+                    self.peers.push(peer);
+                    diff.added.push(addr);
+                },
+            }
+        }
+
+        diff
+    }
+
+    pub fn add_peer_info(&mut self, peer_info: PeerInfo) {
+        self.peers.push(peer_info);
+    }
+
+    // Added even if the connect fails, in which case it is retried later
+    #[allow(dead_code)]
+    pub fn connect_peer(&mut self, peer_info: PeerInfo) -> bool {
+        if self.host.is_empty() || self.same_address(&peer_info.address, &peer_info.port) {
             return false;
         }
 
+        let addr = SocketAddrV4::new(parse_string_to_ip(&peer_info.address), peer_info.port);
+        let connected = peer_info.connected || self.connect_addr(addr);
         self.add_peer_info(peer_info);
 
-        return true;
+        connected
     }
 
+    // Peers that fail to connect are retried every RECONNECT_INTERVAL
     pub fn connect_peers(&mut self) -> bool {
-        if self.peers.len() == 0 {
+        if self.peers.is_empty() {
             return false;
         }
 
@@ -187,19 +437,40 @@ impl HostRepo {
                 continue;
             }
             
-            if self.host.len() > 0 {
-                let peer_host = &mut self.host[0];
-
-                if !self.peers[i].connected {
-                    match peer_host.connect(&Address::new(peer_address.trim().parse().unwrap(), peer_port), MAX_PEERS_COUNT, 0) {
-                        Ok(_) => continue,
-                        Err(_) => return false,
-                    }
-                }
+            if !self.peers[i].connected {
+                self.connect_addr(SocketAddrV4::new(parse_string_to_ip(&peer_address), peer_port));
             }
         }
 
-        return true;
+        true
+    }
+
+    // Start connecting to `addr`, queueing it for a retry if that fails
+    fn connect_addr(&mut self, addr: SocketAddrV4) -> bool {
+        let result = match self.host.first_mut() {
+            Some(transport) => transport.connect(addr),
+            None => return false,
+        };
+        match result {
+            Ok(()) => {
+                self.reconnect.retain(|a| *a != addr);
+                true
+            },
+            Err(e) => {
+                warn!("Host {} {}, retrying in {} ms", self.port, e, RECONNECT_INTERVAL);
+                self.queue_reconnect(addr);
+                false
+            },
+        }
+    }
+
+    fn queue_reconnect(&mut self, addr: SocketAddrV4) {
+        if self.reconnect.is_empty() {
+            self.next_reconnect = self.now_millis() + RECONNECT_INTERVAL;
+        }
+        if !self.reconnect.contains(&addr) {
+            self.reconnect.push(addr);
+        }
     }
 
     pub fn generate_keypair(&mut self) -> bool {
@@ -235,7 +506,7 @@ impl HostRepo {
         let ret_val = self.secure.sign_msg(sm, &mut smlen, m, mlen as u64);
 
         if ret_val > 0 {
-            println!("Failed to sign message...", );
+            error!("Failed to sign message");
         }
 
         // Return Sign message
@@ -276,7 +547,7 @@ impl HostRepo {
         let ret_val = self.secure.sign_foreign_key(sm, &mut smlen, m, mlen as u64, pk.as_mut_ptr());
 
         if ret_val > 0 {
-            println!("Failed to sign message...", );
+            error!("Failed to sign message");
         }
 
         // Return Sign message
@@ -317,7 +588,7 @@ impl HostRepo {
         // Verify
         let ret_val = self.secure.verify_msg(vm, &mut vmlen, sm, smlen as u64);
         if ret_val != 0 {
-            println!("Fail to verify message with public key", );
+            warn!("Fail to verify message with public key");
         }
 
         // Return Verify string
@@ -357,7 +628,7 @@ impl HostRepo {
         // Verify
         let ret_val = self.secure.verify_foreign_key(vm, &mut vmlen, sm, smlen as u64, sk.as_mut_ptr());
         if ret_val != 0 {
-            println!("\nFali to verify with foreign key", );
+            warn!("Fail to verify with foreign key");
         }
 
         // Return Verify string
@@ -373,50 +644,143 @@ impl HostRepo {
         return vmsg;
     }
 
+    pub fn verify_message_public_key(&mut self, _msg: &[u8], pk: &[u8]) -> Vec<u8> {
+        if pk.len() != CRYPTO_PUBLICKEYBYTES as usize {
+            warn!("Public key has wrong length {}", pk.len());
+            return Vec::new();
+        }
+
+        match NistCryptography::verify_public_key(_msg, pk) {
+            Some(vmsg) => vmsg,
+            None => {
+                warn!("Fail to verify with public key");
+                Vec::new()
+            },
+        }
+    }
+
     pub fn broadcast_message(&mut self, _msg: &Vec<u8>) {
-        let sign_msg = self.sign_message(_msg);
-        if sign_msg.len() == 0 {
-            println!("Couldn't sign message...", );
-            return;
+        self.broadcast_payload(PayloadKind::Raw, _msg);
+    }
+
+    // Broadcast `body` tagged with its kind, e.g. an encoded transaction
+    pub fn broadcast_payload(&mut self, kind: PayloadKind, body: &[u8]) {
+        let frames = match self.payload_frames(kind, body) {
+            Ok(frames) => frames,
+            Err(e) => {
+                error!("Couldn't broadcast {:?} payload -> {}", kind, e);
+                return;
+            },
+        };
+        for frame in &frames {
+            let sign_msg = self.seal_frame(frame);
+            if sign_msg.is_empty() {
+                error!("Couldn't sign message");
+                return;
+            }
+            self.broadcast_signed(&sign_msg);
+        }
+    }
+
+    // Send `body` tagged with its kind to the connected peer at `addr` only
+    pub fn send_payload(&mut self, addr: SocketAddrV4, kind: PayloadKind, body: &[u8]) -> bool {
+        let frames = match self.payload_frames(kind, body) {
+            Ok(frames) => frames,
+            Err(e) => {
+                error!("Couldn't send {:?} payload to {} -> {}", kind, addr, e);
+                return false;
+            },
+        };
+        for frame in &frames {
+            if !self.send_frame_to(frame, *addr.ip(), addr.port()) {
+                return false;
+            }
         }
 
+        true
+    }
+
+    fn payload_frames(&mut self, kind: PayloadKind, body: &[u8]) -> Result<Vec<Frame>, FragmentError> {
+        let payload = encode_payload(kind, body);
+
+        // Small payloads go out whole, larger ones as individually signed chunks
+        let mut frames = Vec::<Frame>::new();
+        if payload.len() <= MAX_CHUNK_SIZE {
+            frames.push(Frame::Whole(payload));
+        } else {
+            let payload_id = self.next_payload_id;
+            self.next_payload_id += 1;
+
+            let chunks = split_payload(payload_id, &payload)?;
+            let now = self.now_millis();
+            self.outbox.store(payload_id, chunks.clone(), now);
+            for chunk in chunks {
+                frames.push(Frame::Chunk(chunk));
+            }
+        }
+
+        Ok(frames)
+    }
+
+    // Wrap a frame in a sequenced envelope and sign it
+    fn seal_frame(&mut self, frame: &Frame) -> Vec<u8> {
+        let envelope = Envelope {
+            sequence: self.next_sequence,
+            timestamp: self.now_millis(),
+            body: frame.encode(),
+        };
+        self.next_sequence += 1;
+
+        self.sign_message(&envelope.encode())
+    }
+
+    fn broadcast_signed(&mut self, sign_msg: &Vec<u8>) {
         let peer_host = &mut self.host[0];
-        let mut addresses = Vec::<Ipv4Addr>::new();
-        let mut ports = Vec::<u16>::new();
-        for mut _peer in peer_host.peers() {
-            if !_peer.address().ip().is_unspecified() {
-                
-                let mut is_exist = false;
-                for i in 0..ports.len() {
-                    if (addresses[i] == *_peer.address().ip()) 
-                    && (ports[i] == _peer.address().port()) {
-                        is_exist = true;
-                    }                    
-                }
-                if !is_exist {
-                    let _pack_res = _peer.send_packet(
-                        Packet::new(sign_msg.as_slice(), PacketMode::ReliableSequenced).unwrap(),
-                        1,
-                    );
-                    addresses.push(*_peer.address().ip());
-                    ports.push(_peer.address().port());
-                }
+        for addr in peer_host.peers() {
+            if !addr.ip().is_unspecified() {
+                peer_host.send(addr, sign_msg.as_slice());
             }
         }
     }
 
-    pub fn send_message(&mut self, _peer: &mut Peer<()>, _msg: &Vec<u8>) -> Result<(), Error> {        
-        return _peer.send_packet(
-            Packet::new(_msg.as_slice(), PacketMode::ReliableSequenced).unwrap(),
-            1,
-        );
+    // Sign a frame and send it to the connected peer at addr:port only
+    fn send_frame_to(&mut self, frame: &Frame, addr: Ipv4Addr, port: u16) -> bool {
+        let sign_msg = self.seal_frame(frame);
+        if sign_msg.is_empty() {
+            return false;
+        }
+
+        self.host[0].send(SocketAddrV4::new(addr, port), sign_msg.as_slice())
+    }
+
+    // Ask a reconnected peer for the chunks still missing from its payloads
+    fn request_resume(&mut self, addr: Ipv4Addr, port: u16) {
+        let pending = self.reassembler.missing_from(SocketAddrV4::new(addr, port));
+        for (payload_id, missing) in pending {
+            let frame = Frame::Resume { payload_id, missing };
+            if !self.send_frame_to(&frame, addr, port) {
+                warn!("Couldn't request resume of payload {}", payload_id);
+            }
+        }
+    }
+
+    pub fn send_message(&mut self, addr: SocketAddrV4, _msg: &Vec<u8>) -> bool {
+        self.host[0].send(addr, _msg.as_slice())
+    }
+
+    // Current time of the underlying transport, virtual under simulation
+    pub fn now_millis(&self) -> u64 {
+        match self.host.first() {
+            Some(transport) => transport.now_millis(),
+            None => now_millis(),
+        }
     }
 
     fn find_peer(&self, addr: Ipv4Addr, port: u16) -> i32 {
         let mut index: i32 = -1;
         for i in 0..self.peers.len() {
             let peer_info = &self.peers[i];
-            if (parse_string_to_reverse_ip(&peer_info.address) == addr) 
+            if (parse_string_to_ip(&peer_info.address) == addr) 
             && (peer_info.port == port) {
                 index = i as i32;
                 break;
@@ -429,8 +793,8 @@ impl HostRepo {
     pub fn is_found_peer(&mut self, peer_info: &PeerInfo) -> bool {
         let mut res = false;
         let peer_host = &mut self.host[0];
-        for p in peer_host.peers() {
-            if (peer_info.address == p.address().ip().to_string()) && (peer_info.port == p.address().port()) {
+        for addr in peer_host.peers() {
+            if (parse_string_to_ip(&peer_info.address) == *addr.ip()) && (peer_info.port == addr.port()) {
                 res = true;
                 break;
             }
@@ -441,12 +805,12 @@ impl HostRepo {
     pub fn process_message(&mut self, data: Vec<u8>, addr: Ipv4Addr, port: u16) -> bool {
         let _data_len = data.len();
         
-        print!("Got packet on {} from {}:{}", self.port, addr.to_string(), port);
+        debug!("Got packet on {} from {}:{}", self.port, addr, port);
 
         // Find peer public key
         let index = self.find_peer(addr, port);
         if index < 0 {
-            println!("\nCouldn't find received peer", );
+            warn!("Couldn't find received peer {}:{}", addr, port);
             return false;
         }
 
@@ -456,34 +820,153 @@ impl HostRepo {
             pk.push(self.peers[index as usize].key[i]);
         }
         
-        let vmsg = self.verify_message_foriegn_key(&data, &mut pk);
+        let vmsg = self.verify_message_public_key(&data, &pk);
+        if vmsg.is_empty() {
+            return false;
+        }
+
+        // Reject replayed or stale envelopes before looking at their content
+        let envelope = match Envelope::decode(&vmsg) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                warn!("Rejected message from {}:{} -> {}", addr, port, e);
+                return false;
+            },
+        };
+        let now = self.now_millis();
+        if let Err(e) = self.replay_guard.check(SocketAddrV4::new(addr, port), &envelope, now) {
+            warn!("Rejected message from {}:{} -> {}", addr, port, e);
+            return false;
+        }
+
+        let payload = match Frame::decode(&envelope.body) {
+            Ok(Frame::Whole(payload)) => payload,
+            Ok(Frame::Chunk(chunk)) => {
+                let sender = SocketAddrV4::new(addr, port);
+                match self.reassembler.insert(sender, chunk, now) {
+                    Ok(Some(payload)) => payload,
+                    Ok(None) => return true,
+                    Err(e) => {
+                        warn!("Dropped chunk from {}:{} -> {}", addr, port, e);
+                        return false;
+                    },
+                }
+            },
+            Ok(Frame::Resume { payload_id, missing }) => {
+                let mut chunks = Vec::<Chunk>::new();
+                for index in missing {
+                    if let Some(chunk) = self.outbox.get(payload_id, index) {
+                        chunks.push(chunk.clone());
+                    }
+                }
+                debug!("Resending {} chunks of payload {}", chunks.len(), payload_id);
+                for chunk in chunks {
+                    self.send_frame_to(&Frame::Chunk(chunk), addr, port);
+                }
+                return true;
+            },
+            Err(e) => {
+                warn!("Invalid frame from {}:{} -> {}", addr, port, e);
+                return false;
+            },
+        };
+
+        let (kind, payload) = match decode_payload(&payload) {
+            Some(v) => v,
+            None => {
+                warn!("Unknown payload kind from {}:{}", addr, port);
+                return false;
+            },
+        };
+
+        if kind == PayloadKind::Consensus {
+            return self.receive_consensus(payload, port);
+        }
+        if kind == PayloadKind::Sync {
+            return self.receive_sync(payload, addr, port);
+        }
+
+        // Transactions go to the pool, only valid ones are kept
+        if kind == PayloadKind::Transaction {
+            return match self.mempool.insert_bytes(payload, &self.chain.state, self.chain.next_height(), now) {
+                Ok(id) => {
+                    self.received = true;
+                    info!("Pooled transaction {} on {} from {} ({} pending)", id, self.port, port, self.mempool.len());
+                    true
+                },
+                Err(e) => {
+                    debug!("Rejected transaction on {} from {} -> {}", self.port, port, e);
+                    false
+                },
+            };
+        }
 
         // Save receive data
         let mut recv_msg = RecvMsg::new();
         let localtime: DateTime<Local> = Local::now();
 
         recv_msg.sender = port;
+        recv_msg.kind = kind;
         recv_msg.timestamp.push_str(&localtime.format("%Y-%m-%d %H:%M:%S").to_string());
 
-        for i in 0..vmsg.len() {
-            recv_msg.msg.push(vmsg[i]);
-        }
+        recv_msg.msg.extend_from_slice(payload);
 
+        if self.recv_messages.len() >= MAX_RECV_MESSAGES {
+            self.recv_messages.remove(0);
+        }
         self.recv_messages.push(recv_msg);
 
         self.received = true;
 
-        print!("\nVerified message -> {} : ", &localtime.format("%Y-%m-%d %H:%M:%S").to_string());
-
-        for i in 0..vmsg.len() {
-            print!("{:02X}", vmsg[i]);
+        let mut hex_str = String::new();
+        for byte in payload.iter().take(64) {
+            hex_str.push_str(&format!("{:02X}", byte));
         }
-        print!("\n", );
+        info!("Verified {:?} message on {} from {} ({} bytes) : {}", kind, self.port, port, payload.len(), hex_str);
 
-        if vmsg.len() == 0 {
-            false
-        } else {
-            true
+        true
+    }
+
+    fn receive_consensus(&mut self, payload: &[u8], port: u16) -> bool {
+        let msg = match ConsensusMessage::decode(payload) {
+            Ok(msg) => msg,
+            Err(e) => {
+                warn!("Malformed consensus message on {} from {} -> {}", self.port, port, e);
+                return false;
+            },
+        };
+        debug!("Consensus message for {}/{} on {} from {}", msg.height(), msg.round(), self.port, port);
+
+        let now = self.now_millis();
+        let outputs = self.consensus.handle(msg, &self.chain, &self.mempool, &mut self.secure, now);
+        self.apply_consensus(outputs);
+        self.received = true;
+
+        true
+    }
+
+    fn receive_sync(&mut self, payload: &[u8], addr: Ipv4Addr, port: u16) -> bool {
+        let msg = match SyncMessage::decode(payload) {
+            Ok(msg) => msg,
+            Err(e) => {
+                warn!("Malformed sync message on {} from {} -> {}", self.port, port, e);
+                return false;
+            },
+        };
+
+        let now = self.now_millis();
+        let outputs = self.sync.handle(SocketAddrV4::new(addr, port), msg, &mut self.chain, now);
+        self.send_sync(outputs);
+        self.apply_chain_events();
+
+        true
+    }
+
+    fn send_sync(&mut self, outputs: Vec<(SocketAddrV4, SyncMessage)>) {
+        for (addr, msg) in outputs {
+            if !self.send_payload(addr, PayloadKind::Sync, &msg.encode()) {
+                debug!("Couldn't send sync message to {}", addr);
+            }
         }
     }
 
@@ -492,46 +975,70 @@ impl HostRepo {
         let mut data: Vec<u8> = Vec::new();
         let mut recv_port: u16 = 0;
         let mut recv_address: Ipv4Addr = Ipv4Addr::new(0, 0, 0, 0);
-        let mut sender_peer = Vec::new();
+        let mut connected: Option<(Ipv4Addr, u16)> = None;
+        let mut disconnected: Option<SocketAddrV4> = None;
 
-        match peer_host.service(10).expect("service failed") {
-            Some(Event::Connect(ref p)) => {
+        match peer_host.service(10) {
+            Some(TransportEvent::Connect(addr)) => {
                 for peer in &mut self.peers {
-                    let ip_addr = parse_string_to_reverse_ip(&peer.address);
-                    if (ip_addr == *(p.address().ip())) && (peer.port == p.address().port()) {
+                    let ip_addr = parse_string_to_ip(&peer.address);
+                    if (ip_addr == *addr.ip()) && (peer.port == addr.port()) {
                         peer.connected = true;
                         break;
                     }
                 }
+                self.reconnect.retain(|a| *a != addr);
+                connected = Some((*addr.ip(), addr.port()));
             },
-            Some(Event::Disconnect(ref p, _dt)) => {
+            Some(TransportEvent::Disconnect(addr)) => {
                 for peer in &mut self.peers {
-                    if (peer.address == p.address().ip().to_string()) && (peer.port == p.address().port()) {
+                    if (parse_string_to_ip(&peer.address) == *addr.ip()) && (peer.port == addr.port()) {
                         peer.connected = false;
+                        disconnected = Some(addr);
                         break;
                     }
                 }
             },
-            Some(Event::Receive {
-                ref sender,
-                ref packet,
-                channel_id: _,
-            }) => {
-                recv_port = *(&sender.address().port());
-                recv_address = *(sender.address().ip());
-                let pkdata = packet.data();
-                for i in 0..pkdata.len() {
-                    data.push(pkdata[i]);
-                }
-                sender_peer.push(sender);
+            Some(TransportEvent::Receive { sender, data: pkdata }) => {
+                recv_port = sender.port();
+                recv_address = *sender.ip();
+                data = pkdata;
             },
-            _ => (),
+            None => (),
+        }
+
+        if let Some((addr, port)) = connected {
+            self.request_resume(addr, port);
+        }
+        // A known peer that dropped or never answered is dialled again
+        if let Some(addr) = disconnected {
+            debug!("Host {} lost {}, retrying in {} ms", self.port, addr, RECONNECT_INTERVAL);
+            self.queue_reconnect(addr);
         }
 
         if data.len() > 0 {
             let res = self.process_message(data, recv_address, recv_port);
             if !res {
-                println!("Fail to process message", );
+                debug!("Fail to process message");
+            }
+        }
+
+        let now = self.now_millis();
+        self.reassembler.expire(now);
+        self.outbox.expire(now);
+        let outputs = self.consensus.tick(&self.chain, &self.mempool, &mut self.secure, now);
+        self.apply_consensus(outputs);
+        let connected: Vec<SocketAddrV4> = self.host[0].peers().into_iter().filter(|a| !a.ip().is_unspecified()).collect();
+        let outputs = self.sync.tick(&mut self.chain, &connected, now);
+        self.send_sync(outputs);
+        self.apply_chain_events();
+        if now >= self.next_prune {
+            self.mempool.prune(&self.chain.state, self.chain.next_height(), now);
+            self.next_prune = now + POOL_PRUNE_INTERVAL;
+        }
+        if now >= self.next_reconnect && !self.reconnect.is_empty() {
+            for addr in mem::take(&mut self.reconnect) {
+                self.connect_addr(addr);
             }
         }
     }
@@ -541,8 +1048,13 @@ impl HostRepo {
  *  Declaratio of Class Node
  */
 pub struct Node {
-    pub net: Enet,
+    pub config: NodeConfig,
+    pub net: Option<Enet>,
+    pub sim: Option<SimNetwork>,
     pub hosts: Vec<HostRepo>,
+    pub watcher: ConfigWatcher,
+    // Loaded at start; a changed genesis file needs a restart
+    pub genesis: Option<Genesis>,
 }
 
 impl Debug for Node {
@@ -553,9 +1065,29 @@ impl Debug for Node {
 
 impl Node {
     pub fn new() -> Self {
+        Node::with_config(NodeConfig::at(ConfigPaths::from_args()))
+    }
+
+    pub fn with_config(config: NodeConfig) -> Self {
         Node {
-            net: Enet::new().expect("could not initialize ENet"),
+            config,
+            net: Some(Enet::new().expect("could not initialize ENet")),
+            sim: None,
             hosts: Vec::<HostRepo>::new(),
+            watcher: ConfigWatcher::new(reload::DEFAULT_WATCH_INTERVAL),
+            genesis: None,
+        }
+    }
+
+    // A node whose hosts live on a simulated network instead of UDP sockets
+    pub fn simulated(network: SimNetwork) -> Self {
+        Node {
+            config: NodeConfig::default(),
+            net: None,
+            sim: Some(network),
+            hosts: Vec::<HostRepo>::new(),
+            watcher: ConfigWatcher::new(reload::DEFAULT_WATCH_INTERVAL),
+            genesis: None,
         }
     }
 
@@ -564,58 +1096,115 @@ impl Node {
     }
 
     pub fn create_host(&mut self, port: u16) {
+        self.create_host_at(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port));
+    }
+
+    pub fn create_host_at(&mut self, listen: SocketAddrV4) {
+        let port = listen.port();
+
         // Create a HostRepo instance
         let mut host_repo = HostRepo::new(port);
+        host_repo.config = self.config.clone();
+        host_repo.apply_limits(&self.config.limits);
+        host_repo.apply_chain(&self.config.chain);
+        if let Some(genesis) = &self.genesis {
+            host_repo.apply_genesis(genesis);
+        }
         
-        // Get local address: 127.0.0.1:{port}
-        let local_addr = Address::new(Ipv4Addr::LOCALHOST, port);
-
-        // Create a enet::Host
-        let host = (&self).net
-            .create_host::<()>(
-                Some(&local_addr),
-                MAX_PEERS_COUNT,
-                ChannelLimit::Maximum,
-                BandwidthLimit::Unlimited,
-                BandwidthLimit::Unlimited,
-            )
-            .expect("could not create host");
+        // Get local address: {listen}:{port}
+        let local_addr = Address::new(*listen.ip(), port);
+
+        let transport: Box<dyn Transport> = match (&self.net, &self.sim) {
+            (_, Some(sim)) => Box::new(sim.endpoint(listen)),
+            (Some(net), None) => {
+                // Create a enet::Host
+                let host = net
+                    .create_host::<()>(
+                        Some(&local_addr),
+                        self.config.limits.max_peers,
+                        ChannelLimit::Maximum,
+                        BandwidthLimit::Unlimited,
+                        BandwidthLimit::Unlimited,
+                    )
+                    .expect("could not create host");
+                Box::new(EnetTransport::new(host))
+            },
+            (None, None) => panic!("node has no network"),
+        };
         
         // Initialize a HostRepo instance
         host_repo.host.clear();
-        host_repo.host.push(host);
+        host_repo.host.push(transport);
 
         // Save HostRepo information in Node network
         self.hosts.push(host_repo);
     }
 
     pub fn read_hosts(&mut self) -> bool {
-        // Read Config.ini file
-        let hosts_info: Vec<HostInfo> = config::get_hosts();
-        if hosts_info.len() == 0 {
-            return false;
+        // Read node.toml, or conf.ini + peerlist.csv
+        let mut config = match NodeConfig::load(&self.config.paths) {
+            Ok(v) => v,
+            Err(e) => {
+                init_logging(&self.config.logging.level);
+                error!("{}", e);
+                return false;
+            },
+        };
+        init_logging(&config.logging.level);
+        self.genesis = match config.load_genesis() {
+            Ok(genesis) => genesis,
+            Err(e) => {
+                error!("{}", e);
+                return false;
+            },
+        };
+        if let Some(genesis) = &self.genesis {
+            config.chain.adopt_genesis(genesis);
+        }
+        if config.source == ConfigSource::Legacy {
+            warn!("Using legacy {}, run `node-tool migrate-config` to convert it to {}",
+                config.paths.conf_path.display(), config.paths.toml_path.display());
         }
+        self.config = config;
+        self.watcher.watch(&self.config);
+
+        // Key pairs of the hosts, matched by port
+        let keystore: Vec<HostInfo> = config::get_hosts(&self.config.keystore_path());
+        let mut missing_keys = false;
         
         // Construct Node network in local machine
-        for host_info in &hosts_info {
-            self.create_host(host_info.port);
-
-            for j in 0..host_info.public_key.len() {
-                if let Some(host) = self.hosts.last_mut() {
-                    (*host).secure.public_key[j] = host_info.public_key[j];
-                } else {
-                    return false;
-                }
-            }
-            for j in 0..host_info.private_key.len() {
-                if let Some(host) = self.hosts.last_mut() {
-                    (*host).secure.private_key[j] = host_info.private_key[j];
-                } else {
-                    return false;
-                }
+        let listen = self.config.listen.clone();
+        for addr in &listen {
+            self.create_host_at(*addr);
+
+            let host = match self.hosts.last_mut() {
+                Some(host) => host,
+                None => return false,
+            };
+            match keystore.iter().find(|h| h.port == addr.port()) {
+                Some(host_info) => {
+                    for j in 0..host_info.public_key.len().min(host.secure.public_key.len()) {
+                        host.secure.public_key[j] = host_info.public_key[j];
+                    }
+                    for j in 0..host_info.private_key.len().min(host.secure.private_key.len()) {
+                        host.secure.private_key[j] = host_info.private_key[j];
+                    }
+                },
+                None => {
+                    warn!("No key pair for port {} in {}, generating one", addr.port(), self.config.keystore_path().display());
+                    host.secure.init();
+                    if !host.generate_keypair() {
+                        return false;
+                    }
+                    missing_keys = true;
+                },
             }
         }
 
+        if missing_keys {
+            self.save_hosts();
+        }
+
         return true;
     }
 
@@ -632,7 +1221,7 @@ impl Node {
 
             hosts_info.push(host_info);
         }
-        config::set_hosts(&hosts_info);
+        config::set_hosts(&self.config.keystore_path(), &hosts_info);
     }
 
     pub fn save_host(&mut self, id: u16) {
@@ -644,11 +1233,72 @@ impl Node {
             host_info.public_key = host.secure.public_key.iter().cloned().collect();
             host_info.private_key = host.secure.private_key.iter().cloned().collect();
  
-            config::set_host(&host_info, id);
+            config::set_host(&self.config.keystore_path(), &host_info, id);
+        }
+    }
+
+    /*
+     *  Re-read the configuration and apply what can change live: log level,
+     *  limits and peers. Listen addresses and the keystore need a restart.
+     *  On error the running configuration is left untouched.
+     */
+    pub fn reload(&mut self) -> Result<(), ConfigError> {
+        let mut config = NodeConfig::load(&self.config.paths)?;
+        self.watcher.watch(&config);
+        let genesis = config.load_genesis()?;
+        if genesis.as_ref().map(|g| g.hash()) != self.genesis.as_ref().map(|g| g.hash()) {
+            warn!("Genesis changed, restart the node with a fresh data directory to apply it");
+        }
+        if let Some(genesis) = &self.genesis {
+            config.chain.adopt_genesis(genesis);
+        }
+
+        if config.listen != self.config.listen || config.keystore_path() != self.config.keystore_path() {
+            warn!("Listen addresses or keystore changed, restart the node to apply them");
+        }
+        if config.limits.max_peers != self.config.limits.max_peers {
+            warn!("limits.max_peers only applies to hosts created after a restart");
+        }
+        if !set_log_level(&config.logging.level) {
+            warn!("Unknown log level `{}`", config.logging.level);
+        }
+
+        let peers = config.peers();
+        for host in &mut self.hosts {
+            host.config = config.clone();
+            host.apply_limits(&config.limits);
+            host.apply_chain(&config.chain);
+
+            let diff = host.update_peers(peers.iter().map(|p| PeerInfo {
+                address: p.address.clone(),
+                port: p.port,
+                key: p.key.clone(),
+                connected: false,
+            }).collect());
+            if !diff.is_empty() {
+                info!("Host {}: {} peers added, {} removed, {} re-keyed",
+                    host.port, diff.added.len(), diff.removed.len(), diff.rekeyed.len());
+            }
+        }
+        self.config = config;
+
+        Ok(())
+    }
+
+    // Reload when asked to (signal or `request_reload`) or when the config files changed
+    pub fn poll_reload(&mut self) {
+        let requested = reload::take_reload_request();
+        if !(self.watcher.changed(now_millis()) || requested) {
+            return;
+        }
+        match self.reload() {
+            Ok(()) => info!("Configuration reloaded from {}", self.config.paths.data_dir.display()),
+            Err(e) => error!("Keeping the running configuration, reload failed: {}", e),
         }
     }
 
     pub fn execute(&mut self) {
+        self.poll_reload();
         for i in 0..self.hosts.len() {
             let peer_host = &mut self.hosts[i];
             peer_host.execute();
@@ -658,14 +1308,19 @@ impl Node {
     pub fn start(&mut self) {
         // Read conf file
         if !self.read_hosts() {
-            println!("Couldn't read conf.ini file", );
+            error!("Couldn't read configuration from {}", self.config.paths.data_dir.display());
             return;
         }
+        install_reload_signal();
 
         // Initialize hosts of node
         for host in &mut self.hosts {
             if !(&host.init()) {
-                println!("Couldn't read peerlist.csv file", );
+                error!("Couldn't read bootstrap peers of host {}", host.port);
+                return;
+            }
+            let dir = self.config.paths.data_dir.join("chain").join(host.port.to_string());
+            if !host.open_store(&dir) {
                 return;
             }
         }
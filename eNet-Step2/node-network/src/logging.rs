@@ -0,0 +1,58 @@
+extern crate log;
+
+use chrono::prelude::*;
+use log::{ LevelFilter, Log, Metadata, Record };
+
+/*
+ *  Declaration of Class ConsoleLogger
+ *
+ *  Writes log records to stdout, the way the node always reported its state.
+ *  The level can be changed at any time with `set_log_level`.
+ */
+struct ConsoleLogger;
+
+static LOGGER: ConsoleLogger = ConsoleLogger;
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let localtime: DateTime<Local> = Local::now();
+        println!("{} {:<5} {}", localtime.format("%Y-%m-%d %H:%M:%S"), record.level(), record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn parse_level(level: &str) -> Option<LevelFilter> {
+    match level.trim().to_ascii_lowercase().as_str() {
+        "off" => Some(LevelFilter::Off),
+        "error" => Some(LevelFilter::Error),
+        "warn" | "warning" => Some(LevelFilter::Warn),
+        "info" => Some(LevelFilter::Info),
+        "debug" => Some(LevelFilter::Debug),
+        "trace" => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+// Install the console logger (once per process) and apply `level`
+pub fn init_logging(level: &str) -> bool {
+    let _ = log::set_logger(&LOGGER);
+    set_log_level(level)
+}
+
+pub fn set_log_level(level: &str) -> bool {
+    match parse_level(level) {
+        Some(filter) => {
+            log::set_max_level(filter);
+            true
+        },
+        None => false,
+    }
+}
@@ -0,0 +1,51 @@
+/*
+ *  Declaration of PayloadKind
+ *
+ *  Every payload handed to `broadcast_payload` or `send_payload` is prefixed
+ *  with one tag byte so receivers can route it without guessing. The network layer does not look
+ *  inside; decoding and validation belong to the chain code.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadKind {
+    // Plain bytes from `broadcast_message`
+    Raw,
+    // A chain-core Transaction in canonical encoding
+    Transaction,
+    // A chain-core ConsensusMessage: a proposal carrying a block, or a vote
+    Consensus,
+    // A SyncMessage for catching up, sent to one peer rather than broadcast
+    Sync,
+}
+
+impl PayloadKind {
+    pub fn tag(self) -> u8 {
+        match self {
+            PayloadKind::Raw => 0,
+            PayloadKind::Transaction => 1,
+            PayloadKind::Consensus => 2,
+            PayloadKind::Sync => 3,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<PayloadKind> {
+        match tag {
+            0 => Some(PayloadKind::Raw),
+            1 => Some(PayloadKind::Transaction),
+            2 => Some(PayloadKind::Consensus),
+            3 => Some(PayloadKind::Sync),
+            _ => None,
+        }
+    }
+}
+
+pub fn encode_payload(kind: PayloadKind, body: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(body.len() + 1);
+    payload.push(kind.tag());
+    payload.extend_from_slice(body);
+    payload
+}
+
+pub fn decode_payload(payload: &[u8]) -> Option<(PayloadKind, &[u8])> {
+    let (tag, body) = payload.split_first()?;
+    Some((PayloadKind::from_tag(*tag)?, body))
+}
@@ -0,0 +1,531 @@
+extern crate serde;
+extern crate toml;
+
+use serde::{ Deserialize, Serialize };
+use std::env;
+use std::fmt::{ self, Display, Formatter };
+use std::fs;
+use std::net::SocketAddrV4;
+use std::path::{ Path, PathBuf };
+
+use chain_core::{ Genesis, Timeouts };
+
+use crate::config::{ self, PeerInfo };
+use crate::genesis;
+use crate::logging;
+use crate::persist;
+
+/*
+ *  Declaration of Constants
+ */
+pub const CONFIG_ENV: &str = "FRINK_CONFIG";
+pub const DATA_DIR_ENV: &str = "FRINK_DATA_DIR";
+pub const TOML_FILE: &str = "node.toml";
+pub const CONF_FILE: &str = "conf.ini";
+pub const PEERLIST_FILE: &str = "peerlist.csv";
+
+const PUBLIC_KEY_BYTES: usize = secure_sign::CRYPTO_PUBLICKEYBYTES as usize;
+
+/*
+ *  Declaration of ConfigError
+ *
+ *  `key` names the offending setting, e.g. `bootstrap_peers[2].public_key`.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub file: PathBuf,
+    pub key: String,
+    pub message: String,
+}
+
+impl ConfigError {
+    pub fn new(file: &Path, key: &str, message: String) -> Self {
+        ConfigError {
+            file: file.to_path_buf(),
+            key: key.to_string(),
+            message,
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.key.len() {
+            0 => write!(f, "{}: {}", self.file.display(), self.message),
+            _ => write!(f, "{}: `{}`: {}", self.file.display(), self.key, self.message),
+        }
+    }
+}
+
+/*
+ *  Declaration of ConfigPaths
+ *
+ *  Where a node keeps its configuration. The location is taken, in order, from
+ *  an explicit path (`--config`), FRINK_CONFIG, FRINK_DATA_DIR, a `config`
+ *  folder in the working directory, and finally the per-user config directory.
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ConfigPaths {
+    pub data_dir: PathBuf,
+    pub toml_path: PathBuf,
+    pub conf_path: PathBuf,
+    pub peerlist_path: PathBuf,
+}
+
+impl ConfigPaths {
+    pub fn from_data_dir(dir: &Path) -> Self {
+        ConfigPaths {
+            data_dir: dir.to_path_buf(),
+            toml_path: dir.join(TOML_FILE),
+            conf_path: dir.join(CONF_FILE),
+            peerlist_path: dir.join(PEERLIST_FILE),
+        }
+    }
+
+    // `path` is either a data directory or a node.toml / conf.ini inside one
+    pub fn from_path(path: &Path) -> Self {
+        if path.is_dir() || path.extension().is_none() {
+            return ConfigPaths::from_data_dir(path);
+        }
+
+        let dir = match path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let mut paths = ConfigPaths::from_data_dir(&dir);
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("ini") => paths.conf_path = path.to_path_buf(),
+            _ => paths.toml_path = path.to_path_buf(),
+        }
+
+        paths
+    }
+
+    pub fn locate(explicit: Option<PathBuf>) -> Self {
+        if let Some(path) = explicit {
+            return ConfigPaths::from_path(&path);
+        }
+        if let Some(path) = env::var_os(CONFIG_ENV) {
+            return ConfigPaths::from_path(Path::new(&path));
+        }
+        if let Some(dir) = env::var_os(DATA_DIR_ENV) {
+            return ConfigPaths::from_data_dir(Path::new(&dir));
+        }
+
+        let local = ConfigPaths::from_data_dir(Path::new("config"));
+        if local.toml_path.is_file() || local.conf_path.is_file() {
+            return local;
+        }
+
+        ConfigPaths::from_data_dir(&default_data_dir())
+    }
+
+    // Locate using the `--config` flag of the running binary, if any
+    pub fn from_args() -> Self {
+        ConfigPaths::locate(config_arg(env::args().skip(1)))
+    }
+}
+
+/*
+ *  Declaration of configuration sections
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConfigSource {
+    #[default]
+    Toml,
+    Legacy,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PeerConfig {
+    pub address: SocketAddrV4,
+    pub public_key: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_peers: usize,
+    pub max_message_bytes: usize,
+    pub reassembly_bytes: usize,
+    pub reassembly_timeout_ms: u64,
+    pub outbox_bytes: usize,
+    pub outbox_timeout_ms: u64,
+    pub replay_window: u64,
+    pub max_clock_skew_ms: u64,
+    pub mempool_bytes: usize,
+    pub mempool_ttl_ms: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_peers: crate::MAX_PEERS_COUNT,
+            max_message_bytes: crate::fragment::MAX_PAYLOAD_SIZE,
+            reassembly_bytes: crate::fragment::DEFAULT_REASSEMBLY_LIMIT,
+            reassembly_timeout_ms: crate::fragment::DEFAULT_REASSEMBLY_TIMEOUT,
+            outbox_bytes: crate::fragment::DEFAULT_OUTBOX_LIMIT,
+            outbox_timeout_ms: crate::fragment::DEFAULT_OUTBOX_TIMEOUT,
+            replay_window: crate::replay::DEFAULT_REPLAY_WINDOW,
+            max_clock_skew_ms: crate::replay::DEFAULT_MAX_CLOCK_SKEW,
+            mempool_bytes: chain_core::DEFAULT_POOL_BYTES,
+            mempool_ttl_ms: chain_core::DEFAULT_POOL_TTL,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub level: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig { level: String::from("info") }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainConfig {
+    pub chain_id: String,
+    // Pause after a block is decided before the next height starts
+    pub block_time_ms: u64,
+    pub max_block_bytes: usize,
+    pub max_tx_bytes: usize,
+    // Hex public keys that may validate, once verified in the identity registry
+    pub validators: Vec<String>,
+    // Consensus step timeouts; each later round waits `timeout_delta_ms` longer
+    pub timeout_propose_ms: u64,
+    pub timeout_vote_ms: u64,
+    pub timeout_delta_ms: u64,
+    // Keep a snapshot of the state every this many finalized blocks for peers to fast sync from; 0 keeps none
+    pub snapshot_interval: u64,
+    // Start an empty chain from a peer's snapshot instead of replaying every block
+    pub fast_sync: bool,
+}
+
+impl Default for ChainConfig {
+    fn default() -> Self {
+        ChainConfig {
+            chain_id: String::from("frink-local"),
+            block_time_ms: 5_000,
+            max_block_bytes: 1024 * 1024,
+            max_tx_bytes: 64 * 1024,
+            validators: Vec::new(),
+            timeout_propose_ms: 3_000,
+            timeout_vote_ms: 1_000,
+            timeout_delta_ms: 500,
+            snapshot_interval: 1_000,
+            fast_sync: true,
+        }
+    }
+}
+
+impl ChainConfig {
+    // Decoded validator keys; `validate` has already rejected malformed ones
+    pub fn validator_keys(&self) -> Vec<Vec<u8>> {
+        self.validators.iter().filter_map(|key| hex::decode(key).ok()).collect()
+    }
+
+    // Take the network-wide settings from `genesis` over what node.toml says
+    pub fn adopt_genesis(&mut self, genesis: &Genesis) {
+        self.chain_id = genesis.chain_id.clone();
+        self.max_block_bytes = genesis.params.max_block_bytes as usize;
+        self.max_tx_bytes = genesis.params.max_tx_bytes as usize;
+        self.block_time_ms = genesis.params.block_time_ms;
+        self.validators = genesis.validators.iter().map(hex::encode_upper).collect();
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        Timeouts {
+            propose: self.timeout_propose_ms,
+            vote: self.timeout_vote_ms,
+            delta: self.timeout_delta_ms,
+            commit: self.block_time_ms,
+        }
+    }
+}
+
+/*
+ *  Declaration of NodeConfig
+ *
+ *  Read from `node.toml`. Host key pairs stay out of it, in the keystore file
+ *  (`[Host N]` sections of port/public/private, matched to `listen` by port).
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    #[serde(skip)]
+    pub paths: ConfigPaths,
+    #[serde(skip)]
+    pub source: ConfigSource,
+    pub keystore: PathBuf,
+    // genesis.toml of the network; chain ID, limits and validators then come from it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub genesis: Option<PathBuf>,
+    pub listen: Vec<SocketAddrV4>,
+    pub bootstrap_peers: Vec<PeerConfig>,
+    pub limits: Limits,
+    pub logging: LoggingConfig,
+    pub chain: ChainConfig,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            paths: ConfigPaths::default(),
+            source: ConfigSource::Toml,
+            keystore: PathBuf::from(CONF_FILE),
+            genesis: None,
+            listen: Vec::new(),
+            bootstrap_peers: Vec::new(),
+            limits: Limits::default(),
+            logging: LoggingConfig::default(),
+            chain: ChainConfig::default(),
+        }
+    }
+}
+
+impl NodeConfig {
+    // Empty configuration bound to a location, used until `load` succeeds
+    pub fn at(paths: ConfigPaths) -> Self {
+        NodeConfig { paths, ..NodeConfig::default() }
+    }
+
+    // node.toml when present, otherwise the legacy conf.ini + peerlist.csv
+    pub fn load(paths: &ConfigPaths) -> Result<NodeConfig, ConfigError> {
+        if paths.toml_path.is_file() {
+            return persist::load_or_recover(&paths.toml_path, |path| {
+                let text = fs::read_to_string(path).map_err(|e| ConfigError::new(path, "", e.to_string()))?;
+                NodeConfig::from_toml(paths, &text)
+            });
+        }
+        if paths.conf_path.is_file() {
+            return NodeConfig::from_legacy(paths);
+        }
+
+        Err(ConfigError::new(&paths.toml_path, "", String::from("no configuration found")))
+    }
+
+    pub fn from_toml(paths: &ConfigPaths, text: &str) -> Result<NodeConfig, ConfigError> {
+        let mut config: NodeConfig = toml::from_str(text)
+            .map_err(|e| ConfigError::new(&paths.toml_path, "", e.to_string()))?;
+        config.paths = paths.clone();
+        config.source = ConfigSource::Toml;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    // Build a configuration from conf.ini (hosts and keys) and peerlist.csv
+    pub fn from_legacy(paths: &ConfigPaths) -> Result<NodeConfig, ConfigError> {
+        let hosts = persist::load_or_recover(&paths.conf_path, config::load_hosts)?;
+        let peers = match paths.peerlist_path.is_file() {
+            true => persist::load_or_recover(&paths.peerlist_path, config::load_peerlist)?,
+            false => Vec::new(),
+        };
+
+        let mut config = NodeConfig::at(paths.clone());
+        config.source = ConfigSource::Legacy;
+        config.keystore = paths.conf_path.clone();
+        for host in &hosts {
+            config.listen.push(SocketAddrV4::new(std::net::Ipv4Addr::LOCALHOST, host.port));
+        }
+        config.set_peers(&peers);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn keystore_path(&self) -> PathBuf {
+        if self.keystore.is_absolute() {
+            return self.keystore.clone();
+        }
+        self.paths.data_dir.join(&self.keystore)
+    }
+
+    pub fn genesis_path(&self) -> Option<PathBuf> {
+        match &self.genesis {
+            Some(path) if path.is_absolute() => Some(path.clone()),
+            Some(path) => Some(self.paths.data_dir.join(path)),
+            None => None,
+        }
+    }
+
+    pub fn load_genesis(&self) -> Result<Option<Genesis>, ConfigError> {
+        match self.genesis_path() {
+            Some(path) => Ok(Some(genesis::load_genesis(&path)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn to_toml(&self) -> Result<String, ConfigError> {
+        toml::to_string_pretty(self).map_err(|e| ConfigError::new(&self.paths.toml_path, "", e.to_string()))
+    }
+
+    pub fn save(&self) -> Result<(), ConfigError> {
+        let text = self.to_toml()?;
+        persist::write_sealed(&self.paths.toml_path, &text)
+            .map_err(|e| ConfigError::new(&self.paths.toml_path, "", e.to_string()))
+    }
+
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        let mut peers = Vec::<PeerInfo>::new();
+        for peer in &self.bootstrap_peers {
+            peers.push(PeerInfo {
+                address: peer.address.ip().to_string(),
+                port: peer.address.port(),
+                key: hex::decode(&peer.public_key).unwrap_or_default(),
+                connected: false,
+            });
+        }
+
+        peers
+    }
+
+    pub fn set_peers(&mut self, peers: &[PeerInfo]) {
+        self.bootstrap_peers.clear();
+        for peer in peers {
+            self.bootstrap_peers.push(PeerConfig {
+                address: SocketAddrV4::new(config::parse_string_to_ip(&peer.address), peer.port),
+                public_key: hex::encode_upper(&peer.key),
+            });
+        }
+    }
+
+    // Persist the peer list in whichever format the configuration came from
+    pub fn save_peers(&self) -> bool {
+        match self.source {
+            ConfigSource::Toml => match self.save() {
+                Ok(()) => true,
+                Err(e) => {
+                    log::error!("{}", e);
+                    false
+                },
+            },
+            ConfigSource::Legacy => config::save_peerlist(&self.paths.peerlist_path, &self.peers()),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let file = match self.source {
+            ConfigSource::Toml => &self.paths.toml_path,
+            ConfigSource::Legacy => &self.paths.conf_path,
+        };
+        let err = |key: String, message: String| Err(ConfigError::new(file, &key, message));
+
+        if self.listen.is_empty() {
+            return err(String::from("listen"), String::from("at least one listen address is required"));
+        }
+        for (i, addr) in self.listen.iter().enumerate() {
+            if addr.port() == 0 {
+                return err(format!("listen[{}]", i), String::from("port must not be 0"));
+            }
+            if self.listen[..i].iter().any(|a| a.port() == addr.port()) {
+                return err(format!("listen[{}]", i), format!("port {} is listed twice", addr.port()));
+            }
+        }
+
+        for (i, peer) in self.bootstrap_peers.iter().enumerate() {
+            if peer.address.port() == 0 {
+                return err(format!("bootstrap_peers[{}].address", i), String::from("port must not be 0"));
+            }
+            match hex::decode(&peer.public_key) {
+                Ok(key) if key.len() == PUBLIC_KEY_BYTES => (),
+                Ok(key) => return err(format!("bootstrap_peers[{}].public_key", i),
+                    format!("expected {} bytes, found {}", PUBLIC_KEY_BYTES, key.len())),
+                Err(e) => return err(format!("bootstrap_peers[{}].public_key", i), format!("invalid hex: {}", e)),
+            }
+        }
+
+        let limits = &self.limits;
+        let positive = [
+            ("limits.max_peers", limits.max_peers as u64),
+            ("limits.max_message_bytes", limits.max_message_bytes as u64),
+            ("limits.reassembly_bytes", limits.reassembly_bytes as u64),
+            ("limits.reassembly_timeout_ms", limits.reassembly_timeout_ms),
+            ("limits.outbox_bytes", limits.outbox_bytes as u64),
+            ("limits.outbox_timeout_ms", limits.outbox_timeout_ms),
+            ("limits.replay_window", limits.replay_window),
+            ("limits.max_clock_skew_ms", limits.max_clock_skew_ms),
+            ("limits.mempool_bytes", limits.mempool_bytes as u64),
+            ("limits.mempool_ttl_ms", limits.mempool_ttl_ms),
+            ("chain.block_time_ms", self.chain.block_time_ms),
+            ("chain.max_block_bytes", self.chain.max_block_bytes as u64),
+            ("chain.max_tx_bytes", self.chain.max_tx_bytes as u64),
+            ("chain.timeout_propose_ms", self.chain.timeout_propose_ms),
+            ("chain.timeout_vote_ms", self.chain.timeout_vote_ms),
+        ];
+        for (key, value) in positive.iter() {
+            if *value == 0 {
+                return err(key.to_string(), String::from("must be greater than 0"));
+            }
+        }
+        if limits.max_message_bytes > crate::fragment::MAX_PAYLOAD_SIZE {
+            return err(String::from("limits.max_message_bytes"),
+                format!("must be at most {}", crate::fragment::MAX_PAYLOAD_SIZE));
+        }
+        if self.chain.max_tx_bytes > self.chain.max_block_bytes {
+            return err(String::from("chain.max_tx_bytes"), String::from("must not exceed chain.max_block_bytes"));
+        }
+        if self.chain.chain_id.trim().is_empty() {
+            return err(String::from("chain.chain_id"), String::from("must not be empty"));
+        }
+        for (i, key) in self.chain.validators.iter().enumerate() {
+            match hex::decode(key) {
+                Ok(key) if key.len() == PUBLIC_KEY_BYTES => (),
+                Ok(key) => return err(format!("chain.validators[{}]", i),
+                    format!("expected {} bytes, found {}", PUBLIC_KEY_BYTES, key.len())),
+                Err(e) => return err(format!("chain.validators[{}]", i), format!("invalid hex: {}", e)),
+            }
+        }
+
+        if logging::parse_level(&self.logging.level).is_none() {
+            return err(String::from("logging.level"),
+                format!("unknown level `{}` (expected off, error, warn, info, debug or trace)", self.logging.level));
+        }
+
+        Ok(())
+    }
+}
+
+/*
+ *  Value of `--config <path>` or `--config=<path>` in a list of arguments
+ */
+pub fn config_arg<I: Iterator<Item = String>>(mut args: I) -> Option<PathBuf> {
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(value) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(value));
+        }
+    }
+
+    None
+}
+
+/*
+ *  Per-user configuration directory: $XDG_CONFIG_HOME/frink or ~/.config/frink,
+ *  %APPDATA%\frink on Windows
+ */
+pub fn default_data_dir() -> PathBuf {
+    if cfg!(windows) {
+        if let Some(dir) = env::var_os("APPDATA") {
+            return PathBuf::from(dir).join("frink");
+        }
+    }
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME") {
+        if !dir.is_empty() {
+            return PathBuf::from(dir).join("frink");
+        }
+    }
+    if let Some(home) = env::var_os("HOME") {
+        return PathBuf::from(home).join(".config").join("frink");
+    }
+
+    PathBuf::from("config")
+}
@@ -0,0 +1,205 @@
+extern crate crc32fast;
+
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ self, Write };
+use std::path::{ Path, PathBuf };
+
+use crate::node_config::ConfigError;
+
+/*
+ *  Crash-safe persistence of configuration files
+ *
+ *  Files are written to `<name>.tmp`, fsynced and renamed over the original, so a
+ *  reader sees either the old or the new contents. The previous good copy is kept
+ *  as `<name>.bak.1` (older ones shift up to BACKUP_COUNT). Every file we write ends
+ *  with a `# checksum` comment line which INI, CSV and TOML readers all skip. A file
+ *  that lost that line was cut short and is restored from a backup; one that still
+ *  has it but no longer matches was edited by hand and is loaded as it is.
+ */
+pub const BACKUP_COUNT: usize = 3;
+const CHECKSUM_PREFIX: &str = "# checksum ";
+
+#[derive(Debug, PartialEq)]
+pub enum Integrity {
+    Missing,
+    // No checksum line: written by hand, by an older version, or truncated
+    Unsealed,
+    Sealed,
+    // Checksum line present but stale, the file was edited after we wrote it
+    Edited,
+    Corrupt(String),
+}
+
+// Append the checksum line to `text`
+pub fn seal(text: &str) -> String {
+    let mut sealed = String::from(text);
+    if !sealed.is_empty() && !sealed.ends_with('\n') {
+        sealed.push('\n');
+    }
+    let line = format!("{}{:08x} {}\n", CHECKSUM_PREFIX, crc32fast::hash(sealed.as_bytes()), sealed.len());
+    sealed.push_str(&line);
+
+    sealed
+}
+
+pub fn verify(bytes: &[u8]) -> Integrity {
+    if bytes.is_empty() {
+        return Integrity::Corrupt(String::from("file is empty"));
+    }
+
+    // The checksum line is normally the last line, hand edits may append after it
+    let trimmed = match bytes.last() {
+        Some(b'\n') => &bytes[..bytes.len() - 1],
+        _ => bytes,
+    };
+    let start = match trimmed.iter().rposition(|b| *b == b'\n') {
+        Some(pos) => pos + 1,
+        None => 0,
+    };
+    let line = String::from_utf8_lossy(&trimmed[start..]);
+    let fields = match line.strip_prefix(CHECKSUM_PREFIX) {
+        Some(v) => v,
+        None if String::from_utf8_lossy(bytes).lines().any(|l| l.starts_with(CHECKSUM_PREFIX)) => return Integrity::Edited,
+        None => return Integrity::Unsealed,
+    };
+
+    let mut parts = fields.trim().split(' ');
+    let crc = parts.next().and_then(|v| u32::from_str_radix(v, 16).ok());
+    let len = parts.next().and_then(|v| v.parse::<usize>().ok());
+    match (crc, len) {
+        (Some(crc), Some(len)) if len == start && crc == crc32fast::hash(&bytes[..start]) => Integrity::Sealed,
+        _ => Integrity::Edited,
+    }
+}
+
+pub fn check_integrity(path: &Path) -> Integrity {
+    match fs::read(path) {
+        Ok(bytes) => verify(&bytes),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Integrity::Missing,
+        Err(e) => Integrity::Corrupt(e.to_string()),
+    }
+}
+
+pub fn backup_path(path: &Path, index: usize) -> PathBuf {
+    sibling(path, &format!("bak.{}", index))
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+// Replace `path` with `contents` without ever leaving a partial file behind
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)?;
+        }
+    }
+
+    // Only a good copy is worth a backup slot. An unsealed file may be one cut
+    // off before its checksum line, so it only gets one while no sealed backup
+    // exists, as when a legacy file is first rewritten.
+    let worth_keeping = match check_integrity(path) {
+        Integrity::Sealed => true,
+        Integrity::Unsealed => check_integrity(&backup_path(path, 1)) != Integrity::Sealed,
+        _ => false,
+    };
+    if worth_keeping {
+        rotate_backups(path)?;
+    }
+
+    let tmp_path = sibling(path, "tmp");
+    {
+        let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    sync_dir(path);
+
+    Ok(())
+}
+
+pub fn write_sealed(path: &Path, text: &str) -> io::Result<()> {
+    write_atomic(path, seal(text).as_bytes())
+}
+
+fn rotate_backups(path: &Path) -> io::Result<()> {
+    for i in (1..BACKUP_COUNT).rev() {
+        let older = backup_path(path, i);
+        if older.exists() {
+            fs::rename(&older, backup_path(path, i + 1))?;
+        }
+    }
+    let backup = backup_path(path, 1);
+    fs::copy(path, &backup)?;
+    File::open(&backup)?.sync_all()
+}
+
+// Make the rename itself durable
+#[cfg(unix)]
+fn sync_dir(path: &Path) {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) {}
+
+/*
+ *  Load `path` with `load`, falling back to the newest backup that loads when the
+ *  file is empty or lost its checksum line. The backup is copied back over `path`
+ *  and the damaged file is kept as `<name>.corrupt` for inspection. Errors in a
+ *  file that was merely edited are returned as they are.
+ */
+pub fn load_or_recover<T, F>(path: &Path, load: F) -> Result<T, ConfigError>
+where
+    F: Fn(&Path) -> Result<T, ConfigError>,
+{
+    let integrity = check_integrity(path);
+    let err = match &integrity {
+        Integrity::Missing | Integrity::Sealed | Integrity::Edited => return load(path),
+        // We always seal what we write, so a missing line next to a sealed backup means a cut-off file
+        Integrity::Unsealed if check_integrity(&backup_path(path, 1)) == Integrity::Sealed =>
+            ConfigError::new(path, "", String::from("checksum line missing, file looks truncated")),
+        Integrity::Unsealed => match load(path) {
+            Ok(v) => return Ok(v),
+            Err(e) => e,
+        },
+        Integrity::Corrupt(message) => ConfigError::new(path, "", message.clone()),
+    };
+
+    for i in 1..=BACKUP_COUNT {
+        let backup = backup_path(path, i);
+        match check_integrity(&backup) {
+            Integrity::Sealed | Integrity::Edited | Integrity::Unsealed => {},
+            _ => continue,
+        }
+        let value = match load(&backup) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        log::warn!("{}, restoring it from {}", err, backup.display());
+        let _ = fs::rename(path, sibling(path, "corrupt"));
+        let restored = fs::read(&backup).and_then(|bytes| write_atomic(path, &bytes));
+        if let Err(e) = restored {
+            log::error!("Couldn't restore {}: {}", path.display(), e);
+        }
+        return Ok(value);
+    }
+
+    // No usable backup: an unsealed file is still better than nothing
+    match integrity {
+        Integrity::Unsealed => load(path),
+        _ => Err(err),
+    }
+}
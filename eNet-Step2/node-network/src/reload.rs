@@ -0,0 +1,111 @@
+use std::fs;
+use std::net::SocketAddrV4;
+use std::path::PathBuf;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::time::SystemTime;
+
+use crate::node_config::{ ConfigSource, NodeConfig };
+
+pub const DEFAULT_WATCH_INTERVAL: u64 = 1000;
+
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// Ask running nodes to re-read their configuration on the next `Node::execute`
+pub fn request_reload() {
+    RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+pub fn take_reload_request() -> bool {
+    RELOAD_REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+#[cfg(unix)]
+extern "C" fn on_sighup(_signal: libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+// `kill -HUP <pid>` then reloads the configuration, like most daemons
+#[cfg(unix)]
+pub fn install_reload_signal() {
+    unsafe {
+        libc::signal(libc::SIGHUP, on_sighup as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install_reload_signal() {}
+
+/*
+ *  Declaration of PeerDiff
+ *
+ *  What `HostRepo::update_peers` changed, by peer address.
+ */
+#[derive(Debug, Default, PartialEq)]
+pub struct PeerDiff {
+    pub added: Vec<SocketAddrV4>,
+    pub removed: Vec<SocketAddrV4>,
+    pub rekeyed: Vec<SocketAddrV4>,
+}
+
+impl PeerDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.rekeyed.is_empty()
+    }
+}
+
+/*
+ *  Declaration of Class ConfigWatcher
+ *
+ *  Polls the files a configuration was loaded from, at most every `interval`
+ *  milliseconds, and reports when their size or modification time moved.
+ */
+pub struct ConfigWatcher {
+    pub interval: u64,
+    files: Vec<(PathBuf, Option<(SystemTime, u64)>)>,
+    last_check: u64,
+}
+
+impl ConfigWatcher {
+    pub fn new(interval: u64) -> Self {
+        ConfigWatcher {
+            interval,
+            files: Vec::new(),
+            last_check: 0,
+        }
+    }
+
+    // Start watching the files `config` came from, as they are now
+    pub fn watch(&mut self, config: &NodeConfig) {
+        let paths = match config.source {
+            ConfigSource::Toml => vec![config.paths.toml_path.clone()],
+            ConfigSource::Legacy => vec![config.paths.conf_path.clone(), config.paths.peerlist_path.clone()],
+        };
+        self.files = paths.into_iter().map(|p| {
+            let stamp = stamp(&p);
+            (p, stamp)
+        }).collect();
+    }
+
+    pub fn changed(&mut self, now: u64) -> bool {
+        if now < self.last_check + self.interval {
+            return false;
+        }
+        self.last_check = now;
+
+        let mut changed = false;
+        for (path, last) in &mut self.files {
+            let current = stamp(path);
+            if current != *last {
+                *last = current;
+                changed = true;
+            }
+        }
+
+        changed
+    }
+}
+
+fn stamp(path: &PathBuf) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
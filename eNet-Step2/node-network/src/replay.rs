@@ -0,0 +1,154 @@
+use std::collections::{ HashMap, HashSet };
+use std::fmt::{ self, Display, Formatter };
+use std::net::SocketAddrV4;
+
+/*
+ *  Declaration of Constants
+ */
+// sequence(8) + timestamp(8)
+pub const ENVELOPE_HEADER_LEN: usize = 16;
+// How far behind the high-water mark a sequence number may still arrive
+pub const DEFAULT_REPLAY_WINDOW: u64 = 1024;
+// Largest accepted difference between sender and receiver clocks, in milliseconds
+pub const DEFAULT_MAX_CLOCK_SKEW: u64 = 5 * 60 * 1000;
+
+/*
+ *  Declaration of ReplayError
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayError {
+    Malformed,
+    Duplicate(u64),
+    Stale { sequence: u64, high_water: u64 },
+    Expired(u64),
+    FromFuture(u64),
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Malformed => write!(f, "envelope too short"),
+            ReplayError::Duplicate(seq) => write!(f, "sequence {} was already received", seq),
+            ReplayError::Stale { sequence, high_water } =>
+                write!(f, "sequence {} is behind the replay window (high-water mark {})", sequence, high_water),
+            ReplayError::Expired(ts) => write!(f, "timestamp {} is too old", ts),
+            ReplayError::FromFuture(ts) => write!(f, "timestamp {} is too far in the future", ts),
+        }
+    }
+}
+
+/*
+ *  Declaration of Envelope
+ *
+ *  Every signed packet carries the sender's sequence number and send time in
+ *  front of the frame, so both are covered by the signature.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub body: Vec<u8>,
+}
+
+impl Envelope {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::<u8>::with_capacity(ENVELOPE_HEADER_LEN + self.body.len());
+        out.extend_from_slice(&self.sequence.to_be_bytes());
+        out.extend_from_slice(&self.timestamp.to_be_bytes());
+        out.extend_from_slice(&self.body);
+
+        out
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Envelope, ReplayError> {
+        if data.len() < ENVELOPE_HEADER_LEN {
+            return Err(ReplayError::Malformed);
+        }
+
+        let mut seq = [0u8; 8];
+        seq.copy_from_slice(&data[0..8]);
+        let mut ts = [0u8; 8];
+        ts.copy_from_slice(&data[8..16]);
+
+        Ok(Envelope {
+            sequence: u64::from_be_bytes(seq),
+            timestamp: u64::from_be_bytes(ts),
+            body: data[ENVELOPE_HEADER_LEN..].to_vec(),
+        })
+    }
+}
+
+/*
+ *  Declaration of Class ReplayGuard
+ *
+ *  Tracks, per sender, the highest sequence number seen and the sequence
+ *  numbers received within `window` of it. Anything at or below
+ *  `high_water - window` is stale, anything inside the window is accepted once.
+ */
+struct SenderWindow {
+    high_water: u64,
+    recent: HashSet<u64>,
+}
+
+pub struct ReplayGuard {
+    senders: HashMap<SocketAddrV4, SenderWindow>,
+    pub window: u64,
+    pub max_skew: u64,
+}
+
+impl ReplayGuard {
+    pub fn new(window: u64, max_skew: u64) -> Self {
+        ReplayGuard {
+            senders: HashMap::new(),
+            window,
+            max_skew,
+        }
+    }
+
+    pub fn high_water(&self, sender: SocketAddrV4) -> Option<u64> {
+        self.senders.get(&sender).map(|w| w.high_water)
+    }
+
+    pub fn forget(&mut self, sender: SocketAddrV4) {
+        self.senders.remove(&sender);
+    }
+
+    pub fn check(&mut self, sender: SocketAddrV4, envelope: &Envelope, now: u64) -> Result<(), ReplayError> {
+        // Saturating, so a timestamp near u64::MAX can't wrap past either bound
+        if envelope.timestamp.saturating_add(self.max_skew) < now {
+            return Err(ReplayError::Expired(envelope.timestamp));
+        }
+        if envelope.timestamp > now.saturating_add(self.max_skew) {
+            return Err(ReplayError::FromFuture(envelope.timestamp));
+        }
+
+        let seq = envelope.sequence;
+        let window = self.window;
+        let state = match self.senders.get_mut(&sender) {
+            Some(state) => state,
+            None => {
+                let mut recent = HashSet::new();
+                recent.insert(seq);
+                self.senders.insert(sender, SenderWindow { high_water: seq, recent });
+                return Ok(());
+            },
+        };
+
+        if seq > state.high_water {
+            state.high_water = seq;
+            let floor = seq.saturating_sub(window);
+            state.recent.retain(|s| *s > floor);
+            state.recent.insert(seq);
+            return Ok(());
+        }
+
+        if seq <= state.high_water.saturating_sub(window) {
+            return Err(ReplayError::Stale { sequence: seq, high_water: state.high_water });
+        }
+        if !state.recent.insert(seq) {
+            return Err(ReplayError::Duplicate(seq));
+        }
+
+        Ok(())
+    }
+}
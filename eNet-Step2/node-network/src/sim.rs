@@ -0,0 +1,316 @@
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{ BTreeMap, BTreeSet, BinaryHeap, VecDeque };
+use std::net::SocketAddrV4;
+use std::rc::Rc;
+
+use crate::transport::{ Transport, TransportError, TransportEvent };
+
+// Virtual milliseconds an unanswered connect takes to give up, as ENet's does
+const CONNECT_TIMEOUT: u64 = 1000;
+
+/*
+ *  Declaration of SimConfig
+ *
+ *  Link behaviour of a simulated network. Latencies are in virtual milliseconds,
+ *  `loss_rate` is the probability in [0, 1] that a packet is dropped, and with
+ *  `reorder` off every link delivers in send order.
+ */
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub seed: u64,
+    pub min_latency: u64,
+    pub max_latency: u64,
+    pub loss_rate: f64,
+    pub reorder: bool,
+}
+
+impl SimConfig {
+    pub fn new(seed: u64) -> Self {
+        SimConfig {
+            seed,
+            min_latency: 5,
+            max_latency: 20,
+            loss_rate: 0.0,
+            reorder: false,
+        }
+    }
+}
+
+/*
+ *  Small deterministic generator (SplitMix64) so runs only depend on the seed
+ */
+struct SimRng(u64);
+
+impl SimRng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn range(&mut self, min: u64, max: u64) -> u64 {
+        if max <= min {
+            return min;
+        }
+        min + self.next_u64() % (max - min + 1)
+    }
+}
+
+/*
+ *  Declaration of shared simulator state
+ */
+struct Delivery {
+    to: SocketAddrV4,
+    event: TransportEvent,
+    // A connect that timed out; reported even though no connection was made
+    timed_out: bool,
+}
+
+#[derive(Default)]
+struct Endpoint {
+    inbox: VecDeque<TransportEvent>,
+    connections: BTreeSet<SocketAddrV4>,
+}
+
+struct SimState {
+    config: SimConfig,
+    clock: u64,
+    rng: SimRng,
+    endpoints: BTreeMap<SocketAddrV4, Endpoint>,
+    in_flight: BinaryHeap<Reverse<(u64, u64)>>,
+    deliveries: BTreeMap<u64, Delivery>,
+    next_id: u64,
+    link_clock: BTreeMap<(SocketAddrV4, SocketAddrV4), u64>,
+    partition: Option<BTreeMap<SocketAddrV4, usize>>,
+    sent: u64,
+    dropped: u64,
+}
+
+impl SimState {
+    fn reachable(&self, from: SocketAddrV4, to: SocketAddrV4) -> bool {
+        if !self.endpoints.contains_key(&to) {
+            return false;
+        }
+        match &self.partition {
+            Some(groups) => groups.get(&from) == groups.get(&to),
+            None => true,
+        }
+    }
+
+    fn schedule(&mut self, from: SocketAddrV4, to: SocketAddrV4, event: TransportEvent) {
+        let latency = self.rng.range(self.config.min_latency, self.config.max_latency);
+        let mut deliver_at = self.clock + latency;
+        if !self.config.reorder {
+            let last = self.link_clock.entry((from, to)).or_insert(0);
+            if deliver_at < *last {
+                deliver_at = *last;
+            }
+            *last = deliver_at;
+        }
+
+        self.push(deliver_at, Delivery { to, event, timed_out: false });
+    }
+
+    // Tell `from` its connect to `to` went unanswered once CONNECT_TIMEOUT has passed
+    fn schedule_timeout(&mut self, from: SocketAddrV4, to: SocketAddrV4) {
+        let deliver_at = self.clock + CONNECT_TIMEOUT;
+        self.push(deliver_at, Delivery { to: from, event: TransportEvent::Disconnect(to), timed_out: true });
+    }
+
+    fn push(&mut self, deliver_at: u64, delivery: Delivery) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.deliveries.insert(id, delivery);
+        self.in_flight.push(Reverse((deliver_at, id)));
+    }
+
+    fn deliver_due(&mut self) {
+        while let Some(Reverse((deliver_at, id))) = self.in_flight.peek().cloned() {
+            if deliver_at > self.clock {
+                break;
+            }
+            self.in_flight.pop();
+
+            let delivery = self.deliveries.remove(&id).unwrap();
+            let endpoint = match self.endpoints.get_mut(&delivery.to) {
+                Some(endpoint) => endpoint,
+                None => continue,
+            };
+            match &delivery.event {
+                TransportEvent::Connect(addr) => {
+                    if !endpoint.connections.insert(*addr) {
+                        continue;
+                    }
+                },
+                TransportEvent::Disconnect(addr) => {
+                    if !endpoint.connections.remove(addr) && !delivery.timed_out {
+                        continue;
+                    }
+                },
+                TransportEvent::Receive { sender, data: _ } => {
+                    if !endpoint.connections.contains(sender) {
+                        continue;
+                    }
+                },
+            }
+            endpoint.inbox.push_back(delivery.event);
+        }
+    }
+}
+
+/*
+ *  Declaration of Class SimNetwork
+ *
+ *  An in-memory network of SimTransport endpoints driven by a virtual clock.
+ *  Nothing moves until the test calls `advance`.
+ */
+#[derive(Clone)]
+pub struct SimNetwork {
+    state: Rc<RefCell<SimState>>,
+}
+
+impl SimNetwork {
+    pub fn new(config: SimConfig) -> Self {
+        let seed = config.seed;
+        SimNetwork {
+            state: Rc::new(RefCell::new(SimState {
+                config,
+                clock: 0,
+                rng: SimRng(seed),
+                endpoints: BTreeMap::new(),
+                in_flight: BinaryHeap::new(),
+                deliveries: BTreeMap::new(),
+                next_id: 0,
+                link_clock: BTreeMap::new(),
+                partition: None,
+                sent: 0,
+                dropped: 0,
+            })),
+        }
+    }
+
+    pub fn endpoint(&self, addr: SocketAddrV4) -> SimTransport {
+        self.state.borrow_mut().endpoints.entry(addr).or_default();
+        SimTransport {
+            addr,
+            network: self.clone(),
+        }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.state.borrow().clock
+    }
+
+    pub fn advance(&self, millis: u64) {
+        let mut state = self.state.borrow_mut();
+        state.clock += millis;
+        state.deliver_due();
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.state.borrow().in_flight.len()
+    }
+
+    pub fn set_loss_rate(&self, loss_rate: f64) {
+        self.state.borrow_mut().config.loss_rate = loss_rate;
+    }
+
+    // Split endpoints into groups which can only talk among themselves
+    pub fn partition(&self, groups: &[Vec<SocketAddrV4>]) {
+        let mut map = BTreeMap::new();
+        for (i, group) in groups.iter().enumerate() {
+            for addr in group {
+                map.insert(*addr, i);
+            }
+        }
+        self.state.borrow_mut().partition = Some(map);
+    }
+
+    pub fn heal(&self) {
+        self.state.borrow_mut().partition = None;
+    }
+
+    // Packets handed to the network and packets it dropped (loss or partition)
+    pub fn stats(&self) -> (u64, u64) {
+        let state = self.state.borrow();
+        (state.sent, state.dropped)
+    }
+}
+
+/*
+ *  Declaration of Class SimTransport
+ */
+pub struct SimTransport {
+    pub addr: SocketAddrV4,
+    network: SimNetwork,
+}
+
+impl Transport for SimTransport {
+    fn connect(&mut self, addr: SocketAddrV4) -> Result<(), TransportError> {
+        let mut state = self.network.state.borrow_mut();
+        let local = self.addr;
+        if !state.reachable(local, addr) {
+            state.schedule_timeout(local, addr);
+            return Ok(());
+        }
+        state.schedule(local, addr, TransportEvent::Connect(local));
+        state.schedule(addr, local, TransportEvent::Connect(addr));
+
+        Ok(())
+    }
+
+    fn disconnect(&mut self, addr: SocketAddrV4) {
+        let mut state = self.network.state.borrow_mut();
+        let local = self.addr;
+        state.schedule(local, addr, TransportEvent::Disconnect(local));
+        state.schedule(addr, local, TransportEvent::Disconnect(addr));
+    }
+
+    fn send(&mut self, addr: SocketAddrV4, data: &[u8]) -> bool {
+        let mut state = self.network.state.borrow_mut();
+        let connected = match state.endpoints.get(&self.addr) {
+            Some(endpoint) => endpoint.connections.contains(&addr),
+            None => false,
+        };
+        if !connected {
+            return false;
+        }
+
+        state.sent += 1;
+        let lost = state.rng.next_f64() < state.config.loss_rate;
+        if lost || !state.reachable(self.addr, addr) {
+            state.dropped += 1;
+            return true;
+        }
+
+        let local = self.addr;
+        state.schedule(local, addr, TransportEvent::Receive { sender: local, data: data.to_vec() });
+
+        true
+    }
+
+    fn peers(&mut self) -> Vec<SocketAddrV4> {
+        match self.network.state.borrow().endpoints.get(&self.addr) {
+            Some(endpoint) => endpoint.connections.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn service(&mut self, _timeout_ms: u32) -> Option<TransportEvent> {
+        match self.network.state.borrow_mut().endpoints.get_mut(&self.addr) {
+            Some(endpoint) => endpoint.inbox.pop_front(),
+            None => None,
+        }
+    }
+
+    fn now_millis(&self) -> u64 {
+        self.network.now()
+    }
+}
@@ -0,0 +1,660 @@
+use std::collections::{ BTreeMap, HashMap, VecDeque };
+use std::mem;
+use std::net::SocketAddrV4;
+
+use chain_core::{ Block, BlockHeader, Chain, ChainError, Decode, DecodeError, Decoder, Encode, Encoder, Hash, QuorumCertificate, SnapshotManifest, StateChunk };
+
+/*
+ *  Declaration of Constants
+ */
+// Most headers in one Headers reply
+pub const MAX_SYNC_HEADERS: usize = 256;
+// Most blocks asked for in one GetBlocks
+pub const MAX_SYNC_BLOCKS: usize = 16;
+// A Blocks reply stops growing past this size; it always holds at least one block
+pub const MAX_SYNC_REPLY_BYTES: usize = 4 * 1024 * 1024;
+// Headers held ahead of the chain, which also bounds the blocks buffered
+pub const SYNC_WINDOW: usize = 1024;
+// Block requests outstanding with one peer
+pub const REQUESTS_PER_PEER: usize = 2;
+pub const DEFAULT_SYNC_POLL: u64 = 1000;
+pub const DEFAULT_SYNC_TIMEOUT: u64 = 5000;
+
+/*
+ *  Declaration of SyncMessage
+ *
+ *  Catch-up traffic between two peers, always sent to one peer rather than
+ *  broadcast. Heights refer to the sender's canonical chain as its store
+ *  holds it.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncMessage {
+    // Headers from height `from` on, at most `max`; `max` 0 only asks for the tip
+    GetHeaders { from: u64, max: u32 },
+    // `tip` is the height the sender's chain continues at, so an empty reply still tells how far it is
+    Headers { tip: u64, headers: Vec<BlockHeader> },
+    GetBlocks(Vec<Hash>),
+    // Blocks the sender has among those asked for, each with its certificate if stored
+    Blocks(Vec<(Block, Option<QuorumCertificate>)>),
+    GetSnapshot,
+    // The newest snapshot the sender keeps, if any
+    Snapshot(Option<Box<SnapshotManifest>>),
+    GetChunk { height: u64, index: u32 },
+    // None if the sender no longer keeps that snapshot
+    Chunk { height: u64, chunk: Option<StateChunk> },
+}
+
+impl Encode for SyncMessage {
+    fn encode_to(&self, out: &mut Encoder) {
+        match self {
+            SyncMessage::GetHeaders { from, max } => {
+                out.u8(0).u64(*from).u32(*max);
+            },
+            SyncMessage::Headers { tip, headers } => {
+                out.u8(1).u64(*tip).list(headers);
+            },
+            SyncMessage::GetBlocks(hashes) => {
+                out.u8(2).u32(hashes.len() as u32);
+                for hash in hashes {
+                    out.hash(hash);
+                }
+            },
+            SyncMessage::Blocks(blocks) => {
+                out.u8(3).u32(blocks.len() as u32);
+                for (block, certificate) in blocks {
+                    block.encode_to(out);
+                    match certificate {
+                        Some(certificate) => {
+                            out.u8(1);
+                            certificate.encode_to(out);
+                        },
+                        None => {
+                            out.u8(0);
+                        },
+                    }
+                }
+            },
+            SyncMessage::GetSnapshot => {
+                out.u8(4);
+            },
+            SyncMessage::Snapshot(manifest) => {
+                out.u8(5);
+                encode_option(out, manifest.as_deref());
+            },
+            SyncMessage::GetChunk { height, index } => {
+                out.u8(6).u64(*height).u32(*index);
+            },
+            SyncMessage::Chunk { height, chunk } => {
+                out.u8(7).u64(*height);
+                encode_option(out, chunk.as_ref());
+            },
+        }
+    }
+}
+
+impl Decode for SyncMessage {
+    fn decode_from(input: &mut Decoder) -> Result<Self, DecodeError> {
+        match input.u8()? {
+            0 => Ok(SyncMessage::GetHeaders { from: input.u64()?, max: input.u32()? }),
+            1 => Ok(SyncMessage::Headers { tip: input.u64()?, headers: input.list("headers", MAX_SYNC_HEADERS)? }),
+            2 => {
+                let count = input.u32()? as usize;
+                if count > MAX_SYNC_BLOCKS {
+                    return Err(DecodeError::TooLong { field: "hashes", len: count, max: MAX_SYNC_BLOCKS });
+                }
+                let mut hashes = Vec::with_capacity(count);
+                for _ in 0..count {
+                    hashes.push(input.hash()?);
+                }
+                Ok(SyncMessage::GetBlocks(hashes))
+            },
+            3 => {
+                let count = input.u32()? as usize;
+                if count > MAX_SYNC_BLOCKS {
+                    return Err(DecodeError::TooLong { field: "blocks", len: count, max: MAX_SYNC_BLOCKS });
+                }
+                let mut blocks = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let block = Block::decode_from(input)?;
+                    let certificate = match input.u8()? {
+                        0 => None,
+                        1 => Some(QuorumCertificate::decode_from(input)?),
+                        tag => return Err(DecodeError::UnknownKind(tag)),
+                    };
+                    blocks.push((block, certificate));
+                }
+                Ok(SyncMessage::Blocks(blocks))
+            },
+            4 => Ok(SyncMessage::GetSnapshot),
+            5 => Ok(SyncMessage::Snapshot(decode_option(input)?.map(Box::new))),
+            6 => Ok(SyncMessage::GetChunk { height: input.u64()?, index: input.u32()? }),
+            7 => Ok(SyncMessage::Chunk { height: input.u64()?, chunk: decode_option(input)? }),
+            tag => Err(DecodeError::UnknownKind(tag)),
+        }
+    }
+}
+
+fn encode_option<T: Encode>(out: &mut Encoder, value: Option<&T>) {
+    match value {
+        Some(value) => {
+            out.u8(1);
+            value.encode_to(out);
+        },
+        None => {
+            out.u8(0);
+        },
+    }
+}
+
+fn decode_option<T: Decode>(input: &mut Decoder) -> Result<Option<T>, DecodeError> {
+    match input.u8()? {
+        0 => Ok(None),
+        1 => Ok(Some(T::decode_from(input)?)),
+        tag => Err(DecodeError::UnknownKind(tag)),
+    }
+}
+
+// One GetBlocks waiting for its reply
+struct BlockRequest {
+    peer: SocketAddrV4,
+    hashes: Vec<Hash>,
+    sent: u64,
+}
+
+// Where fast sync is while the chain is still empty
+enum FastSync {
+    // Peers asked for their snapshot and not answered yet, and the offers so far
+    Asking { waiting: Vec<SocketAddrV4>, offers: Vec<(SocketAddrV4, SnapshotManifest)>, sent: u64 },
+    // Chunks of the chosen snapshot coming in from the peers offering it
+    Fetching { manifest: Box<SnapshotManifest>, sources: Vec<SocketAddrV4>, chunks: BTreeMap<u32, StateChunk>, requests: Vec<(SocketAddrV4, u32, u64)> },
+}
+
+/*
+ *  Declaration of Class Syncer
+ *
+ *  Brings the chain up to its peers, headers first. Every `poll_interval` each
+ *  connected peer is asked where its chain is; while one is ahead, headers
+ *  linking onto the last finalized block are downloaded from the furthest
+ *  peer, and their blocks fetched in batches spread over every peer that is
+ *  far enough. Blocks are checked as they arrive, in any order, and handed to
+ *  `Chain::add_block` in height order while later batches are still on the
+ *  way. What is applied is in the store, so a restarted node carries on from
+ *  there. It also answers other peers' requests from the store.
+ *
+ *  With `fast_sync` on, a chain with no blocks first asks its peers for their
+ *  newest snapshot. The highest one that verifies against the configured
+ *  validators is fetched chunk by chunk from every peer offering it, each
+ *  chunk checked against the snapshot's state root, and the chain restored
+ *  from it; blocks above it then come in as above. Without an offer, or if
+ *  the snapshot cannot be had, it syncs from genesis instead.
+ */
+pub struct Syncer {
+    pub poll_interval: u64,
+    // A request not answered within this long is given up and its peer forgotten until polled again
+    pub timeout: u64,
+    pub fast_sync: bool,
+    fast: Option<FastSync>,
+    // Fast sync is tried once, before the chain has any block
+    fast_tried: bool,
+    // Height each peer last said its chain continues at
+    peers: BTreeMap<SocketAddrV4, u64>,
+    // Headers above the chain, in height order, with their hashes
+    headers: VecDeque<(Hash, BlockHeader)>,
+    header_request: Option<(SocketAddrV4, u64)>,
+    block_requests: Vec<BlockRequest>,
+    // Checked blocks waiting for those below them, with the peer each came from
+    arrived: HashMap<Hash, (Block, Option<QuorumCertificate>, SocketAddrV4)>,
+    next_poll: u64,
+}
+
+impl Default for Syncer {
+    fn default() -> Self {
+        Syncer::new()
+    }
+}
+
+impl Syncer {
+    pub fn new() -> Self {
+        Syncer {
+            poll_interval: DEFAULT_SYNC_POLL,
+            timeout: DEFAULT_SYNC_TIMEOUT,
+            fast_sync: true,
+            fast: None,
+            fast_tried: false,
+            peers: BTreeMap::new(),
+            headers: VecDeque::new(),
+            header_request: None,
+            block_requests: Vec::new(),
+            arrived: HashMap::new(),
+            next_poll: 0,
+        }
+    }
+
+    // Furthest height any peer reported
+    pub fn best_tip(&self) -> u64 {
+        self.peers.values().copied().max().unwrap_or(0)
+    }
+
+    pub fn is_behind(&self, chain: &Chain) -> bool {
+        self.best_tip() > chain.next_height()
+    }
+
+    // Headers downloaded but not yet applied
+    pub fn queued(&self) -> usize {
+        self.headers.len()
+    }
+
+    // Drop what was downloaded; peers stay known
+    pub fn reset(&mut self) {
+        self.headers.clear();
+        self.header_request = None;
+        self.block_requests.clear();
+        self.arrived.clear();
+    }
+
+    /*
+     *  Run timeouts, poll peers when due and send whatever requests can go out
+     *  now. `connected` are the peers currently reachable; anything waiting on
+     *  one that is gone is asked elsewhere.
+     */
+    pub fn tick(&mut self, chain: &mut Chain, connected: &[SocketAddrV4], now: u64) -> Vec<(SocketAddrV4, SyncMessage)> {
+        let timeout = self.timeout;
+        let mut lost: Vec<SocketAddrV4> = self.peers.keys().filter(|p| !connected.contains(p)).copied().collect();
+        lost.extend(self.block_requests.iter().filter(|r| now >= r.sent + timeout).map(|r| r.peer));
+        if let Some((peer, sent)) = self.header_request {
+            if now >= sent + timeout || !connected.contains(&peer) {
+                self.header_request = None;
+                lost.push(peer);
+            }
+        }
+        if let Some(FastSync::Fetching { requests, .. }) = &self.fast {
+            lost.extend(requests.iter().filter(|(peer, _, sent)| now >= sent + timeout || !connected.contains(peer)).map(|(peer, _, _)| *peer));
+        }
+        for peer in lost {
+            self.drop_peer(peer);
+        }
+
+        let mut out = Vec::new();
+        if let Some(FastSync::Asking { waiting, .. }) = &mut self.fast {
+            waiting.retain(|p| connected.contains(p));
+        }
+        if now >= self.next_poll && self.fast_sync && !self.fast_tried && chain.tip().is_none() && !connected.is_empty() {
+            // Peers that have not answered are asked again, the request may have been lost
+            match &self.fast {
+                Some(FastSync::Asking { waiting, .. }) => out.extend(waiting.iter().map(|p| (*p, SyncMessage::GetSnapshot))),
+                Some(FastSync::Fetching { .. }) => (),
+                None => {
+                    out.extend(connected.iter().map(|p| (*p, SyncMessage::GetSnapshot)));
+                    self.fast = Some(FastSync::Asking { waiting: connected.to_vec(), offers: Vec::new(), sent: now });
+                },
+            }
+        }
+        if now >= self.next_poll {
+            let (from, _) = self.next_header(chain);
+            for peer in connected {
+                out.push((*peer, SyncMessage::GetHeaders { from, max: 0 }));
+            }
+            self.next_poll = now + self.poll_interval;
+        }
+        out.extend(self.progress(chain, now));
+
+        out
+    }
+
+    // Answer a request from `from` or take in its reply, returning what to send next
+    pub fn handle(&mut self, from: SocketAddrV4, msg: SyncMessage, chain: &mut Chain, now: u64) -> Vec<(SocketAddrV4, SyncMessage)> {
+        match msg {
+            SyncMessage::GetHeaders { from: height, max } => {
+                return vec![(from, serve_headers(chain, height, max))];
+            },
+            SyncMessage::GetBlocks(hashes) => {
+                return vec![(from, serve_blocks(chain, &hashes))];
+            },
+            SyncMessage::GetSnapshot => {
+                let manifest = chain.store().and_then(|store| store.latest_snapshot().unwrap_or(None));
+                return vec![(from, SyncMessage::Snapshot(manifest.map(Box::new)))];
+            },
+            SyncMessage::GetChunk { height, index } => {
+                let chunk = chain.store().and_then(|store| store.snapshot_chunk(height, index).unwrap_or(None));
+                return vec![(from, SyncMessage::Chunk { height, chunk })];
+            },
+            SyncMessage::Headers { tip, headers } => {
+                self.peers.insert(from, tip);
+                // An empty reply leaves the request to time out, so a peer lacking old blocks is not asked again at once
+                if !headers.is_empty() && self.header_request.map(|(peer, _)| peer == from).unwrap_or(false) {
+                    self.header_request = None;
+                }
+                self.take_headers(chain, headers);
+            },
+            SyncMessage::Blocks(blocks) => {
+                self.take_blocks(from, blocks);
+            },
+            SyncMessage::Snapshot(manifest) => {
+                if let Some(FastSync::Asking { waiting, offers, .. }) = &mut self.fast {
+                    if let Some(i) = waiting.iter().position(|p| *p == from) {
+                        waiting.remove(i);
+                        offers.extend(manifest.map(|m| (from, *m)));
+                    }
+                }
+            },
+            SyncMessage::Chunk { height, chunk } => {
+                self.take_chunk(from, height, chunk);
+            },
+        }
+
+        self.progress(chain, now)
+    }
+
+    // Forget `peer` and give back what it was asked for
+    fn drop_peer(&mut self, peer: SocketAddrV4) {
+        self.peers.remove(&peer);
+        self.block_requests.retain(|r| r.peer != peer);
+        if let Some(FastSync::Fetching { sources, requests, .. }) = &mut self.fast {
+            sources.retain(|p| *p != peer);
+            requests.retain(|(p, _, _)| *p != peer);
+        }
+    }
+
+    // Keep a chunk of the snapshot being fetched if it is the one asked for and proves against its root
+    fn take_chunk(&mut self, from: SocketAddrV4, height: u64, chunk: Option<StateChunk>) {
+        let (manifest, chunks, requests) = match &mut self.fast {
+            Some(FastSync::Fetching { manifest, chunks, requests, .. }) if manifest.height() == height => (manifest, chunks, requests),
+            _ => return,
+        };
+        let index = match requests.iter().position(|(p, i, _)| *p == from && chunk.as_ref().map(|c| c.index == *i).unwrap_or(true)) {
+            Some(position) => requests.remove(position).1,
+            None => return,
+        };
+        match chunk {
+            Some(chunk) if chunk.depth == manifest.depth && chunk.verify(&manifest.state_root()) => {
+                chunks.insert(index, chunk);
+            },
+            Some(_) => {
+                warn!("Dropping snapshot chunk {} from {} -> does not prove against the state root", index, from);
+                self.drop_peer(from);
+            },
+            None => {
+                debug!("Peer {} no longer has snapshot {}", from, height);
+                self.drop_peer(from);
+            },
+        }
+    }
+
+    /*
+     *  Move fast sync along: pick a snapshot once every peer asked has answered
+     *  or the timeout passed, ask for missing chunks, and restore the chain once
+     *  all are in. Returns None when fast sync is over and block sync can run.
+     */
+    fn fast_progress(&mut self, chain: &mut Chain, now: u64) -> Option<Vec<(SocketAddrV4, SyncMessage)>> {
+        let timeout = self.timeout;
+        let mut fast = self.fast.take()?;
+        if chain.tip().is_some() {
+            self.fast_tried = true;
+            return None;
+        }
+
+        if let FastSync::Asking { waiting, offers, sent } = &mut fast {
+            if !waiting.is_empty() && now < *sent + timeout {
+                self.fast = Some(fast);
+                return Some(Vec::new());
+            }
+            let mut best: Option<SnapshotManifest> = None;
+            for (peer, manifest) in offers.iter() {
+                if let Err(e) = manifest.verify(&chain.validator_keys, &chain.chain_id) {
+                    warn!("Ignoring snapshot {} from {} -> {}", manifest.height(), peer, e);
+                    continue;
+                }
+                if best.as_ref().map(|b| manifest.height() > b.height()).unwrap_or(true) {
+                    best = Some(manifest.clone());
+                }
+            }
+            let manifest = match best {
+                Some(manifest) => manifest,
+                None => {
+                    debug!("No snapshot offered, syncing from genesis");
+                    self.fast_tried = true;
+                    return None;
+                },
+            };
+            let hash = manifest.block.hash();
+            let sources = offers.iter().filter(|(_, m)| m.block.hash() == hash).map(|(peer, _)| *peer).collect();
+            info!("Fetching snapshot {} of {} chunks", manifest.height(), manifest.chunk_count());
+            fast = FastSync::Fetching { manifest: Box::new(manifest), sources, chunks: BTreeMap::new(), requests: Vec::new() };
+        }
+
+        let (manifest, sources, chunks, requests) = match &mut fast {
+            FastSync::Fetching { manifest, sources, chunks, requests } => (manifest, sources, chunks, requests),
+            FastSync::Asking { .. } => unreachable!(),
+        };
+        if chunks.len() as u32 == manifest.chunk_count() {
+            self.fast_tried = true;
+            let chunks: Vec<StateChunk> = mem::take(chunks).into_values().collect();
+            let restored = manifest.assemble(&chunks).map_err(ChainError::from).and_then(|state| chain.restore(manifest, state));
+            match restored {
+                Ok(()) => info!("Restored snapshot {} with {} accounts, chain continues at {}",
+                    manifest.height(), chain.state.len(), chain.next_height()),
+                Err(e) => warn!("Couldn't restore snapshot {} -> {}, syncing from genesis", manifest.height(), e),
+            }
+            self.reset();
+            return None;
+        }
+        if sources.is_empty() {
+            warn!("No peer left to fetch snapshot {} from, syncing from genesis", manifest.height());
+            self.fast_tried = true;
+            return None;
+        }
+
+        let mut out = Vec::new();
+        for index in 0..manifest.chunk_count() {
+            if chunks.contains_key(&index) || requests.iter().any(|(_, i, _)| *i == index) {
+                continue;
+            }
+            let peer = sources.iter()
+                .map(|peer| (*peer, requests.iter().filter(|(p, _, _)| p == peer).count()))
+                .filter(|(_, count)| *count < REQUESTS_PER_PEER)
+                .min_by_key(|(_, count)| *count);
+            let peer = match peer {
+                Some((peer, _)) => peer,
+                None => break,
+            };
+            out.push((peer, SyncMessage::GetChunk { height: manifest.height(), index }));
+            requests.push((peer, index, now));
+        }
+        self.fast = Some(fast);
+
+        Some(out)
+    }
+
+    // Height and parent of the next header wanted
+    fn next_header(&self, chain: &Chain) -> (u64, Hash) {
+        match self.headers.back() {
+            Some((hash, header)) => (header.height + 1, *hash),
+            None => match chain.finalized() {
+                Some((hash, height)) => (height + 1, hash),
+                None => (0, Hash::ZERO),
+            },
+        }
+    }
+
+    // Keep the headers that extend the queue; a reply is cut at the first that does not
+    fn take_headers(&mut self, chain: &Chain, headers: Vec<BlockHeader>) {
+        let (mut height, mut parent) = self.next_header(chain);
+        for header in headers {
+            if self.headers.len() >= SYNC_WINDOW || header.height != height || header.parent != parent {
+                break;
+            }
+            if header.last_commit.as_ref().map(|c| c.block != parent).unwrap_or(header.height > 0) {
+                break;
+            }
+            let hash = header.hash();
+            self.headers.push_back((hash, header));
+            height += 1;
+            parent = hash;
+        }
+    }
+
+    // Accept the blocks of the request they answer that look right before execution
+    fn take_blocks(&mut self, from: SocketAddrV4, blocks: Vec<(Block, Option<QuorumCertificate>)>) {
+        let first = blocks.first().map(|(block, _)| block.hash());
+        let index = self.block_requests.iter().position(|r| {
+            r.peer == from && first.map(|hash| r.hashes.contains(&hash)).unwrap_or(true)
+        });
+        let request = match index {
+            Some(i) => self.block_requests.remove(i),
+            None => return,
+        };
+        // The peer no longer has what its headers promised
+        if blocks.is_empty() {
+            debug!("Peer {} has none of the {} blocks asked for", from, request.hashes.len());
+            self.peers.remove(&from);
+            self.reset();
+            return;
+        }
+
+        for (block, certificate) in blocks {
+            let hash = block.hash();
+            if !request.hashes.contains(&hash) {
+                continue;
+            }
+            if !block.has_valid_tx_root() || certificate.as_ref().map(|c| c.block != hash).unwrap_or(false) {
+                warn!("Dropping block {} from {} -> body or certificate does not match", block.header.height, from);
+                self.peers.remove(&from);
+                continue;
+            }
+            self.arrived.insert(hash, (block, certificate, from));
+        }
+    }
+
+    fn progress(&mut self, chain: &mut Chain, now: u64) -> Vec<(SocketAddrV4, SyncMessage)> {
+        if let Some(out) = self.fast_progress(chain, now) {
+            return out;
+        }
+        self.apply(chain, now);
+
+        let mut out = Vec::new();
+        let (from, _) = self.next_header(chain);
+        if self.header_request.is_none() && self.headers.len() < SYNC_WINDOW {
+            let best = self.peers.iter().filter(|(_, tip)| **tip > from).max_by_key(|(_, tip)| **tip);
+            if let Some((peer, _)) = best {
+                let max = MAX_SYNC_HEADERS.min(SYNC_WINDOW - self.headers.len()) as u32;
+                out.push((*peer, SyncMessage::GetHeaders { from, max }));
+                self.header_request = Some((*peer, now));
+            }
+        }
+
+        let wanted: Vec<(Hash, u64)> = self.headers.iter()
+            .filter(|(hash, _)| !self.arrived.contains_key(hash) && !self.block_requests.iter().any(|r| r.hashes.contains(hash)))
+            .map(|(hash, header)| (*hash, header.height))
+            .collect();
+        for batch in wanted.chunks(MAX_SYNC_BLOCKS) {
+            let last = batch[batch.len() - 1].1;
+            let peer = self.peers.iter()
+                .filter(|(_, tip)| **tip > last)
+                .map(|(peer, _)| (*peer, self.block_requests.iter().filter(|r| r.peer == *peer).count()))
+                .filter(|(_, requests)| *requests < REQUESTS_PER_PEER)
+                .min_by_key(|(_, requests)| *requests);
+            let peer = match peer {
+                Some((peer, _)) => peer,
+                None => break,
+            };
+            let hashes: Vec<Hash> = batch.iter().map(|(hash, _)| *hash).collect();
+            out.push((peer, SyncMessage::GetBlocks(hashes.clone())));
+            self.block_requests.push(BlockRequest { peer, hashes, sent: now });
+        }
+
+        out
+    }
+
+    // Add the blocks that continue the chain; a block it rejects throws away the download
+    fn apply(&mut self, chain: &mut Chain, now: u64) {
+        self.tidy(chain);
+        let mut applied = 0;
+        while let Some((hash, _)) = self.headers.front() {
+            let (block, certificate, peer) = match self.arrived.remove(hash) {
+                Some(arrived) => arrived,
+                None => break,
+            };
+            let height = block.header.height;
+            if let Err(e) = chain.add_block(block, certificate, now) {
+                warn!("Sync block {} from {} rejected -> {}", height, peer, e);
+                self.peers.remove(&peer);
+                self.reset();
+                break;
+            }
+            self.headers.pop_front();
+            applied += 1;
+        }
+
+        if applied > 0 {
+            info!("Synced {} blocks, chain continues at {} of {}", applied, chain.next_height(), self.best_tip());
+        }
+    }
+
+    // Drop headers the chain already has, and all of them once they no longer link to it
+    fn tidy(&mut self, chain: &Chain) {
+        let (root, first) = match chain.finalized() {
+            Some((hash, height)) => (hash, height + 1),
+            None => (Hash::ZERO, 0),
+        };
+        while let Some((hash, header)) = self.headers.front() {
+            if header.height >= first && !chain.pending().contains(hash) {
+                break;
+            }
+            self.arrived.remove(hash);
+            self.headers.pop_front();
+        }
+        if let Some((_, header)) = self.headers.front() {
+            if header.parent != root && !chain.pending().contains(&header.parent) {
+                debug!("Downloaded headers no longer link to the chain at {}", header.height);
+                self.reset();
+            }
+        }
+    }
+}
+
+// Headers of the stored chain from `from` on
+fn serve_headers(chain: &Chain, from: u64, max: u32) -> SyncMessage {
+    let store = match chain.store() {
+        Some(store) => store,
+        None => return SyncMessage::Headers { tip: 0, headers: Vec::new() },
+    };
+    let tip = chain.next_height();
+    let end = tip.min(from.saturating_add((max as usize).min(MAX_SYNC_HEADERS) as u64));
+    let mut headers = Vec::new();
+    for height in from..end {
+        match store.block_at(height) {
+            Ok(Some(block)) => headers.push(block.header),
+            _ => break,
+        }
+    }
+
+    SyncMessage::Headers { tip, headers }
+}
+
+// The stored blocks among `hashes`, up to the reply size
+fn serve_blocks(chain: &Chain, hashes: &[Hash]) -> SyncMessage {
+    let mut blocks = Vec::new();
+    let store = match chain.store() {
+        Some(store) => store,
+        None => return SyncMessage::Blocks(blocks),
+    };
+    let mut size = 0;
+    for hash in hashes {
+        let block = match store.block(hash) {
+            Ok(Some(block)) => block,
+            _ => continue,
+        };
+        let height = block.header.height;
+        let certificate = match store.hash_at(height) {
+            Ok(Some(canonical)) if canonical == *hash => store.certificate(height).unwrap_or(None),
+            _ => None,
+        };
+        size += block.encode().len() + certificate.as_ref().map(|c| c.encode().len()).unwrap_or(0);
+        if size > MAX_SYNC_REPLY_BYTES && !blocks.is_empty() {
+            break;
+        }
+        blocks.push((block, certificate));
+    }
+
+    SyncMessage::Blocks(blocks)
+}
@@ -0,0 +1,128 @@
+use enet::*;
+use std::fmt::{ self, Display, Formatter };
+use std::net::{ Ipv4Addr, SocketAddrV4 };
+
+use chrono::prelude::*;
+
+/*
+ *  Declaration of TransportEvent
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransportEvent {
+    Connect(SocketAddrV4),
+    Disconnect(SocketAddrV4),
+    Receive { sender: SocketAddrV4, data: Vec<u8> },
+}
+
+/*
+ *  Declaration of TransportError
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransportError {
+    // No connection could be started, e.g. every peer slot is taken
+    ConnectFailed(SocketAddrV4),
+}
+
+impl Display for TransportError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            TransportError::ConnectFailed(addr) => write!(f, "couldn't connect to {}", addr),
+        }
+    }
+}
+
+/*
+ *  Declaration of Trait Transport
+ *
+ *  Everything a HostRepo needs from the wire. Addresses are always reported in
+ *  normal (network) octet order.
+ */
+pub trait Transport {
+    fn connect(&mut self, addr: SocketAddrV4) -> Result<(), TransportError>;
+    fn disconnect(&mut self, addr: SocketAddrV4);
+    fn send(&mut self, addr: SocketAddrV4, data: &[u8]) -> bool;
+    fn peers(&mut self) -> Vec<SocketAddrV4>;
+    fn service(&mut self, timeout_ms: u32) -> Option<TransportEvent>;
+    fn now_millis(&self) -> u64;
+}
+
+/*
+ *  Declaration of Class EnetTransport
+ */
+pub struct EnetTransport {
+    pub host: Host<()>,
+}
+
+impl EnetTransport {
+    pub fn new(host: Host<()>) -> Self {
+        EnetTransport { host }
+    }
+}
+
+// enet 0.2 reads incoming host addresses with their octets reversed
+fn from_enet_address(addr: &Address) -> SocketAddrV4 {
+    let o = addr.ip().octets();
+    SocketAddrV4::new(Ipv4Addr::new(o[3], o[2], o[1], o[0]), addr.port())
+}
+
+impl Transport for EnetTransport {
+    fn connect(&mut self, addr: SocketAddrV4) -> Result<(), TransportError> {
+        match self.host.connect(&Address::new(*addr.ip(), addr.port()), super::MAX_PEERS_COUNT, 0) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(TransportError::ConnectFailed(addr)),
+        }
+    }
+
+    fn disconnect(&mut self, addr: SocketAddrV4) {
+        for mut peer in self.host.peers() {
+            if from_enet_address(&peer.address()) == addr {
+                peer.disconnect(0);
+            }
+        }
+    }
+
+    fn send(&mut self, addr: SocketAddrV4, data: &[u8]) -> bool {
+        for mut peer in self.host.peers() {
+            if from_enet_address(&peer.address()) == addr {
+                let packet = match Packet::new(data, PacketMode::ReliableSequenced) {
+                    Ok(packet) => packet,
+                    Err(_) => return false,
+                };
+                return peer.send_packet(packet, 1).is_ok();
+            }
+        }
+
+        false
+    }
+
+    fn peers(&mut self) -> Vec<SocketAddrV4> {
+        let mut addresses = Vec::<SocketAddrV4>::new();
+        for peer in self.host.peers() {
+            if peer.address().ip().is_unspecified() {
+                continue;
+            }
+            let addr = from_enet_address(&peer.address());
+            if !addresses.contains(&addr) {
+                addresses.push(addr);
+            }
+        }
+
+        addresses
+    }
+
+    fn service(&mut self, timeout_ms: u32) -> Option<TransportEvent> {
+        match self.host.service(timeout_ms).expect("service failed") {
+            Some(Event::Connect(ref p)) => Some(TransportEvent::Connect(from_enet_address(&p.address()))),
+            Some(Event::Disconnect(ref p, _dt)) => Some(TransportEvent::Disconnect(from_enet_address(&p.address()))),
+            Some(Event::Receive { ref sender, ref packet, channel_id: _ }) => Some(TransportEvent::Receive {
+                sender: from_enet_address(&sender.address()),
+                data: packet.data().to_vec(),
+            }),
+            None => None,
+        }
+    }
+
+    fn now_millis(&self) -> u64 {
+        Utc::now().timestamp_millis() as u64
+    }
+}
@@ -0,0 +1,26 @@
+// Helpers shared by the node-network tests; not every test uses each of them
+#![allow(dead_code)]
+
+use chain_core::{ Block, Chain, QuorumCertificate, Vote, VoteKind };
+use secure_sign::NistCryptography;
+
+pub const CHAIN: &str = "frink-test";
+pub const MAX_TX: usize = 64 * 1024;
+
+pub fn keypair() -> NistCryptography {
+    let mut secure = NistCryptography::new();
+    secure.init();
+    assert_eq!(secure.generate_keypair(), 0);
+    secure
+}
+
+// Precommits from the first three of four validators
+pub fn certify(block: &Block, keys: &mut [NistCryptography], chain: &Chain) -> QuorumCertificate {
+    let mut precommits = Vec::new();
+    for secure in &mut keys[..3] {
+        let mut vote = Vote::new(VoteKind::Precommit, block.header.height, 0, Some(block.hash()));
+        assert!(vote.sign(secure, CHAIN));
+        precommits.push(vote);
+    }
+    QuorumCertificate::from_precommits(&chain.validators(), block.header.height, 0, block.hash(), &precommits)
+}
@@ -0,0 +1,79 @@
+use std::net::{ Ipv4Addr, SocketAddrV4 };
+
+use node_network::{ Chunk, Frame, FragmentError, Reassembler, split_payload };
+use node_network::{ MAX_CHUNK_SIZE, MAX_PAYLOAD_SIZE };
+
+#[test]
+fn reassemble_out_of_order() {
+    let payload: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+    let sender = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 8875);
+
+    let mut chunks = split_payload(7, &payload).unwrap();
+    assert_eq!(chunks.len(), 10);
+    chunks.reverse();
+
+    let mut reassembler = Reassembler::new(1_000, 64 * 1024);
+    let mut result = None;
+    for chunk in chunks {
+        let frame = Frame::decode(&Frame::Chunk(chunk).encode()).unwrap();
+        if let Frame::Chunk(chunk) = frame {
+            if let Some(done) = reassembler.insert(sender, chunk, 0).unwrap() {
+                result = Some(done);
+            }
+        }
+    }
+
+    assert_eq!(result, Some(payload));
+    assert_eq!(reassembler.used_bytes(), 0);
+}
+
+#[test]
+fn resume_and_limits() {
+    let sender = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 8876);
+    let first = split_payload(1, &vec![0xAB; 4 * MAX_CHUNK_SIZE]).unwrap();
+    let second = split_payload(2, &vec![0xCD; 2 * MAX_CHUNK_SIZE]).unwrap();
+
+    let mut reassembler = Reassembler::new(1_000, 4 * MAX_CHUNK_SIZE + MAX_CHUNK_SIZE / 2);
+    assert_eq!(reassembler.insert(sender, first[0].clone(), 0), Ok(None));
+    assert_eq!(reassembler.insert(sender, first[2].clone(), 0), Ok(None));
+    assert_eq!(reassembler.missing_from(sender), vec![(1, vec![1, 3])]);
+
+    // Payloads larger than the cap are refused outright
+    let huge = split_payload(3, &vec![0u8; 8 * MAX_CHUNK_SIZE]).unwrap();
+    assert_eq!(reassembler.insert(sender, huge[0].clone(), 0), Err(FragmentError::TooLarge(8 * MAX_CHUNK_SIZE)));
+
+    // The stalled payload is evicted to make room for a newer one
+    assert_eq!(reassembler.insert(sender, second[0].clone(), 10), Ok(None));
+    assert_eq!(reassembler.insert(sender, first[1].clone(), 5), Ok(None));
+    assert_eq!(reassembler.insert(sender, second[1].clone(), 30), Ok(Some(vec![0xCD; 2 * MAX_CHUNK_SIZE])));
+    assert_eq!(reassembler.pending_count(), 0);
+    assert_eq!(reassembler.used_bytes(), 0);
+
+    // Partial payloads without progress time out
+    reassembler.insert(sender, first[3].clone(), 100).unwrap();
+    assert_eq!(reassembler.expire(500), 0);
+    assert_eq!(reassembler.expire(5_000), 1);
+    assert_eq!(reassembler.pending_count(), 0);
+}
+
+#[test]
+fn chunk_headers_must_match_the_split() {
+    let sender = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 8877);
+    let chunk = Chunk { payload_id: 9, index: 0, count: u32::MAX, total_len: 10, data: vec![0u8; 10] };
+
+    // A huge count would otherwise size the reassembly buffer
+    assert_eq!(Frame::decode(&Frame::Chunk(chunk.clone()).encode()), Err(FragmentError::Malformed));
+    let mut reassembler = Reassembler::new(1_000, 64 * 1024);
+    assert_eq!(reassembler.insert(sender, chunk.clone(), 0), Err(FragmentError::Inconsistent(9)));
+    assert_eq!(reassembler.pending_count(), 0);
+
+    // So does a short chunk in the middle of a payload
+    let mut chunks = split_payload(9, &vec![0u8; 3 * MAX_CHUNK_SIZE]).unwrap();
+    chunks[1].data.pop();
+    assert_eq!(Frame::decode(&Frame::Chunk(chunks[1].clone()).encode()), Err(FragmentError::Malformed));
+    assert_eq!(Frame::decode(&Frame::Chunk(Chunk { count: 1, ..chunks[0].clone() }).encode()), Err(FragmentError::Malformed));
+
+    // Payloads the header could not describe are never split
+    let oversized = vec![0u8; MAX_PAYLOAD_SIZE + 1];
+    assert_eq!(split_payload(9, &oversized), Err(FragmentError::TooLarge(oversized.len())));
+}
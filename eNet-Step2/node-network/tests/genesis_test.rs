@@ -0,0 +1,54 @@
+use std::env;
+use std::fs;
+
+use chain_core::{ Genesis, GenesisAccount, IdentityStatus };
+use node_network::{ ConfigPaths, GenesisFile, NodeConfig, GENESIS_FILE, load_genesis, save_genesis };
+use secure_sign::NistCryptography;
+
+fn public_key() -> Vec<u8> {
+    let mut secure = NistCryptography::new();
+    secure.init();
+    assert_eq!(secure.generate_keypair(), 0);
+    secure.public_key.to_vec()
+}
+
+#[test]
+fn genesis_file_round_trip_and_config() {
+    let dir = env::temp_dir().join(format!("frink-genesis-file-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.join(GENESIS_FILE);
+
+    let mut genesis = Genesis::new("frink-net", 1_700_000_000_000);
+    genesis.params.block_time_ms = 2_000;
+    genesis.validators = vec![public_key(), public_key()];
+    genesis.accounts.push(GenesisAccount { public_key: public_key(), balance: 42, identity: IdentityStatus::Verified });
+    save_genesis(&path, &genesis).unwrap();
+    let loaded = load_genesis(&path).unwrap();
+    assert_eq!(loaded, genesis);
+    assert_eq!(loaded.hash(), genesis.hash());
+
+    // Errors name the setting
+    let mut file = GenesisFile::from_genesis(&genesis);
+    file.validators[1].push_str("00");
+    assert_eq!(file.to_genesis(&path).unwrap_err().key, "validators[1]");
+    let mut file = GenesisFile::from_genesis(&genesis);
+    file.accounts[0].identity = String::from("trusted");
+    assert_eq!(file.to_genesis(&path).unwrap_err().key, "accounts[0].identity");
+    let mut file = GenesisFile::from_genesis(&genesis);
+    file.validators.clear();
+    assert!(file.to_genesis(&path).unwrap_err().message.contains("validator"));
+
+    // node.toml points at it relative to itself; the chain settings then come from it
+    let paths = ConfigPaths::from_data_dir(&dir);
+    let mut config = NodeConfig::from_toml(&paths, "genesis = \"genesis.toml\"\nlisten = [\"127.0.0.1:8871\"]\n").unwrap();
+    assert_eq!(config.genesis_path(), Some(path.clone()));
+    let loaded = config.load_genesis().unwrap().unwrap();
+    config.chain.adopt_genesis(&loaded);
+    assert_eq!(config.chain.chain_id, "frink-net");
+    assert_eq!(config.chain.block_time_ms, 2_000);
+    assert_eq!(config.chain.validator_keys(), genesis.validators);
+    assert!(!config.to_toml().unwrap().is_empty());
+    assert_eq!(NodeConfig::from_toml(&paths, "listen = [\"127.0.0.1:8871\"]\n").unwrap().load_genesis().unwrap(), None);
+
+    let _ = fs::remove_dir_all(&dir);
+}
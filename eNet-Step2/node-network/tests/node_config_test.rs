@@ -0,0 +1,132 @@
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use node_network::{ ConfigPaths, ConfigSource, NodeConfig, config_arg };
+use common::keypair;
+
+fn legacy_dir(name: &str, conf: &str, peerlist: &str) -> ConfigPaths {
+    let dir = env::temp_dir().join(format!("frink-legacy-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let paths = ConfigPaths::from_data_dir(&dir);
+    fs::write(&paths.conf_path, conf).unwrap();
+    // An empty peer list is an error of its own, leave the file out instead
+    if !peerlist.is_empty() {
+        fs::write(&paths.peerlist_path, peerlist).unwrap();
+    }
+    paths
+}
+
+fn public_key() -> String {
+    hex::encode_upper(&keypair().public_key[..])
+}
+
+#[test]
+fn config_flag_and_paths() {
+    let args = vec!["--verbose", "--config", "/etc/frink"].into_iter().map(String::from);
+    assert_eq!(config_arg(args), Some(PathBuf::from("/etc/frink")));
+
+    let args = vec!["--config=node/conf.ini"].into_iter().map(String::from);
+    assert_eq!(config_arg(args), Some(PathBuf::from("node/conf.ini")));
+    assert_eq!(config_arg(Vec::<String>::new().into_iter()), None);
+
+    let paths = ConfigPaths::from_path(&PathBuf::from("node/conf.ini"));
+    assert_eq!(paths.data_dir, PathBuf::from("node"));
+    assert_eq!(paths.conf_path, PathBuf::from("node/conf.ini"));
+    assert_eq!(paths.toml_path, PathBuf::from("node").join("node.toml"));
+
+    let paths = ConfigPaths::locate(Some(PathBuf::from("/etc/frink")));
+    assert_eq!(paths.peerlist_path, PathBuf::from("/etc/frink").join("peerlist.csv"));
+}
+
+#[test]
+fn toml_defaults_and_validation() {
+    let paths = ConfigPaths::from_data_dir(&PathBuf::from("node"));
+
+    let config = NodeConfig::from_toml(&paths, "listen = [\"127.0.0.1:8871\"]\n").unwrap();
+    assert_eq!(config.limits.max_peers, 50);
+    assert_eq!(config.keystore_path(), PathBuf::from("node").join("conf.ini"));
+
+    let text = "listen = [\"127.0.0.1:8871\"]\n[[bootstrap_peers]]\naddress = \"127.0.0.1:8873\"\npublic_key = \"0AFF\"\n";
+    let err = NodeConfig::from_toml(&paths, text).unwrap_err();
+    assert_eq!(err.key, "bootstrap_peers[0].public_key");
+
+    let err = NodeConfig::from_toml(&paths, "listen = [\"127.0.0.1:8871\"]\n[logging]\nlevel = \"loud\"\n").unwrap_err();
+    assert_eq!(err.key, "logging.level");
+
+    let err = NodeConfig::from_toml(&paths, "listen = [\"localhost\"]\n").unwrap_err();
+    assert!(err.message.contains("listen"), "{}", err);
+}
+
+#[test]
+fn legacy_pair_converts() {
+    let key = public_key();
+    let conf = "[Host 1]\nport = 8871\npublic = 0A0B\nprivate = 0C0D\n\n[Host 2]\nport = 8872\npublic = 0A0C\nprivate = 0C0E\n";
+    let peerlist = format!("# address,port,key\n127.0.0.1,8873,{}\n10.0.0.2, 8874, {}\n", key, key);
+    let paths = legacy_dir("valid", conf, &peerlist);
+
+    let config = NodeConfig::from_legacy(&paths).unwrap();
+    assert_eq!(config.source, ConfigSource::Legacy);
+    assert_eq!(config.keystore, paths.conf_path);
+    let ports: Vec<u16> = config.listen.iter().map(|a| a.port()).collect();
+    assert_eq!(ports, vec![8871, 8872]);
+    let peers: Vec<String> = config.bootstrap_peers.iter().map(|p| p.address.to_string()).collect();
+    assert_eq!(peers, vec!["127.0.0.1:8873", "10.0.0.2:8874"]);
+    assert!(config.bootstrap_peers.iter().all(|p| p.public_key == key));
+
+    // A missing peer list just means no bootstrap peers
+    fs::remove_file(&paths.peerlist_path).unwrap();
+    assert!(NodeConfig::from_legacy(&paths).unwrap().bootstrap_peers.is_empty());
+}
+
+#[test]
+fn legacy_host_errors_name_the_key() {
+    let conf = "[Host 1]\nport = 8871\npublic = 0A0B\nprivate = 0C0D\n\n[Host 2]\nport = 88x2\npublic = 0A0C\nprivate = 0C0E\n";
+    let err = NodeConfig::from_legacy(&legacy_dir("port", conf, "")).unwrap_err();
+    assert_eq!(err.key, "Host 2.port");
+    assert!(err.message.contains("88x2"), "{}", err);
+
+    let conf = "[Host 1]\nport = 8871\npublic = 0A0G\nprivate = 0C0D\n";
+    let err = NodeConfig::from_legacy(&legacy_dir("public", conf, "")).unwrap_err();
+    assert_eq!(err.key, "Host 1.public");
+
+    let conf = "[Host 1]\nport = 8871\npublic = 0A0B\n";
+    let paths = legacy_dir("missing", conf, "");
+    let err = NodeConfig::from_legacy(&paths).unwrap_err();
+    assert_eq!((err.key.as_str(), err.message.as_str()), ("Host 1.private", "missing"));
+    assert_eq!(err.file, paths.conf_path);
+
+    let conf = "[Host 1]\nport = 8871\npublic = 0A0B\nprivate = 0C0D\n\n[Host 2]\nport = 8871\npublic = 0A0C\nprivate = 0C0E\n";
+    let err = NodeConfig::from_legacy(&legacy_dir("twice", conf, "")).unwrap_err();
+    assert_eq!(err.key, "listen[1]");
+}
+
+#[test]
+fn legacy_peerlist_errors_name_the_line() {
+    let key = public_key();
+    let conf = "[Host 1]\nport = 8871\npublic = 0A0B\nprivate = 0C0D\n";
+
+    let peerlist = format!("127.0.0.1,8873,{}\n127.0.0.1,port,{}\n", key, key);
+    let paths = legacy_dir("csv-port", conf, &peerlist);
+    let err = NodeConfig::from_legacy(&paths).unwrap_err();
+    assert_eq!(err.key, "line 2 port");
+    assert!(err.message.contains("`port`"), "{}", err);
+    assert_eq!(err.file, paths.peerlist_path);
+
+    let peerlist = format!("127.0.0.1,8873,{}\n127.0.0.1,8874\n", key);
+    let err = NodeConfig::from_legacy(&legacy_dir("csv-record", conf, &peerlist)).unwrap_err();
+    assert_eq!(err.key, "line 2 record");
+
+    let err = NodeConfig::from_legacy(&legacy_dir("csv-address", conf, "localhost,8873,0A0B\n")).unwrap_err();
+    assert_eq!(err.key, "line 1 address");
+
+    let err = NodeConfig::from_legacy(&legacy_dir("csv-key", conf, "127.0.0.1,8873,XYZ\n")).unwrap_err();
+    assert_eq!(err.key, "line 1 key");
+
+    // Keys of the wrong length get through the CSV but not validation
+    let err = NodeConfig::from_legacy(&legacy_dir("csv-short", conf, "127.0.0.1,8873,0A0B\n")).unwrap_err();
+    assert_eq!(err.key, "bootstrap_peers[0].public_key");
+}
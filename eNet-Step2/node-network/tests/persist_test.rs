@@ -0,0 +1,105 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use node_network::{ HostInfo, Integrity, PeerInfo, backup_path, check_integrity, get_hosts, read_peerlist };
+use node_network::{ save_peerlist, set_hosts, write_sealed };
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("frink-persist-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn host(port: u16) -> HostInfo {
+    HostInfo {
+        port,
+        public_key: vec![port as u8; 16],
+        private_key: vec![!(port as u8); 16],
+    }
+}
+
+#[test]
+fn set_hosts_replaces_and_keeps_backups() {
+    let dir = scratch_dir("hosts");
+    let conf = dir.join("conf.ini");
+
+    assert!(set_hosts(&conf, &vec![host(8871), host(8872), host(8873)]));
+    assert!(set_hosts(&conf, &vec![host(8874)]));
+    assert_eq!(check_integrity(&conf), Integrity::Sealed);
+
+    // The second write replaces the first instead of merging into it
+    let hosts = get_hosts(&conf);
+    assert_eq!(hosts.len(), 1);
+    assert_eq!(hosts[0].port, 8874);
+    assert_eq!(get_hosts(&backup_path(&conf, 1)).len(), 3);
+
+    // A file cut short is detected and the previous good copy comes back
+    let text = fs::read_to_string(&conf).unwrap();
+    fs::write(&conf, &text[..text.len() / 2]).unwrap();
+    assert_eq!(get_hosts(&conf).len(), 3);
+    assert_eq!(check_integrity(&conf), Integrity::Sealed);
+    assert!(dir.join("conf.ini.corrupt").exists());
+
+    // A hand edit keeps the checksum line and is taken as it is, not reverted
+    let text = fs::read_to_string(&conf).unwrap().replacen("8871", "8881", 1);
+    fs::write(&conf, &text).unwrap();
+    assert_eq!(check_integrity(&conf), Integrity::Edited);
+    assert_eq!(get_hosts(&conf)[0].port, 8881);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn truncated_peerlist_recovers() {
+    let dir = scratch_dir("peers");
+    let csv = dir.join("peerlist.csv");
+    let peer = |port: u16| PeerInfo {
+        address: String::from("127.0.0.1"),
+        port,
+        key: vec![0xAB; 8],
+        connected: false,
+    };
+
+    assert!(save_peerlist(&csv, &[peer(8871)]));
+    assert!(save_peerlist(&csv, &[peer(8871), peer(8872), peer(8873)]));
+    assert_eq!(read_peerlist(&csv).len(), 3);
+
+    // Truncated on a line boundary still parses, only the missing checksum line gives it away
+    let text = fs::read_to_string(&csv).unwrap();
+    let cut: usize = text.lines().take(2).map(|l| l.len() + 1).sum();
+    fs::write(&csv, &text[..cut]).unwrap();
+    assert_eq!(check_integrity(&csv), Integrity::Unsealed);
+    assert_eq!(read_peerlist(&csv).len(), 1);
+
+    // Hand-written files without a checksum line are accepted as they are
+    let manual = dir.join("manual.csv");
+    fs::write(&manual, "127.0.0.1,8875,ABCD\n").unwrap();
+    assert_eq!(read_peerlist(&manual).len(), 1);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn only_good_copies_take_a_backup_slot() {
+    let dir = scratch_dir("backups");
+    let path = dir.join("node.toml");
+
+    // A legacy file without a checksum line is kept the first time it is rewritten
+    fs::write(&path, "legacy = true\n").unwrap();
+    write_sealed(&path, "first = true\n").unwrap();
+    assert_eq!(check_integrity(&backup_path(&path, 1)), Integrity::Unsealed);
+    write_sealed(&path, "second = true\n").unwrap();
+    assert_eq!(check_integrity(&backup_path(&path, 1)), Integrity::Sealed);
+
+    // A copy cut off before its checksum line does not push the good backups out
+    let text = fs::read_to_string(&path).unwrap();
+    fs::write(&path, text.lines().next().unwrap()).unwrap();
+    assert_eq!(check_integrity(&path), Integrity::Unsealed);
+    write_sealed(&path, "third = true\n").unwrap();
+    assert!(fs::read_to_string(backup_path(&path, 1)).unwrap().starts_with("first = true"));
+    assert!(fs::read_to_string(backup_path(&path, 2)).unwrap().starts_with("legacy = true"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,86 @@
+use std::env;
+use std::fs;
+use std::net::{ Ipv4Addr, SocketAddrV4 };
+
+use node_network::{ ConfigPaths, Node, PeerInfo, SimConfig, SimNetwork, request_reload };
+
+fn addr(port: u16) -> SocketAddrV4 {
+    SocketAddrV4::new(Ipv4Addr::LOCALHOST, port)
+}
+
+fn run(network: &SimNetwork, node: &mut Node, millis: u64) {
+    for _ in 0..(millis / 5) {
+        network.advance(5);
+        for _ in 0..node.hosts.len() * 2 {
+            node.execute();
+        }
+    }
+}
+
+#[test]
+fn reload_applies_peer_changes_live() {
+    let dir = env::temp_dir().join(format!("frink-reload-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("node.toml"), "listen = [\"127.0.0.1:9100\", \"127.0.0.1:9101\", \"127.0.0.1:9102\"]\n").unwrap();
+
+    let network = SimNetwork::new(SimConfig::new(7));
+    let mut node = Node::simulated(network.clone());
+    node.config.paths = ConfigPaths::from_data_dir(&dir);
+    assert!(node.read_hosts());
+    assert_eq!(node.hosts.len(), 3);
+    for host in &mut node.hosts {
+        host.recv_messages.clear();
+    }
+
+    let peer = |node: &Node, i: usize| PeerInfo {
+        address: String::from("127.0.0.1"),
+        port: node.hosts[i].port,
+        key: node.hosts[i].secure.public_key.to_vec(),
+        connected: false,
+    };
+
+    // Every host learns about 9101 and 9102
+    let mut config = node.config.clone();
+    config.set_peers(&[peer(&node, 1), peer(&node, 2)]);
+    config.save().unwrap();
+    node.reload().unwrap();
+    run(&network, &mut node, 200);
+    assert_eq!(node.hosts[0].peers.len(), 2);
+    assert!(node.hosts[0].peers.iter().all(|p| p.connected));
+
+    // Drop 9101 and raise the replay window; the 9102 link must survive untouched
+    let mut config = node.config.clone();
+    config.set_peers(&[peer(&node, 0), peer(&node, 2)]);
+    config.limits.replay_window = 4096;
+    config.save().unwrap();
+    request_reload();
+    node.execute();
+
+    let host = &node.hosts[0];
+    let ports: Vec<u16> = host.peers.iter().map(|p| p.port).collect();
+    assert_eq!(ports, vec![9102, 9100]);
+    assert!(host.peers[0].connected);
+    assert_eq!(host.replay_guard.window, 4096);
+
+    run(&network, &mut node, 200);
+    assert!(node.hosts[0].host[0].peers().contains(&addr(9102)));
+
+    node.hosts[0].broadcast_message(&Vec::from("after reload"));
+    run(&network, &mut node, 200);
+    assert_eq!(node.hosts[2].recv_messages.last().unwrap().msg, Vec::from("after reload"));
+
+    // 9101 is no longer a known peer of 9100, so its messages are dropped
+    node.hosts[1].broadcast_message(&Vec::from("from 9101"));
+    run(&network, &mut node, 200);
+    assert!(node.hosts[0].recv_messages.iter().all(|m| m.msg != Vec::from("from 9101")));
+
+    // A broken edit is reported and the running configuration stays in place
+    let text = fs::read_to_string(dir.join("node.toml")).unwrap().replace("replay_window = 4096", "replay_window = 0");
+    fs::write(dir.join("node.toml"), text).unwrap();
+    assert!(node.reload().is_err());
+    assert_eq!(node.hosts[0].peers.len(), 2);
+    assert_eq!(node.hosts[0].replay_guard.window, 4096);
+
+    let _ = fs::remove_dir_all(&dir);
+}
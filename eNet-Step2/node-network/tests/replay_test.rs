@@ -0,0 +1,40 @@
+use std::net::{ Ipv4Addr, SocketAddrV4 };
+
+use node_network::{ Envelope, ReplayError, ReplayGuard };
+
+fn envelope(sequence: u64, timestamp: u64) -> Envelope {
+    Envelope { sequence, timestamp, body: Vec::from("Hello World") }
+}
+
+#[test]
+fn rejects_replayed_envelopes() {
+    let sender = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 8875);
+    let now = 1_000_000;
+    let mut guard = ReplayGuard::new(16, 1_000);
+
+    let first = Envelope::decode(&envelope(100, now).encode()).unwrap();
+    assert_eq!(guard.check(sender, &first, now), Ok(()));
+    assert_eq!(guard.check(sender, &first, now), Err(ReplayError::Duplicate(100)));
+
+    // Reordered delivery inside the window is fine, once
+    assert_eq!(guard.check(sender, &envelope(110, now), now), Ok(()));
+    assert_eq!(guard.check(sender, &envelope(105, now), now), Ok(()));
+    assert_eq!(guard.check(sender, &envelope(105, now), now), Err(ReplayError::Duplicate(105)));
+    assert_eq!(guard.check(sender, &envelope(94, now), now),
+        Err(ReplayError::Stale { sequence: 94, high_water: 110 }));
+
+    assert_eq!(guard.check(sender, &envelope(111, now - 5_000), now), Err(ReplayError::Expired(now - 5_000)));
+    assert_eq!(guard.check(sender, &envelope(111, now + 5_000), now), Err(ReplayError::FromFuture(now + 5_000)));
+    assert_eq!(guard.high_water(sender), Some(110));
+}
+
+#[test]
+fn extreme_timestamps_do_not_overflow() {
+    let sender = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 8876);
+    let mut guard = ReplayGuard::new(16, 1_000);
+
+    assert_eq!(guard.check(sender, &envelope(1, u64::MAX), 1_000_000), Err(ReplayError::FromFuture(u64::MAX)));
+    assert_eq!(guard.check(sender, &envelope(1, u64::MAX), u64::MAX), Ok(()));
+    assert_eq!(guard.check(sender, &envelope(2, u64::MAX - 500), u64::MAX), Ok(()));
+    assert_eq!(guard.check(sender, &envelope(3, 0), u64::MAX), Err(ReplayError::Expired(0)));
+}
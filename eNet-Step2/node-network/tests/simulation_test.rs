@@ -0,0 +1,336 @@
+use std::env;
+use std::fs;
+use std::net::{ Ipv4Addr, SocketAddrV4 };
+
+use chain_core::{ Account, Encode, IdentityStatus, KeyId, Transaction, TxKind };
+use node_network::{ Node, PayloadKind, PeerInfo, SimConfig, SimNetwork, SimTransport, Transport, TransportError, TransportEvent };
+use secure_sign::NistCryptography;
+
+const BASE_PORT: u16 = 9000;
+
+fn build_node(network: &SimNetwork, count: u16) -> Node {
+    let mut node = Node::simulated(network.clone());
+    for i in 0..count {
+        node.create_host(BASE_PORT + i);
+        let host = node.hosts.last_mut().unwrap();
+        host.secure.init();
+        assert!(host.generate_keypair());
+        host.recv_messages.clear();
+    }
+
+    // Full mesh: every host knows every other host's public key
+    let keys: Vec<(u16, Vec<u8>)> = node.hosts.iter().map(|h| (h.port, h.secure.public_key.to_vec())).collect();
+    for host in &mut node.hosts {
+        for (port, key) in &keys {
+            if *port != host.port {
+                host.add_peer_info(PeerInfo {
+                    address: String::from("127.0.0.1"),
+                    port: *port,
+                    key: key.clone(),
+                    connected: false,
+                });
+            }
+        }
+        assert!(host.connect_peers());
+    }
+
+    node
+}
+
+fn run(network: &SimNetwork, node: &mut Node, millis: u64) {
+    for _ in 0..(millis / 5) {
+        network.advance(5);
+        for _ in 0..node.hosts.len() * 2 {
+            node.execute();
+        }
+    }
+}
+
+// Make `keys` the verified validator set on every host, with short block times and timeouts
+fn set_validators(node: &mut Node, keys: &[Vec<u8>]) {
+    for host in &mut node.hosts {
+        for key in keys {
+            let verified = Account { identity: IdentityStatus::Verified, ..Account::default() };
+            host.chain.state.set_account(KeyId::from_public_key(key), verified);
+        }
+        host.config.chain.validators = keys.iter().map(hex::encode_upper).collect();
+        host.config.chain.block_time_ms = 100;
+        host.config.chain.timeout_propose_ms = 200;
+        host.config.chain.timeout_vote_ms = 100;
+        host.config.chain.timeout_delta_ms = 50;
+        let chain = host.config.chain.clone();
+        host.apply_chain(&chain);
+    }
+}
+
+// Hold off the next height so the last decision can reach everyone
+fn hold_blocks(node: &mut Node) {
+    for host in &mut node.hosts {
+        host.config.chain.block_time_ms = 1_000_000;
+        let chain = host.config.chain.clone();
+        host.apply_chain(&chain);
+    }
+}
+
+fn addr(i: u16) -> SocketAddrV4 {
+    SocketAddrV4::new(Ipv4Addr::LOCALHOST, BASE_PORT + i)
+}
+
+// Refuses the first `refusals` connects, then behaves like the simulated transport
+struct RefusingTransport {
+    inner: SimTransport,
+    refusals: u32,
+}
+
+impl Transport for RefusingTransport {
+    fn connect(&mut self, addr: SocketAddrV4) -> Result<(), TransportError> {
+        if self.refusals > 0 {
+            self.refusals -= 1;
+            return Err(TransportError::ConnectFailed(addr));
+        }
+        self.inner.connect(addr)
+    }
+
+    fn disconnect(&mut self, addr: SocketAddrV4) {
+        self.inner.disconnect(addr)
+    }
+
+    fn send(&mut self, addr: SocketAddrV4, data: &[u8]) -> bool {
+        self.inner.send(addr, data)
+    }
+
+    fn peers(&mut self) -> Vec<SocketAddrV4> {
+        self.inner.peers()
+    }
+
+    fn service(&mut self, timeout_ms: u32) -> Option<TransportEvent> {
+        self.inner.service(timeout_ms)
+    }
+
+    fn now_millis(&self) -> u64 {
+        self.inner.now_millis()
+    }
+}
+
+#[test]
+fn broadcast_reaches_every_node() {
+    let network = SimNetwork::new(SimConfig::new(1));
+    let mut node = build_node(&network, 20);
+    run(&network, &mut node, 200);
+
+    for host in &node.hosts {
+        assert!(host.peers.iter().all(|p| p.connected));
+    }
+
+    let large: Vec<u8> = (0..20_000u32).map(|i| (i % 253) as u8).collect();
+    node.hosts[3].broadcast_message(&Vec::from("Hello World"));
+    node.hosts[7].broadcast_message(&large);
+    run(&network, &mut node, 500);
+
+    for (i, host) in node.hosts.iter().enumerate() {
+        let msgs: Vec<&Vec<u8>> = host.recv_messages.iter().map(|m| &m.msg).collect();
+        let expected = match i {
+            3 | 7 => 1,
+            _ => 2,
+        };
+        assert_eq!(msgs.len(), expected, "host {}", i);
+        if i != 3 {
+            assert!(msgs.contains(&&Vec::from("Hello World")));
+        }
+        if i != 7 {
+            assert!(msgs.contains(&&large));
+        }
+    }
+}
+
+#[test]
+fn partition_isolates_groups() {
+    let network = SimNetwork::new(SimConfig::new(2));
+    let mut node = build_node(&network, 6);
+    run(&network, &mut node, 200);
+
+    network.partition(&[(0..3).map(addr).collect(), (3..6).map(addr).collect()]);
+    node.hosts[0].broadcast_message(&Vec::from("left"));
+    run(&network, &mut node, 200);
+
+    for (i, host) in node.hosts.iter().enumerate() {
+        let expected = match i {
+            1 | 2 => 1,
+            _ => 0,
+        };
+        assert_eq!(host.recv_messages.len(), expected, "host {}", i);
+    }
+
+    network.heal();
+    node.hosts[5].broadcast_message(&Vec::from("healed"));
+    run(&network, &mut node, 200);
+    assert_eq!(node.hosts[0].recv_messages.last().unwrap().msg, Vec::from("healed"));
+}
+
+#[test]
+fn connect_during_partition_completes_after_heal() {
+    let network = SimNetwork::new(SimConfig::new(7));
+    network.partition(&[vec![addr(0)], vec![addr(1)]]);
+    let mut node = build_node(&network, 2);
+    run(&network, &mut node, 2_000);
+    assert!(node.hosts.iter().all(|h| !h.peers[0].connected));
+
+    // The timed out connect is dialled again once the link is back
+    network.heal();
+    run(&network, &mut node, 6_000);
+    assert!(node.hosts.iter().all(|h| h.peers[0].connected));
+    node.hosts[0].broadcast_message(&Vec::from("healed"));
+    run(&network, &mut node, 200);
+    assert_eq!(node.hosts[1].recv_messages.last().unwrap().msg, Vec::from("healed"));
+}
+
+#[test]
+fn same_seed_same_run() {
+    let mut stats = Vec::new();
+    for _ in 0..2 {
+        let mut config = SimConfig::new(42);
+        config.loss_rate = 0.2;
+        config.reorder = true;
+        let network = SimNetwork::new(config);
+        let mut node = build_node(&network, 5);
+        run(&network, &mut node, 200);
+
+        node.hosts[0].broadcast_message(&Vec::from("Hello World"));
+        run(&network, &mut node, 200);
+
+        let received: Vec<usize> = node.hosts.iter().map(|h| h.recv_messages.len()).collect();
+        stats.push((network.stats(), received, network.now()));
+    }
+
+    assert_eq!(stats[0], stats[1]);
+}
+
+#[test]
+fn transaction_travels_as_typed_payload() {
+    let network = SimNetwork::new(SimConfig::new(3));
+    let mut node = build_node(&network, 3);
+    run(&network, &mut node, 200);
+
+    let sender = KeyId::from_public_key(&node.hosts[0].secure.public_key);
+    for host in &mut node.hosts {
+        host.chain.state.set_account(sender, Account { balance: 10, ..Account::default() });
+    }
+
+    let chain_id = node.hosts[0].config.chain.chain_id.clone();
+    let mut tx = Transaction::new(&chain_id, TxKind::Data, Vec::from("Hello World"), 0, 1, 0);
+    assert!(tx.sign(&mut node.hosts[0].secure));
+    let mut foreign = Transaction::new("frink-other", TxKind::Data, Vec::from("Hello World"), 1, 1, 0);
+    assert!(foreign.sign(&mut node.hosts[0].secure));
+    let mut unfunded = Transaction::new(&chain_id, TxKind::Data, Vec::from("Hello World"), 1, 100, 0);
+    assert!(unfunded.sign(&mut node.hosts[0].secure));
+
+    for bad in &[&foreign, &unfunded] {
+        node.hosts[0].broadcast_payload(PayloadKind::Transaction, &bad.encode());
+    }
+    node.hosts[0].broadcast_payload(PayloadKind::Transaction, &tx.encode());
+    node.hosts[0].broadcast_message(&Vec::from("raw"));
+    run(&network, &mut node, 200);
+
+    // Only the valid transaction is pooled; raw messages still reach the inbox
+    for host in &node.hosts[1..] {
+        assert_eq!(host.mempool.len(), 1);
+        assert_eq!(host.mempool.get(&tx.id()), Some(&tx));
+        assert_eq!(host.recv_messages.len(), 1);
+        assert_eq!(host.recv_messages[0].kind, PayloadKind::Raw);
+        assert_eq!(host.recv_messages[0].msg, Vec::from("raw"));
+    }
+}
+
+#[test]
+fn validators_agree_on_blocks_with_one_absent() {
+    let network = SimNetwork::new(SimConfig::new(4));
+    let mut node = build_node(&network, 4);
+    run(&network, &mut node, 200);
+
+    // Hosts 0 to 2 validate with a fourth validator that never shows up; host 3 follows
+    let mut absent = NistCryptography::new();
+    absent.init();
+    assert_eq!(absent.generate_keypair(), 0);
+    let mut keys: Vec<Vec<u8>> = node.hosts[..3].iter().map(|h| h.secure.public_key.to_vec()).collect();
+    keys.push(absent.public_key.to_vec());
+    set_validators(&mut node, &keys);
+
+    let chain_id = node.hosts[0].config.chain.chain_id.clone();
+    let mut tx = Transaction::new(&chain_id, TxKind::Data, Vec::from("in a block"), 0, 0, 0);
+    assert!(tx.sign(&mut node.hosts[3].secure));
+    node.hosts[3].broadcast_payload(PayloadKind::Transaction, &tx.encode());
+    run(&network, &mut node, 2000);
+
+    hold_blocks(&mut node);
+    run(&network, &mut node, 500);
+
+    let tip = node.hosts[0].chain.tip().unwrap().clone();
+    assert!(tip.height >= 4);
+    for host in &node.hosts {
+        assert_eq!(host.chain.tip_hash(), tip.hash());
+        assert_eq!(host.chain.state, node.hosts[0].chain.state);
+        assert!(host.mempool.is_empty());
+    }
+    let sender = KeyId::from_public_key(&node.hosts[3].secure.public_key);
+    assert_eq!(node.hosts[3].chain.state.nonce(&sender), 1);
+}
+
+#[test]
+fn late_node_catches_up_from_genesis() {
+    let dir = env::temp_dir().join(format!("frink-sim-sync-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let network = SimNetwork::new(SimConfig::new(5));
+    let mut node = build_node(&network, 4);
+    for host in &mut node.hosts {
+        assert!(host.open_store(&dir.join(host.port.to_string())));
+    }
+    run(&network, &mut node, 200);
+
+    // Hosts 0 to 2 validate while host 3 is cut off from them
+    let keys: Vec<Vec<u8>> = node.hosts[..3].iter().map(|h| h.secure.public_key.to_vec()).collect();
+    set_validators(&mut node, &keys);
+    for host in &mut node.hosts {
+        host.sync.poll_interval = 200;
+    }
+    network.partition(&[(0..3).map(addr).collect(), vec![addr(3)]]);
+    run(&network, &mut node, 2000);
+    assert!(node.hosts[0].chain.next_height() >= 5);
+    assert_eq!(node.hosts[3].chain.next_height(), 0);
+
+    network.heal();
+    run(&network, &mut node, 1000);
+    hold_blocks(&mut node);
+    run(&network, &mut node, 500);
+
+    let tip = node.hosts[0].chain.tip_hash();
+    for host in &node.hosts {
+        assert_eq!(host.chain.tip_hash(), tip);
+        assert_eq!(host.chain.store().unwrap().tip().unwrap().unwrap().hash, tip);
+    }
+    assert_eq!(node.hosts[3].chain.state, node.hosts[0].chain.state);
+
+    drop(node);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn failed_connect_is_retried() {
+    let network = SimNetwork::new(SimConfig::new(6));
+    let mut node = Node::simulated(network.clone());
+    for i in 0..2 {
+        node.create_host(BASE_PORT + i);
+        let host = node.hosts.last_mut().unwrap();
+        host.secure.init();
+        assert!(host.generate_keypair());
+    }
+    node.hosts[0].host[0] = Box::new(RefusingTransport { inner: network.endpoint(addr(0)), refusals: 1 });
+    let key = node.hosts[1].secure.public_key.to_vec();
+    let peer = PeerInfo { address: String::from("127.0.0.1"), port: BASE_PORT + 1, key, connected: false };
+
+    // The refused connect is logged and queued rather than taking the node down
+    assert!(!node.hosts[0].connect_peer(peer));
+    run(&network, &mut node, 1_000);
+    assert!(!node.hosts[0].peers[0].connected);
+    run(&network, &mut node, 5_000);
+    assert!(node.hosts[0].peers[0].connected);
+}
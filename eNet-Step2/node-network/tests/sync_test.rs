@@ -0,0 +1,172 @@
+mod common;
+
+use std::collections::VecDeque;
+use std::env;
+use std::fs;
+use std::net::{ Ipv4Addr, SocketAddrV4 };
+use std::path::Path;
+
+use chain_core::{ Account, Chain, Decode, Encode, IdentityStatus, KeyId, Mempool, State, Store };
+use node_network::{ SyncMessage, Syncer };
+use secure_sign::NistCryptography;
+use common::{ certify, keypair, CHAIN, MAX_TX };
+
+const NOW: u64 = 1_600_000_000_000;
+
+struct Peer {
+    addr: SocketAddrV4,
+    chain: Chain,
+    syncer: Syncer,
+}
+
+fn chain_in(dir: &Path, genesis: &State, keys: &[NistCryptography]) -> Chain {
+    let mut chain = Chain::new(CHAIN, 1 << 20, MAX_TX);
+    chain.validator_keys = keys.iter().map(|k| k.public_key.to_vec()).collect();
+    chain.attach_store(Store::open(dir).unwrap()).unwrap();
+    if chain.tip().is_none() {
+        chain.state = genesis.clone();
+    }
+    chain
+}
+
+// Deliver what `target` sends and the replies, in order, until `stop` holds or nothing is left
+fn exchange(target: &mut Peer, peers: &mut [Peer], first: Vec<(SocketAddrV4, SyncMessage)>, asked: &mut Vec<SocketAddrV4>, stop: u64) {
+    let mut queue = VecDeque::new();
+    send(&mut queue, first, asked);
+    while let Some((to, msg)) = queue.pop_front() {
+        if target.chain.next_height() >= stop {
+            return;
+        }
+        let peer = peers.iter_mut().find(|p| p.addr == to).unwrap();
+        for (back, reply) in peer.syncer.handle(target.addr, msg, &mut peer.chain, NOW + 1000) {
+            assert_eq!(back, target.addr);
+            let reply = SyncMessage::decode(&reply.encode()).unwrap();
+            let next = target.syncer.handle(peer.addr, reply, &mut target.chain, NOW + 1000);
+            send(&mut queue, next, asked);
+        }
+    }
+}
+
+// Queue messages as they would go over the wire, noting who is asked for blocks or snapshot chunks
+fn send(queue: &mut VecDeque<(SocketAddrV4, SyncMessage)>, msgs: Vec<(SocketAddrV4, SyncMessage)>, asked: &mut Vec<SocketAddrV4>) {
+    for (to, msg) in msgs {
+        if let SyncMessage::GetBlocks(_) | SyncMessage::GetChunk { .. } = msg {
+            asked.push(to);
+        }
+        queue.push_back((to, SyncMessage::decode(&msg.encode()).unwrap()));
+    }
+}
+
+#[test]
+fn catches_up_from_two_peers_and_resumes() {
+    let base = env::temp_dir().join(format!("frink-sync-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&base);
+
+    let mut keys: Vec<NistCryptography> = (0..4).map(|_| keypair()).collect();
+    let mut genesis = State::new();
+    for secure in &keys {
+        let account = Account { identity: IdentityStatus::Verified, ..Account::default() };
+        genesis.set_account(KeyId::from_public_key(&secure.public_key), account);
+    }
+
+    // One peer decides 20 blocks, the other has them from it
+    let pool = Mempool::new(CHAIN, MAX_TX, 1 << 20, 600_000);
+    let mut source = chain_in(&base.join("a"), &genesis, &keys);
+    let mut copy = chain_in(&base.join("b"), &genesis, &keys);
+    for height in 0..20 {
+        let (block, state) = source.build_block(&pool, &mut keys[height % 4], NOW + height as u64 * 10).unwrap();
+        let certificate = certify(&block, &mut keys, &source);
+        source.commit(&block, state, certificate.clone()).unwrap();
+        copy.add_block(block, Some(certificate), NOW + 1000).unwrap();
+    }
+    assert_eq!(copy.tip_hash(), source.tip_hash());
+
+    let addr = |port| SocketAddrV4::new(Ipv4Addr::LOCALHOST, port);
+    let mut peers = vec![
+        Peer { addr: addr(1), chain: source, syncer: Syncer::new() },
+        Peer { addr: addr(2), chain: copy, syncer: Syncer::new() },
+    ];
+    let connected: Vec<SocketAddrV4> = peers.iter().map(|p| p.addr).collect();
+
+    // Both peers are asked for a batch; stop once the first is in, before the other peer's reply
+    let mut target = Peer { addr: addr(3), chain: chain_in(&base.join("c"), &genesis, &keys), syncer: Syncer::new() };
+    let mut asked = Vec::new();
+    let first = target.syncer.tick(&mut target.chain, &connected, NOW);
+    exchange(&mut target, &mut peers, first, &mut asked, 16);
+    assert_eq!(target.chain.next_height(), 16);
+    assert!(asked.contains(&addr(1)) && asked.contains(&addr(2)));
+
+    // After a restart the node asks from where its store left off
+    drop(target);
+    let mut target = Peer { addr: addr(3), chain: chain_in(&base.join("c"), &genesis, &keys), syncer: Syncer::new() };
+    assert_eq!(target.chain.finalized().map(|(_, h)| h), Some(15));
+    let first = target.syncer.tick(&mut target.chain, &connected, NOW);
+    assert!(first.iter().all(|(_, msg)| *msg == SyncMessage::GetHeaders { from: 16, max: 0 }));
+    exchange(&mut target, &mut peers, first, &mut asked, u64::MAX);
+
+    assert_eq!(target.chain.tip_hash(), peers[0].chain.tip_hash());
+    assert_eq!(target.chain.state, peers[0].chain.state);
+    assert_eq!(target.chain.finalized(), peers[0].chain.finalized());
+    assert!(!target.syncer.is_behind(&target.chain));
+    assert_eq!(target.syncer.queued(), 0);
+
+    drop(target);
+    drop(peers);
+    let _ = fs::remove_dir_all(&base);
+}
+
+#[test]
+fn fast_syncs_from_snapshot_then_blocks() {
+    let base = env::temp_dir().join(format!("frink-fast-sync-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&base);
+
+    let mut keys: Vec<NistCryptography> = (0..4).map(|_| keypair()).collect();
+    let mut genesis = State::new();
+    for secure in &keys {
+        let account = Account { identity: IdentityStatus::Verified, ..Account::default() };
+        genesis.set_account(KeyId::from_public_key(&secure.public_key), account);
+    }
+
+    // Both peers keep a snapshot of height 12, cut into one chunk per account
+    let pool = Mempool::new(CHAIN, MAX_TX, 1 << 20, 600_000);
+    let mut source = chain_in(&base.join("a"), &genesis, &keys);
+    let mut copy = chain_in(&base.join("b"), &genesis, &keys);
+    for height in 0..20 {
+        let (block, state) = source.build_block(&pool, &mut keys[height % 4], NOW + height as u64 * 10).unwrap();
+        let certificate = certify(&block, &mut keys, &source);
+        source.commit(&block, state, certificate.clone()).unwrap();
+        copy.add_block(block, Some(certificate), NOW + 1000).unwrap();
+        if height == 12 {
+            for chain in [&source, &copy] {
+                let snapshot = chain.snapshot(1).unwrap().unwrap();
+                chain.store().unwrap().save_snapshot(&snapshot).unwrap();
+            }
+        }
+    }
+
+    let addr = |port| SocketAddrV4::new(Ipv4Addr::LOCALHOST, port);
+    let mut peers = vec![
+        Peer { addr: addr(1), chain: source, syncer: Syncer::new() },
+        Peer { addr: addr(2), chain: copy, syncer: Syncer::new() },
+    ];
+    let connected: Vec<SocketAddrV4> = peers.iter().map(|p| p.addr).collect();
+
+    let mut target = Peer { addr: addr(3), chain: chain_in(&base.join("c"), &genesis, &keys), syncer: Syncer::new() };
+    let mut asked = Vec::new();
+    let first = target.syncer.tick(&mut target.chain, &connected, NOW);
+    assert_eq!(first.iter().filter(|(_, msg)| *msg == SyncMessage::GetSnapshot).count(), 2);
+    exchange(&mut target, &mut peers, first, &mut asked, u64::MAX);
+
+    // Chunks came from both peers and only the blocks above the snapshot were fetched
+    assert!(asked.contains(&addr(1)) && asked.contains(&addr(2)));
+    let store = target.chain.store().unwrap();
+    assert!(store.block_at(12).unwrap().is_some());
+    assert!(store.block_at(11).unwrap().is_none());
+    assert_eq!(target.chain.tip_hash(), peers[0].chain.tip_hash());
+    assert_eq!(target.chain.state, peers[0].chain.state);
+    assert!(!target.syncer.is_behind(&target.chain));
+
+    drop(target);
+    drop(peers);
+    let _ = fs::remove_dir_all(&base);
+}
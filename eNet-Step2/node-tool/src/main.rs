@@ -0,0 +1,192 @@
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use chain_core::{ Genesis, GenesisAccount, IdentityStatus };
+use node_network::{ ConfigPaths, ConfigSource, HostInfo, NodeConfig, GENESIS_FILE, config_arg, get_hosts, load_genesis, save_genesis, set_hosts };
+use secure_sign::NistCryptography;
+
+const USAGE: &str = "Usage: node-tool <command> [options]
+
+Commands:
+    migrate-config    Convert conf.ini + peerlist.csv into node.toml
+    create-genesis    Write genesis.toml with every host in the keystore as a validator,
+                      generating key pairs for listen addresses that have none
+    genesis-hash      Print the hash of the genesis file
+
+Options:
+    --config <path>   Configuration folder (or conf.ini inside it)
+    --output <path>   Where to write node.toml or genesis.toml (default: configuration folder)
+    --force           Overwrite an existing node.toml or genesis.toml
+
+Genesis options:
+    --chain-id <id>       Chain ID (default: chain.chain_id of the configuration)
+    --validator <hex>     Another validator's public key, may be repeated
+    --balance <amount>    Initial balance of every validator
+    --time <ms>           Genesis time in ms since the Unix epoch (default: now)
+    --genesis <path>      File for genesis-hash (default: the configured genesis)";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let res = match args.first().map(|s| s.as_str()) {
+        Some("migrate-config") => migrate_config(&args[1..]),
+        Some("create-genesis") => create_genesis(&args[1..]),
+        Some("genesis-hash") => genesis_hash(&args[1..]),
+        _ => Err(String::from(USAGE)),
+    };
+
+    if let Err(e) = res {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+// Value following `name` in the argument list
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    for i in 0..args.len() {
+        if args[i] == name {
+            return args.get(i + 1).cloned();
+        }
+        if let Some(value) = args[i].strip_prefix(&prefix) {
+            return Some(value.to_string());
+        }
+    }
+
+    None
+}
+
+// Every value following `name`, for options that may be repeated
+fn arg_values(args: &[String], name: &str) -> Vec<String> {
+    let prefix = format!("{}=", name);
+    let mut values = Vec::new();
+    for i in 0..args.len() {
+        if args[i] == name {
+            values.extend(args.get(i + 1).cloned());
+        } else if let Some(value) = args[i].strip_prefix(&prefix) {
+            values.push(value.to_string());
+        }
+    }
+
+    values
+}
+
+fn migrate_config(args: &[String]) -> Result<(), String> {
+    let paths = ConfigPaths::locate(config_arg(args.iter().cloned()));
+    let mut config = NodeConfig::from_legacy(&paths).map_err(|e| e.to_string())?;
+
+    if let Some(output) = arg_value(args, "--output") {
+        config.paths.toml_path = PathBuf::from(output);
+    }
+    if config.paths.toml_path.exists() && !args.iter().any(|a| a == "--force") {
+        return Err(format!("{} already exists, pass --force to overwrite it", config.paths.toml_path.display()));
+    }
+
+    // Keys stay in conf.ini, referenced relative to node.toml when it sits next to it
+    let same_dir = config.paths.toml_path.parent() == Some(paths.data_dir.as_path());
+    config.keystore = match (same_dir, paths.conf_path.strip_prefix(&paths.data_dir)) {
+        (true, Ok(relative)) => relative.to_path_buf(),
+        _ => paths.conf_path.clone(),
+    };
+    config.source = ConfigSource::Toml;
+    config.validate().map_err(|e| e.to_string())?;
+    config.save().map_err(|e| e.to_string())?;
+
+    println!("Wrote {} ({} hosts, {} bootstrap peers, keystore {})",
+        config.paths.toml_path.display(), config.listen.len(), config.bootstrap_peers.len(), config.keystore.display());
+
+    Ok(())
+}
+
+fn create_genesis(args: &[String]) -> Result<(), String> {
+    let paths = ConfigPaths::locate(config_arg(args.iter().cloned()));
+    let mut config = NodeConfig::load(&paths).map_err(|e| e.to_string())?;
+    let output = arg_value(args, "--output").map(PathBuf::from).unwrap_or_else(|| paths.data_dir.join(GENESIS_FILE));
+    if output.exists() && !args.iter().any(|a| a == "--force") {
+        return Err(format!("{} already exists, pass --force to overwrite it", output.display()));
+    }
+
+    let chain_id = arg_value(args, "--chain-id").unwrap_or_else(|| config.chain.chain_id.clone());
+    let time = match arg_value(args, "--time") {
+        Some(v) => v.parse::<u64>().map_err(|e| format!("--time: {}", e))?,
+        None => SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0),
+    };
+    let balance = match arg_value(args, "--balance") {
+        Some(v) => v.parse::<u64>().map_err(|e| format!("--balance: {}", e))?,
+        None => 0,
+    };
+
+    // Listen addresses without a key pair get one, as the node would on its first start
+    let keystore = config.keystore_path();
+    let mut hosts = get_hosts(&keystore);
+    let mut generated = 0;
+    for addr in &config.listen {
+        if hosts.iter().any(|h| h.port == addr.port()) {
+            continue;
+        }
+        let mut secure = NistCryptography::new();
+        secure.init();
+        if secure.generate_keypair() != 0 {
+            return Err(format!("could not generate a key pair for port {}", addr.port()));
+        }
+        let mut host = HostInfo::new();
+        host.port = addr.port();
+        host.public_key = secure.public_key.to_vec();
+        host.private_key = secure.private_key.to_vec();
+        hosts.push(host);
+        generated += 1;
+    }
+    if generated > 0 && !set_hosts(&keystore, &hosts) {
+        return Err(format!("could not write the key pairs to {}", keystore.display()));
+    }
+
+    let mut genesis = Genesis::new(&chain_id, time);
+    genesis.params.max_block_bytes = config.chain.max_block_bytes as u64;
+    genesis.params.max_tx_bytes = config.chain.max_tx_bytes as u64;
+    genesis.params.block_time_ms = config.chain.block_time_ms;
+    genesis.validators = hosts.iter().map(|h| h.public_key.clone()).collect();
+    for key in arg_values(args, "--validator") {
+        genesis.validators.push(hex::decode(&key).map_err(|e| format!("--validator: {}", e))?);
+    }
+    if balance > 0 {
+        genesis.accounts = genesis.validators.iter().map(|key| GenesisAccount {
+            public_key: key.clone(),
+            balance,
+            identity: IdentityStatus::Verified,
+        }).collect();
+    }
+    genesis.validate().map_err(|e| e.to_string())?;
+    save_genesis(&output, &genesis).map_err(|e| e.to_string())?;
+
+    // node.toml refers to the genesis relative to itself when it sits next to it
+    if config.source == ConfigSource::Toml {
+        config.genesis = Some(match output.strip_prefix(&paths.data_dir) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => output.clone(),
+        });
+        config.save().map_err(|e| e.to_string())?;
+    }
+
+    println!("Wrote {} for `{}` with {} validators ({} key pairs generated), genesis hash {}",
+        output.display(), genesis.chain_id, genesis.validators.len(), generated, genesis.hash().to_hex());
+
+    Ok(())
+}
+
+fn genesis_hash(args: &[String]) -> Result<(), String> {
+    let path = match arg_value(args, "--genesis") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let paths = ConfigPaths::locate(config_arg(args.iter().cloned()));
+            let config = NodeConfig::load(&paths).unwrap_or_else(|_| NodeConfig::at(paths.clone()));
+            config.genesis_path().unwrap_or_else(|| paths.data_dir.join(GENESIS_FILE))
+        },
+    };
+    let genesis = load_genesis(&path).map_err(|e| e.to_string())?;
+
+    println!("{}", genesis.hash().to_hex());
+
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{ Command, Output };
+
+use node_network::{ ConfigPaths, ConfigSource, NodeConfig };
+use secure_sign::NistCryptography;
+
+fn legacy_dir(name: &str, conf: &str, peerlist: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("frink-migrate-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("conf.ini"), conf).unwrap();
+    fs::write(dir.join("peerlist.csv"), peerlist).unwrap();
+    dir
+}
+
+fn node_tool(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_node-tool")).args(args).output().unwrap()
+}
+
+fn public_key() -> String {
+    let mut secure = NistCryptography::new();
+    secure.init();
+    assert_eq!(secure.generate_keypair(), 0);
+    hex::encode_upper(&secure.public_key[..])
+}
+
+const CONF: &str = "[Host 1]\nport = 8871\npublic = 0A0B\nprivate = 0C0D\n";
+
+#[test]
+fn migrate_config_writes_node_toml() {
+    let key = public_key();
+    let dir = legacy_dir("valid", CONF, &format!("127.0.0.1,8873,{}\n", key));
+    let config_arg = dir.to_str().unwrap();
+
+    let out = node_tool(&["migrate-config", "--config", config_arg]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+
+    let config = NodeConfig::load(&ConfigPaths::from_data_dir(&dir)).unwrap();
+    assert_eq!(config.source, ConfigSource::Toml);
+    assert_eq!(config.keystore, PathBuf::from("conf.ini"));
+    assert_eq!(config.keystore_path(), dir.join("conf.ini"));
+    assert_eq!(config.listen[0].port(), 8871);
+    assert_eq!(config.bootstrap_peers[0].address.to_string(), "127.0.0.1:8873");
+    assert_eq!(config.bootstrap_peers[0].public_key, key);
+
+    // An existing node.toml is only replaced on request
+    let out = node_tool(&["migrate-config", "--config", config_arg]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("--force"));
+    assert!(node_tool(&["migrate-config", "--config", config_arg, "--force"]).status.success());
+}
+
+#[test]
+fn migrate_config_reports_the_bad_entry() {
+    let conf = "[Host 1]\nport = 88x1\npublic = 0A0B\nprivate = 0C0D\n";
+    let dir = legacy_dir("host", conf, "");
+    let out = node_tool(&["migrate-config", "--config", dir.to_str().unwrap()]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("Host 1.port") && stderr.contains("88x1"), "{}", stderr);
+    assert!(!dir.join("node.toml").exists());
+
+    let dir = legacy_dir("peer", CONF, "127.0.0.1,8873,XYZ\n");
+    let out = node_tool(&["migrate-config", "--config", dir.to_str().unwrap()]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("peerlist.csv") && stderr.contains("line 1 key"), "{}", stderr);
+    assert!(!dir.join("node.toml").exists());
+}
@@ -13,6 +13,10 @@ use rand::Rng;
 include!("falcon.rs");
 
 const SEEDBYTES: usize = 48;
+const NONCEBYTES: usize = 40;
+
+// Largest detached signature: length (2) || nonce (40) || compressed signature
+pub const DETACHED_SIGNATURE_MAX: usize = CRYPTO_BYTES as usize;
 
 pub struct NistCryptography {
     pub seed: [u8; SEEDBYTES],
@@ -99,10 +103,81 @@ impl NistCryptography {
         }
     }
 
+    // Open the signed message `sm` with a 897-byte public key, None unless it verifies
+    pub fn verify_public_key(sm: &[u8], pk: &[u8]) -> Option<Vec<u8>> {
+        if pk.len() != CRYPTO_PUBLICKEYBYTES as usize {
+            return None;
+        }
+
+        let mut m = vec![0u8; sm.len()];
+        let mut mlen: u64 = 0;
+        if unsafe { crypto_sign_open(m.as_mut_ptr(), &mut mlen, sm.as_ptr(), sm.len() as u64, pk.as_ptr()) } != 0 {
+            return None;
+        }
+        m.truncate(mlen as usize);
+
+        Some(m)
+    }
+
     pub fn verify_foreign_key(&mut self, m: *mut u8, mlen: *mut u64, sm: *const u8, smlen: u64, _sk: *mut u8) -> i32 {
         unsafe {
             let ret_val = crypto_sign_open_private(m, mlen, sm, smlen, _sk);
             return ret_val;
         }
     }
-}
\ No newline at end of file
+
+    // Falcon signature of `m` without the message: sig_len (2, BE) || nonce (40) || sig
+    pub fn sign_detached(&mut self, m: &[u8]) -> Option<Vec<u8>> {
+        let mut sm = vec![0u8; m.len() + CRYPTO_BYTES as usize];
+        let mut smlen: u64 = 0;
+        if self.sign_msg(sm.as_mut_ptr(), &mut smlen, m.as_ptr(), m.len() as u64) != 0 {
+            return None;
+        }
+        sm.truncate(smlen as usize);
+        sm.drain(2 + NONCEBYTES..2 + NONCEBYTES + m.len());
+
+        Some(sm)
+    }
+
+    // Check a `sign_detached` signature of `m` against a 897-byte public key
+    pub fn verify_detached(m: &[u8], sig: &[u8], pk: &[u8]) -> bool {
+        if sig.len() < 2 + NONCEBYTES || sig.len() > DETACHED_SIGNATURE_MAX || pk.len() != CRYPTO_PUBLICKEYBYTES as usize {
+            return false;
+        }
+        // The length prefix must cover exactly the rest, or the message boundary would move
+        let sig_len = ((sig[0] as usize) << 8) | sig[1] as usize;
+        if sig_len != sig.len() - 2 - NONCEBYTES {
+            return false;
+        }
+
+        let mut sm = Vec::<u8>::with_capacity(sig.len() + m.len());
+        sm.extend_from_slice(&sig[..2 + NONCEBYTES]);
+        sm.extend_from_slice(m);
+        sm.extend_from_slice(&sig[2 + NONCEBYTES..]);
+
+        let mut out = vec![0u8; sm.len()];
+        let mut mlen: u64 = 0;
+        unsafe {
+            crypto_sign_open(out.as_mut_ptr(), &mut mlen, sm.as_ptr(), sm.len() as u64, pk.as_ptr()) == 0
+        }
+    }
+
+    /*
+     *  Check many `(message, signature, public key)` triples, spread over the
+     *  available cores. True only if every signature verifies.
+     */
+    pub fn verify_detached_batch(items: &[(&[u8], &[u8], &[u8])]) -> bool {
+        let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(items.len());
+        if threads <= 1 {
+            return items.iter().all(|(m, sig, pk)| NistCryptography::verify_detached(m, sig, pk));
+        }
+
+        let per_thread = items.len().div_ceil(threads);
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = items.chunks(per_thread)
+                .map(|chunk| scope.spawn(move || chunk.iter().all(|(m, sig, pk)| NistCryptography::verify_detached(m, sig, pk))))
+                .collect();
+            workers.into_iter().all(|w| w.join().unwrap_or(false))
+        })
+    }
+}
@@ -4,8 +4,9 @@ extern crate hex;
 use libc;
 use std::mem;
 
+use chain_core::{ Encode, Transaction, TxKind };
 use node_network;
-use node_network::Node;
+use node_network::{ Node, PayloadKind };
 use secure_sign::NistCryptography;
 
 #[allow(unused_assignments)]
@@ -70,7 +71,14 @@ fn test_net() {
         }
     }
 
-    node.hosts[1].broadcast_message(&msg_str);
+    // Send the data as a signed transaction of host 1
+    let host = &mut node.hosts[1];
+    let mut tx = Transaction::new(&host.config.chain.chain_id, TxKind::Data, msg_str, 0, 0, 0);
+    if !tx.sign(&mut host.secure) {
+        println!("Couldn't sign transaction", );
+        return;
+    }
+    host.broadcast_payload(PayloadKind::Transaction, &tx.encode());
     loop {
         node.execute();
 
@@ -484,7 +484,10 @@ fn set_widgets(ui: &mut conrod::UiCell, app: &mut HostData, ids: &mut Ids) {
             }
             app.node.hosts[0].save_peerlist();
 
-            app.node.restart();
+            // Apply the saved peers to the running hosts instead of rebuilding them
+            if let Err(e) = app.node.reload() {
+                println!("{}", e);
+            }
         }
 
         // Public Key Label.